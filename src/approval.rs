@@ -0,0 +1,108 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::git_extras::Repo;
+use super::github::{rest, PullRequest};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A GitHub team written as `org/team-slug`, or just `team-slug` for a team
+/// in the organization owning the repository.
+pub struct Team {
+    pub org: String,
+    pub slug: String,
+}
+
+impl Team {
+    pub fn parse(team: &str, repo: &Repo) -> Team {
+        match team.find('/') {
+            Some(idx) => Team {
+                org: team[..idx].to_string(),
+                slug: team[idx + 1..].to_string(),
+            },
+            None => Team {
+                org: repo.owner.clone(),
+                slug: team.to_string(),
+            },
+        }
+    }
+}
+
+/// Posts the integration plan as an issue and blocks until a member of `team`
+/// other than the token owner approves it with a 👍 reaction or an `/approve`
+/// comment. Returns the approver's login, or `None` when `timeout` elapses.
+pub fn wait_for_approval(
+    token: &str,
+    repo: &Repo,
+    team: &Team,
+    dest_branch: &str,
+    base: &str,
+    pull_requests: &[PullRequest],
+    timeout: Duration,
+) -> Result<Option<String>, reqwest::Error> {
+    let requester = rest::current_user(token)?.login;
+
+    let title = format!("Approval requested: integrate into {}", dest_branch);
+    let mut body = format!(
+        "@{} wants to rebuild `{}` from `{}` by merging:\n\n",
+        requester, dest_branch, base
+    );
+    for pr in pull_requests {
+        body.push_str(&format!("1. #{} `{}`\n", pr.number, pr.branch));
+    }
+    body.push_str(&format!(
+        "\nA member of @{}/{} can approve with a 👍 reaction or an `/approve` comment.",
+        team.org, team.slug
+    ));
+
+    let issue = rest::create_issue(token, repo, &title, &body, &[], &[])?;
+    info!("\nWaiting for approval from {}/{}", team.org, team.slug);
+    info!("  {}", issue.html_url);
+
+    let started = Instant::now();
+    loop {
+        if let Some(approver) = find_approver(token, repo, team, &requester, issue.number)? {
+            let note = format!("Approved by @{}, integrating now.", approver);
+            rest::create_comment(token, repo, issue.number, &note)?;
+            rest::close_issue(token, repo, issue.number)?;
+            return Ok(Some(approver));
+        }
+
+        if started.elapsed() >= timeout {
+            rest::create_comment(token, repo, issue.number, "Timed out waiting for approval.")?;
+            rest::close_issue(token, repo, issue.number)?;
+            return Ok(None);
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn find_approver(
+    token: &str,
+    repo: &Repo,
+    team: &Team,
+    requester: &str,
+    issue: i64,
+) -> Result<Option<String>, reqwest::Error> {
+    let mut candidates: Vec<String> = rest::issue_reactions(token, repo, issue)?
+        .into_iter()
+        .filter(|r| r.content == "+1")
+        .map(|r| r.user.login)
+        .collect();
+
+    candidates.extend(
+        rest::issue_comments(token, repo, issue)?
+            .into_iter()
+            .filter(|c| c.body.trim().starts_with("/approve"))
+            .map(|c| c.user.login),
+    );
+
+    for login in candidates {
+        if login != requester && rest::is_team_member(token, &team.org, &team.slug, &login)? {
+            return Ok(Some(login));
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,183 @@
+use git2::{Commit, Oid, Repository};
+use std::collections::{HashMap, HashSet};
+
+use super::compare;
+use super::history::Run;
+
+/// How one first-parent commit of an integration branch compares to what
+/// git-integrate would have made.
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    /// A merge that merging the same commits again reproduces exactly.
+    Reproduced,
+    /// An empty section marker.
+    Section,
+    /// A merge whose conflicts were resolved, which can't be redone without
+    /// the resolution; everything else about it is reproduced.
+    Resolved,
+    /// A merge of a pull request whose content differs from merging the same
+    /// commits again, as after amending it.
+    Modified,
+    /// A merge carrying the trailer of a pull request, of a commit no run
+    /// recorded as its head, as when such a merge is made by hand.
+    Unrecorded,
+    /// A commit git-integrate does not make.
+    Foreign,
+}
+
+pub struct Finding {
+    pub commit: Oid,
+    pub summary: String,
+    pub verdict: Verdict,
+}
+
+/// The heads each pull request was merged at, as `runs` recorded them.
+pub type Heads = HashMap<i64, HashSet<Oid>>;
+
+pub fn recorded_heads(runs: &[Run]) -> Heads {
+    let mut heads = Heads::new();
+    for pull in runs.iter().flat_map(|run| &run.pulls) {
+        if let Some(head) = pull
+            .head
+            .as_deref()
+            .and_then(|head| Oid::from_str(head).ok())
+        {
+            heads.entry(pull.number).or_default().insert(head);
+        }
+    }
+    heads
+}
+
+/// Checks every commit `branch` adds on top of `base` along its first
+/// parents, oldest first, without touching the work tree. The second value
+/// tells whether the branch starts from `base` at all.
+pub fn audit(
+    repository: &Repository,
+    branch: &str,
+    base: &str,
+    heads: &Heads,
+) -> Result<(Vec<Finding>, bool), git2::Error> {
+    let base = repository.revparse_single(base)?.peel_to_commit()?.id();
+    let mut commit = repository.revparse_single(branch)?.peel_to_commit()?;
+    let mut findings = vec![];
+
+    let on_base = loop {
+        if commit.id() == base || repository.graph_descendant_of(base, commit.id())? {
+            break true;
+        }
+        findings.push(Finding {
+            commit: commit.id(),
+            summary: commit.summary().unwrap_or_default().to_string(),
+            verdict: verdict(repository, &commit, heads)?,
+        });
+        commit = match commit.parent(0) {
+            Ok(parent) => parent,
+            Err(_) => break false,
+        };
+    };
+
+    findings.reverse();
+    Ok((findings, on_base))
+}
+
+fn verdict(
+    repository: &Repository,
+    commit: &Commit,
+    heads: &Heads,
+) -> Result<Verdict, git2::Error> {
+    match commit.parent_count() {
+        1 => {
+            let is_marker = commit
+                .message()
+                .is_some_and(|message| message.starts_with("Section: "));
+            if is_marker && commit.tree_id() == commit.parent(0)?.tree_id() {
+                Ok(Verdict::Section)
+            } else {
+                Ok(Verdict::Foreign)
+            }
+        }
+        2 if compare::pr_number(commit).is_some() => {
+            let merged = commit.parent_id(1)?;
+            let recorded = compare::pr_number(commit)
+                .and_then(|number| heads.get(&number))
+                .is_some_and(|heads| heads.contains(&merged));
+            if !recorded {
+                return Ok(Verdict::Unrecorded);
+            }
+            let index = repository.merge_commits(&commit.parent(0)?, &commit.parent(1)?, None)?;
+            let conflicted: Vec<Vec<u8>> = index
+                .conflicts()?
+                .filter_map(Result::ok)
+                .filter_map(|conflict| conflict.our.or(conflict.their).or(conflict.ancestor))
+                .map(|entry| entry.path)
+                .collect();
+            // Resolving conflicts may only change the conflicted paths.
+            let diff = repository.diff_tree_to_index(Some(&commit.tree()?), Some(&index), None)?;
+            let changed_elsewhere = diff.deltas().any(|delta| {
+                [delta.old_file().path_bytes(), delta.new_file().path_bytes()]
+                    .iter()
+                    .flatten()
+                    .any(|path| !conflicted.iter().any(|c| c == path))
+            });
+            Ok(match (changed_elsewhere, conflicted.is_empty()) {
+                (true, _) => Verdict::Modified,
+                (false, true) => Verdict::Reproduced,
+                (false, false) => Verdict::Resolved,
+            })
+        }
+        _ => Ok(Verdict::Foreign),
+    }
+}
+
+/// Prints the audit of `branch` against the heads `runs` merged and answers
+/// whether it holds nothing but what git-integrate would have built. Merges
+/// with resolved conflicts only pass with `allow_resolved`.
+pub fn run(
+    repository: &Repository,
+    branch: &str,
+    base: &str,
+    runs: &[Run],
+    allow_resolved: bool,
+) -> bool {
+    let (findings, on_base) = match audit(repository, branch, base, &recorded_heads(runs)) {
+        Ok(audit) => audit,
+        Err(e) => panic!("{}", e),
+    };
+
+    summary!("Auditing {} against {}", branch, base);
+    let mut clean = on_base;
+    for finding in &findings {
+        let verdict = match finding.verdict {
+            Verdict::Reproduced => "✅ reproduced",
+            Verdict::Section => "✅ section",
+            Verdict::Resolved => "⚠️ conflicts resolved, not reproducible",
+            Verdict::Modified => "❌ modified after merging",
+            Verdict::Unrecorded => "❌ merges a head no run recorded",
+            Verdict::Foreign => "❌ not made by git-integrate",
+        };
+        clean &= match finding.verdict {
+            Verdict::Reproduced | Verdict::Section => true,
+            Verdict::Resolved => allow_resolved,
+            Verdict::Modified | Verdict::Unrecorded | Verdict::Foreign => false,
+        };
+        summary!(
+            "  {} {}  {}",
+            &finding.commit.to_string()[..10],
+            finding.summary,
+            verdict
+        );
+    }
+    if !on_base {
+        error!("\n{} is not built on {}", branch, base);
+    }
+
+    if clean {
+        summary!("\n{} matches what git-integrate builds", branch);
+    } else {
+        error!(
+            "\n{} was changed by hand or by something else than git-integrate",
+            branch
+        );
+    }
+    clean
+}
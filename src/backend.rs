@@ -0,0 +1,612 @@
+use git2::{Repository, RepositoryState};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+
+use super::binary;
+use super::conflicts;
+use super::git_extras::{git_command, path_from_git, remote};
+use super::github::PullRequest;
+use super::timeouts::{self, Timed};
+
+/// The git operations a run is made of. Commands answer whether git
+/// succeeded; an `Err` means git could not be run at all.
+pub trait GitBackend {
+    fn fetch(&self) -> io::Result<bool>;
+
+    /// Fetches only `branches` from origin, spread over `jobs` concurrent
+    /// fetches.
+    fn fetch_branches(&self, branches: &[String], jobs: usize) -> io::Result<bool>;
+
+    /// Fetches `refspecs` from origin as they are.
+    fn fetch_refs(&self, refspecs: &[String]) -> io::Result<bool>;
+
+    /// Fetches `depth` more commits of history into a shallow clone, or all
+    /// of it.
+    fn deepen(&self, depth: Option<usize>) -> io::Result<bool>;
+
+    /// Creates or resets `branch` to the base and checks it out.
+    fn checkout(&self, branch: &str) -> io::Result<bool>;
+
+    /// Starts merging `revision` without committing it.
+    fn merge(&self, revision: &str, allow_unrelated: bool) -> io::Result<bool>;
+
+    /// Whether a merge is in progress.
+    fn merging(&self) -> bool;
+
+    /// Adds a trailer to the message of the merge in progress.
+    fn add_trailer(&self, trailer: &str) -> io::Result<bool>;
+
+    /// Conflicted paths rerere has no resolution for.
+    fn rerere_remaining(&self) -> io::Result<Vec<PathBuf>>;
+
+    fn conflicted_paths(&self) -> Vec<PathBuf>;
+
+    fn has_conflict_markers(&self, path: &Path) -> bool;
+
+    fn has_conflicts(&self) -> bool;
+
+    fn add(&self, paths: &[String]) -> io::Result<bool>;
+
+    fn resolve_binary(
+        &self,
+        policy: binary::Policy,
+        pr: &PullRequest,
+        merged: &[PullRequest],
+    ) -> Result<Vec<binary::Choice>, git2::Error>;
+
+    /// Tells the user how to resolve the conflicts left by merging `pr`.
+    fn explain_conflicts(&self, pr: &PullRequest, merged: &[PullRequest], rerun: &str);
+
+    fn commit(&self) -> io::Result<bool>;
+
+    /// Commits nothing but `message`, to head a section of the branch.
+    fn mark_section(&self, message: &str) -> io::Result<bool>;
+
+    /// Force pushes HEAD to `branch` on origin.
+    fn push(&self, branch: &str) -> io::Result<bool>;
+
+    /// Force pushes HEAD to `branch` on origin unless it was pushed to since
+    /// the last fetch.
+    fn publish(&self, branch: &str) -> io::Result<bool>;
+
+    /// Force pushes HEAD to `branch` on `remote`, such as a mirror.
+    fn push_to(&self, remote: &str, branch: &str) -> io::Result<bool>;
+
+    /// Moves the checked out branch back to `commit`, dropping what was merged
+    /// since.
+    fn reset_hard(&self, commit: &str) -> io::Result<bool>;
+
+    /// Moves what HEAD has on top of `upstream` onto `onto`, merges included,
+    /// leaving HEAD as it was if that fails.
+    fn rebase_onto(&self, onto: &str, upstream: &str) -> io::Result<bool>;
+
+    /// Where `refname` points on origin.
+    fn ls_remote(&self, refname: &str) -> io::Result<Option<String>>;
+
+    /// Every branch on origin with the commit it points at.
+    fn remote_branches(&self) -> io::Result<HashMap<String, String>>;
+}
+
+/// Runs the `git` executable on a repository.
+pub struct Subprocess<'a> {
+    pub repository: &'a Repository,
+    /// What the integration branch is built on.
+    pub base: &'a str,
+}
+
+impl GitBackend for Subprocess<'_> {
+    fn fetch(&self) -> io::Result<bool> {
+        Ok(git_command(self.repository)
+            .arg("fetch")
+            .arg("--all")
+            .status_timed()?
+            .success())
+    }
+
+    fn fetch_branches(&self, branches: &[String], jobs: usize) -> io::Result<bool> {
+        if branches.is_empty() {
+            return Ok(true);
+        }
+        let per_job = branches.len().div_ceil(jobs.max(1));
+        let mut children = vec![];
+        for chunk in branches.chunks(per_job) {
+            let spawned = git_command(self.repository)
+                .arg("fetch")
+                .arg(remote())
+                .args(
+                    chunk
+                        .iter()
+                        .map(|b| format!("+refs/heads/{}:refs/remotes/{}/{}", b, remote(), b)),
+                )
+                .spawn();
+            match spawned {
+                Ok(child) => children.push(child),
+                Err(e) => {
+                    timeouts::kill_all(&mut children);
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(timeouts::wait_all(&mut children, "git fetch")?
+            .iter()
+            .all(ExitStatus::success))
+    }
+
+    fn fetch_refs(&self, refspecs: &[String]) -> io::Result<bool> {
+        if refspecs.is_empty() {
+            return Ok(true);
+        }
+        Ok(git_command(self.repository)
+            .arg("fetch")
+            .arg(remote())
+            .args(refspecs)
+            .status_timed()?
+            .success())
+    }
+
+    fn deepen(&self, depth: Option<usize>) -> io::Result<bool> {
+        Ok(git_command(self.repository)
+            .arg("fetch")
+            .arg(match depth {
+                Some(depth) => format!("--deepen={}", depth),
+                None => "--unshallow".to_string(),
+            })
+            .arg(remote())
+            .status_timed()?
+            .success())
+    }
+
+    fn checkout(&self, branch: &str) -> io::Result<bool> {
+        Ok(git_command(self.repository)
+            .arg("checkout")
+            .arg("--no-track")
+            .arg("-B")
+            .arg(branch)
+            .arg(self.base)
+            .status_timed()?
+            .success())
+    }
+
+    fn merge(&self, revision: &str, allow_unrelated: bool) -> io::Result<bool> {
+        let mut command = git_command(self.repository);
+        command
+            .arg("merge")
+            .arg("--no-ff")
+            .arg("--no-commit")
+            .arg("--no-rerere-autoupdate")
+            .arg("--log");
+        if allow_unrelated {
+            command.arg("--allow-unrelated-histories");
+        }
+        let merged = command.arg(revision).status_within(timeouts::merge());
+        if matches!(&merged, Err(e) if e.kind() == io::ErrorKind::TimedOut) {
+            // The killed merge couldn't drop its lock on the index.
+            let _ = fs::remove_file(self.repository.path().join("index.lock"));
+        }
+        Ok(merged?.success())
+    }
+
+    fn merging(&self) -> bool {
+        self.repository.state() == RepositoryState::Merge
+    }
+
+    fn add_trailer(&self, trailer: &str) -> io::Result<bool> {
+        Ok(git_command(self.repository)
+            .arg("interpret-trailers")
+            .arg("--in-place")
+            .arg("--trailer")
+            .arg(trailer)
+            .arg(self.repository.path().join("MERGE_MSG"))
+            .status_timed()?
+            .success())
+    }
+
+    fn rerere_remaining(&self) -> io::Result<Vec<PathBuf>> {
+        let output = git_command(self.repository)
+            .arg("rerere")
+            .arg("remaining")
+            .stdout(Stdio::piped())
+            .output_timed()?;
+        Ok(output
+            .stdout
+            .split(|&byte| byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(path_from_git)
+            .collect())
+    }
+
+    fn conflicted_paths(&self) -> Vec<PathBuf> {
+        conflicts::conflicted_paths(self.repository)
+    }
+
+    fn has_conflict_markers(&self, path: &Path) -> bool {
+        conflicts::has_conflict_markers(self.repository, path)
+    }
+
+    fn has_conflicts(&self) -> bool {
+        conflicts::has_conflicts(self.repository)
+    }
+
+    fn add(&self, paths: &[String]) -> io::Result<bool> {
+        Ok(git_command(self.repository)
+            .arg("add")
+            .arg("--")
+            .args(paths)
+            .status_timed()?
+            .success())
+    }
+
+    fn resolve_binary(
+        &self,
+        policy: binary::Policy,
+        pr: &PullRequest,
+        merged: &[PullRequest],
+    ) -> Result<Vec<binary::Choice>, git2::Error> {
+        binary::resolve(self.repository, self.base, policy, pr, merged)
+    }
+
+    fn explain_conflicts(&self, pr: &PullRequest, merged: &[PullRequest], rerun: &str) {
+        conflicts::explain(self.repository, self.base, pr, merged, rerun)
+    }
+
+    fn commit(&self) -> io::Result<bool> {
+        Ok(git_command(self.repository)
+            .arg("commit")
+            .arg("--no-edit")
+            .status_timed()?
+            .success())
+    }
+
+    fn mark_section(&self, message: &str) -> io::Result<bool> {
+        Ok(git_command(self.repository)
+            .arg("commit")
+            .arg("--allow-empty")
+            .arg("--message")
+            .arg(message)
+            .status_timed()?
+            .success())
+    }
+
+    fn push(&self, branch: &str) -> io::Result<bool> {
+        Ok(git_command(self.repository)
+            .arg("push")
+            .arg("--force")
+            .arg(remote())
+            .arg(format!("HEAD:refs/heads/{}", branch))
+            .status_timed()?
+            .success())
+    }
+
+    fn publish(&self, branch: &str) -> io::Result<bool> {
+        Ok(git_command(self.repository)
+            .arg("push")
+            .arg(format!("--force-with-lease=refs/heads/{}", branch))
+            .arg(remote())
+            .arg(format!("HEAD:refs/heads/{}", branch))
+            .status_timed()?
+            .success())
+    }
+
+    fn push_to(&self, remote: &str, branch: &str) -> io::Result<bool> {
+        Ok(git_command(self.repository)
+            .arg("push")
+            .arg("--force")
+            .arg(remote)
+            .arg(format!("HEAD:refs/heads/{}", branch))
+            .status_timed()?
+            .success())
+    }
+
+    fn reset_hard(&self, commit: &str) -> io::Result<bool> {
+        Ok(git_command(self.repository)
+            .arg("reset")
+            .arg("--hard")
+            .arg(commit)
+            .status_timed()?
+            .success())
+    }
+
+    fn rebase_onto(&self, onto: &str, upstream: &str) -> io::Result<bool> {
+        let rebased = git_command(self.repository)
+            .arg("rebase")
+            .arg("--rebase-merges")
+            .arg("--onto")
+            .arg(onto)
+            .arg(upstream)
+            .status_timed()?
+            .success();
+        if !rebased && self.repository.state() != RepositoryState::Clean {
+            git_command(self.repository)
+                .arg("rebase")
+                .arg("--abort")
+                .status_timed()?;
+        }
+        Ok(rebased)
+    }
+
+    fn ls_remote(&self, refname: &str) -> io::Result<Option<String>> {
+        let output = git_command(self.repository)
+            .arg("ls-remote")
+            .arg(remote())
+            .arg(refname)
+            .stdout(Stdio::piped())
+            .output_timed()?;
+        // Otherwise not reaching origin would look like the ref not being there.
+        if !output.status.success() {
+            return Err(io::Error::other("git ls-remote failed"));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .find(|(_, name)| *name == refname)
+            .map(|(sha, _)| sha.to_string()))
+    }
+
+    fn remote_branches(&self) -> io::Result<HashMap<String, String>> {
+        let output = git_command(self.repository)
+            .arg("ls-remote")
+            .arg("--heads")
+            .arg(remote())
+            .stdout(Stdio::piped())
+            .output_timed()?;
+        if !output.status.success() {
+            return Err(io::Error::other("git ls-remote failed"));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .filter_map(|(sha, name)| {
+                let branch = name.strip_prefix("refs/heads/")?;
+                Some((branch.to_string(), sha.to_string()))
+            })
+            .collect())
+    }
+}
+
+/// A backend that plays back scripted merges and records every call, for
+/// testing the run without a repository.
+#[cfg(test)]
+pub mod fake {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    use super::super::binary;
+    use super::super::github::PullRequest;
+    use super::GitBackend;
+
+    /// What merging a revision does.
+    #[derive(Debug, Clone, Default)]
+    pub struct Merge {
+        /// Paths left conflicted.
+        pub conflicts: Vec<PathBuf>,
+        /// Conflicted paths rerere has a resolution for.
+        pub rerere: Vec<PathBuf>,
+        /// Conflicted paths that are binary files.
+        pub binary: Vec<PathBuf>,
+        /// Nothing to merge, so no merge is left in progress.
+        pub up_to_date: bool,
+    }
+
+    #[derive(Default)]
+    struct State {
+        calls: Vec<String>,
+        merges: HashMap<String, Merge>,
+        failing: Vec<&'static str>,
+        remote_refs: HashMap<String, String>,
+        current: Merge,
+        merging: bool,
+        conflicted: Vec<PathBuf>,
+    }
+
+    #[derive(Default)]
+    pub struct Fake {
+        state: RefCell<State>,
+    }
+
+    impl Fake {
+        pub fn new() -> Fake {
+            Fake::default()
+        }
+
+        /// Scripts what merging `revision` does; unscripted revisions merge
+        /// cleanly.
+        pub fn merge_of(self, revision: &str, merge: Merge) -> Fake {
+            self.state
+                .borrow_mut()
+                .merges
+                .insert(revision.to_string(), merge);
+            self
+        }
+
+        /// Scripts where `refname` points on origin.
+        pub fn remote_ref(self, refname: &str, sha: &str) -> Fake {
+            self.state
+                .borrow_mut()
+                .remote_refs
+                .insert(refname.to_string(), sha.to_string());
+            self
+        }
+
+        /// Makes every call to `operation` (`fetch`, `commit`, ...) fail.
+        pub fn failing(self, operation: &'static str) -> Fake {
+            self.state.borrow_mut().failing.push(operation);
+            self
+        }
+
+        /// Every call so far, as `operation argument...`.
+        pub fn calls(&self) -> Vec<String> {
+            self.state.borrow().calls.clone()
+        }
+
+        fn call(&self, operation: &'static str, args: &[&str]) -> bool {
+            let mut state = self.state.borrow_mut();
+            let mut call = operation.to_string();
+            for arg in args {
+                call.push(' ');
+                call.push_str(arg);
+            }
+            state.calls.push(call);
+            !state.failing.contains(&operation)
+        }
+    }
+
+    impl GitBackend for Fake {
+        fn fetch(&self) -> io::Result<bool> {
+            Ok(self.call("fetch", &[]))
+        }
+
+        fn fetch_branches(&self, branches: &[String], _jobs: usize) -> io::Result<bool> {
+            let args: Vec<&str> = branches.iter().map(String::as_str).collect();
+            Ok(self.call("fetch_branches", &args))
+        }
+
+        fn fetch_refs(&self, refspecs: &[String]) -> io::Result<bool> {
+            let args: Vec<&str> = refspecs.iter().map(String::as_str).collect();
+            Ok(self.call("fetch_refs", &args))
+        }
+
+        fn deepen(&self, depth: Option<usize>) -> io::Result<bool> {
+            let depth = depth.map(|depth| depth.to_string());
+            Ok(self.call("deepen", &[depth.as_deref().unwrap_or("all")]))
+        }
+
+        fn checkout(&self, branch: &str) -> io::Result<bool> {
+            Ok(self.call("checkout", &[branch]))
+        }
+
+        fn merge(&self, revision: &str, _allow_unrelated: bool) -> io::Result<bool> {
+            if !self.call("merge", &[revision]) {
+                return Ok(false);
+            }
+            let mut state = self.state.borrow_mut();
+            let merge = state.merges.get(revision).cloned().unwrap_or_default();
+            state.merging = !merge.up_to_date;
+            state.conflicted = merge.conflicts.clone();
+            state.current = merge;
+            Ok(state.conflicted.is_empty())
+        }
+
+        fn merging(&self) -> bool {
+            self.state.borrow().merging
+        }
+
+        fn add_trailer(&self, trailer: &str) -> io::Result<bool> {
+            Ok(self.call("add_trailer", &[trailer]))
+        }
+
+        fn rerere_remaining(&self) -> io::Result<Vec<PathBuf>> {
+            let state = self.state.borrow();
+            Ok(state
+                .conflicted
+                .iter()
+                .filter(|path| !state.current.rerere.contains(path))
+                .cloned()
+                .collect())
+        }
+
+        fn conflicted_paths(&self) -> Vec<PathBuf> {
+            self.state.borrow().conflicted.clone()
+        }
+
+        fn has_conflict_markers(&self, path: &Path) -> bool {
+            let state = self.state.borrow();
+            let path = path.to_path_buf();
+            !state.current.rerere.contains(&path) && !state.current.binary.contains(&path)
+        }
+
+        fn has_conflicts(&self) -> bool {
+            !self.state.borrow().conflicted.is_empty()
+        }
+
+        fn add(&self, paths: &[String]) -> io::Result<bool> {
+            let args: Vec<&str> = paths.iter().map(String::as_str).collect();
+            if !self.call("add", &args) {
+                return Ok(false);
+            }
+            self.state
+                .borrow_mut()
+                .conflicted
+                .retain(|path| !paths.iter().any(|p| Path::new(p) == path));
+            Ok(true)
+        }
+
+        fn resolve_binary(
+            &self,
+            policy: binary::Policy,
+            pr: &PullRequest,
+            _merged: &[PullRequest],
+        ) -> Result<Vec<binary::Choice>, git2::Error> {
+            if policy == binary::Policy::Fail {
+                return Ok(vec![]);
+            }
+            let side = match policy {
+                binary::Policy::Ours => binary::Side::Ours,
+                _ => binary::Side::Theirs,
+            };
+
+            let mut state = self.state.borrow_mut();
+            let binary = state.current.binary.clone();
+            state.conflicted.retain(|path| !binary.contains(path));
+            Ok(binary
+                .into_iter()
+                .map(|path| binary::Choice {
+                    path,
+                    side,
+                    supplied_by: format!("#{}", pr.number),
+                })
+                .collect())
+        }
+
+        fn explain_conflicts(&self, pr: &PullRequest, _merged: &[PullRequest], _rerun: &str) {
+            self.call("explain_conflicts", &[&pr.branch]);
+        }
+
+        fn commit(&self) -> io::Result<bool> {
+            if !self.call("commit", &[]) || self.has_conflicts() {
+                return Ok(false);
+            }
+            self.state.borrow_mut().merging = false;
+            Ok(true)
+        }
+
+        fn mark_section(&self, message: &str) -> io::Result<bool> {
+            Ok(self.call("mark_section", &[message]))
+        }
+
+        fn push(&self, branch: &str) -> io::Result<bool> {
+            Ok(self.call("push", &[branch]))
+        }
+
+        fn publish(&self, branch: &str) -> io::Result<bool> {
+            Ok(self.call("publish", &[branch]))
+        }
+
+        fn push_to(&self, remote: &str, branch: &str) -> io::Result<bool> {
+            Ok(self.call("push_to", &[remote, branch]))
+        }
+
+        fn reset_hard(&self, commit: &str) -> io::Result<bool> {
+            Ok(self.call("reset_hard", &[commit]))
+        }
+
+        fn rebase_onto(&self, onto: &str, upstream: &str) -> io::Result<bool> {
+            Ok(self.call("rebase_onto", &[onto, upstream]))
+        }
+
+        fn ls_remote(&self, refname: &str) -> io::Result<Option<String>> {
+            self.call("ls_remote", &[refname]);
+            Ok(self.state.borrow().remote_refs.get(refname).cloned())
+        }
+
+        fn remote_branches(&self) -> io::Result<HashMap<String, String>> {
+            self.call("remote_branches", &[]);
+            Ok(HashMap::new())
+        }
+    }
+}
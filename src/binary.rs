@@ -0,0 +1,147 @@
+use git2::Repository;
+use std::path::PathBuf;
+
+use super::conflicts;
+use super::git_extras::{git_command, path_from_bytes};
+use super::github::PullRequest;
+use super::timeouts::Timed;
+
+/// What to do with a binary file both sides changed, since there is nothing
+/// to resolve line by line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /// Keep the version already on the integration branch.
+    Ours,
+    /// Take the version from the pull request being merged.
+    Theirs,
+    /// Leave the conflict for a person to resolve.
+    Fail,
+    /// Keep whichever version is bigger.
+    Largest,
+}
+
+impl Policy {
+    pub fn parse(value: &str) -> Option<Policy> {
+        match value {
+            "ours" => Some(Policy::Ours),
+            "theirs" => Some(Policy::Theirs),
+            "fail" => Some(Policy::Fail),
+            "largest" => Some(Policy::Largest),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Ours,
+    Theirs,
+}
+
+/// A binary conflict resolved by the policy.
+#[derive(Debug, Clone)]
+pub struct Choice {
+    pub path: PathBuf,
+    pub side: Side,
+    /// The pull request (or base) the kept version came from.
+    pub supplied_by: String,
+}
+
+impl Choice {
+    pub fn describe(&self) -> String {
+        let side = match self.side {
+            Side::Ours => "kept ours",
+            Side::Theirs => "took theirs",
+        };
+        format!(
+            "{}: {}, from {}",
+            self.path.display(),
+            side,
+            self.supplied_by
+        )
+    }
+}
+
+/// Resolves the conflicted binary files of the merge in progress according to
+/// `policy` and stages them. Text conflicts and binary files deleted on one
+/// side are left alone.
+pub fn resolve(
+    repository: &Repository,
+    base: &str,
+    policy: Policy,
+    pr: &PullRequest,
+    merged: &[PullRequest],
+) -> Result<Vec<Choice>, git2::Error> {
+    if policy == Policy::Fail {
+        return Ok(vec![]);
+    }
+
+    let mut choices = vec![];
+    for conflict in repository.index()?.conflicts()? {
+        let conflict = conflict?;
+        let (ours, theirs) = match (conflict.our, conflict.their) {
+            (Some(ours), Some(theirs)) => (ours, theirs),
+            _ => continue,
+        };
+        let our_blob = repository.find_blob(ours.id)?;
+        let their_blob = repository.find_blob(theirs.id)?;
+        if !our_blob.is_binary() && !their_blob.is_binary() {
+            continue;
+        }
+
+        let side = match policy {
+            Policy::Ours => Side::Ours,
+            Policy::Theirs => Side::Theirs,
+            Policy::Largest if our_blob.content().len() > their_blob.content().len() => Side::Ours,
+            _ => Side::Theirs,
+        };
+
+        let path = path_from_bytes(&ours.path);
+        let supplied_by = match side {
+            Side::Theirs => format!("#{} {}", pr.number, pr.title),
+            Side::Ours => match conflicts::last_changed_by(repository, base, merged, &path) {
+                Some(m) => format!("#{} {}", m.number, m.title),
+                None => base.to_string(),
+            },
+        };
+        choices.push(Choice {
+            path,
+            side,
+            supplied_by,
+        });
+    }
+
+    for choice in &choices {
+        let side = match choice.side {
+            Side::Ours => "--ours",
+            Side::Theirs => "--theirs",
+        };
+        let checked_out = git_command(repository)
+            .arg("checkout")
+            .arg(side)
+            .arg("--")
+            .arg(&choice.path)
+            .status_timed()
+            .and_then(|status| {
+                if !status.success() {
+                    return Ok(status);
+                }
+                git_command(repository)
+                    .arg("add")
+                    .arg("--")
+                    .arg(&choice.path)
+                    .status_timed()
+            })
+            .map(|status| status.success())
+            .unwrap_or(false);
+
+        if !checked_out {
+            return Err(git2::Error::from_str(&format!(
+                "could not resolve {}",
+                choice.path.display()
+            )));
+        }
+    }
+
+    Ok(choices)
+}
@@ -0,0 +1,149 @@
+use git2::Repository;
+use std::fmt::Write as _;
+use std::panic::{self, PanicHookInfo};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::{env, fs, process};
+
+use super::git_extras::common_dir;
+use super::settings::Settings;
+use super::version;
+
+/// The git commands of the run, as traced by git itself.
+const TRACE_FILE: &str = "trace.log";
+
+/// How much of the git trace goes into a report.
+const TRACE_LINES: usize = 200;
+
+static CONFIG: OnceLock<Vec<(String, String)>> = OnceLock::new();
+static LAST_RESPONSE: Mutex<Option<String>> = Mutex::new(None);
+static TRACE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Writes a bug report when the run panics, after the usual message.
+pub fn install() {
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default(info);
+        match write(info) {
+            Ok(path) => eprintln!(
+                "\nDetails of the run were written to {}. If this looks like a bug in \
+                 \ngit-integrate, please attach the file to an issue at {}",
+                path.display(),
+                env!("CARGO_PKG_REPOSITORY")
+            ),
+            Err(e) => eprintln!("\nCould not write the bug report: {}", e),
+        }
+    }));
+}
+
+/// Keeps the `integrate.*` settings for a report, hiding credentials and
+/// anything from the encrypted config.
+pub fn set_config(settings: &Settings) {
+    let config = settings
+        .integrate_entries()
+        .into_iter()
+        .map(|(key, value, encrypted)| {
+            let value = if encrypted || is_secret(&key) {
+                "<redacted>".to_string()
+            } else {
+                value
+            };
+            (key, value)
+        })
+        .collect();
+    let _ = CONFIG.set(config);
+}
+
+fn is_secret(key: &str) -> bool {
+    ["token", "secret", "password", "header", "key", "webhook"]
+        .iter()
+        .any(|word| key.contains(word))
+}
+
+/// Remembers where the last API response came from and how it went.
+pub fn record_response(response: &reqwest::Response) {
+    let headers = response.headers();
+    let mut metadata = format!("{} {}", response.status(), redact_url(response.url()));
+    for name in [
+        "x-github-request-id",
+        "x-ratelimit-remaining",
+        "x-ratelimit-reset",
+        "x-request-id",
+        "date",
+    ] {
+        if let Some(value) = headers.get(name).and_then(|value| value.to_str().ok()) {
+            let _ = write!(metadata, "\n{}: {}", name, value);
+        }
+    }
+    *LAST_RESPONSE.lock().unwrap_or_else(|e| e.into_inner()) = Some(metadata);
+}
+
+/// The URL without its query, which may carry tokens.
+fn redact_url(url: &reqwest::Url) -> String {
+    let mut url = url.clone();
+    url.set_query(None);
+    let _ = url.set_password(None);
+    url.to_string()
+}
+
+/// Has git trace the commands it runs to a file of `repository`, emptied for
+/// every run, unless the user traces them elsewhere.
+pub fn trace_git(repository: &Repository) {
+    let path = common_dir(repository).join("integrate").join(TRACE_FILE);
+    let traced_elsewhere = env::var_os("GIT_TRACE")
+        .is_some_and(|trace| !Path::new(&trace).ends_with(Path::new("integrate").join(TRACE_FILE)));
+    if traced_elsewhere {
+        return;
+    }
+    let created = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, ""));
+    if created.is_ok() {
+        env::set_var("GIT_TRACE", &path);
+        let _ = TRACE.set(path);
+    }
+}
+
+fn write(info: &PanicHookInfo) -> std::io::Result<PathBuf> {
+    let mut report = String::new();
+    let _ = writeln!(report, "git-integrate {}", version::CURRENT);
+    let _ = writeln!(report, "{} {}", env::consts::OS, env::consts::ARCH);
+    let _ = writeln!(
+        report,
+        "Arguments: {}",
+        env::args().skip(1).collect::<Vec<_>>().join(" ")
+    );
+    let _ = writeln!(report, "\nPanic: {}", info);
+
+    let _ = writeln!(report, "\nConfiguration:");
+    for (key, value) in CONFIG.get().map(Vec::as_slice).unwrap_or_default() {
+        let _ = writeln!(report, "  {} = {}", key, value);
+    }
+
+    let _ = writeln!(report, "\nLast API response:");
+    match &*LAST_RESPONSE.lock().unwrap_or_else(|e| e.into_inner()) {
+        Some(metadata) => {
+            for line in metadata.lines() {
+                let _ = writeln!(report, "  {}", line);
+            }
+        }
+        None => {
+            let _ = writeln!(report, "  none");
+        }
+    }
+
+    let _ = writeln!(report, "\nGit commands:");
+    let trace = TRACE
+        .get()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .unwrap_or_default();
+    let lines: Vec<&str> = trace.lines().collect();
+    for line in &lines[lines.len().saturating_sub(TRACE_LINES)..] {
+        let _ = writeln!(report, "  {}", line);
+    }
+
+    let path = env::temp_dir().join(format!("git-integrate-bug-{}.txt", process::id()));
+    fs::write(&path, report)?;
+    Ok(path)
+}
@@ -0,0 +1,118 @@
+use std::process;
+
+use super::git_extras::Repo;
+use super::github::rest::{self, Issue};
+use super::settings::Settings;
+
+/// A repository that has this one as a submodule and should follow an
+/// integration branch.
+#[derive(Debug)]
+pub struct Parent {
+    pub repo: Repo,
+    /// Where the submodule is checked out in the parent.
+    pub path: String,
+    /// The parent's branch bump pull requests are opened against, its
+    /// default branch unless configured.
+    pub base: Option<String>,
+}
+
+impl Parent {
+    /// Parses `OWNER/REPO PATH [BASE]`.
+    pub fn parse(value: &str) -> Option<Parent> {
+        let mut parts = value.split_whitespace();
+        let (owner, name) = parts.next()?.split_once('/')?;
+        let path = parts.next()?.trim_matches('/').to_string();
+        let base = parts.next().map(String::from);
+        if parts.next().is_some() || owner.is_empty() || name.is_empty() {
+            return None;
+        }
+        Some(Parent {
+            repo: Repo {
+                owner: owner.to_string(),
+                name: name.to_string(),
+            },
+            path,
+            base,
+        })
+    }
+}
+
+/// The `integrate.<branch>.bump-parent` repositories of `branch`.
+pub fn parents(settings: &Settings, branch: &str) -> Vec<Parent> {
+    settings
+        .get_all(&format!("integrate.{}.bump-parent", branch))
+        .iter()
+        .map(|value| {
+            Parent::parse(value).unwrap_or_else(|| {
+                error!("Invalid integrate.{}.bump-parent {}", branch, value);
+                process::exit(1)
+            })
+        })
+        .collect()
+}
+
+/// Opens a pull request in the parent bumping its submodule to `sha`, the
+/// tip of `branch` in `repo`, or updates the one opened by an earlier bump.
+pub fn bump(
+    token: &str,
+    repo: &Repo,
+    branch: &str,
+    sha: &str,
+    parent: &Parent,
+) -> Result<Issue, reqwest::Error> {
+    let bump_branch = format!("integrate-bump/{}-{}", repo.name, branch.replace('/', "-"));
+    let title = format!("Bump {} to the latest {} build", parent.path, branch);
+    let message = format!(
+        "{}\n\nPoints {} at {}/{}@{}, the latest build of {}.",
+        title, parent.path, repo.owner, repo.name, sha, branch
+    );
+
+    let base_branch = match &parent.base {
+        Some(base) => base.clone(),
+        None => rest::repository(token, &parent.repo)?.default_branch,
+    };
+    let base = rest::branch_sha(token, &parent.repo, &base_branch)?;
+    let commit = rest::commit_submodule(token, &parent.repo, &base, &parent.path, sha, &message)?;
+    rest::force_branch(token, &parent.repo, &bump_branch, &commit)?;
+
+    match rest::open_pull_from(token, &parent.repo, &bump_branch)? {
+        Some(pull) => Ok(pull),
+        None => rest::create_pull(
+            token,
+            &parent.repo,
+            &title,
+            &format!(
+                "Opened by git-integrate after building `{}` in {}/{}.\n\n\
+                 Later builds update this pull request.",
+                branch, repo.owner, repo.name
+            ),
+            &bump_branch,
+            &base_branch,
+        ),
+    }
+}
+
+/// Bumps every one of `parents`, telling how each went. Returns whether they
+/// all were.
+pub fn bump_all(token: &str, repo: &Repo, branch: &str, sha: &str, parents: &[Parent]) -> bool {
+    let mut bumped = true;
+    for parent in parents {
+        match bump(token, repo, branch, sha, parent) {
+            Ok(pull) => summary!(
+                "Bumped {} in {}/{}: {}",
+                parent.path,
+                parent.repo.owner,
+                parent.repo.name,
+                pull.html_url
+            ),
+            Err(e) => {
+                error!(
+                    "Could not bump {} in {}/{}: {}",
+                    parent.path, parent.repo.owner, parent.repo.name, e
+                );
+                bumped = false;
+            }
+        }
+    }
+    bumped
+}
@@ -0,0 +1,212 @@
+use super::github::PullRequest;
+
+/// Categories merged in this order unless `integrate.categories` says
+/// otherwise.
+pub const DEFAULT: &[&str] = &["feat", "fix", "chore"];
+
+/// Orders the merges of a run by category, so the first-parent log of the
+/// integration branch reads like a categorized changelog.
+pub struct Categories {
+    names: Vec<String>,
+}
+
+impl Categories {
+    /// Comma or space separated category names, in merge order.
+    pub fn parse(value: &str) -> Categories {
+        Categories {
+            names: value
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|name| !name.is_empty())
+                .map(str::to_lowercase)
+                .collect(),
+        }
+    }
+
+    /// The category of `pr`: a label named after it, or failing that a
+    /// conventional commit title such as `fix(parser): ...`. Pull requests in
+    /// none of the categories go last.
+    pub fn of(&self, pr: &PullRequest) -> Option<&str> {
+        let labelled = self
+            .names
+            .iter()
+            .find(|name| pr.labels.iter().any(|label| label.to_lowercase() == **name));
+        let titled = || {
+            let prefix = pr.title.split(':').next()?;
+            let kind = prefix.split('(').next()?.trim_end_matches('!').trim();
+            self.names.iter().find(|name| **name == kind.to_lowercase())
+        };
+        labelled.or_else(titled).map(String::as_str)
+    }
+
+    fn rank(&self, pr: &PullRequest) -> usize {
+        self.of(pr)
+            .and_then(|name| self.names.iter().position(|n| n == name))
+            .unwrap_or(self.names.len())
+    }
+
+    /// The rank of each pull request, where a stacked pull request joins its
+    /// parent's category if that one comes later, so it never goes first.
+    fn ranks(&self, pull_requests: &[PullRequest]) -> Vec<usize> {
+        let mut ranks: Vec<usize> = vec![];
+        for (idx, pr) in pull_requests.iter().enumerate() {
+            let parent = pull_requests[..idx]
+                .iter()
+                .position(|parent| parent.branch == pr.base)
+                .map(|parent| ranks[parent])
+                .unwrap_or(0);
+            ranks.push(self.rank(pr).max(parent));
+        }
+        ranks
+    }
+
+    /// Sorts `pull_requests` by category, keeping the order within each one.
+    pub fn sort(&self, pull_requests: Vec<PullRequest>) -> Vec<PullRequest> {
+        let ranks = self.ranks(&pull_requests);
+        let mut ranked: Vec<(usize, PullRequest)> = ranks.into_iter().zip(pull_requests).collect();
+        ranked.sort_by_key(|(rank, _)| *rank);
+        ranked.into_iter().map(|(_, pr)| pr).collect()
+    }
+
+    /// For each of the sorted `pull_requests`, the name of the section it
+    /// starts if it is the first of its category.
+    pub fn sections(&self, pull_requests: &[PullRequest]) -> Vec<Option<String>> {
+        let ranks = self.ranks(pull_requests);
+        ranks
+            .iter()
+            .enumerate()
+            .map(|(idx, rank)| {
+                if idx > 0 && ranks[idx - 1] == *rank {
+                    return None;
+                }
+                Some(
+                    self.names
+                        .get(*rank)
+                        .map(String::as_str)
+                        .unwrap_or("other")
+                        .to_string(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Labels marking a pull request as a breaking change.
+const BREAKING_LABELS: &[&str] = &["breaking", "breaking-change", "breaking change"];
+
+/// Whether `pr` is a breaking change: labelled so, titled like `feat!: ...`
+/// or `fix(api)!: ...`, or with a `BREAKING CHANGE:` footer as conventional
+/// commits have it.
+pub fn is_breaking(pr: &PullRequest) -> bool {
+    let labelled = pr
+        .labels
+        .iter()
+        .any(|label| BREAKING_LABELS.contains(&label.to_lowercase().as_str()));
+    let titled = pr
+        .title
+        .split_once(':')
+        .is_some_and(|(prefix, _)| prefix.ends_with('!') && !prefix.contains(' '));
+    let footer = pr
+        .body
+        .lines()
+        .any(|line| line.starts_with("BREAKING CHANGE:") || line.starts_with("BREAKING-CHANGE:"));
+    labelled || titled || footer
+}
+
+/// What a branch does with breaking changes, set per branch with
+/// `integrate.<branch>.breaking`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakingPolicy {
+    Allow,
+    Warn,
+    /// Leaves them out like any other excluded pull request.
+    Exclude,
+    /// Stops the run before anything is merged.
+    Fail,
+}
+
+impl BreakingPolicy {
+    pub fn parse(value: &str) -> Option<BreakingPolicy> {
+        match value {
+            "allow" => Some(BreakingPolicy::Allow),
+            "warn" => Some(BreakingPolicy::Warn),
+            "exclude" => Some(BreakingPolicy::Exclude),
+            "fail" => Some(BreakingPolicy::Fail),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Categories {
+    fn default() -> Categories {
+        Categories::parse(&DEFAULT.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::pull_request;
+
+    fn pr(title: &str, body: &str, labels: &[&str]) -> PullRequest {
+        PullRequest {
+            title: title.to_string(),
+            body: body.to_string(),
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+            ..pull_request(1, "topic")
+        }
+    }
+
+    #[test]
+    fn breaking_changes_are_labelled_titled_or_noted_in_a_footer() {
+        assert!(is_breaking(&pr("feat!: drop v1", "", &[])));
+        assert!(is_breaking(&pr("fix(api)!: rename field", "", &[])));
+        assert!(is_breaking(&pr("Rename field", "", &["Breaking Change"])));
+        assert!(is_breaking(&pr(
+            "feat: new auth",
+            "Details\n\nBREAKING CHANGE: tokens expire",
+            &[]
+        )));
+        assert!(!is_breaking(&pr("feat: new auth", "", &["feat"])));
+        assert!(!is_breaking(&pr("Wow! Faster: 2x", "", &[])));
+    }
+
+    #[test]
+    fn stacks_follow_their_parent_and_each_category_starts_a_section() {
+        let categories = Categories::default();
+        let pull_requests = vec![
+            PullRequest {
+                title: "chore: bump deps".to_string(),
+                ..pull_request(1, "deps")
+            },
+            PullRequest {
+                title: "fix: crash on start".to_string(),
+                ..pull_request(2, "crash")
+            },
+            PullRequest {
+                title: "feat: retry on top of the fix".to_string(),
+                base: "crash".to_string(),
+                ..pull_request(3, "retry")
+            },
+            PullRequest {
+                labels: vec!["Feat".to_string()],
+                ..pull_request(4, "login")
+            },
+            pull_request(5, "readme"),
+        ];
+        assert_eq!(categories.ranks(&pull_requests), [2, 1, 1, 0, 3]);
+
+        let sorted = categories.sort(pull_requests);
+        let numbers: Vec<i64> = sorted.iter().map(|pr| pr.number).collect();
+        assert_eq!(numbers, [4, 2, 3, 1, 5]);
+        assert_eq!(
+            categories.sections(&sorted),
+            [
+                Some("feat".to_string()),
+                Some("fix".to_string()),
+                None,
+                Some("chore".to_string()),
+                Some("other".to_string()),
+            ]
+        );
+    }
+}
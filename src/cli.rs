@@ -0,0 +1,744 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use super::version;
+
+/// What a run goes by: the options given after `integrate`, `plan` or
+/// `graph`, or without any of them, and whether it only shows what it would
+/// merge as `--dry-run` or `--graph` do.
+pub struct Run<'m, 'a> {
+    pub opts: &'m ArgMatches<'a>,
+    pub dry_run: bool,
+    pub graph: bool,
+}
+
+pub fn run<'m, 'a>(matches: &'m ArgMatches<'a>) -> Run<'m, 'a> {
+    let (opts, command) = match matches.subcommand() {
+        (command @ ("integrate" | "plan" | "graph"), Some(opts)) => (opts, command),
+        _ => (matches, ""),
+    };
+    Run {
+        opts,
+        dry_run: command == "plan" || opts.is_present("dry-run"),
+        graph: command == "graph" || opts.is_present("graph"),
+    }
+}
+
+/// The command line of git-integrate.
+pub fn app<'a, 'b>() -> App<'a, 'b> {
+    let app = run_args(
+        App::new("git-integrate")
+            .version(version::CURRENT)
+            .after_help(
+                "A run can also start with `integrate`, which changes nothing, `plan`, \
+         which is the same as --dry-run, or `graph`, the same as --graph. To \
+         select a label named like a subcommand, start with `integrate`.",
+            ),
+    )
+    .subcommand(run_command(
+        "integrate",
+        "Build BRANCH from the selected pull requests, as without a subcommand",
+    ))
+    .subcommand(run_command(
+        "plan",
+        "Show what a run would merge and where it conflicts, as --dry-run does",
+    ))
+    .subcommand(run_command(
+        "graph",
+        "Draw the order and conflicts of a run, as --graph does",
+    ));
+    let app = app
+        .subcommand(
+            SubCommand::with_name("compare")
+                .about("List integrated pull requests that have not reached production yet")
+                .arg(
+                    Arg::with_name("integration")
+                        .value_name("INTEGRATION_BRANCH")
+                        .help("Integration branch built by git-integrate")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("production")
+                        .value_name("PROD_REF")
+                        .help("Ref that is deployed to production")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("audit")
+                .about("Check that a pushed integration branch holds only what git-integrate merged")
+                .arg(
+                    Arg::with_name("branch")
+                        .value_name("BRANCH")
+                        .help("Integration branch to check, such as origin/staging")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("base")
+                        .long("base")
+                        .value_name("REF")
+                        .help("What the branch was built on, the default branch of origin by default")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("manifest")
+                        .long("manifest")
+                        .value_name("PATH")
+                        .help("Check the merged heads against the manifest of the build instead of the history")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("allow-resolved")
+                        .long("allow-resolved")
+                        .help("Pass merges whose conflicts were resolved, which can't be reproduced"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("continue")
+                .about("Carry on with a run stopped by a conflict once it is resolved"),
+        )
+        .subcommand(
+            SubCommand::with_name("abort")
+                .about("Give up on a run stopped by a conflict and put the branch back as it was"),
+        )
+        .subcommand(
+            SubCommand::with_name("cleanup")
+                .about("Delete the refs runs fetched pull requests from forks to"),
+        )
+        .subcommand(
+            SubCommand::with_name("all")
+                .about("Build every branch configured with integrate.<branch>.selection")
+                .arg(
+                    Arg::with_name("args")
+                        .value_name("ARGS")
+                        .help("Extra options passed to each run, after --")
+                        .multiple(true)
+                        .last(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("bump-parents")
+                .about("Open pull requests bumping this repository in the repositories using it as a submodule")
+                .arg(
+                    Arg::with_name("branch")
+                        .value_name("BRANCH")
+                        .help("Pushed integration branch the submodules should point at")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("selftest")
+                .about("Build and abort integration branches in a scratch repository to check this install works"),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Show which files and authors most often break integration runs")
+                .arg(
+                    Arg::with_name("limit")
+                        .long("limit")
+                        .value_name("N")
+                        .help("How many files and authors to list")
+                        .default_value("10")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("org")
+                .about("Build the integration branch in every repository of an organization with a topic")
+                .arg(
+                    Arg::with_name("org")
+                        .value_name("ORG")
+                        .help("GitHub organization")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("topic")
+                        .long("topic")
+                        .value_name("TOPIC")
+                        .help("Only repositories tagged with this topic")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("label")
+                        .long("label")
+                        .value_name("MILESTONE|LABEL")
+                        .help("GitHub milestone number or pull request label")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("branch")
+                        .long("branch")
+                        .value_name("BRANCH")
+                        .help("Branch to build in each repository")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("workspace")
+                        .long("workspace")
+                        .value_name("DIR")
+                        .help("Where repositories are cloned")
+                        .default_value(".")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("args")
+                        .value_name("ARGS")
+                        .help("Extra options passed to each run, after --")
+                        .multiple(true)
+                        .last(true),
+                ),
+        );
+    server_commands(app)
+}
+
+/// A subcommand taking every option of a run.
+fn run_command<'a, 'b>(name: &str, about: &'b str) -> App<'a, 'b> {
+    run_args(SubCommand::with_name(name).about(about))
+}
+
+/// The options of a run, with or without `integrate`, `plan` or `graph`.
+fn run_args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    let app = app
+        .arg(
+            Arg::with_name("milestone")
+                .value_name("MILESTONE|LABEL")
+                .help("GitHub milestone number or pull request label")
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("branch")
+                .value_name("BRANCH")
+                .help("Branch to build")
+                .index(2),
+        )
+        .arg(
+            Arg::with_name("label")
+                .long("label")
+                .value_name("LABEL")
+                .help("Merge pull requests with this label, repeat to add more; BRANCH is then the only argument")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .conflicts_with_all(&["label-pattern", "search"]),
+        )
+        .arg(
+            Arg::with_name("label-pattern")
+                .long("label-pattern")
+                .value_name("REGEX")
+                .help("Merge pull requests with any label matching this expression; BRANCH is then the only argument")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("search")
+                .long("search")
+                .value_name("QUERY")
+                .conflicts_with("label-pattern")
+                .help("Merge the open pull requests matching this GitHub search; BRANCH is then the only argument")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("stdin")
+                .long("stdin")
+                .conflicts_with_all(&["label", "label-pattern", "search"])
+                .help("Merge the pull requests whose numbers or branches are read from stdin, one per line; BRANCH is then the only argument"),
+        )
+        .arg(
+            Arg::with_name("train")
+                .long("train")
+                .value_name("VERSION")
+                .conflicts_with_all(&["label", "label-pattern", "search", "stdin"])
+                .help("Build the release train train/VERSION from the milestone, or else the label, named VERSION and tag it train/VERSION/rc.N")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("derive-branch")
+                .long("derive-branch")
+                .value_name("RULE")
+                .help("Build a branch for every label matching 'PATTERN => BRANCH', e.g. 'deploy:(?P<env>\\w+) => staging-${env}'")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("require-approval-from")
+                .long("require-approval-from")
+                .value_name("TEAM")
+                .help("Wait for a member of this GitHub team to approve the plan before merging")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("approval-timeout")
+                .long("approval-timeout")
+                .value_name("MINUTES")
+                .help("How long to wait for approval before giving up")
+                .default_value("60")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-rerere-autocommit")
+                .long("no-rerere-autocommit")
+                .help("Stop after rerere resolves a conflict so the resolution can be inspected"),
+        )
+        .arg(
+            Arg::with_name("binary-conflicts")
+                .long("binary-conflicts")
+                .value_name("POLICY")
+                .help("How to resolve conflicts in binary files [default: fail]")
+                .possible_values(&["ours", "theirs", "fail", "largest"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("checkpoint-every")
+                .long("checkpoint-every")
+                .value_name("N")
+                .help("Push the branch after every N successful merges")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("checkpoint-branch")
+                .long("checkpoint-branch")
+                .value_name("NAME")
+                .help("Remote branch checkpoints are pushed to [default: integrate-checkpoint/BRANCH]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-file-size")
+                .long("max-file-size")
+                .value_name("SIZE")
+                .help("Refuse pull requests adding files larger than this outside git-lfs, e.g. 10M")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("scan-secrets")
+                .long("scan-secrets")
+                .help("Fail before pushing if the merged changes appear to contain credentials"),
+        )
+        .arg(
+            Arg::with_name("override-freeze")
+                .long("override-freeze")
+                .help("Push even during a configured freeze window"),
+        )
+        .arg(
+            Arg::with_name("notify-conflicts")
+                .long("notify-conflicts")
+                .value_name("HOW")
+                .help("Tell authors when their branch conflicts")
+                .possible_values(&["comment", "request-changes"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("push")
+                .long("push")
+                .help("Push the branch, unless it was pushed to since the fetch, once everything is merged"),
+        )
+        .arg(
+            Arg::with_name("push-remote")
+                .long("push-remote")
+                .value_name("NAME")
+                .help("With --push, force push the branch to remote NAME as well, repeat to add more")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("write-back")
+                .long("write-back")
+                .value_name("HOW")
+                .help("Tell pull requests whether they made it into the pushed branch [default: integrate.write-back]")
+                .takes_value(true)
+                .possible_values(&["status", "comment"]),
+        )
+        .arg(
+            Arg::with_name("summary")
+                .long("summary")
+                .value_name("FILE")
+                .help("Append a Markdown report of the run [default: $GITHUB_STEP_SUMMARY]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ownership")
+                .long("ownership")
+                .value_name("FILE")
+                .help("Write which pull requests changed each file of the branch, and its CODEOWNERS [default: integrate.ownership]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("include-stack-parents")
+                .long("include-stack-parents")
+                .help("Also merge the open pull requests that selected stacked pull requests are based on"),
+        )
+        .arg(
+            Arg::with_name("stacks")
+                .long("stacks")
+                .value_name("MODE")
+                .help("How to merge stacked pull requests")
+                .possible_values(&["independent", "tops", "flatten"])
+                .default_value("independent")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("group-by-category")
+                .long("group-by-category")
+                .help("Merge pull requests grouped by category (feat, fix, chore)"),
+        )
+        .arg(
+            Arg::with_name("section-markers")
+                .long("section-markers")
+                .help("Group by category and start each one with an empty commit naming it"),
+        )
+        .arg(
+            Arg::with_name("yes")
+                .long("yes")
+                .short("y")
+                .help("Carry on without asking when conflicts are forecast"),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .conflicts_with("resume")
+                .help("Only report which pull requests conflict with the base and each other"),
+        )
+        .arg(
+            Arg::with_name("explain")
+                .long("explain")
+                .conflicts_with_all(&["resume", "dry-run", "graph"])
+                .help("Only print the git commands the run would execute, to audit or reproduce it"),
+        )
+        .arg(
+            Arg::with_name("graph")
+                .long("graph")
+                .conflicts_with_all(&["resume", "dry-run"])
+                .help("Only print the merge order, stacked pull requests and conflicts as a graph"),
+        )
+        .arg(
+            Arg::with_name("graph-format")
+                .long("graph-format")
+                .value_name("FORMAT")
+                .possible_values(&["dot", "mermaid"])
+                .default_value("dot")
+                .help("Graphviz DOT or a Mermaid flowchart"),
+        )
+        .arg(
+            Arg::with_name("fetch-jobs")
+                .long("fetch-jobs")
+                .value_name("N")
+                .help("Fetch only the base and the merged branches, N fetches at a time, instead of every remote")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("base")
+                .long("base")
+                .value_name("REF")
+                .help("Build on REF instead of the default branch of origin, such as another integration branch")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("remote")
+                .long("remote")
+                .value_name("NAME")
+                .help("Fetch from and push to NAME instead of origin")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-fetch")
+                .long("no-fetch")
+                .help("Build from the remote-tracking branches as they are, without fetching"),
+        )
+        .arg(
+            Arg::with_name("deepen")
+                .long("deepen")
+                .value_name("N")
+                .help("In a shallow clone, fetch N more commits at a time until every pull request shares history with the base [default: 100]")
+                .takes_value(true)
+                .conflicts_with("unshallow"),
+        )
+        .arg(
+            Arg::with_name("unshallow")
+                .long("unshallow")
+                .help("In a shallow clone, fetch the whole history when a pull request shares none with the base"),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .long("exclude")
+                .value_name("NUMBER")
+                .help("Leave out this pull request, repeat to leave out more")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("allow-stale-base")
+                .long("allow-stale-base")
+                .help("Only warn when the base is behind the remote after fetching"),
+        )
+        .arg(
+            Arg::with_name("on-base-moved")
+                .long("on-base-moved")
+                .value_name("POLICY")
+                .help("What to do when the base moves on origin during the run [default: fail]")
+                .takes_value(true)
+                .possible_values(&["fail", "rebase"]),
+        )
+        .arg(
+            Arg::with_name("pull-requests-from")
+                .long("pull-requests-from")
+                .value_name("FILE")
+                .help("Pull requests already fetched by a parent process")
+                .takes_value(true)
+                .hidden(true),
+        )
+        .arg(
+            Arg::with_name("version-check")
+                .long("version-check")
+                .help("Check this binary against the minimum version the repository requires, then exit"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Only print errors and the final summary"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .value_name("FORMAT")
+                .takes_value(true)
+                .possible_values(&["text", "legacy"])
+                .help("Keep to the plain text lines scripts grep for with `legacy`"),
+        )
+        .arg(
+            Arg::with_name("require-resolved-threads")
+                .long("require-resolved-threads")
+                .help("Leave out pull requests with unresolved review threads"),
+        )
+        .arg(
+            Arg::with_name("git-backend")
+                .long("git-backend")
+                .takes_value(true)
+                .possible_values(&["subprocess", "in-process"])
+                .help("Run git, or fetch, check out and merge with libgit2 instead"),
+        )
+        .arg(
+            Arg::with_name("manifest")
+                .long("manifest")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Write what the run merged, with the commits and trees, as JSON to PATH"),
+        )
+        .arg(
+            Arg::with_name("verify")
+                .long("verify")
+                .takes_value(true)
+                .value_name("COMMAND")
+                .help("Run COMMAND after every merge, stopping at the pull request that breaks it"),
+        )
+        .arg(
+            Arg::with_name("verify-skip-failures")
+                .long("verify-skip-failures")
+                .help("Undo merges that fail --verify and carry on without them"),
+        )
+        .arg(
+            Arg::with_name("verify-retries")
+                .long("verify-retries")
+                .takes_value(true)
+                .value_name("N")
+                .help("Run a failing --verify up to N more times before blaming the merge"),
+        )
+        .arg(
+            Arg::with_name("min-age")
+                .long("min-age")
+                .takes_value(true)
+                .value_name("AGE")
+                .help("Leave out pull requests labelled or milestoned less than AGE ago, such as 2h"),
+        )
+        .arg(
+            Arg::with_name("breaking")
+                .long("breaking")
+                .value_name("POLICY")
+                .help("What to do with breaking changes: allow them, warn, exclude them or fail the run [default: integrate.<branch>.breaking, or allow]")
+                .takes_value(true)
+                .possible_values(&["allow", "warn", "exclude", "fail"]),
+        )
+        .arg(
+            Arg::with_name("skip-draft")
+                .long("skip-draft")
+                .help("Leave out draft pull requests"),
+        )
+        .arg(
+            Arg::with_name("require-approved")
+                .long("require-approved")
+                .help("Leave out pull requests that are not approved"),
+        )
+        .arg(
+            Arg::with_name("require-checks-passing")
+                .long("require-checks-passing")
+                .help("Leave out pull requests whose checks did not all pass"),
+        )
+        .arg(
+            Arg::with_name("skip-conflicting")
+                .long("skip-conflicting")
+                .help("Leave out pull requests GitHub reports as conflicting with their base"),
+        )
+        .arg(
+            Arg::with_name("order")
+                .long("order")
+                .takes_value(true)
+                .possible_values(&["oldest", "newest"])
+                .help("Merge the oldest or the newest pull requests first, oldest by default"),
+        )
+        .arg(
+            Arg::with_name("interactive")
+                .long("interactive")
+                .help("Let git prompt for credentials and passphrases, sign commits and use your own identity"),
+        )
+        .arg(
+            Arg::with_name("abort")
+                .long("abort")
+                .help("Give up on a run stopped by a conflict and put the branch back as it was"),
+        )
+        .arg(
+            Arg::with_name("gc")
+                .long("gc")
+                .help("Let git pack and prune the repository, if due, after a failed or aborted run"),
+        )
+        .arg(
+            Arg::with_name("no-api-writes")
+                .long("no-api-writes")
+                .help("Only log the comments, labels and issues the run would make on GitHub"),
+        )
+        .arg(
+            Arg::with_name("allowed-host")
+                .long("allowed-host")
+                .value_name("HOST")
+                .takes_value(true)
+                .help("Refuse API requests and redirects to any other host"),
+        )
+        .arg(
+            Arg::with_name("http-timeout")
+                .long("http-timeout")
+                .value_name("SECONDS")
+                .takes_value(true)
+                .help("Give up on API requests that get no answer in time, 30s by default"),
+        )
+        .arg(
+            Arg::with_name("git-timeout")
+                .long("git-timeout")
+                .value_name("SECONDS")
+                .takes_value(true)
+                .help("Stop git commands that run longer, such as a fetch that hangs"),
+        )
+        .arg(
+            Arg::with_name("merge-timeout")
+                .long("merge-timeout")
+                .value_name("SECONDS")
+                .takes_value(true)
+                .help("Leave out pull requests whose merge takes longer and carry on"),
+        )
+        .arg(
+            Arg::with_name("deadline")
+                .long("deadline")
+                .value_name("SECONDS")
+                .takes_value(true)
+                .help("Stop the run once it has taken that long, merged or not"),
+        )
+        .arg(Arg::with_name("resume").long("resume").hidden(true));
+    conflict_shell_args(app)
+}
+
+#[cfg(feature = "server")]
+fn server_commands<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.subcommand(
+        SubCommand::with_name("daemon")
+            .about("Build the configured branches on their integrate.<branch>.schedule")
+            .arg(
+                Arg::with_name("schedule")
+                    .long("schedule")
+                    .value_name("CRON")
+                    .help(
+                        "Schedule for the branches without one of their own, such as \"0 2 * * *\"",
+                    )
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("jitter")
+                    .long("jitter")
+                    .value_name("SECONDS")
+                    .help("Delay the builds of this repository by up to this long")
+                    .default_value("0")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("listen")
+                    .long("listen")
+                    .value_name("ADDRESS")
+                    .help("Answer /healthz and /readyz on this address, such as 0.0.0.0:8080")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("args")
+                    .value_name("ARGS")
+                    .help("Extra options passed to each run, after --")
+                    .multiple(true)
+                    .last(true),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("serve")
+            .about("Keep selected pull requests informed of their place in the next build")
+            .arg(
+                Arg::with_name("selection")
+                    .value_name("MILESTONE|LABEL")
+                    .help("GitHub milestone number or pull request label")
+                    .required(true)
+                    .index(1),
+            )
+            .arg(
+                Arg::with_name("branch")
+                    .value_name("BRANCH")
+                    .help("Branch the pull requests are integrated into")
+                    .required(true)
+                    .index(2),
+            )
+            .arg(
+                Arg::with_name("interval")
+                    .long("interval")
+                    .value_name("MINUTES")
+                    .help("How often to refresh the queue")
+                    .default_value("10")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("listen")
+                    .long("listen")
+                    .value_name("ADDRESS")
+                    .help("Answer /healthz and /readyz on this address, such as 0.0.0.0:8080")
+                    .takes_value(true),
+            ),
+    )
+}
+
+#[cfg(not(feature = "server"))]
+fn server_commands<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app
+}
+
+#[cfg(feature = "conflict-shell")]
+fn conflict_shell_args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app.arg(
+        Arg::with_name("mergetool")
+            .long("mergetool")
+            .help("Run git mergetool on conflicts and carry on once they are resolved"),
+    )
+}
+
+#[cfg(not(feature = "conflict-shell"))]
+fn conflict_shell_args<'a, 'b>(app: App<'a, 'b>) -> App<'a, 'b> {
+    app
+}
@@ -0,0 +1,224 @@
+use git2::{Delta, FileMode, Repository, TreeWalkMode, TreeWalkResult};
+use std::collections::HashMap;
+
+use super::conflicts::fork_point_tree;
+use super::github::PullRequest;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    File,
+    Symlink,
+    Submodule,
+}
+
+impl Kind {
+    fn from_mode(mode: FileMode) -> Option<Kind> {
+        match mode {
+            FileMode::Blob | FileMode::BlobExecutable => Some(Kind::File),
+            FileMode::Link => Some(Kind::Symlink),
+            FileMode::Commit => Some(Kind::Submodule),
+            _ => None,
+        }
+    }
+
+    fn from_raw(mode: i32) -> Option<Kind> {
+        match mode {
+            0o100644 | 0o100755 => Some(Kind::File),
+            0o120000 => Some(Kind::Symlink),
+            0o160000 => Some(Kind::Submodule),
+            _ => None,
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Kind::File => "a regular file",
+            Kind::Symlink => "a symlink",
+            Kind::Submodule => "a submodule",
+        }
+    }
+}
+
+struct Known {
+    path: String,
+    kind: Kind,
+    /// The pull request that introduced the path, `None` for the base.
+    owner: Option<i64>,
+}
+
+/// A path a pull request would add that can't coexist with what the base or
+/// an earlier pull request in the plan already has.
+pub struct Collision {
+    pub pr: PullRequest,
+    pub message: String,
+}
+
+/// Looks for paths that only differ by case (which corrupt the work tree on
+/// case-insensitive filesystems), paths that are a file in one place and a
+/// directory in another, and paths two pull requests turn into different
+/// kinds of entries (file, symlink, submodule).
+pub fn check(
+    repository: &Repository,
+    base: &str,
+    plan: &[PullRequest],
+) -> Result<Vec<Collision>, git2::Error> {
+    let base_commit = repository.revparse_single(base)?.peel_to_commit()?;
+    let mut known: HashMap<String, Known> = HashMap::new();
+
+    base_commit
+        .tree()?
+        .walk(TreeWalkMode::PreOrder, |root, entry| {
+            if let (Some(name), Some(kind)) = (entry.name(), Kind::from_raw(entry.filemode())) {
+                let path = format!("{}{}", root, name);
+                known.insert(
+                    path.to_lowercase(),
+                    Known {
+                        path,
+                        kind,
+                        owner: None,
+                    },
+                );
+            }
+            TreeWalkResult::Ok
+        })?;
+
+    let mut collisions = vec![];
+    for pr in plan {
+        let head = repository.revparse_single(&pr.revision)?.peel_to_commit()?;
+        let fork_point = fork_point_tree(repository, &base_commit, &head)?;
+        let diff = repository.diff_tree_to_tree(fork_point.as_ref(), Some(&head.tree()?), None)?;
+
+        let mut added = vec![];
+        for delta in diff.deltas() {
+            if delta.status() == Delta::Deleted || delta.status() == Delta::Renamed {
+                if let Some(old) = delta.old_file().path().and_then(|p| p.to_str()) {
+                    known.remove(&old.to_lowercase());
+                }
+            }
+            if delta.status() != Delta::Deleted {
+                let new = delta.new_file();
+                if let (Some(path), Some(kind)) = (
+                    new.path().and_then(|p| p.to_str()),
+                    Kind::from_mode(new.mode()),
+                ) {
+                    added.push((path.to_string(), kind));
+                }
+            }
+        }
+
+        for (path, kind) in added {
+            let lower = path.to_lowercase();
+            let mut conflict = |message: String| {
+                collisions.push(Collision {
+                    pr: pr.clone(),
+                    message,
+                })
+            };
+
+            if let Some(existing) = known.get(&lower) {
+                if existing.path != path {
+                    conflict(format!(
+                        "adds `{}`, which only differs in case from `{}` {}",
+                        path,
+                        existing.path,
+                        origin(existing.owner)
+                    ));
+                } else if existing.kind != kind && existing.owner.is_some_and(|n| n != pr.number) {
+                    conflict(format!(
+                        "makes `{}` {} but it is {} {}",
+                        path,
+                        kind.describe(),
+                        existing.kind.describe(),
+                        origin(existing.owner)
+                    ));
+                }
+            }
+
+            let mut parent = lower.as_str();
+            while let Some(idx) = parent.rfind('/') {
+                parent = &parent[..idx];
+                if let Some(existing) = known.get(parent) {
+                    conflict(format!(
+                        "adds `{}` but `{}` is {} {}",
+                        path,
+                        existing.path,
+                        existing.kind.describe(),
+                        origin(existing.owner)
+                    ));
+                    break;
+                }
+            }
+
+            let prefix = format!("{}/", lower);
+            if let Some(existing) = known
+                .values()
+                .find(|k| k.path.to_lowercase().starts_with(&prefix))
+            {
+                conflict(format!(
+                    "adds `{}` as {} but it is a directory containing `{}` {}",
+                    path,
+                    kind.describe(),
+                    existing.path,
+                    origin(existing.owner)
+                ));
+            }
+
+            known.insert(
+                lower,
+                Known {
+                    path,
+                    kind,
+                    owner: Some(pr.number),
+                },
+            );
+        }
+    }
+
+    Ok(collisions)
+}
+
+fn origin(owner: Option<i64>) -> String {
+    match owner {
+        Some(number) => format!("(from #{})", number),
+        None => "(in the base)".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::{Oid, Signature};
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::github::pull_request;
+
+    /// A root commit holding `files`, on `branch`.
+    fn root(repository: &Repository, branch: &str, files: &[(&str, &str)]) -> Oid {
+        let mut builder = repository.treebuilder(None).unwrap();
+        for (name, contents) in files {
+            let blob = repository.blob(contents.as_bytes()).unwrap();
+            builder.insert(name, blob, 0o100644).unwrap();
+        }
+        let tree = repository.find_tree(builder.write().unwrap()).unwrap();
+        let signature = Signature::now("Test", "test@localhost").unwrap();
+        let reference = format!("refs/heads/{}", branch);
+        repository
+            .commit(Some(&reference), &signature, &signature, branch, &tree, &[])
+            .unwrap()
+    }
+
+    #[test]
+    fn unrelated_history_counts_as_all_added() {
+        let path = env::temp_dir().join(format!("git-integrate-{}-collisions", process::id()));
+        let _ = fs::remove_dir_all(&path);
+        let repository = Repository::init(&path).unwrap();
+        root(&repository, "base", &[("README", "base\n")]);
+        root(&repository, "mirror", &[("readme", "mirror\n")]);
+
+        let mut pr = pull_request(1, "mirror");
+        pr.revision = "mirror".to_string();
+        let collisions = check(&repository, "base", &[pr]).unwrap();
+        assert_eq!(collisions.len(), 1);
+        assert!(collisions[0].message.contains("only differs in case"));
+    }
+}
@@ -0,0 +1,85 @@
+use git2::{Oid, Repository};
+
+use super::git_extras::PR_TRAILER;
+
+pub struct Pending {
+    pub number: i64,
+    pub summary: String,
+}
+
+/// Pull requests merged into `integration` whose branch has not reached
+/// `production` yet, oldest first.
+///
+/// Walks the first-parent history of the integration branch and looks at the
+/// merge commits carrying the `Integrated-PR` trailer; a pull request is
+/// pending when the branch tip it merged is not contained in production.
+pub fn pending(
+    repository: &Repository,
+    integration: &str,
+    production: &str,
+) -> Result<Vec<Pending>, git2::Error> {
+    let production = repository
+        .revparse_single(production)?
+        .peel_to_commit()?
+        .id();
+    let mut commit = repository.revparse_single(integration)?.peel_to_commit()?;
+    let mut pending = vec![];
+
+    loop {
+        if contains(repository, production, commit.id())? {
+            break;
+        }
+
+        if let (Some(number), Ok(merged)) = (pr_number(&commit), commit.parent_id(1)) {
+            if !contains(repository, production, merged)? {
+                pending.push(Pending {
+                    number,
+                    summary: commit.summary().unwrap_or_default().to_string(),
+                });
+            }
+        }
+
+        commit = match commit.parent(0) {
+            Ok(parent) => parent,
+            Err(_) => break,
+        };
+    }
+
+    pending.reverse();
+    Ok(pending)
+}
+
+fn contains(repository: &Repository, tip: Oid, commit: Oid) -> Result<bool, git2::Error> {
+    Ok(tip == commit || repository.graph_descendant_of(tip, commit)?)
+}
+
+pub fn pr_number(commit: &git2::Commit) -> Option<i64> {
+    let prefix = format!("{}: ", PR_TRAILER);
+    commit
+        .message()?
+        .lines()
+        .filter_map(|line| line.strip_prefix(&prefix))
+        .filter_map(|value| value.trim().trim_start_matches('#').parse().ok())
+        .next_back()
+}
+
+pub fn run(repository: &Repository, integration: &str, production: &str) {
+    let pending = match pending(repository, integration, production) {
+        Ok(pending) => pending,
+        Err(e) => panic!("{}", e),
+    };
+
+    if pending.is_empty() {
+        summary!("Everything in {} is in {}", integration, production);
+        return;
+    }
+
+    summary!(
+        "Pull requests in {} but not in {}:",
+        integration,
+        production
+    );
+    for pr in pending {
+        summary!("  #{} {}", pr.number, pr.summary);
+    }
+}
@@ -0,0 +1,170 @@
+use git2::{Commit, Repository, Status, Tree};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::git_extras::{path_from_bytes, remote, remote_branch};
+use super::github::PullRequest;
+
+/// The default branch when the remote doesn't tell.
+pub const BASE_BRANCH: &str = "master";
+
+/// The branch integration branches are built on unless configured otherwise:
+/// the default branch of the remote, as cloning or `git remote set-head`
+/// recorded it, or [`BASE_BRANCH`].
+pub fn default_branch(repository: &Repository) -> String {
+    let prefix = format!("refs/remotes/{}/", remote());
+    repository
+        .find_reference(&format!("{}HEAD", prefix))
+        .ok()
+        .and_then(|head| {
+            head.symbolic_target()
+                .and_then(|target| target.strip_prefix(&prefix))
+                .map(String::from)
+        })
+        .unwrap_or_else(|| BASE_BRANCH.to_string())
+}
+
+/// The remote-tracking ref of [`default_branch`], such as `origin/main`.
+pub fn default_base(repository: &Repository) -> String {
+    remote_branch(&default_branch(repository))
+}
+
+/// Paths currently in a conflicted state in the work tree.
+pub fn conflicted_paths(repository: &Repository) -> Vec<PathBuf> {
+    repository
+        .statuses(None)
+        .expect("Error checking dirty repository")
+        .iter()
+        .filter(|s| s.status() == Status::CONFLICTED)
+        .map(|s| path_from_bytes(s.path_bytes()))
+        .collect()
+}
+
+/// Whether the index still holds unresolved conflict entries. The index is
+/// read again first, git may have changed it since.
+pub fn has_conflicts(repository: &Repository) -> bool {
+    let conflicted = repository
+        .index()
+        .and_then(|mut index| index.read(false).map(|_| index.has_conflicts()))
+        .expect("Error reading the index");
+    conflicted || !conflicted_paths(repository).is_empty()
+}
+
+/// Whether the work tree copy of `path` still contains conflict markers.
+pub fn has_conflict_markers(repository: &Repository, path: &Path) -> bool {
+    let contents = match repository.workdir().map(|dir| fs::read(dir.join(path))) {
+        Some(Ok(contents)) => contents,
+        _ => return true,
+    };
+
+    String::from_utf8_lossy(&contents)
+        .lines()
+        .any(|line| line.starts_with("<<<<<<< ") || line.starts_with(">>>>>>> "))
+}
+
+/// Whether the pull request branch shares no history with the base, as
+/// happens with squashed mirrors or branches re-created from scratch.
+pub fn is_unrelated(repository: &Repository, base: &str, pr: &PullRequest) -> bool {
+    let unrelated = || -> Result<bool, git2::Error> {
+        let base = repository.revparse_single(base)?.id();
+        let head = repository.revparse_single(&pr.revision)?.id();
+        match repository.merge_base(base, head) {
+            Ok(_) => Ok(false),
+            Err(ref e) if e.code() == git2::ErrorCode::NotFound => Ok(true),
+            Err(e) => Err(e),
+        }
+    };
+
+    unrelated().unwrap_or(false)
+}
+
+/// The tree of where `head` forked from `base`, none when they share no
+/// history, in which case everything in `head` is a change.
+pub fn fork_point_tree<'r>(
+    repository: &'r Repository,
+    base: &Commit,
+    head: &Commit,
+) -> Result<Option<Tree<'r>>, git2::Error> {
+    match repository.merge_base(base.id(), head.id()) {
+        Ok(id) => Ok(Some(repository.find_commit(id)?.tree()?)),
+        Err(ref e) if e.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Files changed by a pull request relative to where it forked from the base.
+pub fn changed_paths(repository: &Repository, base: &str, pr: &PullRequest) -> Vec<PathBuf> {
+    let changed = || -> Result<Vec<PathBuf>, git2::Error> {
+        let base = repository.revparse_single(base)?.peel_to_commit()?;
+        let head = repository.revparse_single(&pr.revision)?.peel_to_commit()?;
+        let fork_point = fork_point_tree(repository, &base, &head)?;
+
+        let diff = repository.diff_tree_to_tree(fork_point.as_ref(), Some(&head.tree()?), None)?;
+
+        Ok(diff
+            .deltas()
+            .flat_map(|d| vec![d.old_file().path(), d.new_file().path()])
+            .flatten()
+            .map(PathBuf::from)
+            .collect())
+    };
+
+    changed().unwrap_or_default()
+}
+
+/// The most recently merged pull request that changed `path`.
+pub fn last_changed_by<'a>(
+    repository: &Repository,
+    base: &str,
+    merged: &'a [PullRequest],
+    path: &Path,
+) -> Option<&'a PullRequest> {
+    merged
+        .iter()
+        .rev()
+        .find(|m| changed_paths(repository, base, m).iter().any(|p| p == path))
+}
+
+/// Prints which files conflict while merging `pr`, which of the already
+/// merged pull requests most recently touched each of them, and the commands
+/// to either resolve and carry on or abort.
+pub fn explain(
+    repository: &Repository,
+    base: &str,
+    pr: &PullRequest,
+    merged: &[PullRequest],
+    rerun: &str,
+) {
+    error!(
+        "\nMerge conflict while merging #{} {} ({})",
+        pr.number, pr.title, pr.branch
+    );
+
+    let touched: Vec<(&PullRequest, Vec<PathBuf>)> = merged
+        .iter()
+        .rev()
+        .map(|m| (m, changed_paths(repository, base, m)))
+        .collect();
+
+    error!("\nConflicting files:");
+    for path in conflicted_paths(repository) {
+        error!("  {}", path.display());
+        match touched.iter().find(|(_, paths)| paths.contains(&path)) {
+            Some((m, _)) => error!(
+                "    last changed by #{} {} ({})",
+                m.number, m.title, m.branch
+            ),
+            None => error!("    conflicts with {}", base),
+        }
+    }
+
+    error!(
+        "\nNext steps:\
+         \n  git status               inspect the conflicted files\
+         \n  git mergetool            resolve the conflicts\
+         \n  git add <path>...        mark them as resolved\
+         \n  {:<25}commit the resolution and merge the rest\
+         \n\nOr give up on the run with `git integrate --abort`",
+        rerun
+    );
+}
@@ -0,0 +1,147 @@
+use chrono::{DateTime, FixedOffset};
+use git2::Repository;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::dates;
+use super::freeze::Cron;
+use super::git_extras::Repo;
+use super::health;
+use super::history::History;
+use super::mappings::{self, Mapping};
+
+/// How often the schedules are checked.
+const TICK: Duration = Duration::from_secs(1);
+
+/// A mapping built whenever its cron expression matches.
+pub struct Scheduled {
+    pub mapping: Mapping,
+    pub cron: Cron,
+    /// When the mapping last ran, so builds missed while the daemon was down
+    /// are caught up once.
+    last: DateTime<FixedOffset>,
+}
+
+impl Scheduled {
+    /// Picks up where the last recorded run of the mapping left off, or
+    /// waits for the next match if it never ran.
+    pub fn new(mapping: Mapping, cron: Cron, history: &History) -> Scheduled {
+        let last = history
+            .runs()
+            .unwrap_or_default()
+            .iter()
+            .rev()
+            .find(|run| run.builds(&mapping.branch) && run.selection == mapping.selector)
+            .and_then(|run| DateTime::parse_from_rfc3339(&run.finished_at).ok())
+            .map(|time| dates::local(&time))
+            .unwrap_or_else(dates::now);
+        Scheduled {
+            mapping,
+            cron,
+            last,
+        }
+    }
+
+    fn is_due(&self, now: &DateTime<FixedOffset>) -> bool {
+        self.cron.latest_between(&self.last, now).is_some()
+    }
+}
+
+/// The same delay of up to `jitter` every time for a repository, so daemons
+/// of many repositories on the same schedule don't hit the API at once.
+fn jitter_for(repo: &Repo, jitter: Duration) -> Duration {
+    if jitter.as_secs() == 0 {
+        return Duration::from_secs(0);
+    }
+    let mut hasher = DefaultHasher::new();
+    (&repo.owner, &repo.name).hash(&mut hasher);
+    Duration::from_secs(hasher.finish() % jitter.as_secs())
+}
+
+/// Builds each scheduled mapping when its schedule says so, until SIGTERM or
+/// SIGINT, which let the build in progress finish first. Due mappings are
+/// built together so their pull requests are fetched in one batch.
+pub fn run(
+    token: Option<&str>,
+    repo: &Repo,
+    mut schedule: Vec<Scheduled>,
+    jitter: Duration,
+    batch_size: usize,
+    extra_args: &[String],
+    listen: Option<&str>,
+) -> ! {
+    let status = health::Status::with_signals().expect("Could not handle signals");
+    if let Some(addr) = listen {
+        health::listen(addr, Arc::clone(&status))
+            .unwrap_or_else(|e| panic!("Could not listen on {}: {}", addr, e));
+    }
+    status.set_ready();
+    let delay = jitter_for(repo, jitter);
+
+    while !status.is_stopping() {
+        let now = dates::now();
+        let due: Vec<usize> = (0..schedule.len())
+            .filter(|&idx| schedule[idx].is_due(&now))
+            .collect();
+        if due.is_empty() {
+            status.sleep(TICK);
+            continue;
+        }
+
+        status.sleep(delay);
+        if status.is_stopping() {
+            break;
+        }
+        let mappings: Vec<Mapping> = due
+            .iter()
+            .map(|&idx| schedule[idx].mapping.clone())
+            .collect();
+        info!(
+            "\nScheduled build of {}",
+            mappings
+                .iter()
+                .map(|m| m.branch.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        match mappings::run(token, repo, &mappings, batch_size, extra_args) {
+            Ok(failed) if !failed.is_empty() => error!("\nFailed to build: {}", failed.join(", ")),
+            Ok(_) => (),
+            Err(e) => error!("\nCould not run the scheduled builds: {}", e),
+        }
+        for idx in due {
+            schedule[idx].last = now;
+        }
+    }
+
+    info!("Stopped the scheduled builds");
+    process::exit(0)
+}
+
+/// The mappings `schedule_for` finds a cron expression for, warning about
+/// the others.
+pub fn load(
+    repository: &Repository,
+    mappings: Vec<Mapping>,
+    schedule_for: impl Fn(&Mapping) -> Option<String>,
+) -> Vec<Scheduled> {
+    let history = History::open(repository);
+    mappings
+        .into_iter()
+        .filter_map(|mapping| {
+            let spec = match schedule_for(&mapping) {
+                Some(spec) => spec,
+                None => {
+                    warn!("{} has no schedule, leaving it out", mapping.branch);
+                    return None;
+                }
+            };
+            let cron = Cron::parse(&spec)
+                .unwrap_or_else(|| panic!("Invalid schedule `{}` for {}", spec, mapping.branch));
+            Some(Scheduled::new(mapping, cron, &history))
+        })
+        .collect()
+}
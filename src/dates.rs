@@ -0,0 +1,165 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Duration, FixedOffset, Local, TimeZone, Utc};
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+/// How dates are written unless `integrate.date-format` says otherwise.
+pub const DEFAULT_FORMAT: &str = "%Y-%m-%d";
+
+static FORMAT: OnceLock<String> = OnceLock::new();
+static ZONE: OnceLock<FixedOffset> = OnceLock::new();
+
+/// Sets the `strftime` format of the dates in branch names and reports.
+pub fn set_format(format: &str) -> Result<(), String> {
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        return Err(format!("`{}` is not a valid date format", format));
+    }
+    FORMAT
+        .set(format.to_string())
+        .map_err(|_| "the date format is already set".to_string())
+}
+
+fn format() -> &'static str {
+    FORMAT.get().map(String::as_str).unwrap_or(DEFAULT_FORMAT)
+}
+
+/// Makes local time, as used by dates, freeze windows and schedules, that of
+/// `timezone`, `UTC` or an offset such as `+02:00`, instead of the machine's.
+pub fn set_timezone(timezone: &str) -> Result<(), String> {
+    let zone = parse_zone(timezone)
+        .ok_or_else(|| format!("`{}` is not UTC or an offset such as +02:00", timezone))?;
+    ZONE.set(zone)
+        .map_err(|_| "the timezone is already set".to_string())
+}
+
+fn parse_zone(timezone: &str) -> Option<FixedOffset> {
+    let timezone = timezone.trim();
+    if ["UTC", "GMT", "Z"].contains(&timezone) {
+        return FixedOffset::east_opt(0);
+    }
+    let sign = match timezone.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits = timezone[1..].replace(':', "");
+    if digits.len() != 2 && digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = match &digits[2..] {
+        "" => 0,
+        minutes => minutes.parse().ok()?,
+    };
+    if minutes >= 60 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// `time` in local time.
+pub fn local<Tz: TimeZone>(time: &DateTime<Tz>) -> DateTime<FixedOffset> {
+    match ZONE.get() {
+        Some(zone) => time.with_timezone(zone),
+        None => time.with_timezone(&Local).fixed_offset(),
+    }
+}
+
+/// The time now in local time.
+pub fn now() -> DateTime<FixedOffset> {
+    local(&Utc::now())
+}
+
+pub fn date<Tz: TimeZone>(time: &DateTime<Tz>) -> String
+where
+    Tz::Offset: Display,
+{
+    local(time).format(format()).to_string()
+}
+
+pub fn date_time<Tz: TimeZone>(time: &DateTime<Tz>) -> String
+where
+    Tz::Offset: Display,
+{
+    let time = local(time);
+    format!("{} {}", date(&time), time.format("%H:%M %:z"))
+}
+
+/// Replaces `{date}` in a branch name with today's date, or `{date:FORMAT}`
+/// with it in that `strftime` format.
+pub fn expand(template: &str) -> String {
+    let now = now();
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{date") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let spec = &rest[start + "{date".len()..end];
+        let date = match spec.strip_prefix(':') {
+            Some(custom) if !StrftimeItems::new(custom).any(|item| item == Item::Error) => {
+                now.format(custom).to_string()
+            }
+            None if spec.is_empty() => date(&now),
+            _ => rest[start..=end].to_string(),
+        };
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&date);
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// A span such as `90m`, `2h` or `1d`, minutes when no unit is given.
+pub fn parse_age(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => value.split_at(idx),
+        None => (value, "m"),
+    };
+    let number: i64 = number.parse().ok()?;
+    match unit {
+        "m" => Some(Duration::minutes(number)),
+        "h" => Some(Duration::hours(number)),
+        "d" => Some(Duration::days(number)),
+        _ => None,
+    }
+}
+
+/// `age` in hours and minutes, such as `1h 5m`.
+pub fn describe_age(age: Duration) -> String {
+    let minutes = age.num_minutes().max(0);
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{}m", minutes),
+        (hours, 0) => format!("{}h", hours),
+        (hours, minutes) => format!("{}h {}m", hours, minutes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ages_take_minutes_hours_and_days() {
+        assert_eq!(parse_age("45"), Some(Duration::minutes(45)));
+        assert_eq!(parse_age("90m"), Some(Duration::minutes(90)));
+        assert_eq!(parse_age(" 2h"), Some(Duration::hours(2)));
+        assert_eq!(parse_age("1d"), Some(Duration::days(1)));
+        assert_eq!(parse_age("2 hours"), None);
+        assert_eq!(parse_age("h"), None);
+        assert_eq!(describe_age(Duration::minutes(125)), "2h 5m");
+    }
+
+    #[test]
+    fn zones_are_utc_or_an_offset() {
+        assert_eq!(parse_zone("UTC"), FixedOffset::east_opt(0));
+        assert_eq!(parse_zone("+02:00"), FixedOffset::east_opt(7200));
+        assert_eq!(parse_zone("-0530"), FixedOffset::west_opt(5 * 3600 + 1800));
+        assert_eq!(parse_zone("+01"), FixedOffset::east_opt(3600));
+        assert_eq!(parse_zone("Europe/Berlin"), None);
+        assert_eq!(parse_zone("+1:00"), None);
+    }
+}
@@ -0,0 +1,263 @@
+use git2::{Oid, Repository, Sort};
+use std::collections::HashMap;
+use std::io;
+
+use super::backend::GitBackend;
+use super::compare::pr_number;
+use super::report::Report;
+
+/// What to do when the base moves on origin while a run is merging.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /// Stop before pushing so the branch is rebuilt on the new base.
+    Fail,
+    /// Rebase the built branch onto the new base and carry on.
+    Rebase,
+}
+
+impl Policy {
+    pub fn parse(value: &str) -> Option<Policy> {
+        match value {
+            "fail" => Some(Policy::Fail),
+            "rebase" => Some(Policy::Rebase),
+            _ => None,
+        }
+    }
+}
+
+/// A remote-tracking base and the commit the run started from.
+pub struct Base<'a> {
+    /// The remote-tracking ref, such as `origin/master`.
+    pub name: &'a str,
+    /// The branch on origin it tracks.
+    pub branch: &'a str,
+    pub built_on: String,
+}
+
+impl Base<'_> {
+    /// Whether the branch can be pushed: the base did not move on origin since
+    /// the run started, or it did and `policy` rebased the branch onto it.
+    pub fn check(&mut self, git: &dyn GitBackend, policy: Policy) -> io::Result<bool> {
+        let current = match git.ls_remote(&format!("refs/heads/{}", self.branch))? {
+            Some(current) if current != self.built_on => current,
+            _ => return Ok(true),
+        };
+        warn!(
+            "\n{} on origin moved from {} to {} during the run",
+            self.branch,
+            &self.built_on[..10],
+            &current[..10]
+        );
+
+        if policy == Policy::Fail {
+            error!(
+                "Nothing was pushed, the branch would lag behind {}. Run again to \
+                 \nbuild on the new base, or pass --on-base-moved rebase to rebase \
+                 \nonto it automatically.",
+                self.branch
+            );
+            return Ok(false);
+        }
+
+        if !git.fetch_branches(&[self.branch.to_string()], 1)?
+            || !git.rebase_onto(self.name, &self.built_on)?
+        {
+            error!(
+                "Could not rebase onto the new {}, nothing was pushed. Run again to \
+                 \nbuild on the new base.",
+                self.branch
+            );
+            return Ok(false);
+        }
+        info!("Rebased onto {} at {}", self.name, &current[..10]);
+        self.built_on = current;
+        Ok(true)
+    }
+}
+
+/// Points the merges and trees `report` recorded at the commits rebasing onto
+/// `base` rewrote them as, found by the pull request each merge names.
+pub fn refresh(
+    repository: &Repository,
+    base: &str,
+    report: &mut Report,
+) -> Result<(), git2::Error> {
+    let base = Oid::from_str(base)?;
+    let mut walk = repository.revwalk()?;
+    walk.push_head()?;
+    walk.hide(base)?;
+    walk.simplify_first_parent()?;
+    walk.set_sorting(Sort::TOPOLOGICAL)?;
+    let mut merges: HashMap<i64, (Oid, Oid)> = HashMap::new();
+    for oid in walk {
+        let commit = repository.find_commit(oid?)?;
+        if let Some(number) = pr_number(&commit) {
+            // Newest first, so a pull request merged twice keeps its last merge.
+            merges
+                .entry(number)
+                .or_insert((commit.id(), commit.tree_id()));
+        }
+    }
+
+    // A branch that was already merged left the tree as the merge before it.
+    let mut tree = repository.find_commit(base)?.tree_id();
+    for entry in report.entries.iter_mut() {
+        match merges.get(&entry.pr.number) {
+            Some(&(merge, merged_tree)) if entry.merge_commit.is_some() => {
+                entry.merge_commit = Some(merge.to_string());
+                entry.tree = Some(merged_tree.to_string());
+                tree = merged_tree;
+            }
+            _ if entry.tree.is_some() => entry.tree = Some(tree.to_string()),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::Signature;
+    use std::time::Duration;
+    use std::{env, fs, process};
+
+    use super::super::backend::fake::Fake;
+    use super::super::report::{Entry, Outcome};
+    use super::*;
+    use crate::github::pull_request;
+
+    const BUILT_ON: &str = "1111111111111111111111111111111111111111";
+    const MOVED_TO: &str = "2222222222222222222222222222222222222222";
+
+    fn base(built_on: &str) -> Base<'static> {
+        Base {
+            name: "origin/master",
+            branch: "master",
+            built_on: built_on.to_string(),
+        }
+    }
+
+    #[test]
+    fn unmoved_base_can_be_pushed() {
+        let git = Fake::new().remote_ref("refs/heads/master", BUILT_ON);
+        let mut tracked = base(BUILT_ON);
+        assert!(tracked.check(&git, Policy::Fail).unwrap());
+        assert_eq!(git.calls(), vec!["ls_remote refs/heads/master"]);
+    }
+
+    #[test]
+    fn moved_base_fails_or_is_rebased_onto() {
+        let git = Fake::new().remote_ref("refs/heads/master", MOVED_TO);
+        let mut tracked = base(BUILT_ON);
+        assert!(!tracked.check(&git, Policy::Fail).unwrap());
+        assert_eq!(tracked.built_on, BUILT_ON);
+
+        assert!(tracked.check(&git, Policy::Rebase).unwrap());
+        assert_eq!(tracked.built_on, MOVED_TO);
+        assert_eq!(
+            git.calls()[1..],
+            [
+                "ls_remote refs/heads/master".to_string(),
+                "fetch_branches master".to_string(),
+                format!("rebase_onto origin/master {}", BUILT_ON),
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_rebase_stops_the_push() {
+        let git = Fake::new()
+            .remote_ref("refs/heads/master", MOVED_TO)
+            .failing("rebase_onto");
+        let mut tracked = base(BUILT_ON);
+        assert!(!tracked.check(&git, Policy::Rebase).unwrap());
+        assert_eq!(tracked.built_on, BUILT_ON);
+    }
+
+    fn commit(repository: &Repository, message: &str, parents: &[Oid], file: &str) -> Oid {
+        let mut builder = repository.treebuilder(None).unwrap();
+        let blob = repository.blob(file.as_bytes()).unwrap();
+        builder.insert(file, blob, 0o100644).unwrap();
+        let tree = repository.find_tree(builder.write().unwrap()).unwrap();
+        let parents: Vec<_> = parents
+            .iter()
+            .map(|id| repository.find_commit(*id).unwrap())
+            .collect();
+        let signature = Signature::now("Test", "test@localhost").unwrap();
+        repository
+            .commit(
+                None,
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &parents.iter().collect::<Vec<_>>(),
+            )
+            .unwrap()
+    }
+
+    fn entry(number: i64, merge_commit: Option<&str>, tree: Option<&str>) -> Entry {
+        Entry {
+            pr: pull_request(number, "feature"),
+            outcome: Outcome::Merged,
+            conflicts: vec![],
+            binary: vec![],
+            authors: vec![],
+            head: None,
+            merge_commit: merge_commit.map(String::from),
+            tree: tree.map(String::from),
+            duration: Duration::from_secs(0),
+            verify_retries: 0,
+        }
+    }
+
+    #[test]
+    fn rebased_merges_are_recorded_as_rewritten() {
+        let path = env::temp_dir().join(format!("git-integrate-{}-drift", process::id()));
+        let _ = fs::remove_dir_all(&path);
+        let repository = Repository::init(&path).unwrap();
+
+        let moved = commit(&repository, "Moved base", &[], "base");
+        let one = commit(&repository, "One", &[moved], "one");
+        let merge_one = commit(
+            &repository,
+            "Merge one\n\nIntegrated-PR: 1",
+            &[moved, one],
+            "merged-one",
+        );
+        let section = commit(&repository, "Section", &[merge_one], "merged-one");
+        let two = commit(&repository, "Two", &[moved], "two");
+        let merge_two = commit(
+            &repository,
+            "Merge two\n\nIntegrated-PR: 2",
+            &[section, two],
+            "merged-two",
+        );
+        repository.set_head_detached(merge_two).unwrap();
+
+        let mut report = Report::new("staging", "deploy", &[]);
+        report.entries = vec![
+            entry(1, Some(BUILT_ON), Some(BUILT_ON)),
+            entry(3, None, Some(BUILT_ON)),
+            entry(2, Some(BUILT_ON), Some(BUILT_ON)),
+            entry(4, None, None),
+        ];
+        refresh(&repository, &moved.to_string(), &mut report).unwrap();
+
+        let tree = |id: Oid| repository.find_commit(id).unwrap().tree_id().to_string();
+        let recorded: Vec<(Option<String>, Option<String>)> = report
+            .entries
+            .iter()
+            .map(|entry| (entry.merge_commit.clone(), entry.tree.clone()))
+            .collect();
+        assert_eq!(
+            recorded,
+            vec![
+                (Some(merge_one.to_string()), Some(tree(merge_one))),
+                (None, Some(tree(merge_one))),
+                (Some(merge_two.to_string()), Some(tree(merge_two))),
+                (None, None),
+            ]
+        );
+    }
+}
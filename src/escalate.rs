@@ -0,0 +1,99 @@
+use super::git_extras::Repo;
+use super::github::rest::{self, Issue};
+use super::history::Run;
+use super::messages;
+use super::report::Report;
+
+/// Label put on the issues opened for failing integrations.
+pub const LABEL: &str = "integrate-failure";
+
+/// How many lines of the run output are attached to the issue.
+const LOG_LINES: usize = 200;
+
+/// Opens an issue once the same branch and selection failed `after` runs in
+/// a row, so a nightly build nobody watches doesn't stay broken for weeks.
+pub struct Escalation<'a> {
+    pub token: &'a str,
+    pub repo: &'a Repo,
+    pub after: usize,
+    pub owners: Vec<String>,
+}
+
+impl Escalation<'_> {
+    /// Opens the issue, or comments on the one already open, when the latest
+    /// of `runs` completes a streak of failures. Returns the issue it touched.
+    pub fn check(&self, runs: &[Run], report: &Report) -> Result<Option<Issue>, reqwest::Error> {
+        let streak: Vec<&Run> = runs
+            .iter()
+            .rev()
+            .filter(|run| run.builds(&report.branch_template) && run.selection == report.selection)
+            .take_while(|run| !run.success)
+            .collect();
+
+        if streak.len() < self.after {
+            return Ok(None);
+        }
+
+        let title = format!(
+            "Integration of `{}` ({}) keeps failing",
+            report.dest_branch, report.selection
+        );
+        let body = body(&streak, report);
+
+        let existing = rest::open_issues(self.token, self.repo, LABEL)?
+            .into_iter()
+            .find(|issue| issue.title == title);
+        match existing {
+            Some(issue) => {
+                rest::create_comment(self.token, self.repo, issue.number, &body)?;
+                Ok(Some(issue))
+            }
+            None => rest::create_issue(
+                self.token,
+                self.repo,
+                &title,
+                &body,
+                &self.owners,
+                &[LABEL.to_string()],
+            )
+            .map(Some),
+        }
+    }
+}
+
+fn body(streak: &[&Run], report: &Report) -> String {
+    let mut out = format!(
+        "`{}` failed the last {} runs in a row.\n\n",
+        report.dest_branch,
+        streak.len()
+    );
+    out.push_str("| Finished | Stopped at | Conflicting files |\n");
+    out.push_str("| --- | --- | --- |\n");
+    for run in streak {
+        let failed = run.pulls.iter().find(|pull| !pull.outcome.is_success());
+        out.push_str(&format!(
+            "| {} | {} | {} |\n",
+            run.finished_at,
+            failed
+                .map(|pull| format!("#{} `{}`", pull.number, pull.branch))
+                .unwrap_or_else(|| "before merging".to_string()),
+            failed
+                .map(|pull| {
+                    pull.conflicts
+                        .iter()
+                        .map(|path| format!("`{}`", path.display()))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                })
+                .unwrap_or_default()
+        ));
+    }
+
+    out.push('\n');
+    out.push_str(&report.to_markdown());
+    out.push_str(&format!(
+        "\n<details><summary>Output of the latest run</summary>\n\n```\n{}\n```\n</details>\n",
+        messages::log_tail(LOG_LINES).join("\n")
+    ));
+    out
+}
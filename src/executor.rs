@@ -0,0 +1,555 @@
+use git2::Repository;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use super::backend::GitBackend;
+use super::git_extras::{remote, Repo};
+use super::github::PullRequest;
+use super::planner::MergePlan;
+use super::report::{Entry, Outcome, Report};
+use super::reporter::Reporter;
+#[cfg(feature = "conflict-shell")]
+use super::shell;
+use super::state::State;
+use super::verify::Verify;
+use super::writeback::{self, WriteBack};
+use super::{
+    bump, drift, freeze, gc, mailmap, merge, notify, ownership, secrets, temp_refs, timeouts,
+    trains,
+};
+
+/// Merges a plan into the checked out integration branch, pushing
+/// checkpoints along the way, and reports how it went.
+pub struct Executor<'a> {
+    pub git: &'a dyn GitBackend,
+    pub repository: &'a Repository,
+    pub repo: &'a Repo,
+    pub dest_branch: &'a str,
+    pub base: &'a str,
+    pub github_token: Option<&'a str>,
+    pub merge_options: merge::Options<'a>,
+    #[cfg(feature = "conflict-shell")]
+    pub resolver: Option<shell::Resolver>,
+    pub notify: Option<notify::Notify>,
+    pub checkpoint_every: Option<usize>,
+    pub checkpoint_branch: String,
+    pub freeze: freeze::Freeze,
+    pub secret_scan: Option<(Vec<secrets::Rule>, Vec<regex::Regex>)>,
+    pub base_moved: drift::Policy,
+    pub verify: Option<Verify>,
+    /// Push the branch once everything is merged.
+    pub push: bool,
+    /// Remotes the pushed branch goes to as well, such as mirrors.
+    pub push_remotes: Vec<String>,
+    pub write_back: Option<WriteBack>,
+    /// Repositories whose submodule is bumped to the pushed branch.
+    pub bump_parents: Vec<bump::Parent>,
+    /// Where to write which pull requests changed each file, and its owners.
+    pub ownership: Option<PathBuf>,
+    /// The release candidate to tag once everything is merged.
+    pub train: Option<trains::Train>,
+    /// The arguments of the run, saved to continue it after a conflict.
+    pub args: Vec<String>,
+    pub run_id: String,
+    /// When the run started, the one it continues if it does, in seconds
+    /// since the epoch.
+    pub started_at: i64,
+    /// Run `git gc --auto` after a run that failed.
+    pub gc: bool,
+    pub reporter: Reporter<'a>,
+}
+
+/// HEAD and its tree.
+fn head_ids(repository: &Repository) -> Option<(String, String)> {
+    let commit = repository.head().ok()?.peel_to_commit().ok()?;
+    Some((commit.id().to_string(), commit.tree_id().to_string()))
+}
+
+/// The commit `pr` is at on origin.
+pub fn head_of(repository: &Repository, pr: &PullRequest) -> Option<String> {
+    repository
+        .revparse_single(&pr.head_ref())
+        .map(|commit| commit.id().to_string())
+        .ok()
+}
+
+impl<'a> Executor<'a> {
+    /// Merges what is left of `plan`, after the merge `resumed` stopped on if
+    /// the run is continued.
+    pub fn run(&self, plan: MergePlan<'a>, mut report: Report, resumed: Option<&State>) -> ! {
+        let MergePlan {
+            pull_requests,
+            sections,
+            mut tracked_base,
+            original_head,
+            original_branch,
+            ..
+        } = plan;
+
+        // Continuing is only possible once the merge the run stopped on is
+        // committed.
+        if let Some(state) = resumed {
+            let pr = &pull_requests[state.stopped_at];
+            let (merge_commit, tree) = head_ids(self.repository).unzip();
+            summary!("Continuing after #{} {}", pr.number, pr.branch);
+            report.record(Entry {
+                pr: pr.clone(),
+                outcome: if state.conflicts.is_empty() {
+                    Outcome::Resolved
+                } else {
+                    Outcome::ResolvedByHand
+                },
+                conflicts: state.conflicts.clone(),
+                binary: vec![],
+                authors: mailmap::authors(self.repository, self.base, &pr.revision)
+                    .unwrap_or_default(),
+                head: head_of(self.repository, pr),
+                merge_commit,
+                tree,
+                duration: Duration::default(),
+                verify_retries: 0,
+            });
+        }
+
+        let next = resumed.map_or(0, |state| state.stopped_at + 1);
+        for (idx, pr) in pull_requests.iter().enumerate().skip(next) {
+            if let Err(expired) = timeouts::check_deadline() {
+                error!("{}", expired);
+                self.finish(&report, 1);
+            }
+            if let Some(section) = &sections[idx] {
+                if !self
+                    .git
+                    .mark_section(&format!("Section: {}", section))
+                    .expect("Error committing the section marker")
+                {
+                    error!("Failure starting the {} section", section);
+                    self.finish(&report, 1);
+                }
+            }
+
+            info!("\nMerging {}", pr.branch);
+            let started = Instant::now();
+            let (before, _) = head_ids(self.repository).expect("Could not read HEAD");
+            let (outcome, conflicts, binary) =
+                merge::merge_branch(self.git, pr, &pull_requests[..idx], &self.merge_options);
+            #[cfg(feature = "conflict-shell")]
+            let outcome = match (outcome, &self.resolver) {
+                (Outcome::Conflicted, Some(resolver)) => self.resolve_by_hand(resolver, pr),
+                _ => outcome,
+            };
+            let (outcome, verify_retries) = match &self.verify {
+                Some(verify) if outcome.is_success() => self.verify(verify, pr, &before, outcome),
+                _ => (outcome, 0),
+            };
+
+            if let (Outcome::Conflicted, Some(notify)) = (outcome, self.notify) {
+                if let Err(e) = notify::conflict(
+                    self.require_token(),
+                    self.repo,
+                    notify,
+                    pr,
+                    self.dest_branch,
+                    &conflicts,
+                ) {
+                    warn!("Could not notify the author of #{}: {}", pr.number, e);
+                }
+            }
+            if let (Outcome::Conflicted, Some(write_back), Some(head)) =
+                (outcome, self.write_back, head_of(self.repository, pr))
+            {
+                if let Err(e) = writeback::conflicted(
+                    self.require_token(),
+                    self.repo,
+                    write_back,
+                    pr,
+                    &head,
+                    self.dest_branch,
+                    &conflicts,
+                ) {
+                    warn!(
+                        "Could not report the conflict back to #{}: {}",
+                        pr.number, e
+                    );
+                }
+            }
+
+            let duration = started.elapsed();
+            // Skipped merges were undone, leaving nothing to point at.
+            let (merge_commit, tree) = match head_ids(self.repository) {
+                Some((head, tree)) if outcome.is_success() => {
+                    (Some(head).filter(|head| *head != before), Some(tree))
+                }
+                _ => (None, None),
+            };
+            let authors = mailmap::authors(self.repository, self.base, &pr.revision)
+                .unwrap_or_else(|e| {
+                    warn!("Could not list the authors of {}: {}", pr.branch, e);
+                    vec![]
+                });
+            let stopped = matches!(outcome, Outcome::Conflicted | Outcome::ResolutionPending);
+            let state = stopped.then(|| State {
+                args: self.args.clone(),
+                dest_branch: self.dest_branch.to_string(),
+                branch_template: Some(report.branch_template.clone()),
+                base: self.base.to_string(),
+                built_on: tracked_base.as_ref().map(|base| base.built_on.clone()),
+                pull_requests: pull_requests.clone(),
+                sections: sections.clone(),
+                excluded: report.excluded.clone(),
+                stopped_at: idx,
+                conflicts: conflicts.clone(),
+                original_head: original_head.clone(),
+                original_branch: original_branch.clone(),
+                run_id: self.run_id.clone(),
+                started_at: self.started_at,
+            });
+            report.record(Entry {
+                pr: pr.clone(),
+                outcome,
+                conflicts,
+                binary,
+                authors,
+                head: head_of(self.repository, pr),
+                merge_commit,
+                tree,
+                duration,
+                verify_retries,
+            });
+
+            let skipped = outcome == Outcome::TimedOut
+                || outcome == Outcome::VerifyFailed
+                    && self
+                        .verify
+                        .as_ref()
+                        .is_some_and(|verify| verify.skip_failures);
+            if outcome == Outcome::TimedOut {
+                warn!(
+                    "Left out {}, its merge took longer than {}s",
+                    pr.branch,
+                    timeouts::merge().map_or(0, |budget| budget.as_secs())
+                );
+            }
+            if !outcome.is_success() && !skipped {
+                if let Some(state) = state {
+                    if let Err(e) = state.save(self.repository) {
+                        warn!("Could not save the run to continue it later: {}", e);
+                    }
+                    self.end(&report, 1);
+                }
+                self.finish(&report, 1);
+            }
+
+            let merged = idx + 1;
+            if self.checkpoint_every.is_some_and(|n| merged % n == 0)
+                && merged < pull_requests.len()
+                && self.freeze.allows_push(&self.checkpoint_branch)
+            {
+                self.check_pushable(&mut tracked_base, &pull_requests[..merged], &mut report);
+
+                info!(
+                    "\nCheckpoint: pushing {} merges to {}",
+                    merged, self.checkpoint_branch
+                );
+                if !self
+                    .git
+                    .push(&self.checkpoint_branch)
+                    .expect("Error pushing checkpoint")
+                {
+                    error!("Failure pushing checkpoint to {}", self.checkpoint_branch);
+                    self.finish(&report, 1);
+                }
+            }
+        }
+
+        self.check_pushable(&mut tracked_base, &pull_requests, &mut report);
+
+        summary!("\nFinished merging successfully!");
+        if let Some(train) = &self.train {
+            info!("\nTagging {}", train.tag());
+            if !trains::tag(self.repository, train).expect("Error tagging the release candidate") {
+                error!("Failure tagging {}", train.tag());
+                self.finish(&report, 1);
+            }
+            report.release_candidate = Some(train.rc);
+        }
+        if self.push {
+            self.publish(&mut report);
+        }
+        let code = if report.pushes.iter().all(|(_, pushed)| *pushed) {
+            0
+        } else {
+            1
+        };
+        self.finish(&report, code);
+    }
+
+    /// Ends the run for good, as opposed to stopping it to be continued, so
+    /// the state of the run it may have continued goes too.
+    fn finish(&self, report: &Report, code: i32) -> ! {
+        if let Err(e) = State::clear(self.repository) {
+            warn!("Could not discard the stopped run: {}", e);
+        }
+        self.end(report, code)
+    }
+
+    /// Ends the run, deleting the temporary refs and the objects a failed
+    /// run left unless it stopped on a conflict, and writing the ownership
+    /// report of what was merged.
+    fn end(&self, report: &Report, code: i32) -> ! {
+        temp_refs::clean_up(self.repository);
+        if code != 0 && matches!(State::load(self.repository), Ok(None)) {
+            gc::clean_up(self.repository, self.started_at, self.gc);
+        }
+        if let Some(path) = &self.ownership {
+            if let Err(e) = ownership::write(self.repository, self.base, self.dest_branch, path) {
+                error!(
+                    "Could not write the ownership report to {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+        self.reporter.finish(report, code)
+    }
+
+    fn require_token(&self) -> &'a str {
+        self.github_token
+            .expect("Could not find integrate.github-token in any git configuration file!")
+    }
+
+    /// Pushes the branch, then to the other push remotes, and tells the
+    /// merged pull requests they are in it.
+    fn publish(&self, report: &mut Report) {
+        if !self.freeze.allows_push(self.dest_branch) {
+            self.finish(report, 1);
+        }
+        info!("\nPushing {}", self.dest_branch);
+        let pushed = self
+            .git
+            .publish(self.dest_branch)
+            .expect("Error pushing the branch");
+        report.pushes.push((remote().to_string(), pushed));
+        if !pushed {
+            error!(
+                "Failure pushing {}, it may have been pushed to since the fetch",
+                self.dest_branch
+            );
+            self.finish(report, 1);
+        }
+        if let Some(train) = &self.train {
+            let pushed = trains::push_tag(self.repository, train).expect("Error pushing the tag");
+            if !pushed {
+                error!("Failure pushing the tag {}", train.tag());
+                self.finish(report, 1);
+            }
+        }
+        // A mirror failing doesn't undo the push, the others are still tried.
+        for push_remote in &self.push_remotes {
+            info!("\nPushing {} to {}", self.dest_branch, push_remote);
+            let pushed = self
+                .git
+                .push_to(push_remote, self.dest_branch)
+                .expect("Error pushing the branch");
+            if !pushed {
+                error!("Failure pushing {} to {}", self.dest_branch, push_remote);
+            }
+            report.pushes.push((push_remote.clone(), pushed));
+        }
+
+        let sha = self
+            .repository
+            .head()
+            .and_then(|head| head.peel_to_commit())
+            .map(|commit| commit.id().to_string())
+            .expect("Could not read HEAD");
+        // A parent that can't be bumped doesn't undo the push either.
+        if !self.bump_parents.is_empty() {
+            bump::bump_all(
+                self.require_token(),
+                self.repo,
+                self.dest_branch,
+                &sha,
+                &self.bump_parents,
+            );
+        }
+
+        let write_back = match self.write_back {
+            Some(write_back) => write_back,
+            None => return,
+        };
+        for entry in report
+            .entries
+            .iter()
+            .filter(|entry| entry.outcome.is_success())
+        {
+            let head = match &entry.head {
+                Some(head) => head,
+                None => continue,
+            };
+            if let Err(e) = writeback::included(
+                self.require_token(),
+                self.repo,
+                write_back,
+                &entry.pr,
+                head,
+                self.dest_branch,
+                &sha,
+            ) {
+                warn!("Could not report back to #{}: {}", entry.pr.number, e);
+            }
+        }
+    }
+
+    /// Stops the run unless the branch holding `merged` can be pushed: its
+    /// base did not move, or was rebased onto, and it leaks no credentials.
+    /// After a rebase the report records the rewritten merges.
+    fn check_pushable(
+        &self,
+        tracked_base: &mut Option<drift::Base>,
+        merged: &[PullRequest],
+        report: &mut Report,
+    ) {
+        if let Some(tracked) = tracked_base {
+            let built_on = tracked.built_on.clone();
+            match tracked.check(self.git, self.base_moved) {
+                Ok(true) => (),
+                Ok(false) => self.finish(report, 1),
+                Err(e) => {
+                    error!(
+                        "Could not check whether {} moved on origin, nothing was pushed: {}",
+                        tracked.branch, e
+                    );
+                    self.finish(report, 1);
+                }
+            }
+            if tracked.built_on != built_on {
+                if let Err(e) = drift::refresh(self.repository, &tracked.built_on, report) {
+                    error!("Could not find the rebased merges: {}", e);
+                    self.finish(report, 1);
+                }
+            }
+        }
+        if let Some((rules, allow)) = &self.secret_scan {
+            if self.found_secrets(rules, allow, merged) {
+                self.finish(report, 1);
+            }
+        }
+    }
+
+    #[cfg(feature = "conflict-shell")]
+    /// Leaves the conflicts of the merge in progress to the user and commits
+    /// the merge once they are resolved.
+    fn resolve_by_hand(&self, resolver: &shell::Resolver, pr: &PullRequest) -> Outcome {
+        if !shell::resolve(self.repository, resolver, pr)
+            .expect("Could not start resolving the conflicts")
+        {
+            error!("Resolving the conflicts failed, stopping");
+            return Outcome::Conflicted;
+        }
+        if self.git.has_conflicts() {
+            error!("Conflicts are left in the index, stopping");
+            return Outcome::Conflicted;
+        }
+        // The merge may have been committed in the shell already.
+        if self.git.merging() && !self.git.commit().expect("Error committing the resolution") {
+            error!("Failure committing the resolution");
+            return Outcome::Failed;
+        }
+        // The merge may also have been given up on, with `git merge --abort`
+        // or a reset, which leaves nothing to commit either.
+        let merged = self
+            .repository
+            .head()
+            .and_then(|head| head.peel_to_commit())
+            .ok()
+            .filter(|commit| commit.parent_count() > 1)
+            .and_then(|commit| super::compare::pr_number(&commit));
+        if merged != Some(pr.number) {
+            error!(
+                "The branch doesn't end with the merge of #{} {}, stopping",
+                pr.number, pr.branch
+            );
+            return Outcome::Conflicted;
+        }
+        Outcome::ResolvedByHand
+    }
+
+    /// Checks the integration branch with `pr` merged into it, which was at
+    /// `before`, undoing the merge if it fails and failures are skipped.
+    /// Answers the outcome and how many failures of a passing check were
+    /// retried.
+    fn verify(
+        &self,
+        verify: &Verify,
+        pr: &PullRequest,
+        before: &str,
+        merged: Outcome,
+    ) -> (Outcome, usize) {
+        info!("Verifying with `{}`", verify.command);
+        let (passed, failures) = verify
+            .check(self.repository, pr)
+            .expect("Could not run the verification command");
+        if passed {
+            if failures > 0 {
+                warn!(
+                    "`{}` only passed on attempt {}, it looks flaky",
+                    verify.command,
+                    failures + 1
+                );
+            }
+            return (merged, failures);
+        }
+        error!(
+            "`{}` failed after merging #{} {}, so it breaks the build",
+            verify.command, pr.number, pr.branch
+        );
+        if verify.skip_failures {
+            if !self
+                .git
+                .reset_hard(before)
+                .expect("Error undoing the merge")
+            {
+                error!("Failure undoing the merge of {}", pr.branch);
+                return (Outcome::Failed, 0);
+            }
+            warn!("Left out {}, carrying on without it", pr.branch);
+        }
+        (Outcome::VerifyFailed, 0)
+    }
+
+    /// Scans what the integration branch adds on top of its base for
+    /// credentials and explains any findings.
+    fn found_secrets(
+        &self,
+        rules: &[secrets::Rule],
+        allow: &[regex::Regex],
+        merged: &[PullRequest],
+    ) -> bool {
+        let findings = secrets::scan(self.repository, self.base, "HEAD", rules, allow, merged)
+            .expect("Error scanning for secrets");
+        if findings.is_empty() {
+            return false;
+        }
+
+        error!("\nPossible credentials in the merged changes:");
+        for finding in &findings {
+            error!(
+                "  {}:{}  {}{}",
+                finding.path.display(),
+                finding.line,
+                finding.rule,
+                finding
+                    .pr
+                    .as_ref()
+                    .map(|pr| format!(" (from #{} {})", pr.number, pr.branch))
+                    .unwrap_or_default()
+            );
+        }
+        error!(
+            "\nNothing was pushed. Remove the credentials from the pull requests, or allow \
+             \nthe files with integrate.secret-allow if they are false positives."
+        );
+        true
+    }
+}
@@ -0,0 +1,178 @@
+use super::git_extras::{remote, PR_TRAILER};
+use super::github::PullRequest;
+
+/// The git commands a run would execute, written out for `--explain` so it
+/// can be audited or reproduced by hand.
+pub struct Script<'a> {
+    pub base: &'a str,
+    pub dest_branch: &'a str,
+    /// The arguments of each fetch, none with `--no-fetch`.
+    pub fetches: Vec<Vec<String>>,
+    pub pull_requests: &'a [PullRequest],
+    pub sections: &'a [Option<String>],
+    /// The remotes the branch is pushed to, origin first, none without
+    /// `--push`.
+    pub pushes: Vec<String>,
+}
+
+/// `git fetch` of `branches` from origin, spread over `jobs` fetches.
+pub fn fetch_branches(branches: &[String], jobs: usize) -> Vec<Vec<String>> {
+    if branches.is_empty() {
+        return vec![];
+    }
+    let per_job = branches.len().div_ceil(jobs.max(1));
+    branches
+        .chunks(per_job)
+        .map(|chunk| {
+            let mut args = vec!["fetch".to_string(), remote().to_string()];
+            args.extend(
+                chunk
+                    .iter()
+                    .map(|b| format!("+refs/heads/{}:refs/remotes/{}/{}", b, remote(), b)),
+            );
+            args
+        })
+        .collect()
+}
+
+/// Quotes `arg` for a POSIX shell when it needs it.
+fn quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=+@,%^".contains(c));
+    match plain {
+        true => arg.to_string(),
+        false => format!("'{}'", arg.replace('\'', r"'\''")),
+    }
+}
+
+fn git(out: &mut String, args: &[&str]) {
+    let args: Vec<String> = args.iter().map(|arg| quote(arg)).collect();
+    out.push_str(&format!("git {}\n", args.join(" ")));
+}
+
+impl Script<'_> {
+    /// The commands as a shell script. A merge that conflicts stops there,
+    /// for the conflicts to be resolved and committed before carrying on.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        if !self.fetches.is_empty() {
+            out.push_str("# Fetch\n");
+            for fetch in &self.fetches {
+                let args: Vec<&str> = fetch.iter().map(String::as_str).collect();
+                git(&mut out, &args);
+            }
+            out.push('\n');
+        }
+        out.push_str(&format!("# Build {} on {}\n", self.dest_branch, self.base));
+        git(
+            &mut out,
+            &["checkout", "--no-track", "-B", self.dest_branch, self.base],
+        );
+        for (pr, section) in self.pull_requests.iter().zip(self.sections) {
+            out.push('\n');
+            if let Some(section) = section {
+                git(
+                    &mut out,
+                    &[
+                        "commit",
+                        "--allow-empty",
+                        "--message",
+                        &format!("Section: {}", section),
+                    ],
+                );
+            }
+            out.push_str(&format!("# #{} {} ({})\n", pr.number, pr.title, pr.branch));
+            let mut merge = vec![
+                "merge",
+                "--no-ff",
+                "--no-commit",
+                "--no-rerere-autoupdate",
+                "--log",
+            ];
+            if pr.has_directive("allow-unrelated") {
+                merge.push("--allow-unrelated-histories");
+            }
+            merge.push(&pr.revision);
+            git(&mut out, &merge);
+            git(
+                &mut out,
+                &[
+                    "interpret-trailers",
+                    "--in-place",
+                    "--trailer",
+                    &format!("{}: {}", PR_TRAILER, pr.number),
+                    ".git/MERGE_MSG",
+                ],
+            );
+            git(&mut out, &["commit", "--no-edit"]);
+        }
+        for (idx, push_remote) in self.pushes.iter().enumerate() {
+            if idx == 0 {
+                out.push_str("\n# Push\n");
+                git(
+                    &mut out,
+                    &[
+                        "push",
+                        &format!("--force-with-lease=refs/heads/{}", self.dest_branch),
+                        push_remote,
+                        &format!("HEAD:refs/heads/{}", self.dest_branch),
+                    ],
+                );
+            } else {
+                git(
+                    &mut out,
+                    &[
+                        "push",
+                        "--force",
+                        push_remote,
+                        &format!("HEAD:refs/heads/{}", self.dest_branch),
+                    ],
+                );
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::pull_request;
+
+    #[test]
+    fn the_script_quotes_what_the_shell_would_split() {
+        let pr = PullRequest {
+            title: "Add login".to_string(),
+            ..pull_request(12, "feature/login")
+        };
+        let script = Script {
+            base: "origin/master",
+            dest_branch: "staging",
+            fetches: vec![vec!["fetch".to_string(), "--all".to_string()]],
+            pull_requests: &[pr],
+            sections: &[Some("Bug fixes".to_string())],
+            pushes: vec!["origin".to_string(), "mirror".to_string()],
+        };
+        assert_eq!(
+            script.to_text(),
+            "# Fetch\n\
+             git fetch --all\n\
+             \n\
+             # Build staging on origin/master\n\
+             git checkout --no-track -B staging origin/master\n\
+             \n\
+             git commit --allow-empty --message 'Section: Bug fixes'\n\
+             # #12 Add login (feature/login)\n\
+             git merge --no-ff --no-commit --no-rerere-autoupdate --log origin/feature/login\n\
+             git interpret-trailers --in-place --trailer 'Integrated-PR: 12' .git/MERGE_MSG\n\
+             git commit --no-edit\n\
+             \n\
+             # Push\n\
+             git push --force-with-lease=refs/heads/staging origin HEAD:refs/heads/staging\n\
+             git push --force mirror HEAD:refs/heads/staging\n"
+        );
+        assert_eq!(quote("it's"), r"'it'\''s'");
+    }
+}
@@ -0,0 +1,199 @@
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, Timelike};
+
+use super::dates;
+
+/// A period during which nothing may be pushed, from `integrate.freeze`.
+///
+/// Either an inclusive date range, `2026-12-19..2027-01-04`, or a cron
+/// expression matching the frozen minutes, `* 18-23 * * 5` for Friday
+/// evenings. Both are in local time.
+#[derive(Debug, Clone)]
+pub enum Window {
+    Dates(NaiveDate, NaiveDate),
+    Cron(Cron),
+}
+
+impl Window {
+    pub fn parse(value: &str) -> Option<Window> {
+        let value = value.trim();
+        if let Some((from, to)) = value.split_once("..") {
+            let from = NaiveDate::parse_from_str(from.trim(), "%Y-%m-%d").ok()?;
+            let to = NaiveDate::parse_from_str(to.trim(), "%Y-%m-%d").ok()?;
+            return Some(Window::Dates(from, to));
+        }
+        Cron::parse(value).map(Window::Cron)
+    }
+
+    pub fn contains(&self, time: &DateTime<FixedOffset>) -> bool {
+        match self {
+            Window::Dates(from, to) => (*from..=*to).contains(&time.date_naive()),
+            Window::Cron(cron) => cron.matches(time),
+        }
+    }
+}
+
+/// The five fields of a cron expression, each as the set of values it allows.
+#[derive(Debug, Clone)]
+pub struct Cron {
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days: Vec<u32>,
+    months: Vec<u32>,
+    weekdays: Vec<u32>,
+    /// Both the day of the month and the day of the week are restricted, so
+    /// as in cron either one matching is enough.
+    either_day: bool,
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Option<Cron> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return None;
+        }
+
+        let mut weekdays = field(fields[4], 0, 7)?;
+        // Both 0 and 7 are Sunday.
+        for day in weekdays.iter_mut() {
+            if *day == 7 {
+                *day = 0;
+            }
+        }
+
+        Some(Cron {
+            minutes: field(fields[0], 0, 59)?,
+            hours: field(fields[1], 0, 23)?,
+            days: field(fields[2], 1, 31)?,
+            months: field(fields[3], 1, 12)?,
+            weekdays,
+            either_day: !fields[2].starts_with('*') && !fields[4].starts_with('*'),
+        })
+    }
+
+    pub fn matches(&self, time: &DateTime<FixedOffset>) -> bool {
+        let day = self.days.contains(&time.day());
+        let weekday = self
+            .weekdays
+            .contains(&time.weekday().num_days_from_sunday());
+        self.minutes.contains(&time.minute())
+            && self.hours.contains(&time.hour())
+            && self.months.contains(&time.month())
+            && if self.either_day {
+                day || weekday
+            } else {
+                day && weekday
+            }
+    }
+
+    /// The latest matching minute after `after` and no later than `until`,
+    /// looking back a month at most.
+    #[cfg(feature = "server")]
+    pub fn latest_between(
+        &self,
+        after: &DateTime<FixedOffset>,
+        until: &DateTime<FixedOffset>,
+    ) -> Option<DateTime<FixedOffset>> {
+        let mut minute = until.with_second(0)?.with_nanosecond(0)?;
+        let oldest = *until - chrono::Duration::days(31);
+        while minute > *after && minute > oldest {
+            if self.matches(&minute) {
+                return Some(minute);
+            }
+            minute -= chrono::Duration::minutes(1);
+        }
+        None
+    }
+}
+
+/// Parses one cron field: `*`, `5`, `1-5`, `*/15`, `0-30/10` or a comma
+/// separated list of those.
+fn field(value: &str, min: u32, max: u32) -> Option<Vec<u32>> {
+    let mut values = vec![];
+    for part in value.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|&s| s > 0)?),
+            None => (part, 1),
+        };
+        let (from, to) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((from, to)) => (from.parse().ok()?, to.parse().ok()?),
+                None => {
+                    let value = range.parse().ok()?;
+                    (value, value)
+                }
+            },
+        };
+        if from < min || to > max || from > to {
+            return None;
+        }
+        values.extend((from..=to).step_by(step));
+    }
+    Some(values)
+}
+
+/// The freeze windows that apply to a branch, and whether the user chose to
+/// push through them.
+pub struct Freeze {
+    pub windows: Vec<(String, Window)>,
+    pub overridden: bool,
+}
+
+impl Freeze {
+    /// The configured window that is in effect right now, if any.
+    pub fn active(&self) -> Option<&str> {
+        let now = dates::now();
+        self.windows
+            .iter()
+            .find(|(_, window)| window.contains(&now))
+            .map(|(spec, _)| spec.as_str())
+    }
+
+    /// Whether pushing to `branch` is allowed now. Attempts during a freeze
+    /// are logged either way.
+    pub fn allows_push(&self, branch: &str) -> bool {
+        match self.active() {
+            None => true,
+            Some(window) if self.overridden => {
+                warn!(
+                    "Pushing {} during the freeze window `{}` because of --override-freeze",
+                    branch, window
+                );
+                true
+            }
+            Some(window) => {
+                error!(
+                    "Not pushing {}, pushes are frozen by `{}`; pass --override-freeze \
+                     \nto push anyway",
+                    branch, window
+                );
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(time).unwrap()
+    }
+
+    #[test]
+    fn restricted_day_and_weekday_either_match() {
+        // Midnight on the 1st and on every Monday.
+        let cron = Cron::parse("0 0 1 * 1").unwrap();
+        assert!(cron.matches(&at("2026-10-01T00:00:00+00:00")));
+        assert!(cron.matches(&at("2026-10-05T00:00:00+00:00")));
+        assert!(!cron.matches(&at("2026-10-06T00:00:00+00:00")));
+
+        // With either one left open, the other has to match.
+        let days = Cron::parse("0 0 1 * *").unwrap();
+        assert!(!days.matches(&at("2026-10-05T00:00:00+00:00")));
+        let weekdays = Cron::parse("* 18-23 * * 5").unwrap();
+        assert!(weekdays.matches(&at("2026-10-16T19:30:00+00:00")));
+        assert!(!weekdays.matches(&at("2026-10-15T19:30:00+00:00")));
+    }
+}
@@ -0,0 +1,89 @@
+use git2::Repository;
+use std::fs;
+use std::io;
+use std::time::{Duration, UNIX_EPOCH};
+
+use super::git_extras::{common_dir, git_command};
+use super::timeouts::Timed;
+
+/// Drops the entries `HEAD` and `dest_branch` got in their reflogs since the
+/// run started, the only thing still keeping the merges of an aborted run.
+pub fn forget_reflog(
+    repository: &Repository,
+    dest_branch: &str,
+    started_at: i64,
+) -> Result<usize, git2::Error> {
+    let mut forgotten = 0;
+    for name in ["HEAD".to_string(), format!("refs/heads/{}", dest_branch)] {
+        let mut reflog = repository.reflog(&name)?;
+        // The newest entries come first.
+        let newer = reflog
+            .iter()
+            .take_while(|entry| entry.committer().when().seconds() >= started_at)
+            .count();
+        if newer == 0 {
+            continue;
+        }
+        for _ in 0..newer {
+            reflog.remove(0, true)?;
+        }
+        reflog.write()?;
+        forgotten += newer;
+    }
+    Ok(forgotten)
+}
+
+/// Deletes the loose objects nothing reaches that were written since the run
+/// started, such as its speculative merges and those of a branch put back,
+/// answering how many there were. Objects from before the run are left to
+/// git's own garbage collection.
+pub fn remove_unreachable(repository: &Repository, started_at: i64) -> io::Result<usize> {
+    let output = git_command(repository)
+        .arg("fsck")
+        .arg("--unreachable")
+        .arg("--connectivity-only")
+        .arg("--no-progress")
+        .output_timed()?;
+    let since = UNIX_EPOCH + Duration::from_secs(started_at.max(0) as u64);
+    let objects = common_dir(repository).join("objects");
+    let mut removed = 0;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let sha = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["unreachable", _, sha] if sha.len() > 2 => sha.to_string(),
+            _ => continue,
+        };
+        let path = objects.join(&sha[..2]).join(&sha[2..]);
+        let written = fs::metadata(&path).and_then(|metadata| metadata.modified());
+        // Packed objects aren't loose files and are left alone.
+        if written.is_ok_and(|written| written >= since) && fs::remove_file(&path).is_ok() {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Cleans up after a run that stopped for good, which started at
+/// `started_at` in seconds since the epoch, and with `gc` lets git pack and
+/// prune the repository too if it thinks that's due.
+pub fn clean_up(repository: &Repository, started_at: i64, gc: bool) {
+    // Runs saved by older versions don't know when they started.
+    if started_at > 0 {
+        match remove_unreachable(repository, started_at) {
+            Ok(0) => (),
+            Ok(removed) => info!("Removed {} objects left by the run", removed),
+            Err(e) => warn!("Could not remove the objects left by the run: {}", e),
+        }
+    }
+    if gc {
+        let collected = git_command(repository)
+            .arg("gc")
+            .arg("--auto")
+            .arg("--quiet")
+            .status_timed();
+        match collected {
+            Ok(status) if status.success() => (),
+            Ok(_) => warn!("git gc --auto failed"),
+            Err(e) => warn!("Could not run git gc --auto: {}", e),
+        }
+    }
+}
@@ -1,4 +1,34 @@
-use git2::Remote;
+use git2::{Remote, Repository};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use super::messages;
+use super::run_env;
+
+/// Trailer recording which pull request a merge commit integrated.
+pub const PR_TRAILER: &str = "Integrated-PR";
+
+static REMOTE: OnceLock<String> = OnceLock::new();
+
+/// Fetches from and pushes to `name` instead of origin from now on.
+pub fn set_remote(name: &str) -> Result<(), String> {
+    REMOTE
+        .set(name.to_string())
+        .map_err(|_| "The remote was already set".to_string())
+}
+
+/// The remote pull requests are fetched from and branches pushed to.
+pub fn remote() -> &'static str {
+    REMOTE.get().map(String::as_str).unwrap_or("origin")
+}
+
+/// The remote-tracking ref of `branch`, such as `origin/master`.
+pub fn remote_branch(branch: &str) -> String {
+    format!("{}/{}", remote(), branch)
+}
 
 #[derive(Debug)]
 pub struct Repo {
@@ -8,21 +38,187 @@ pub struct Repo {
 
 impl Repo {
     pub fn new(remote: &Remote) -> Option<Repo> {
-        let url = match remote.url() {
-            Some(url) => url,
-            None => return None,
-        };
-        let mut parts = url.split('/').rev();
+        Repo::parse(remote.url()?)
+    }
 
-        let name = match parts.next() {
-            Some(name) => name.trim_end_matches(".git").to_string(),
-            None => return None,
+    /// Reads the owner and name from an HTTPS, `ssh://` or `user@host:path`
+    /// remote URL. On GitLab the owner is the whole group path, subgroups
+    /// and all.
+    pub fn parse(url: &str) -> Option<Repo> {
+        let path = match url.split_once("://") {
+            Some((_, rest)) => rest.split_once('/')?.1,
+            None => match url.split_once(':') {
+                Some((host, path)) if !host.contains('/') => path,
+                // A local clone: only the last two directories say anything.
+                _ => {
+                    let url = url.trim_end_matches('/');
+                    let start = url.rmatch_indices('/').nth(1).map_or(0, |(idx, _)| idx + 1);
+                    &url[start..]
+                }
+            },
         };
+        let path = path.trim_matches('/');
+        let path = path.strip_suffix(".git").unwrap_or(path);
+        let (owner, name) = path.rsplit_once('/')?;
+        if owner.is_empty() || name.is_empty() {
+            return None;
+        }
+        Some(Repo {
+            owner: owner.to_string(),
+            name: name.to_string(),
+        })
+    }
+}
 
-        let owner = match parts.next().and_then(|s| s.split(':').rev().next()) {
-            Some(owner) => owner.to_string(),
-            None => return None,
-        };
-        Some(Repo { owner, name })
+/// A `git` command bound to the discovered repository, so spawned processes
+/// act on the right HEAD and index when run from a linked worktree or with a
+/// `GIT_DIR` pointing outside the work tree. With `--quiet` git's own
+/// progress output is dropped too; callers reading stdout must ask for a pipe.
+/// Hooks git runs see the `GI_*` variables of the run. Output is never
+/// translated and paths are left unescaped, so it parses the same in any
+/// locale; see `path_from_git`.
+pub fn git_command(repository: &Repository) -> Command {
+    let mut command = Command::new("git");
+    run_env::apply_run(&mut command);
+    command
+        .env("LC_ALL", "C")
+        .env("LANGUAGE", "C")
+        .arg("-c")
+        .arg("core.quotepath=off");
+    if messages::is_quiet() {
+        command.stdout(Stdio::null());
+    }
+    command.arg("--git-dir").arg(repository.path());
+    if let Some(workdir) = repository.workdir() {
+        command.arg("--work-tree").arg(workdir).current_dir(workdir);
+    }
+    command
+}
+
+/// Identity used for the merge commits of a run.
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+}
+
+/// Makes every command spawned from now on non-interactive, so an unattended
+/// run fails instead of waiting for a credential prompt, an SSH passphrase, a
+/// GPG pinentry or an editor, and commits under `identity`.
+pub fn sandbox_env(identity: &Identity) {
+    env::set_var("GIT_TERMINAL_PROMPT", "0");
+    env::set_var("GCM_INTERACTIVE", "never");
+    env::set_var("GIT_EDITOR", "true");
+    env::set_var("GIT_MERGE_AUTOEDIT", "no");
+    env::remove_var("GIT_ASKPASS");
+    env::remove_var("SSH_ASKPASS");
+    env::remove_var("GPG_TTY");
+    if env::var_os("GIT_SSH_COMMAND").is_none() && env::var_os("GIT_SSH").is_none() {
+        env::set_var("GIT_SSH_COMMAND", "ssh -o BatchMode=yes");
+    }
+
+    for (prefix, value) in &[("NAME", &identity.name), ("EMAIL", &identity.email)] {
+        env::set_var(format!("GIT_AUTHOR_{}", prefix), value);
+        env::set_var(format!("GIT_COMMITTER_{}", prefix), value);
+    }
+
+    // Appended to whatever configuration the caller already passes this way.
+    let mut count: usize = env::var("GIT_CONFIG_COUNT")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(0);
+    for (key, value) in &[("commit.gpgSign", "false"), ("tag.gpgSign", "false")] {
+        env::set_var(format!("GIT_CONFIG_KEY_{}", count), key);
+        env::set_var(format!("GIT_CONFIG_VALUE_{}", count), value);
+        count += 1;
+    }
+    env::set_var("GIT_CONFIG_COUNT", count.to_string());
+}
+
+/// A path git or libgit2 gives as bytes, which need not be UTF-8.
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        PathBuf::from(OsStr::from_bytes(bytes))
+    }
+    #[cfg(not(unix))]
+    {
+        PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+/// A path as git prints it, which even with `core.quotepath=off` is quoted
+/// like a C string when it holds quotes, backslashes or control characters.
+pub fn path_from_git(line: &[u8]) -> PathBuf {
+    let quoted = match line {
+        [b'"', quoted @ .., b'"'] => quoted,
+        _ => return path_from_bytes(line),
+    };
+    let mut bytes = Vec::with_capacity(quoted.len());
+    let mut rest = quoted.iter().copied();
+    while let Some(byte) = rest.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        match rest.next() {
+            Some(b'n') => bytes.push(b'\n'),
+            Some(b't') => bytes.push(b'\t'),
+            Some(b'r') => bytes.push(b'\r'),
+            Some(b'a') => bytes.push(0x07),
+            Some(b'b') => bytes.push(0x08),
+            Some(b'f') => bytes.push(0x0c),
+            Some(b'v') => bytes.push(0x0b),
+            Some(digit @ b'0'..=b'7') => {
+                let mut value = u32::from(digit - b'0');
+                for _ in 0..2 {
+                    match rest.clone().next() {
+                        Some(digit @ b'0'..=b'7') => {
+                            rest.next();
+                            value = value * 8 + u32::from(digit - b'0');
+                        }
+                        _ => break,
+                    }
+                }
+                bytes.push(value as u8);
+            }
+            Some(other) => bytes.push(other),
+            None => bytes.push(b'\\'),
+        }
+    }
+    path_from_bytes(&bytes)
+}
+
+/// The git directory shared by all worktrees of the repository.
+pub fn common_dir(repository: &Repository) -> PathBuf {
+    let git_dir = repository.path();
+    match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(common) => git_dir.join(common.trim()),
+        Err(_) => git_dir.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_paths_are_unescaped_to_their_bytes() {
+        assert_eq!(
+            path_from_git("docs/ünïcøde.md".as_bytes()),
+            PathBuf::from("docs/ünïcøde.md")
+        );
+        assert_eq!(path_from_git(br#""tab\there""#), PathBuf::from("tab\there"));
+        assert_eq!(
+            path_from_git(br#""say \"hi\"""#),
+            PathBuf::from("say \"hi\"")
+        );
+        assert_eq!(path_from_git(br#""caf\303\251""#), PathBuf::from("café"));
+        #[cfg(unix)]
+        assert_eq!(
+            path_from_git(br#""latin1-\351""#),
+            path_from_bytes(b"latin1-\xe9")
+        );
     }
 }
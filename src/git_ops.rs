@@ -0,0 +1,652 @@
+use git2::build::CheckoutBuilder;
+use git2::{
+    AutotagOption, Commit, Cred, CredentialType, Direction, FetchOptions, Oid, RemoteCallbacks,
+    Repository, RepositoryState, Signature, Sort,
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{env, fmt, fs, io};
+
+use super::backend::{GitBackend, Subprocess};
+use super::binary;
+use super::conflicts;
+use super::git_extras::remote;
+use super::github::PullRequest;
+use super::timeouts::Timed;
+
+/// How many times a credential is asked for before giving up, as libgit2
+/// asks again for as long as authentication fails.
+const CREDENTIAL_ATTEMPTS: usize = 3;
+
+/// Subjects listed in a merge message, as `git merge --log` does.
+const LOG_SUBJECTS: usize = 20;
+
+/// Why a git operation failed.
+#[derive(Debug)]
+pub enum Error {
+    Git(git2::Error),
+    Io(io::Error),
+    /// The branch shares no history with the integration branch.
+    UnrelatedHistories(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Git(e) => write!(f, "{}", e.message()),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::UnrelatedHistories(revision) => write!(
+                f,
+                "{} shares no history with the branch, label it integrate:allow-unrelated to merge it anyway",
+                revision
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<git2::Error> for Error {
+    fn from(e: git2::Error) -> Error {
+        Error::Git(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::Io(e) => e,
+            e => io::Error::other(e),
+        }
+    }
+}
+
+/// Fetches, checks out, merges and commits with libgit2 instead of the `git`
+/// executable. rerere, pushing and rebasing still need git.
+pub struct InProcess<'a> {
+    pub repository: &'a Repository,
+    /// What the integration branch is built on.
+    pub base: &'a str,
+}
+
+impl<'a> InProcess<'a> {
+    fn subprocess(&self) -> Subprocess<'a> {
+        Subprocess {
+            repository: self.repository,
+            base: self.base,
+        }
+    }
+
+    /// SSH keys from the agent, then the configured credential helpers, then
+    /// whatever libgit2 finds by default.
+    fn callbacks(&self) -> Result<RemoteCallbacks<'a>, Error> {
+        let config = self.repository.config()?;
+        let mut attempts = 0;
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.credentials(move |url, username, allowed| {
+            attempts += 1;
+            if attempts > CREDENTIAL_ATTEMPTS {
+                return Err(git2::Error::from_str(&format!(
+                    "Authentication to {} failed",
+                    url
+                )));
+            }
+            if allowed.contains(CredentialType::SSH_KEY) {
+                return Cred::ssh_key_from_agent(username.unwrap_or("git"));
+            }
+            if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+                if let Ok(cred) = Cred::credential_helper(&config, url, username) {
+                    return Ok(cred);
+                }
+            }
+            Cred::default()
+        });
+        Ok(callbacks)
+    }
+
+    fn fetch_refspecs(&self, remote_name: &str, refspecs: &[String]) -> Result<(), Error> {
+        let mut remote = self.repository.find_remote(remote_name)?;
+        let mut options = FetchOptions::new();
+        options
+            .remote_callbacks(self.callbacks()?)
+            .download_tags(AutotagOption::Auto);
+        remote.fetch(refspecs, Some(&mut options), None)?;
+        Ok(())
+    }
+
+    fn try_fetch(&self) -> Result<(), Error> {
+        for name in self.repository.remotes()?.iter().flatten() {
+            self.fetch_refspecs(name, &[])?;
+        }
+        Ok(())
+    }
+
+    fn try_checkout(&self, branch: &str) -> Result<(), Error> {
+        let commit = self
+            .repository
+            .revparse_single(self.base)?
+            .peel_to_commit()?;
+        self.repository
+            .checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()))?;
+        let refname = format!("refs/heads/{}", branch);
+        self.repository.reference(
+            &refname,
+            commit.id(),
+            true,
+            &format!("integrate: reset {} to {}", branch, self.base),
+        )?;
+        self.repository.set_head(&refname)?;
+        Ok(())
+    }
+
+    /// Merges `revision` without committing, answering whether it merged
+    /// cleanly.
+    fn try_merge(&self, revision: &str, allow_unrelated: bool) -> Result<bool, Error> {
+        let head = self.repository.head()?.peel_to_commit()?;
+        let theirs = self
+            .repository
+            .revparse_single(revision)?
+            .peel_to_commit()?;
+        if self.repository.merge_base(head.id(), theirs.id()).is_err() && !allow_unrelated {
+            return Err(Error::UnrelatedHistories(revision.to_string()));
+        }
+        let annotated = self.repository.find_annotated_commit(theirs.id())?;
+        let (analysis, _) = self.repository.merge_analysis(&[&annotated])?;
+        if analysis.is_up_to_date() {
+            return Ok(true);
+        }
+
+        self.repository.merge(
+            &[&annotated],
+            None,
+            Some(CheckoutBuilder::new().safe().allow_conflicts(true)),
+        )?;
+        fs::write(
+            self.repository.path().join("MERGE_MSG"),
+            self.merge_message(revision, &head, &theirs)?,
+        )?;
+        Ok(!self.repository.index()?.has_conflicts())
+    }
+
+    /// `Merge remote-tracking branch 'origin/x' into staging` and what it
+    /// brings, as `git merge --log` words it.
+    fn merge_message(
+        &self,
+        revision: &str,
+        head: &Commit,
+        theirs: &Commit,
+    ) -> Result<String, Error> {
+        let into = self
+            .repository
+            .head()?
+            .shorthand()
+            .unwrap_or("HEAD")
+            .to_string();
+        let kind = if revision.starts_with(&format!("{}/", remote())) {
+            "remote-tracking branch"
+        } else {
+            "branch"
+        };
+        let mut message = format!("Merge {} '{}' into {}\n", kind, revision, into);
+
+        let mut walk = self.repository.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL)?;
+        walk.push(theirs.id())?;
+        walk.hide(head.id())?;
+        let subjects: Vec<String> = walk
+            .take(LOG_SUBJECTS)
+            .filter_map(|oid| self.repository.find_commit(oid.ok()?).ok())
+            .map(|commit| commit.summary().unwrap_or_default().to_string())
+            .collect();
+        if !subjects.is_empty() {
+            message.push_str(&format!("\n* {}:\n", revision));
+            for subject in subjects {
+                message.push_str(&format!("  {}\n", subject));
+            }
+        }
+        Ok(message)
+    }
+
+    /// Who commits, from the environment set for the run like git reads it,
+    /// or else the configuration.
+    fn signature(&self, role: &str) -> Result<Signature<'static>, Error> {
+        match (
+            env::var(format!("GIT_{}_NAME", role)),
+            env::var(format!("GIT_{}_EMAIL", role)),
+        ) {
+            (Ok(name), Ok(email)) => Ok(Signature::now(&name, &email)?),
+            _ => Ok(self.repository.signature()?.to_owned()),
+        }
+    }
+
+    /// Commits the index on top of HEAD and the merged commits with
+    /// `message`, unless it has conflicts.
+    fn commit_index(&self, message: &str, merge_heads: &[Oid]) -> Result<bool, Error> {
+        let mut index = self.repository.index()?;
+        index.read(false)?;
+        if index.has_conflicts() {
+            return Ok(false);
+        }
+        let tree = self.repository.find_tree(index.write_tree()?)?;
+        let head = self.repository.head()?.peel_to_commit()?;
+        let mut parents = vec![head];
+        for oid in merge_heads {
+            parents.push(self.repository.find_commit(*oid)?);
+        }
+        let parents: Vec<&Commit> = parents.iter().collect();
+        self.repository.commit(
+            Some("HEAD"),
+            &self.signature("AUTHOR")?,
+            &self.signature("COMMITTER")?,
+            message,
+            &tree,
+            &parents,
+        )?;
+        Ok(true)
+    }
+
+    fn merge_heads(&self) -> Result<Vec<Oid>, Error> {
+        let heads = fs::read_to_string(self.repository.path().join("MERGE_HEAD"))?;
+        Ok(heads.lines().map(Oid::from_str).collect::<Result<_, _>>()?)
+    }
+
+    fn try_commit(&self) -> Result<bool, Error> {
+        let message: String = self
+            .repository
+            .message()?
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect::<Vec<_>>()
+            .join("\n");
+        if !self.commit_index(message.trim_end(), &self.merge_heads()?)? {
+            return Ok(false);
+        }
+        self.repository.cleanup_state()?;
+        Ok(true)
+    }
+
+    fn try_add_trailer(&self, trailer: &str) -> Result<(), Error> {
+        let message = self.repository.message()?;
+        let body: Vec<&str> = message
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect();
+        let body = body.join("\n");
+        let body = body.trim_end();
+        // Trailers go in one paragraph at the end of the message.
+        let last = body.rsplit("\n\n").next().unwrap_or_default();
+        let separator = if body.contains("\n\n") && last.lines().all(is_trailer) {
+            "\n"
+        } else {
+            "\n\n"
+        };
+        fs::write(
+            self.repository.path().join("MERGE_MSG"),
+            format!("{}{}{}\n", body, separator, trailer),
+        )?;
+        Ok(())
+    }
+
+    fn try_add(&self, paths: &[String]) -> Result<(), Error> {
+        let workdir = self.repository.workdir().unwrap_or_else(|| Path::new("."));
+        let mut index = self.repository.index()?;
+        index.read(false)?;
+        for path in paths {
+            if workdir.join(path).exists() {
+                index.add_path(Path::new(path))?;
+            } else {
+                index.remove_path(Path::new(path))?;
+            }
+        }
+        index.write()?;
+        Ok(())
+    }
+
+    /// Every ref on origin with the commit it points at.
+    fn advertised(&self) -> Result<Vec<(String, String)>, Error> {
+        let mut remote = self.repository.find_remote(remote())?;
+        let connection = remote.connect_auth(Direction::Fetch, Some(self.callbacks()?), None)?;
+        Ok(connection
+            .list()?
+            .iter()
+            .map(|head| (head.name().to_string(), head.oid().to_string()))
+            .collect())
+    }
+}
+
+fn is_trailer(line: &str) -> bool {
+    line.split_once(": ").is_some_and(|(key, _)| {
+        !key.is_empty() && key.chars().all(|c| c.is_alphanumeric() || c == '-')
+    })
+}
+
+/// Errors are reported and answer `false`, so the run says what failed
+/// instead of stopping.
+fn reported(operation: &str, result: Result<(), Error>) -> io::Result<bool> {
+    match result {
+        Ok(()) => Ok(true),
+        Err(e) => {
+            error!("Failure to {}: {}", operation, e);
+            Ok(false)
+        }
+    }
+}
+
+impl GitBackend for InProcess<'_> {
+    fn fetch(&self) -> io::Result<bool> {
+        reported("fetch", self.try_fetch())
+    }
+
+    fn fetch_branches(&self, branches: &[String], _jobs: usize) -> io::Result<bool> {
+        if branches.is_empty() {
+            return Ok(true);
+        }
+        // A single fetch already negotiates every branch at once.
+        let refspecs: Vec<String> = branches
+            .iter()
+            .map(|b| format!("+refs/heads/{}:refs/remotes/{}/{}", b, remote(), b))
+            .collect();
+        reported("fetch", self.fetch_refspecs(remote(), &refspecs))
+    }
+
+    fn fetch_refs(&self, refspecs: &[String]) -> io::Result<bool> {
+        if refspecs.is_empty() {
+            return Ok(true);
+        }
+        reported("fetch", self.fetch_refspecs(remote(), refspecs))
+    }
+
+    // libgit2 can't fetch into shallow clones.
+    fn deepen(&self, depth: Option<usize>) -> io::Result<bool> {
+        self.subprocess().deepen(depth)
+    }
+
+    fn checkout(&self, branch: &str) -> io::Result<bool> {
+        reported(&format!("check out {}", branch), self.try_checkout(branch))
+    }
+
+    fn merge(&self, revision: &str, allow_unrelated: bool) -> io::Result<bool> {
+        Ok(self.try_merge(revision, allow_unrelated)?)
+    }
+
+    fn merging(&self) -> bool {
+        self.repository.state() == RepositoryState::Merge
+    }
+
+    fn add_trailer(&self, trailer: &str) -> io::Result<bool> {
+        reported("add the trailer", self.try_add_trailer(trailer))
+    }
+
+    /// rerere only exists in git, which replays what it recorded here when
+    /// it is installed.
+    fn rerere_remaining(&self) -> io::Result<Vec<PathBuf>> {
+        let replayed = super::git_extras::git_command(self.repository)
+            .arg("rerere")
+            .status_timed();
+        match replayed {
+            Ok(_) => self.subprocess().rerere_remaining(),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(self.conflicted_paths()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn conflicted_paths(&self) -> Vec<PathBuf> {
+        conflicts::conflicted_paths(self.repository)
+    }
+
+    fn has_conflict_markers(&self, path: &Path) -> bool {
+        conflicts::has_conflict_markers(self.repository, path)
+    }
+
+    fn has_conflicts(&self) -> bool {
+        conflicts::has_conflicts(self.repository)
+    }
+
+    fn add(&self, paths: &[String]) -> io::Result<bool> {
+        reported("stage the resolutions", self.try_add(paths))
+    }
+
+    fn resolve_binary(
+        &self,
+        policy: binary::Policy,
+        pr: &PullRequest,
+        merged: &[PullRequest],
+    ) -> Result<Vec<binary::Choice>, git2::Error> {
+        binary::resolve(self.repository, self.base, policy, pr, merged)
+    }
+
+    fn explain_conflicts(&self, pr: &PullRequest, merged: &[PullRequest], rerun: &str) {
+        conflicts::explain(self.repository, self.base, pr, merged, rerun)
+    }
+
+    fn commit(&self) -> io::Result<bool> {
+        if !self.merging() {
+            return self.subprocess().commit();
+        }
+        match self.try_commit() {
+            Ok(committed) => Ok(committed),
+            Err(e) => {
+                error!("Failure to commit the merge: {}", e);
+                Ok(false)
+            }
+        }
+    }
+
+    fn mark_section(&self, message: &str) -> io::Result<bool> {
+        match self.commit_index(message, &[]) {
+            Ok(committed) => Ok(committed),
+            Err(e) => {
+                error!("Failure to commit the section marker: {}", e);
+                Ok(false)
+            }
+        }
+    }
+
+    fn push(&self, branch: &str) -> io::Result<bool> {
+        self.subprocess().push(branch)
+    }
+
+    fn publish(&self, branch: &str) -> io::Result<bool> {
+        self.subprocess().publish(branch)
+    }
+
+    fn push_to(&self, remote: &str, branch: &str) -> io::Result<bool> {
+        self.subprocess().push_to(remote, branch)
+    }
+
+    fn reset_hard(&self, commit: &str) -> io::Result<bool> {
+        let reset = || -> Result<(), Error> {
+            let object = self.repository.revparse_single(commit)?;
+            self.repository
+                .reset(&object, git2::ResetType::Hard, None)?;
+            Ok(())
+        };
+        reported(&format!("reset to {}", commit), reset())
+    }
+
+    fn rebase_onto(&self, onto: &str, upstream: &str) -> io::Result<bool> {
+        self.subprocess().rebase_onto(onto, upstream)
+    }
+
+    fn ls_remote(&self, refname: &str) -> io::Result<Option<String>> {
+        Ok(self
+            .advertised()?
+            .into_iter()
+            .find(|(name, _)| name == refname)
+            .map(|(_, sha)| sha))
+    }
+
+    fn remote_branches(&self) -> io::Result<HashMap<String, String>> {
+        Ok(self
+            .advertised()?
+            .into_iter()
+            .filter_map(|(name, sha)| {
+                let branch = name.strip_prefix("refs/heads/")?;
+                Some((branch.to_string(), sha))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::Signature;
+    use std::{env, fs, process};
+
+    use super::*;
+
+    /// A repository with `base.txt` committed on master and checked out.
+    fn repository(name: &str) -> Repository {
+        let path = env::temp_dir().join(format!("git-integrate-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&path);
+        let repository = Repository::init(&path).unwrap();
+        let mut config = repository.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        commit(
+            &repository,
+            "refs/heads/master",
+            &[],
+            "base.txt",
+            "base\n",
+            "base",
+        );
+        repository
+            .checkout_head(Some(CheckoutBuilder::new().force()))
+            .unwrap();
+        repository
+    }
+
+    /// Commits `file` with `contents` to `branch` on top of `parents`,
+    /// leaving the work tree alone.
+    fn commit(
+        repository: &Repository,
+        branch: &str,
+        parents: &[&str],
+        file: &str,
+        contents: &str,
+        message: &str,
+    ) {
+        let parents: Vec<_> = parents
+            .iter()
+            .map(|p| {
+                repository
+                    .revparse_single(p)
+                    .unwrap()
+                    .peel_to_commit()
+                    .unwrap()
+            })
+            .collect();
+        let base = parents.first().map(|parent| parent.tree().unwrap());
+        let mut tree = repository.treebuilder(base.as_ref()).unwrap();
+        let blob = repository.blob(contents.as_bytes()).unwrap();
+        tree.insert(file, blob, 0o100644).unwrap();
+        let tree = repository.find_tree(tree.write().unwrap()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        repository
+            .commit(
+                Some(branch),
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &parents.iter().collect::<Vec<_>>(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn merges_are_no_ff_with_the_log_and_trailer() {
+        let repository = repository("merge");
+        commit(
+            &repository,
+            "refs/heads/feature",
+            &["master"],
+            "f.txt",
+            "f\n",
+            "Add f",
+        );
+        let git = InProcess {
+            repository: &repository,
+            base: "master",
+        };
+
+        assert!(git.checkout("staging").unwrap());
+        assert!(git.merge("feature", false).unwrap());
+        assert!(git.merging());
+        assert!(git.add_trailer("Integrated-PR: 7").unwrap());
+        assert!(git.commit().unwrap());
+
+        let head = repository.head().unwrap();
+        assert_eq!(head.shorthand(), Some("staging"));
+        let merge = head.peel_to_commit().unwrap();
+        assert_eq!(merge.parent_count(), 2);
+        assert_eq!(
+            merge.message(),
+            Some("Merge branch 'feature' into staging\n\n* feature:\n  Add f\n\nIntegrated-PR: 7")
+        );
+        assert_eq!(repository.state(), RepositoryState::Clean);
+        assert!(repository.workdir().unwrap().join("f.txt").exists());
+    }
+
+    #[test]
+    fn conflicts_are_left_in_the_index() {
+        let repository = repository("conflict");
+        commit(
+            &repository,
+            "refs/heads/one",
+            &["master"],
+            "base.txt",
+            "one\n",
+            "One",
+        );
+        commit(
+            &repository,
+            "refs/heads/two",
+            &["master"],
+            "base.txt",
+            "two\n",
+            "Two",
+        );
+        let git = InProcess {
+            repository: &repository,
+            base: "master",
+        };
+
+        assert!(git.checkout("staging").unwrap());
+        assert!(git.merge("one", false).unwrap());
+        assert!(git.commit().unwrap());
+        assert!(!git.merge("two", false).unwrap());
+        assert!(git.has_conflicts());
+        assert_eq!(git.conflicted_paths(), vec![PathBuf::from("base.txt")]);
+        assert!(!git.commit().unwrap());
+    }
+
+    #[test]
+    fn unrelated_histories_are_refused() {
+        let repository = repository("unrelated");
+        commit(
+            &repository,
+            "refs/heads/orphan",
+            &[],
+            "o.txt",
+            "o\n",
+            "Orphan",
+        );
+        let git = InProcess {
+            repository: &repository,
+            base: "master",
+        };
+
+        assert!(git.checkout("staging").unwrap());
+        let error = git.merge("orphan", false).unwrap_err();
+        assert!(error.to_string().contains("shares no history"));
+        assert!(git.merge("orphan", true).unwrap());
+    }
+}
@@ -0,0 +1,294 @@
+use serde_derive::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io, process};
+
+use super::super::git_extras::Repo;
+use super::{
+    client, cost, graphql_url, in_stable_order, pull_requests, Error, PullRequest, Selection,
+    SendRecorded,
+};
+
+/// How many selections go into one request unless configured otherwise.
+/// GitHub limits the nodes a single query may ask for, so very large batches
+/// get rejected.
+pub const DEFAULT_BATCH_SIZE: usize = 10;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Fields {
+    number: i64,
+    title: String,
+    body: String,
+    head_ref_name: String,
+    base_ref_name: String,
+    is_cross_repository: bool,
+    author: Option<Author>,
+    labels: Option<Labels>,
+    is_draft: bool,
+    mergeable: String,
+    review_decision: Option<String>,
+    reviews: Option<Count>,
+    commits: Commits,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Count {
+    total_count: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Commits {
+    nodes: Option<Vec<Option<CommitNode>>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommitNode {
+    commit: Commit,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Commit {
+    status_check_rollup: Option<Rollup>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Rollup {
+    state: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Author {
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Labels {
+    nodes: Option<Vec<Option<Label>>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Label {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Connection {
+    nodes: Option<Vec<Option<Fields>>>,
+    page_info: PageInfo,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PageInfo {
+    has_next_page: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RateLimit {
+    cost: i64,
+    remaining: i64,
+    limit: i64,
+    node_count: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    data: Option<HashMap<String, Value>>,
+    errors: Option<Vec<QueryError>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueryError {
+    message: String,
+}
+
+/// The open pull requests for several repository and selection pairs,
+/// fetched `batch_size` at a time with one aliased GraphQL query per batch
+/// instead of one request each. Results are in the order of `requests`.
+///
+/// Without a token there is no GraphQL API, so each selection is fetched
+/// on its own through the REST fallback.
+pub fn pull_requests_batched(
+    token: Option<&str>,
+    requests: &[(&Repo, &Selection)],
+    batch_size: usize,
+) -> Result<Vec<Vec<PullRequest>>, Error> {
+    let token = match token {
+        Some(token) => token,
+        None => {
+            return requests
+                .iter()
+                .map(|(repo, selection)| pull_requests(None, repo, selection))
+                .collect()
+        }
+    };
+
+    // Label patterns, searches and lists don't fit in a repository query and
+    // are fetched on their own.
+    let mut results: Vec<Option<Vec<PullRequest>>> = vec![None; requests.len()];
+    let mut queried = vec![];
+    for (idx, (repo, selection)) in requests.iter().enumerate() {
+        match selection {
+            Selection::LabelPattern(_) | Selection::Search(_) | Selection::Listed(_) => {
+                results[idx] = Some(pull_requests(Some(token), repo, selection)?)
+            }
+            _ => queried.push((idx, *repo, *selection)),
+        }
+    }
+
+    let client = client()?;
+    for batch in queried.chunks(batch_size.max(1)) {
+        let query = query(batch);
+        let response: Response = client
+            .post(&graphql_url())
+            .bearer_auth(token)
+            .json(&serde_json::json!({ "query": query }))
+            .send_recorded()?
+            .error_for_status()?
+            .json()?;
+        // A failed alias fails the whole batch, or its selection would
+        // come back without pull requests and build a short branch.
+        if let Some(errors) = response.errors.filter(|errors| !errors.is_empty()) {
+            let messages: Vec<String> = errors.into_iter().map(|e| e.message).collect();
+            return Err(Error::GraphQL(messages.join("; ")));
+        }
+        let mut data = response
+            .data
+            .ok_or_else(|| Error::GraphQL("the response holds no data".to_string()))?;
+        if let Some(Ok(rate_limit)) = data
+            .remove("rateLimit")
+            .map(serde_json::from_value::<RateLimit>)
+        {
+            cost::record(
+                rate_limit.cost,
+                rate_limit.remaining,
+                rate_limit.limit,
+                rate_limit.node_count,
+            );
+        }
+
+        for (alias, (idx, repo, selection)) in batch.iter().enumerate() {
+            let repository = match data.remove(&format!("q{}", alias)) {
+                Some(Value::Null) | None => {
+                    return Err(Error::GraphQL(format!(
+                        "no answer for {}/{}",
+                        repo.owner, repo.name
+                    )))
+                }
+                Some(repository) => repository,
+            };
+            let connection = match selection {
+                // A milestone that doesn't exist has no pull requests.
+                Selection::Milestone(_) if repository["milestone"].is_null() => {
+                    results[*idx] = Some(vec![]);
+                    continue;
+                }
+                Selection::Milestone(_) => &repository["milestone"]["pullRequests"],
+                _ => &repository["pullRequests"],
+            };
+            let connection = serde_json::from_value::<Connection>(connection.clone())
+                .map_err(|e| Error::GraphQL(format!("{}/{}: {}", repo.owner, repo.name, e)))?;
+            // Longer lists are paged through on their own.
+            if connection.page_info.has_next_page {
+                results[*idx] = Some(pull_requests(Some(token), repo, selection)?);
+                continue;
+            }
+            results[*idx] = Some(
+                connection
+                    .nodes
+                    .unwrap_or_default()
+                    .into_iter()
+                    .flatten()
+                    .map(|fields| pull_request!(fields))
+                    .collect(),
+            );
+        }
+    }
+    Ok(results
+        .into_iter()
+        .map(|pull_requests| in_stable_order(pull_requests.unwrap_or_default()))
+        .collect())
+}
+
+/// One `q<N>` alias per selection, all sharing the `PullRequestFields`
+/// fragment from the regular queries.
+fn query(batch: &[(usize, &Repo, &Selection)]) -> String {
+    let mut query = String::from("query {\n  rateLimit { cost remaining limit nodeCount }\n");
+    for (idx, (_, repo, selection)) in batch.iter().enumerate() {
+        let pull_requests = match selection {
+            Selection::Milestone(milestone) => format!(
+                "milestone(number: {}) {{ pullRequests(states: OPEN, orderBy: {{field: CREATED_AT, direction: ASC}}, first: 50) {{ nodes {{ ...PullRequestFields }} pageInfo {{ hasNextPage }} }} }}",
+                milestone
+            ),
+            Selection::Label(label) => format!(
+                "pullRequests(states: OPEN, labels: [{}], orderBy: {{field: CREATED_AT, direction: ASC}}, first: 50) {{ nodes {{ ...PullRequestFields }} pageInfo {{ hasNextPage }} }}",
+                string(label)
+            ),
+            Selection::LabelPattern(_) | Selection::Search(_) | Selection::Listed(_) => {
+                unreachable!("label patterns, searches and lists are fetched separately")
+            }
+        };
+        query.push_str(&format!(
+            "  q{}: repository(owner: {}, name: {}) {{ {} }}\n",
+            idx,
+            string(&repo.owner),
+            string(&repo.name),
+            pull_requests
+        ));
+    }
+    query.push_str("}\n");
+    query.push_str(fragment());
+    query
+}
+
+/// GraphQL string literals use the same escapes as JSON.
+fn string(value: &str) -> String {
+    Value::String(value.to_string()).to_string()
+}
+
+fn fragment() -> &'static str {
+    let queries = include_str!("queries.graphql");
+    let start = queries
+        .find("fragment PullRequestFields")
+        .expect("PullRequestFields fragment");
+    let end = queries[start..].find("\n}\n").expect("end of fragment") + start + 3;
+    &queries[start..end]
+}
+
+/// Hands pull requests fetched in a batch to a `git-integrate` child process
+/// through a temporary file, read back with `load`.
+pub fn save(pull_requests: &[PullRequest], name: &str) -> io::Result<PathBuf> {
+    let path = env::temp_dir().join(format!(
+        "git-integrate-{}-{}.json",
+        process::id(),
+        name.replace(|c: char| !c.is_alphanumeric(), "-")
+    ));
+    fs::write(&path, serde_json::to_vec(pull_requests)?)?;
+    Ok(path)
+}
+
+pub fn load(path: &Path) -> io::Result<Vec<PullRequest>> {
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_are_quoted_for_graphql() {
+        let repo = Repo {
+            owner: "acme".to_string(),
+            name: "app".to_string(),
+        };
+        let selection = Selection::Label("🚀 say \"hi\" \\ bye".to_string());
+        let query = query(&[(0, &repo, &selection)]);
+        assert!(query.contains(r#"labels: ["🚀 say \"hi\" \\ bye"]"#));
+    }
+}
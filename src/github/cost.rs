@@ -0,0 +1,64 @@
+use serde_derive::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// What the GraphQL queries of a run cost against GitHub's rate limit.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct QueryCost {
+    pub queries: u64,
+    /// Points, out of the `limit` there are an hour.
+    pub cost: i64,
+    /// The nodes the queries could return, which the page sizes drive.
+    pub nodes: i64,
+    /// The points left after the last query.
+    pub remaining: i64,
+    pub limit: i64,
+}
+
+impl QueryCost {
+    pub fn describe(&self) -> String {
+        format!(
+            "{} GraphQL queries cost {} points for {} nodes, {} of {} points are left",
+            self.queries, self.cost, self.nodes, self.remaining, self.limit
+        )
+    }
+}
+
+static TOTAL: Mutex<QueryCost> = Mutex::new(QueryCost {
+    queries: 0,
+    cost: 0,
+    nodes: 0,
+    remaining: 0,
+    limit: 0,
+});
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Runs warn once fewer points than this share of the limit, in percent, are
+/// left.
+const LOW_POINTS: i64 = 10;
+
+/// Adds the `rateLimit` a query answered with to the run's total.
+pub fn record(cost: i64, remaining: i64, limit: i64, nodes: i64) {
+    let total = {
+        let mut total = TOTAL.lock().unwrap_or_else(|e| e.into_inner());
+        total.queries += 1;
+        total.cost += cost;
+        total.nodes += nodes;
+        total.remaining = remaining;
+        total.limit = limit;
+        *total
+    };
+    if remaining * 100 < limit * LOW_POINTS && !WARNED.swap(true, Ordering::SeqCst) {
+        warn!(
+            "Only {} of {} GraphQL points are left this hour and this run used {} so far; \
+             smaller pages or integrate.graphql-batch-size use fewer",
+            remaining, limit, total.cost
+        );
+    }
+}
+
+/// What the queries of this run cost so far, none when it made none.
+pub fn total() -> Option<QueryCost> {
+    let total = *TOTAL.lock().unwrap_or_else(|e| e.into_inner());
+    (total.queries > 0).then_some(total)
+}
@@ -1,6 +1,534 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
 use graphql_client::{GraphQLQuery, Response};
+use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, RedirectPolicy, Url};
+use serde_derive::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::{fmt, process};
 
-use super::git_extras::Repo;
+use super::git_extras::{remote_branch, Repo};
+use super::{bugreport, dates, temp_refs, timeouts};
+
+pub mod cost;
+pub mod rest;
+
+/// The REST API of github.com. GitHub Enterprise serves it at `/api/v3`.
+pub const DEFAULT_API_URL: &str = "https://api.github.com";
+
+static API_URL: OnceLock<String> = OnceLock::new();
+
+/// Headers sent with every API request, from `integrate.http-header`.
+static HEADERS: OnceLock<HeaderMap> = OnceLock::new();
+
+/// Sends `headers`, each written `Name: value`, with every API request from
+/// now on, for proxies that inspect them before letting requests through.
+pub fn set_headers(headers: &[String]) -> Result<(), String> {
+    let mut map = HeaderMap::new();
+    for header in headers {
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| format!("{} is not written Name: value", header))?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| format!("Invalid header name {}", name.trim()))?;
+        let value = HeaderValue::from_str(value.trim())
+            .map_err(|_| format!("Invalid value for header {}", name))?;
+        map.append(name, value);
+    }
+    HEADERS
+        .set(map)
+        .map_err(|_| "Headers were already set".to_string())
+}
+
+/// The only host requests may go to, from `--allowed-host`.
+static ALLOWED_HOST: OnceLock<String> = OnceLock::new();
+
+/// Refuses to send requests to, or follow redirects to, any host but `host`
+/// from now on.
+pub fn set_allowed_host(host: &str) -> Result<(), String> {
+    ALLOWED_HOST
+        .set(host.trim().to_ascii_lowercase())
+        .map_err(|_| "The allowed host was already set".to_string())
+}
+
+/// Stops the run rather than let a request out to another host than the
+/// allowed one.
+fn check_host(url: &Url) {
+    if let Some(allowed) = ALLOWED_HOST.get() {
+        if !url
+            .host_str()
+            .is_some_and(|host| host.eq_ignore_ascii_case(allowed))
+        {
+            error!(
+                "Refusing to connect to {}, only {} is allowed",
+                url.host_str().unwrap_or_else(|| url.as_str()),
+                allowed
+            );
+            process::exit(1);
+        }
+    }
+}
+
+/// Whether comments, labels, issues and the like are only logged instead of
+/// made, from `--no-api-writes`.
+static WRITES_BLOCKED: AtomicBool = AtomicBool::new(false);
+
+/// Lets every API read through but only logs the writes from now on.
+pub fn block_writes() {
+    WRITES_BLOCKED.store(true, Ordering::SeqCst);
+}
+
+pub fn writes_blocked() -> bool {
+    WRITES_BLOCKED.load(Ordering::SeqCst)
+}
+
+/// The least time between two writes, from `integrate.write-delay`.
+static WRITE_DELAY: OnceLock<Duration> = OnceLock::new();
+
+/// Spaces out comments, labels, statuses and the like by `delay` from now on,
+/// so runs touching dozens of pull requests stay clear of the secondary rate
+/// limits.
+pub fn set_write_delay(delay: Duration) -> Result<(), String> {
+    WRITE_DELAY
+        .set(delay)
+        .map_err(|_| "The write delay was already set".to_string())
+}
+
+fn write_delay() -> Duration {
+    WRITE_DELAY.get().copied().unwrap_or_default()
+}
+
+/// Talks to the API at `url` instead of github.com's from now on.
+pub fn set_api_url(url: &str) -> Result<(), String> {
+    API_URL
+        .set(url.trim_end_matches('/').to_string())
+        .map_err(|_| "The API URL was already set".to_string())
+}
+
+/// Tried instead of the API URL once that can't be reached, from
+/// `integrate.fallback-api-url`.
+static FALLBACK_API_URL: OnceLock<String> = OnceLock::new();
+static FAILED_OVER: AtomicBool = AtomicBool::new(false);
+
+/// Talks to the API at `url` from the first request that can't reach the
+/// usual one on, such as the passive side of a GitHub Enterprise pair.
+pub fn set_fallback_api_url(url: &str) -> Result<(), String> {
+    FALLBACK_API_URL
+        .set(url.trim_end_matches('/').to_string())
+        .map_err(|_| "The fallback API URL was already set".to_string())
+}
+
+/// The fallback API URL, once the run had to switch to it.
+pub fn failed_over() -> Option<&'static str> {
+    FAILED_OVER
+        .load(Ordering::SeqCst)
+        .then(|| FALLBACK_API_URL.get().map(String::as_str))
+        .flatten()
+}
+
+fn primary_api_url() -> &'static str {
+    API_URL.get().map(String::as_str).unwrap_or(DEFAULT_API_URL)
+}
+
+fn api_url() -> &'static str {
+    failed_over().unwrap_or_else(primary_api_url)
+}
+
+/// `url` on the `fallback` API instead of the `primary` one, GraphQL's
+/// included, which GitHub Enterprise serves next to `/v3`.
+fn fallback_url(url: &str, primary: &str, fallback: &str) -> Option<String> {
+    fn root(api: &str) -> &str {
+        api.strip_suffix("/v3").unwrap_or(api)
+    }
+    let path = url.strip_prefix(root(primary))?;
+    Some(format!("{}{}", root(fallback), path))
+}
+
+/// `request`, sent to the fallback API instead, the first time a request to
+/// the usual one doesn't get through.
+fn fail_over(mut request: reqwest::Request) -> Option<reqwest::Request> {
+    let fallback = FALLBACK_API_URL.get()?;
+    let primary = primary_api_url();
+    let url = Url::parse(&fallback_url(request.url().as_str(), primary, fallback)?).ok()?;
+    check_host(&url);
+    if !FAILED_OVER.swap(true, Ordering::SeqCst) {
+        warn!(
+            "The API at {} can't be reached, using {} for the rest of the run",
+            primary, fallback
+        );
+    }
+    *request.url_mut() = url;
+    Some(request)
+}
+
+/// GraphQL next to the REST API, `/api/graphql` on GitHub Enterprise.
+fn graphql_url() -> String {
+    let api = api_url();
+    format!("{}/graphql", api.strip_suffix("/v3").unwrap_or(api))
+}
+
+/// A client sending the configured headers, giving up on requests that take
+/// longer than `--http-timeout`.
+pub fn client() -> Result<Client, reqwest::Error> {
+    Client::builder()
+        .default_headers(HEADERS.get().cloned().unwrap_or_default())
+        .timeout(timeouts::http())
+        .redirect(RedirectPolicy::custom(|attempt| {
+            check_host(attempt.url());
+            RedirectPolicy::default().redirect(attempt)
+        }))
+        .build()
+}
+
+/// Why asking GitHub for pull requests failed.
+#[derive(Debug)]
+pub enum Error {
+    Http(reqwest::Error),
+    /// GitHub answered a GraphQL query with errors, or left out what it was
+    /// asked for, so what came back can't be taken for the whole answer.
+    GraphQL(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "{}", e),
+            Error::GraphQL(message) => write!(f, "GraphQL query failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Error {
+        Error::Http(e)
+    }
+}
+
+/// Sending a request, remembering the response for bug reports.
+pub trait SendRecorded {
+    fn send_recorded(self) -> Result<reqwest::Response, reqwest::Error>;
+}
+
+impl SendRecorded for reqwest::RequestBuilder {
+    fn send_recorded(self) -> Result<reqwest::Response, reqwest::Error> {
+        let request = self.try_clone().and_then(|request| request.build().ok());
+        if let Some(request) = &request {
+            check_host(request.url());
+        }
+        if let Err(expired) = timeouts::check_deadline() {
+            timeouts::expire(expired);
+        }
+        let response = match self.send() {
+            // Errors without a status never got an answer.
+            Err(e) if e.status().is_none() && (e.is_http() || e.is_timeout()) => {
+                match request.and_then(fail_over) {
+                    Some(request) => client()?.execute(request),
+                    None => Err(e),
+                }
+            }
+            response => response,
+        };
+        let response = match response {
+            Err(e) if e.is_timeout() => timeouts::expire(timeouts::http_expired()),
+            response => response?,
+        };
+        bugreport::record_response(&response);
+        check_expiry(&response);
+        explain_permissions(&response);
+        Ok(response)
+    }
+}
+
+/// When the token expires, on responses to requests made with one that does.
+const EXPIRATION_HEADER: &str = "github-authentication-token-expiration";
+
+/// The permissions a fine-grained token needs for the request, on responses
+/// to requests made with one.
+const PERMISSIONS_HEADER: &str = "x-accepted-github-permissions";
+
+/// How many days before the token expires runs start warning about it, unless
+/// `integrate.token-expiry-warning` says otherwise.
+const EXPIRY_WARNING_DAYS: i64 = 14;
+
+static EXPIRY_WARNING: OnceLock<i64> = OnceLock::new();
+static EXPIRY_CHECKED: AtomicBool = AtomicBool::new(false);
+
+/// Warns about tokens expiring within `days` from now on.
+pub fn set_expiry_warning(days: i64) -> Result<(), String> {
+    EXPIRY_WARNING
+        .set(days)
+        .map_err(|_| "The token expiry warning was already set".to_string())
+}
+
+/// Warns, once a run, when the token the response answers expires soon.
+fn check_expiry(response: &reqwest::Response) {
+    let expiration = match response
+        .headers()
+        .get(EXPIRATION_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(expiration) => expiration,
+        None => return,
+    };
+    if EXPIRY_CHECKED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let expires = match token_expiration(expiration) {
+        Some(expires) => expires,
+        None => return,
+    };
+    let days = (expires - Utc::now()).num_days();
+    if days < *EXPIRY_WARNING.get().unwrap_or(&EXPIRY_WARNING_DAYS) {
+        warn!(
+            "integrate.github-token expires on {}, in {} days; renew it before runs start failing",
+            dates::date_time(&expires),
+            days
+        );
+    }
+}
+
+/// The expiration header, written `2024-06-01 08:22:01 UTC` or with an
+/// offset instead of `UTC`.
+fn token_expiration(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S %z")
+        .map(|time| time.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(value.trim_end_matches("UTC").trim(), "%Y-%m-%d %H:%M:%S")
+                .map(|time| time.and_utc())
+        })
+        .ok()
+}
+
+/// Says which permissions a fine-grained token was refused for lacking.
+fn explain_permissions(response: &reqwest::Response) {
+    if response.status() != reqwest::StatusCode::FORBIDDEN {
+        return;
+    }
+    if let Some(permissions) = response
+        .headers()
+        .get(PERMISSIONS_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|permissions| !permissions.trim().is_empty())
+    {
+        error!(
+            "integrate.github-token lacks the permissions {} needs: {}",
+            response.url().path(),
+            describe_permissions(permissions)
+        );
+    }
+}
+
+/// `contents=read; pull_requests=write,issues=write`, any one of the sets
+/// separated by `;` being enough, as `Contents: read, or Pull requests: write
+/// and Issues: write`.
+fn describe_permissions(permissions: &str) -> String {
+    permissions
+        .split(';')
+        .map(|set| {
+            set.split(',')
+                .filter_map(|permission| {
+                    let (name, access) = permission.trim().split_once('=')?;
+                    let mut name = name.replace('_', " ");
+                    if let Some(first) = name.get(..1) {
+                        name = first.to_uppercase() + &name[1..];
+                    }
+                    Some(format!("{}: {}", name, access))
+                })
+                .collect::<Vec<_>>()
+                .join(" and ")
+        })
+        .filter(|set| !set.is_empty())
+        .collect::<Vec<_>>()
+        .join(", or ")
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PullRequest {
+    pub number: i64,
+    pub title: String,
+    pub body: String,
+    pub branch: String,
+    pub base: String,
+    /// What gets merged for this pull request, its `head_ref` unless the
+    /// branch had to be rewritten locally first.
+    pub revision: String,
+    pub author: Option<String>,
+    pub labels: Vec<String>,
+    #[serde(default)]
+    pub draft: bool,
+    /// Reviews, checks and mergeability, unless the API the pull request came
+    /// from doesn't tell.
+    #[serde(default)]
+    pub status: Option<Status>,
+    /// The ref origin serves the head under when the branch is in a fork,
+    /// such as `refs/pull/12/head`.
+    #[serde(default)]
+    pub fork_ref: Option<String>,
+}
+
+/// Whether a pull request is ready to be integrated, as GitHub sees it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Status {
+    /// `APPROVED`, `CHANGES_REQUESTED` or `REVIEW_REQUIRED`, none when the
+    /// base branch doesn't require reviews.
+    pub review_decision: Option<String>,
+    pub approvals: i64,
+    /// The combined state of the checks and commit statuses of the head, such
+    /// as `SUCCESS` or `FAILURE`, none when nothing ran on it.
+    pub checks: Option<String>,
+    /// `MERGEABLE`, `CONFLICTING` or `UNKNOWN` while GitHub works it out.
+    pub mergeable: String,
+}
+
+impl Status {
+    /// Approved, or approved by someone when no review is required.
+    pub fn is_approved(&self) -> bool {
+        match self.review_decision.as_deref() {
+            Some(decision) => decision == "APPROVED",
+            None => self.approvals > 0,
+        }
+    }
+}
+
+/// The name of a GraphQL enum value, from the generated enums as well as
+/// plain strings.
+fn enum_name<T: serde::Serialize>(value: T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+/// Whether two label names are the same to GitHub, which ignores case, and
+/// to people, who can't tell an emoji with a variation selector from one
+/// without.
+pub fn same_label(a: &str, b: &str) -> bool {
+    let normalize = |label: &str| -> String {
+        label
+            .trim()
+            .chars()
+            .filter(|c| !matches!(c, '\u{FE0E}' | '\u{FE0F}'))
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+    normalize(a) == normalize(b)
+}
+
+/// Where the head of a pull request is fetched to: the remote-tracking branch,
+/// or a temporary ref for one from a fork.
+pub fn head_ref(number: i64, branch: &str, fork: bool) -> String {
+    if fork {
+        temp_refs::pull_ref(number)
+    } else {
+        remote_branch(branch)
+    }
+}
+
+impl PullRequest {
+    pub fn head_ref(&self) -> String {
+        head_ref(self.number, &self.branch, self.fork_ref.is_some())
+    }
+
+    pub fn has_label(&self, name: &str) -> bool {
+        self.labels.iter().any(|label| same_label(label, name))
+    }
+
+    /// Whether the pull request opts into `directive`, either with an
+    /// `integrate:<directive>` label or a `/integrate <directive>` line in its
+    /// description.
+    pub fn has_directive(&self, directive: &str) -> bool {
+        let label = format!("integrate:{}", directive);
+        self.has_label(&label)
+            || self.body.lines().any(|line| {
+                let mut words = line.split_whitespace();
+                words.next() == Some("/integrate") && words.any(|w| w == directive)
+            })
+    }
+}
+
+/// Every query selects the `PullRequestFields` fragment, but each one gets its
+/// own generated copy of it, so the conversion is shared as a macro instead.
+macro_rules! pull_request {
+    ($fields:expr) => {{
+        let fields = $fields;
+        let fork_ref = fields
+            .is_cross_repository
+            .then(|| format!("refs/pull/{}/head", fields.number));
+        PullRequest {
+            number: fields.number,
+            title: fields.title,
+            body: fields.body,
+            revision: crate::github::head_ref(
+                fields.number,
+                &fields.head_ref_name,
+                fields.is_cross_repository,
+            ),
+            branch: fields.head_ref_name,
+            base: fields.base_ref_name,
+            author: fields.author.map(|a| a.login),
+            labels: fields
+                .labels
+                .and_then(|l| l.nodes)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|l| l.map(|l| l.name))
+                .collect(),
+            draft: fields.is_draft,
+            fork_ref,
+            status: Some(crate::github::Status {
+                review_decision: fields.review_decision.map(crate::github::enum_name),
+                approvals: fields.reviews.map_or(0, |reviews| reviews.total_count),
+                checks: fields
+                    .commits
+                    .nodes
+                    .unwrap_or_default()
+                    .into_iter()
+                    .flatten()
+                    .last()
+                    .and_then(|node| node.commit.status_check_rollup)
+                    .map(|rollup| crate::github::enum_name(rollup.state)),
+                mergeable: crate::github::enum_name(fields.mergeable),
+            }),
+        }
+    }};
+}
+
+/// Each query gets its own copy of the `RateLimitFields` fragment as well.
+macro_rules! record_cost {
+    ($response:expr) => {
+        if let Some(rate_limit) = $response
+            .data
+            .as_ref()
+            .and_then(|data| data.rate_limit_fields.rate_limit.as_ref())
+        {
+            crate::github::cost::record(
+                rate_limit.cost,
+                rate_limit.remaining,
+                rate_limit.limit,
+                rate_limit.node_count,
+            );
+        }
+    };
+}
+
+pub mod batch;
+
+/// The data of a GraphQL response. GitHub may answer with errors next to
+/// partial data, which is as much a failure as no data at all.
+fn checked<T>(response: Response<T>) -> Result<T, Error> {
+    if let Some(errors) = response.errors.filter(|errors| !errors.is_empty()) {
+        let messages: Vec<String> = errors.into_iter().map(|e| e.message).collect();
+        return Err(Error::GraphQL(messages.join("; ")));
+    }
+    response
+        .data
+        .ok_or_else(|| Error::GraphQL("the response holds no data".to_string()))
+}
 
 #[derive(GraphQLQuery)]
 #[graphql(
@@ -18,68 +546,676 @@ pub struct LabelBranches;
 )]
 pub struct MilestoneBranches;
 
-#[allow(dead_code)]
-pub fn branches_by_pr_label(
-    token: String,
-    repo: Repo,
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/github/schema.json",
+    query_path = "src/github/queries.graphql",
+    response_derives = "Debug,Clone"
+)]
+pub struct HeadBranch;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/github/schema.json",
+    query_path = "src/github/queries.graphql",
+    response_derives = "Debug,Clone"
+)]
+pub struct SearchBranches;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/github/schema.json",
+    query_path = "src/github/queries.graphql",
+    response_derives = "Debug,Clone"
+)]
+pub struct PullRequestByNumber;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/github/schema.json",
+    query_path = "src/github/queries.graphql",
+    response_derives = "Debug,Clone"
+)]
+pub struct ReviewThreads;
+
+/// Which pull requests to integrate.
+#[derive(Debug, Clone)]
+pub enum Selection {
+    Milestone(i64),
+    Label(String),
+    /// Every label the whole name of which matches the expression.
+    LabelPattern(Box<Regex>),
+    /// A GitHub search expression, limited to open pull requests of the
+    /// repository.
+    Search(String),
+    /// Pull request numbers or head branches, as piped to `--stdin`.
+    Listed(Vec<String>),
+}
+
+impl Selection {
+    /// Numbers are milestones, anything else is a label name. Spaces around
+    /// it, as left by quoting, are dropped.
+    pub fn parse(value: &str) -> Selection {
+        let value = value.trim();
+        match value.parse() {
+            Ok(number) => Selection::Milestone(number),
+            Err(_) => Selection::Label(value.to_string()),
+        }
+    }
+
+    pub fn label_pattern(pattern: &str) -> Result<Selection, regex::Error> {
+        Regex::new(&format!("^(?:{})$", pattern)).map(|re| Selection::LabelPattern(Box::new(re)))
+    }
+
+    /// Whether giving a pull request the label `name` selects it.
+    pub fn picks_label(&self, name: &str) -> bool {
+        match self {
+            Selection::Label(label) => same_label(label, name),
+            Selection::LabelPattern(pattern) => pattern.is_match(name),
+            _ => false,
+        }
+    }
+}
+
+pub fn pull_requests(
+    token: Option<&str>,
+    repo: &Repo,
+    selection: &Selection,
+) -> Result<Vec<PullRequest>, Error> {
+    let pull_requests = match selection {
+        Selection::Milestone(milestone) => pull_requests_by_milestone(token, repo, *milestone)?,
+        Selection::Label(label) => pull_requests_by_label(token, repo, label.clone())?,
+        Selection::LabelPattern(pattern) => pull_requests_by_label_pattern(token, repo, pattern)?,
+        Selection::Search(query) => pull_requests_by_search(token, repo, query)?,
+        Selection::Listed(items) => listed_pull_requests(token, repo, items)?,
+    };
+    Ok(in_stable_order(pull_requests))
+}
+
+/// Oldest first by number, each pull request once, however the pages came
+/// back. Every query asks for creation order already, this keeps identical
+/// runs identical should the server not.
+pub fn in_stable_order(mut pull_requests: Vec<PullRequest>) -> Vec<PullRequest> {
+    pull_requests.sort_by_key(|pr| pr.number);
+    pull_requests.dedup_by_key(|pr| pr.number);
+    pull_requests
+}
+
+/// A pull request of `branch` into master with nothing more to it, for the
+/// self-test to list and for tests to build on.
+pub fn pull_request(number: i64, branch: &str) -> PullRequest {
+    PullRequest {
+        number,
+        title: format!("Change {}", number),
+        body: String::new(),
+        branch: branch.to_string(),
+        base: "master".to_string(),
+        revision: format!("origin/{}", branch),
+        author: None,
+        labels: vec![],
+        draft: false,
+        status: None,
+        fork_ref: None,
+    }
+}
+
+/// When pull request `number` was last given the label or milestone that
+/// `selection` picks it by. None for other selections, or if GitHub doesn't
+/// tell.
+pub fn selected_at(
+    token: Option<&str>,
+    repo: &Repo,
+    number: i64,
+    selection: &Selection,
+) -> Result<Option<DateTime<Utc>>, reqwest::Error> {
+    if let Selection::Search(_) | Selection::Listed(_) = selection {
+        return Ok(None);
+    }
+    Ok(rest::issue_events(token, repo, number)?
+        .into_iter()
+        .rev()
+        .find(|event| match (&event.event[..], selection) {
+            ("milestoned", Selection::Milestone(_)) => true,
+            ("labeled", _) => event
+                .label
+                .as_ref()
+                .is_some_and(|label| selection.picks_label(&label.name)),
+            _ => false,
+        })
+        .and_then(|event| DateTime::parse_from_rfc3339(&event.created_at).ok())
+        .map(|time| time.with_timezone(&Utc)))
+}
+
+/// The open pull request each item names, by number or head branch. Items
+/// naming none are left out with a warning.
+pub fn listed_pull_requests(
+    token: Option<&str>,
+    repo: &Repo,
+    items: &[String],
+) -> Result<Vec<PullRequest>, reqwest::Error> {
+    // Without a token every open pull request is listed once instead.
+    let open = match token {
+        Some(_) => vec![],
+        None => rest::open_pull_requests(None, repo)?,
+    };
+    let mut pull_requests: Vec<PullRequest> = vec![];
+    for item in items {
+        let number = item.trim_start_matches('#').parse::<i64>().ok();
+        let found = match (token, number) {
+            (Some(token), Some(number)) => pull_request_by_number(token, repo, number)?,
+            (Some(_), None) => pull_request_by_head(token, repo, item)?,
+            (None, _) => open
+                .iter()
+                .find(|pull| number.map_or(pull.head.name == *item, |number| pull.number == number))
+                .cloned()
+                .map(PullRequest::from),
+        };
+        match found {
+            Some(pr)
+                if !pull_requests
+                    .iter()
+                    .any(|listed| listed.number == pr.number) =>
+            {
+                pull_requests.push(pr)
+            }
+            Some(_) => (),
+            None => warn!("No open pull request for {}", item),
+        }
+    }
+    Ok(pull_requests)
+}
+
+/// The search expression limited to the open pull requests of `repo`,
+/// oldest first unless it asks for another order.
+fn search_query(repo: &Repo, query: &str) -> String {
+    let mut full = format!("repo:{}/{} is:pr is:open {}", repo.owner, repo.name, query);
+    if !query
+        .split_whitespace()
+        .any(|term| term.starts_with("sort:"))
+    {
+        full.push_str(" sort:created-asc");
+    }
+    full
+}
+
+pub fn pull_requests_by_search(
+    token: Option<&str>,
+    repo: &Repo,
+    query: &str,
+) -> Result<Vec<PullRequest>, Error> {
+    let query = search_query(repo, query);
+    let token = match token {
+        Some(token) => token,
+        None => {
+            // REST search results are issues without their branches.
+            let numbers = rest::search_issues(None, &query)?;
+            let mut pulls: Vec<rest::Pull> = rest::open_pull_requests(None, repo)?
+                .into_iter()
+                .filter(|pull| numbers.contains(&pull.number))
+                .collect();
+            pulls.sort_by_key(|pull| numbers.iter().position(|n| *n == pull.number));
+            return Ok(pulls.into_iter().map(PullRequest::from).collect());
+        }
+    };
+
+    let client = client()?;
+    let mut pull_requests = vec![];
+    let mut after = None;
+    loop {
+        let q = SearchBranches::build_query(search_branches::Variables {
+            query: query.clone(),
+            after,
+        });
+
+        let mut res = client
+            .post(&graphql_url())
+            .bearer_auth(token)
+            .json(&q)
+            .send_recorded()?;
+
+        let response: Response<search_branches::ResponseData> = res.json()?;
+
+        record_cost!(response);
+        let search = checked(response)?.search;
+
+        pull_requests.extend(
+            search
+                .nodes
+                .unwrap_or_default()
+                .into_iter()
+                .flatten()
+                .filter_map(|node| match node {
+                    search_branches::SearchBranchesSearchNodes::PullRequest(y) => {
+                        Some(pull_request!(y.pull_request_fields))
+                    }
+                    _ => None,
+                }),
+        );
+        match (search.page_info.has_next_page, search.page_info.end_cursor) {
+            (true, Some(cursor)) => after = Some(cursor),
+            _ => break,
+        }
+    }
+    Ok(pull_requests)
+}
+
+/// GraphQL can only filter by exact label names, so this goes through every
+/// open pull request with the REST API instead.
+pub fn pull_requests_by_label_pattern(
+    token: Option<&str>,
+    repo: &Repo,
+    pattern: &Regex,
+) -> Result<Vec<PullRequest>, reqwest::Error> {
+    Ok(rest::open_pull_requests(token, repo)?
+        .into_iter()
+        .filter(|pull| pull.labels.iter().any(|l| pattern.is_match(&l.name)))
+        .map(PullRequest::from)
+        .collect())
+}
+
+pub fn pull_requests_by_label(
+    token: Option<&str>,
+    repo: &Repo,
     label: String,
-) -> Result<Vec<String>, reqwest::Error> {
-    let q = LabelBranches::build_query(label_branches::Variables {
-        owner: repo.owner,
-        name: repo.name,
-        label: label,
+) -> Result<Vec<PullRequest>, Error> {
+    let token = match token {
+        Some(token) => token,
+        None => {
+            return Ok(rest::open_pull_requests(None, repo)?
+                .into_iter()
+                .filter(|pull| pull.labels.iter().any(|l| same_label(&l.name, &label)))
+                .map(PullRequest::from)
+                .collect())
+        }
+    };
+
+    let client = client()?;
+    let mut pull_requests = vec![];
+    let mut after = None;
+    loop {
+        let q = LabelBranches::build_query(label_branches::Variables {
+            owner: repo.owner.clone(),
+            name: repo.name.clone(),
+            label: label.clone(),
+            after,
+        });
+
+        let mut res = client
+            .post(&graphql_url())
+            .bearer_auth(token)
+            .json(&q)
+            .send_recorded()?;
+
+        let response: Response<label_branches::ResponseData> = res.json()?;
+
+        record_cost!(response);
+        let connection = match checked(response)?.repository {
+            Some(repository) => repository.pull_requests,
+            None => {
+                return Err(Error::GraphQL(format!(
+                    "no repository {}/{}",
+                    repo.owner, repo.name
+                )))
+            }
+        };
+
+        pull_requests.extend(
+            connection
+                .nodes
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|x| x.map(|y| pull_request!(y.pull_request_fields))),
+        );
+        match (
+            connection.page_info.has_next_page,
+            connection.page_info.end_cursor,
+        ) {
+            (true, Some(cursor)) => after = Some(cursor),
+            _ => break,
+        }
+    }
+    Ok(pull_requests)
+}
+
+pub fn pull_requests_by_milestone(
+    token: Option<&str>,
+    repo: &Repo,
+    milestone: i64,
+) -> Result<Vec<PullRequest>, Error> {
+    let token = match token {
+        Some(token) => token,
+        None => {
+            return Ok(rest::open_pull_requests(None, repo)?
+                .into_iter()
+                .filter(|pull| pull.milestone.as_ref().map(|m| m.number) == Some(milestone))
+                .map(PullRequest::from)
+                .collect())
+        }
+    };
+
+    let client = client()?;
+    let mut pull_requests = vec![];
+    let mut after = None;
+    loop {
+        let q = MilestoneBranches::build_query(milestone_branches::Variables {
+            owner: repo.owner.clone(),
+            name: repo.name.clone(),
+            milestone,
+            after,
+        });
+
+        let mut res = client
+            .post(&graphql_url())
+            .bearer_auth(token)
+            .json(&q)
+            .send_recorded()?;
+
+        let response: Response<milestone_branches::ResponseData> = res.json()?;
+
+        record_cost!(response);
+        let repository = checked(response)?
+            .repository
+            .ok_or_else(|| Error::GraphQL(format!("no repository {}/{}", repo.owner, repo.name)))?;
+        let connection = match repository.milestone {
+            Some(milestone) => milestone.pull_requests,
+            // A milestone that doesn't exist has no pull requests.
+            None => break,
+        };
+
+        pull_requests.extend(
+            connection
+                .nodes
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|x| x.map(|y| pull_request!(y.pull_request_fields))),
+        );
+        match (
+            connection.page_info.has_next_page,
+            connection.page_info.end_cursor,
+        ) {
+            (true, Some(cursor)) => after = Some(cursor),
+            _ => break,
+        }
+    }
+    Ok(pull_requests)
+}
+
+/// How many review threads of pull request `number` are not resolved. Only
+/// `--require-resolved-threads` asks, so the other queries leave them out.
+pub fn unresolved_threads(token: &str, repo: &Repo, number: i64) -> Result<usize, Error> {
+    let client = client()?;
+    let mut unresolved = 0;
+    let mut after = None;
+
+    loop {
+        let q = ReviewThreads::build_query(review_threads::Variables {
+            owner: repo.owner.clone(),
+            name: repo.name.clone(),
+            number,
+            after: after.take(),
+        });
+
+        let mut res = client
+            .post(&graphql_url())
+            .bearer_auth(token)
+            .json(&q)
+            .send_recorded()?;
+
+        let response: Response<review_threads::ResponseData> = res.json()?;
+
+        record_cost!(response);
+
+        let threads = checked(response)?
+            .repository
+            .and_then(|x| x.pull_request)
+            .ok_or_else(|| Error::GraphQL(format!("no pull request #{}", number)))?
+            .review_threads;
+        unresolved += threads
+            .nodes
+            .unwrap_or_default()
+            .into_iter()
+            .flatten()
+            .filter(|thread| !thread.is_resolved)
+            .count();
+        match (
+            threads.page_info.has_next_page,
+            threads.page_info.end_cursor,
+        ) {
+            (true, Some(cursor)) => after = Some(cursor),
+            _ => break,
+        }
+    }
+    Ok(unresolved)
+}
+
+/// The reviews, checks and mergeability of pull request `number`, for pull
+/// requests that came from the REST API.
+pub fn status(token: &str, repo: &Repo, number: i64) -> Result<Option<Status>, reqwest::Error> {
+    Ok(pull_request_by_number(token, repo, number)?.and_then(|pr| pr.status))
+}
+
+/// Pull request `number`, if it is open.
+pub fn pull_request_by_number(
+    token: &str,
+    repo: &Repo,
+    number: i64,
+) -> Result<Option<PullRequest>, reqwest::Error> {
+    let q = PullRequestByNumber::build_query(pull_request_by_number::Variables {
+        owner: repo.owner.clone(),
+        name: repo.name.clone(),
+        number,
     });
 
-    let client = reqwest::Client::new();
+    let client = client()?;
 
     let mut res = client
-        .post("https://api.github.com/graphql")
+        .post(&graphql_url())
         .bearer_auth(token)
         .json(&q)
-        .send()?;
+        .send_recorded()?;
+
+    let response: Response<pull_request_by_number::ResponseData> = res.json()?;
 
-    let response: Response<label_branches::ResponseData> = res.json()?;
+    record_cost!(response);
 
     Ok(response
         .data
         .and_then(|x| x.repository)
-        .and_then(|x| x.pull_requests.nodes)
-        .unwrap_or(vec![])
-        .iter()
-        .cloned()
-        .filter_map(|x| x.map(|y| y.head_ref_name))
-        .collect())
+        .and_then(|x| x.pull_request)
+        .filter(|y| matches!(y.state, pull_request_by_number::PullRequestState::OPEN))
+        .map(|y| pull_request!(y.pull_request_fields)))
 }
 
-pub fn branches_by_milestone(
-    token: String,
-    repo: Repo,
-    milestone: i64,
-) -> Result<Vec<String>, reqwest::Error> {
-    let q = MilestoneBranches::build_query(milestone_branches::Variables {
-        owner: repo.owner,
-        name: repo.name,
-        milestone: milestone,
+/// The open pull request whose head is `branch`, if there is one.
+pub fn pull_request_by_head(
+    token: Option<&str>,
+    repo: &Repo,
+    branch: &str,
+) -> Result<Option<PullRequest>, reqwest::Error> {
+    let token = match token {
+        Some(token) => token,
+        None => {
+            return Ok(rest::open_pull_requests(None, repo)?
+                .into_iter()
+                .find(|pull| pull.head.name == branch)
+                .map(PullRequest::from))
+        }
+    };
+
+    let q = HeadBranch::build_query(head_branch::Variables {
+        owner: repo.owner.clone(),
+        name: repo.name.clone(),
+        head: branch.to_string(),
     });
 
-    let client = reqwest::Client::new();
+    let client = client()?;
 
     let mut res = client
-        .post("https://api.github.com/graphql")
+        .post(&graphql_url())
         .bearer_auth(token)
         .json(&q)
-        .send()?;
+        .send_recorded()?;
+
+    let response: Response<head_branch::ResponseData> = res.json()?;
 
-    let response: Response<milestone_branches::ResponseData> = res.json()?;
+    record_cost!(response);
 
     Ok(response
         .data
         .and_then(|x| x.repository)
-        .and_then(|x| x.milestone)
         .and_then(|x| x.pull_requests.nodes)
         .unwrap_or(vec![])
-        .iter()
-        .cloned()
-        .filter_map(|x| x.map(|y| y.head_ref_name))
-        .collect())
+        .into_iter()
+        .flatten()
+        .map(|y| pull_request!(y.pull_request_fields))
+        .next())
+}
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/github/schema.json",
+    query_path = "src/github/queries.graphql",
+    response_derives = "Debug,Clone"
+)]
+pub struct ViewerPermission;
+
+/// Why the user of `token` can't push to `repo`, if they can't, from their
+/// permission on it. Only reads, so a run finds out before it builds anything.
+pub fn push_denied(token: &str, repo: &Repo) -> Result<Option<String>, reqwest::Error> {
+    use viewer_permission::RepositoryPermission;
+
+    let q = ViewerPermission::build_query(viewer_permission::Variables {
+        owner: repo.owner.clone(),
+        name: repo.name.clone(),
+    });
+    let mut res = client()?
+        .post(&graphql_url())
+        .bearer_auth(token)
+        .json(&q)
+        .send_recorded()?
+        .error_for_status()?;
+    let response: Response<viewer_permission::ResponseData> = res.json()?;
+    record_cost!(response);
+    let permission = response
+        .data
+        .and_then(|data| data.repository)
+        .and_then(|repository| repository.viewer_permission);
+    let level = match permission {
+        Some(RepositoryPermission::ADMIN)
+        | Some(RepositoryPermission::MAINTAIN)
+        | Some(RepositoryPermission::WRITE) => return Ok(None),
+        Some(RepositoryPermission::TRIAGE) => "triage",
+        Some(RepositoryPermission::READ) => "read",
+        // A permission GitHub added since is not ours to judge.
+        Some(RepositoryPermission::Other(_)) => return Ok(None),
+        None => "no",
+    };
+    Ok(Some(format!(
+        "The user of integrate.github-token has {} access to {}/{} and can't push \
+         \nto it, so the run would fail at the very end; ask for write access or \
+         \nleave out --push",
+        level, repo.owner, repo.name
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_expiration_is_read_in_utc_or_with_an_offset() {
+        let utc = token_expiration("2024-06-01 08:22:01 UTC").unwrap();
+        assert_eq!(utc.to_rfc3339(), "2024-06-01T08:22:01+00:00");
+        let offset = token_expiration("2024-06-01 08:22:01 -0700").unwrap();
+        assert_eq!(offset.to_rfc3339(), "2024-06-01T15:22:01+00:00");
+        assert!(token_expiration("soon").is_none());
+    }
+
+    #[test]
+    fn fine_grained_permissions_are_spelled_as_on_github() {
+        assert_eq!(
+            describe_permissions("contents=read; pull_requests=write,issues=write"),
+            "Contents: read, or Pull requests: write and Issues: write"
+        );
+    }
+
+    #[test]
+    fn fallback_urls_keep_the_path() {
+        let (primary, fallback) = (
+            "https://ghe.example.com/api/v3",
+            "https://ghe-dr.example.com/api/v3",
+        );
+        assert_eq!(
+            fallback_url(
+                "https://ghe.example.com/api/v3/repos/acme/app/pulls",
+                primary,
+                fallback
+            )
+            .as_deref(),
+            Some("https://ghe-dr.example.com/api/v3/repos/acme/app/pulls")
+        );
+        assert_eq!(
+            fallback_url("https://ghe.example.com/api/graphql", primary, fallback).as_deref(),
+            Some("https://ghe-dr.example.com/api/graphql")
+        );
+        assert_eq!(
+            fallback_url("https://uploads.example.com/x", primary, fallback),
+            None
+        );
+    }
+
+    #[test]
+    fn labels_with_emoji_and_spaces_are_labels() {
+        match Selection::parse("🚀 deploy to staging") {
+            Selection::Label(label) => assert_eq!(label, "🚀 deploy to staging"),
+            other => panic!("{:?}", other),
+        }
+        match Selection::parse("  déploiement: qa  ") {
+            Selection::Label(label) => assert_eq!(label, "déploiement: qa"),
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(Selection::parse(" 12 "), Selection::Milestone(12)));
+    }
+
+    #[test]
+    fn labels_compare_like_github() {
+        assert!(same_label("🚀 Deploy to Staging", "🚀 deploy to staging"));
+        assert!(same_label("❤\u{FE0F} ship it", "❤ ship it"));
+        assert!(same_label("ÜBER", "über"));
+        assert!(!same_label("🚀 deploy to staging", "deploy to staging"));
+    }
+
+    #[test]
+    fn variables_keep_labels_as_given() {
+        let q = LabelBranches::build_query(label_branches::Variables {
+            owner: "acme".to_string(),
+            name: "app".to_string(),
+            label: "🚀 \"deploy\" & staging\\".to_string(),
+            after: None,
+        });
+        let json = serde_json::to_value(&q).unwrap();
+        assert_eq!(json["variables"]["label"], "🚀 \"deploy\" & staging\\");
+    }
+
+    #[test]
+    fn pages_that_overlap_or_come_back_shuffled_merge_the_same() {
+        let pr = |number: i64| pull_request(number, &format!("b{}", number));
+        let numbers = |prs: Vec<PullRequest>| prs.iter().map(|pr| pr.number).collect::<Vec<_>>();
+        assert_eq!(
+            numbers(in_stable_order(vec![pr(7), pr(3), pr(5), pr(3)])),
+            [3, 5, 7]
+        );
+    }
+
+    #[test]
+    fn label_patterns_match_unicode_labels() {
+        let selection = Selection::label_pattern("🚀 deploy:(qa|staging)").unwrap();
+        match selection {
+            Selection::LabelPattern(pattern) => {
+                assert!(pattern.is_match("🚀 deploy:qa"));
+                assert!(!pattern.is_match("🚀 deploy:qa-eu"));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
 }
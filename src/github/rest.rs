@@ -0,0 +1,1010 @@
+use reqwest::header::{RETRY_AFTER, USER_AGENT};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
+use serde_derive::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::super::git_extras::Repo;
+use super::{api_url, client, head_ref, write_delay, writes_blocked, PullRequest, SendRecorded};
+
+/// How often a write GitHub rate limited is tried again.
+const WRITE_RETRIES: usize = 3;
+
+/// Waits GitHub asks for beyond this fail the write instead.
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(15 * 60);
+
+/// When the last write was sent, to space them out.
+static LAST_WRITE: Mutex<Option<Instant>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct User {
+    pub login: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Issue {
+    pub number: i64,
+    pub title: String,
+    pub html_url: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Comment {
+    pub id: i64,
+    pub user: User,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Reaction {
+    pub user: User,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Label {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Milestone {
+    pub number: i64,
+    #[serde(default)]
+    pub title: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IssueEvent {
+    pub event: String,
+    pub created_at: String,
+    #[serde(default)]
+    pub label: Option<Label>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Ref {
+    #[serde(rename = "ref")]
+    pub name: String,
+    /// None once the fork it was in is deleted.
+    #[serde(default)]
+    pub repo: Option<Repository>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Pull {
+    pub number: i64,
+    pub title: String,
+    pub body: Option<String>,
+    pub head: Ref,
+    pub base: Ref,
+    pub user: Option<User>,
+    pub labels: Vec<Label>,
+    pub milestone: Option<Milestone>,
+    #[serde(default)]
+    pub draft: bool,
+}
+
+impl From<Pull> for PullRequest {
+    fn from(pull: Pull) -> PullRequest {
+        let fork = pull.head.repo.as_ref().map(|repo| &repo.clone_url)
+            != pull.base.repo.as_ref().map(|repo| &repo.clone_url);
+        let fork_ref = fork.then(|| format!("refs/pull/{}/head", pull.number));
+        PullRequest {
+            number: pull.number,
+            title: pull.title,
+            body: pull.body.unwrap_or_default(),
+            revision: head_ref(pull.number, &pull.head.name, fork),
+            branch: pull.head.name,
+            base: pull.base.name,
+            author: pull.user.map(|u| u.login),
+            labels: pull.labels.into_iter().map(|l| l.name).collect(),
+            draft: pull.draft,
+            status: None,
+            fork_ref,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Repository {
+    pub name: String,
+    pub clone_url: String,
+    pub archived: bool,
+    pub default_branch: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct SearchResults<T> {
+    items: Vec<T>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct IssueNumber {
+    number: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Membership {
+    state: String,
+}
+
+#[derive(Serialize)]
+struct NewIssue<'a> {
+    title: &'a str,
+    body: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    assignees: &'a [String],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    labels: &'a [String],
+}
+
+#[derive(Serialize)]
+struct NewComment<'a> {
+    body: &'a str,
+}
+
+#[derive(Serialize)]
+struct NewStatus<'a> {
+    state: &'a str,
+    context: &'a str,
+    description: &'a str,
+}
+
+#[derive(Serialize)]
+struct NewReview<'a> {
+    event: &'a str,
+    body: &'a str,
+}
+
+#[derive(Serialize)]
+struct NewLabels<'a> {
+    labels: &'a [String],
+}
+
+#[derive(Serialize)]
+struct NewLabel<'a> {
+    name: &'a str,
+    color: &'a str,
+    description: &'a str,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct GitObject {
+    sha: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct GitRef {
+    object: GitObject,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct GitCommit {
+    tree: GitObject,
+}
+
+#[derive(Serialize)]
+struct TreeEntry<'a> {
+    path: &'a str,
+    mode: &'a str,
+    #[serde(rename = "type")]
+    kind: &'a str,
+    sha: &'a str,
+}
+
+#[derive(Serialize)]
+struct NewTree<'a> {
+    base_tree: &'a str,
+    tree: &'a [TreeEntry<'a>],
+}
+
+#[derive(Serialize)]
+struct NewCommit<'a> {
+    message: &'a str,
+    tree: &'a str,
+    parents: &'a [&'a str],
+}
+
+#[derive(Serialize)]
+struct NewRef<'a> {
+    #[serde(rename = "ref")]
+    name: &'a str,
+    sha: &'a str,
+}
+
+#[derive(Serialize)]
+struct RefUpdate<'a> {
+    sha: &'a str,
+    force: bool,
+}
+
+#[derive(Serialize)]
+struct NewPull<'a> {
+    title: &'a str,
+    body: &'a str,
+    head: &'a str,
+    base: &'a str,
+}
+
+#[derive(Serialize)]
+struct IssueState<'a> {
+    state: &'a str,
+}
+
+/// With `--no-api-writes`, logs what would have been done instead of doing
+/// it and answers true.
+fn skip_write(action: &str) -> bool {
+    if writes_blocked() {
+        info!("Would have {} (--no-api-writes)", action);
+    }
+    writes_blocked()
+}
+
+/// Stands in for an issue or pull request `--no-api-writes` didn't create.
+fn not_created(title: &str) -> Issue {
+    Issue {
+        number: 0,
+        title: title.to_string(),
+        html_url: "(not created, --no-api-writes)".to_string(),
+    }
+}
+
+/// How long GitHub asks to wait before writing again, when it rate limited
+/// `response`: the `Retry-After` of a secondary limit, or until the primary
+/// limit resets.
+fn retry_after(response: &Response) -> Option<Duration> {
+    if response.status() != StatusCode::FORBIDDEN
+        && response.status() != StatusCode::TOO_MANY_REQUESTS
+    {
+        return None;
+    }
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+    };
+    if let Some(seconds) = header(RETRY_AFTER.as_str()) {
+        return Some(Duration::from_secs(seconds));
+    }
+    if header("x-ratelimit-remaining") == Some(0) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        return header("x-ratelimit-reset")
+            .map(|reset| Duration::from_secs(reset.saturating_sub(now).max(1)));
+    }
+    None
+}
+
+/// Sends a request that changes something, at least `integrate.write-delay`
+/// after the previous one, waiting out and retrying rate limited attempts.
+fn send_write(mut request: RequestBuilder) -> Result<Response, reqwest::Error> {
+    let mut retries = 0;
+    loop {
+        {
+            let mut last_write = LAST_WRITE.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(wait) =
+                last_write.and_then(|last| write_delay().checked_sub(last.elapsed()))
+            {
+                thread::sleep(wait);
+            }
+            *last_write = Some(Instant::now());
+        }
+        let retry = request.try_clone();
+        let response = request.send_recorded()?;
+        match (retry_after(&response), retry) {
+            (Some(wait), Some(retry)) if retries < WRITE_RETRIES && wait <= MAX_RETRY_WAIT => {
+                warn!(
+                    "GitHub is rate limiting writes, trying again in {}s",
+                    wait.as_secs()
+                );
+                thread::sleep(wait);
+                request = retry;
+                retries += 1;
+            }
+            _ => return Ok(response),
+        }
+    }
+}
+
+fn request(client: &Client, method: Method, path: &str, token: &str) -> RequestBuilder {
+    anonymous_request(client, method, path).bearer_auth(token)
+}
+
+/// Unauthenticated requests only see public repositories and are limited to
+/// 60 an hour, but let people try the tool without creating a token.
+fn anonymous_request(client: &Client, method: Method, path: &str) -> RequestBuilder {
+    client
+        .request(method, &format!("{}{}", api_url(), path))
+        .header(USER_AGENT, "git-integrate")
+}
+
+/// Open pull requests, oldest first, through the REST API. Works without a
+/// token for public repositories.
+pub fn open_pull_requests(token: Option<&str>, repo: &Repo) -> Result<Vec<Pull>, reqwest::Error> {
+    let client = client()?;
+    let mut pulls = vec![];
+
+    for page in 1.. {
+        let path = format!(
+            "/repos/{}/{}/pulls?state=open&sort=created&direction=asc&per_page=100&page={}",
+            repo.owner, repo.name, page
+        );
+        let builder = match token {
+            Some(token) => request(&client, Method::GET, &path, token),
+            None => anonymous_request(&client, Method::GET, &path),
+        };
+
+        let batch: Vec<Pull> = builder.send_recorded()?.error_for_status()?.json()?;
+        let done = batch.len() < 100;
+        pulls.extend(batch);
+        if done {
+            break;
+        }
+    }
+
+    Ok(pulls)
+}
+
+/// Fine-grained personal access tokens start with this, classic ones with
+/// `ghp_`.
+const FINE_GRAINED_PREFIX: &str = "github_pat_";
+
+/// What keeps `token` from reading `repo`, if anything, found with one cheap
+/// request for each permission a run reads with. Without it a fine-grained
+/// token lacking a permission looks like there is nothing to merge.
+pub fn check_token(token: &str, repo: &Repo) -> Result<Option<String>, reqwest::Error> {
+    let client = client()?;
+    let fine_grained = token.starts_with(FINE_GRAINED_PREFIX);
+    for (path, permission) in [
+        (
+            format!("/repos/{}/{}/pulls?per_page=1", repo.owner, repo.name),
+            "Pull requests",
+        ),
+        (
+            format!("/repos/{}/{}/commits?per_page=1", repo.owner, repo.name),
+            "Contents",
+        ),
+    ] {
+        let res = request(&client, Method::GET, &path, token).send_recorded()?;
+        let rate_limited = res
+            .headers()
+            .get("x-ratelimit-remaining")
+            .is_some_and(|remaining| remaining == "0");
+        match res.status() {
+            StatusCode::UNAUTHORIZED => return Ok(Some(
+                "GitHub rejected integrate.github-token: it expired, was revoked or is mistyped"
+                    .to_string(),
+            )),
+            StatusCode::FORBIDDEN | StatusCode::NOT_FOUND if fine_grained && !rate_limited => {
+                return Ok(Some(format!(
+                    "The fine-grained integrate.github-token can't read {} of {}/{}: give it \
+                     \naccess to the repository with the {} permission, read and write \
+                     \nfor runs that comment, label or push",
+                    permission.to_lowercase(),
+                    repo.owner,
+                    repo.name,
+                    permission
+                )))
+            }
+            StatusCode::NOT_FOUND => {
+                return Ok(Some(format!(
+                    "integrate.github-token can't see {}/{}; classic tokens need the repo \
+                     \nscope for private repositories",
+                    repo.owner, repo.name
+                )))
+            }
+            _ => (),
+        }
+    }
+    Ok(None)
+}
+
+pub fn current_user(token: &str) -> Result<User, reqwest::Error> {
+    let client = client()?;
+    request(&client, Method::GET, "/user", token)
+        .send_recorded()?
+        .error_for_status()?
+        .json()
+}
+
+pub fn create_issue(
+    token: &str,
+    repo: &Repo,
+    title: &str,
+    body: &str,
+    assignees: &[String],
+    labels: &[String],
+) -> Result<Issue, reqwest::Error> {
+    if skip_write(&format!("opened the issue \"{}\"", title)) {
+        return Ok(not_created(title));
+    }
+    let client = client()?;
+    let path = format!("/repos/{}/{}/issues", repo.owner, repo.name);
+    send_write(
+        request(&client, Method::POST, &path, token).json(&NewIssue {
+            title,
+            body,
+            assignees,
+            labels,
+        }),
+    )?
+    .error_for_status()?
+    .json()
+}
+
+/// Open issues with `label`, most recently created first.
+pub fn open_issues(token: &str, repo: &Repo, label: &str) -> Result<Vec<Issue>, reqwest::Error> {
+    let client = client()?;
+    let path = format!("/repos/{}/{}/issues", repo.owner, repo.name);
+    // Labels may hold spaces, emoji and `&`, so they go through the query
+    // encoding.
+    request(&client, Method::GET, &path, token)
+        .query(&[("state", "open"), ("labels", label), ("per_page", "100")])
+        .send_recorded()?
+        .error_for_status()?
+        .json()
+}
+
+/// The open milestones of the repository.
+pub fn open_milestones(token: Option<&str>, repo: &Repo) -> Result<Vec<Milestone>, reqwest::Error> {
+    let client = client()?;
+    let path = format!(
+        "/repos/{}/{}/milestones?state=open&per_page=100",
+        repo.owner, repo.name
+    );
+    let builder = match token {
+        Some(token) => request(&client, Method::GET, &path, token),
+        None => anonymous_request(&client, Method::GET, &path),
+    };
+    builder.send_recorded()?.error_for_status()?.json()
+}
+
+pub fn add_labels(
+    token: &str,
+    repo: &Repo,
+    number: i64,
+    labels: &[String],
+) -> Result<(), reqwest::Error> {
+    if skip_write(&format!("labeled #{} {}", number, labels.join(", "))) {
+        return Ok(());
+    }
+    let client = client()?;
+    let path = format!(
+        "/repos/{}/{}/issues/{}/labels",
+        repo.owner, repo.name, number
+    );
+    send_write(request(&client, Method::POST, &path, token).json(&NewLabels { labels }))?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Creates the label `name` in `repo`, answering whether it didn't exist
+/// yet.
+pub fn create_label(
+    token: &str,
+    repo: &Repo,
+    name: &str,
+    color: &str,
+    description: &str,
+) -> Result<bool, reqwest::Error> {
+    if skip_write(&format!("created the label {}", name)) {
+        return Ok(false);
+    }
+    let client = client()?;
+    let path = format!("/repos/{}/{}/labels", repo.owner, repo.name);
+    let res = send_write(
+        request(&client, Method::POST, &path, token).json(&NewLabel {
+            name,
+            color,
+            description,
+        }),
+    )?;
+    // Validation fails when the label already exists.
+    if res.status() == reqwest::StatusCode::UNPROCESSABLE_ENTITY {
+        return Ok(false);
+    }
+    res.error_for_status()?;
+    Ok(true)
+}
+
+/// Removes `label` from issue or pull request `number`, answering whether it
+/// had it.
+pub fn remove_label(
+    token: &str,
+    repo: &Repo,
+    number: i64,
+    label: &str,
+) -> Result<bool, reqwest::Error> {
+    if skip_write(&format!("removed the label {} from #{}", label, number)) {
+        return Ok(false);
+    }
+    let client = client()?;
+    let path = format!(
+        "/repos/{}/{}/issues/{}/labels/{}",
+        repo.owner,
+        repo.name,
+        number,
+        path_segment(label)
+    );
+    let res = send_write(request(&client, Method::DELETE, &path, token))?;
+    if res.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(false);
+    }
+    res.error_for_status()?;
+    Ok(true)
+}
+
+/// Percent-encodes `value` for use as one segment of an API path, as label
+/// names can contain spaces, slashes and the like.
+pub fn path_segment(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+pub fn close_issue(token: &str, repo: &Repo, number: i64) -> Result<(), reqwest::Error> {
+    if skip_write(&format!("closed #{}", number)) {
+        return Ok(());
+    }
+    let client = client()?;
+    let path = format!("/repos/{}/{}/issues/{}", repo.owner, repo.name, number);
+    send_write(
+        request(&client, Method::PATCH, &path, token).json(&IssueState { state: "closed" }),
+    )?
+    .error_for_status()?;
+    Ok(())
+}
+
+pub fn create_comment(
+    token: &str,
+    repo: &Repo,
+    number: i64,
+    body: &str,
+) -> Result<(), reqwest::Error> {
+    if skip_write(&format!("commented on #{}:\n{}", number, body)) {
+        return Ok(());
+    }
+    let client = client()?;
+    let path = format!(
+        "/repos/{}/{}/issues/{}/comments",
+        repo.owner, repo.name, number
+    );
+    send_write(request(&client, Method::POST, &path, token).json(&NewComment { body }))?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Submits a review with the given `event` (`COMMENT`, `APPROVE` or
+/// `REQUEST_CHANGES`) on a pull request.
+pub fn create_review(
+    token: &str,
+    repo: &Repo,
+    number: i64,
+    event: &str,
+    body: &str,
+) -> Result<(), reqwest::Error> {
+    if skip_write(&format!("reviewed #{} with {}:\n{}", number, event, body)) {
+        return Ok(());
+    }
+    let client = client()?;
+    let path = format!(
+        "/repos/{}/{}/pulls/{}/reviews",
+        repo.owner, repo.name, number
+    );
+    send_write(request(&client, Method::POST, &path, token).json(&NewReview { event, body }))?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Sets the commit status `context` of `sha` to `state` (`success`,
+/// `failure`, `error` or `pending`).
+pub fn create_status(
+    token: &str,
+    repo: &Repo,
+    sha: &str,
+    state: &str,
+    context: &str,
+    description: &str,
+) -> Result<(), reqwest::Error> {
+    if skip_write(&format!(
+        "set the {} status of {} to {}: {}",
+        context, sha, state, description
+    )) {
+        return Ok(());
+    }
+    let client = client()?;
+    let path = format!("/repos/{}/{}/statuses/{}", repo.owner, repo.name, sha);
+    send_write(
+        request(&client, Method::POST, &path, token).json(&NewStatus {
+            state,
+            context,
+            description,
+        }),
+    )?
+    .error_for_status()?;
+    Ok(())
+}
+
+pub fn update_comment(token: &str, repo: &Repo, id: i64, body: &str) -> Result<(), reqwest::Error> {
+    if skip_write(&format!("updated comment {}:\n{}", id, body)) {
+        return Ok(());
+    }
+    let client = client()?;
+    let path = format!("/repos/{}/{}/issues/comments/{}", repo.owner, repo.name, id);
+    send_write(request(&client, Method::PATCH, &path, token).json(&NewComment { body }))?
+        .error_for_status()?;
+    Ok(())
+}
+
+pub fn issue_comments(
+    token: &str,
+    repo: &Repo,
+    number: i64,
+) -> Result<Vec<Comment>, reqwest::Error> {
+    let client = client()?;
+    let mut comments = vec![];
+
+    for page in 1.. {
+        let path = format!(
+            "/repos/{}/{}/issues/{}/comments?per_page=100&page={}",
+            repo.owner, repo.name, number, page
+        );
+        let batch: Vec<Comment> = request(&client, Method::GET, &path, token)
+            .send_recorded()?
+            .error_for_status()?
+            .json()?;
+        let done = batch.len() < 100;
+        comments.extend(batch);
+        if done {
+            break;
+        }
+    }
+
+    Ok(comments)
+}
+
+/// Edits the comment on `number` that starts with `marker` instead of adding
+/// another, and leaves it alone when `body` is what it says already.
+pub fn upsert_comment(
+    token: &str,
+    repo: &Repo,
+    number: i64,
+    marker: &str,
+    body: &str,
+) -> Result<(), reqwest::Error> {
+    let existing = issue_comments(token, repo, number)?
+        .into_iter()
+        .find(|comment| comment.body.starts_with(marker));
+
+    match existing {
+        Some(comment) if comment.body == body => Ok(()),
+        Some(comment) => update_comment(token, repo, comment.id, body),
+        None => create_comment(token, repo, number, body),
+    }
+}
+
+#[cfg(feature = "server")]
+pub fn delete_comment(token: &str, repo: &Repo, id: i64) -> Result<(), reqwest::Error> {
+    if skip_write(&format!("deleted comment {}", id)) {
+        return Ok(());
+    }
+    let client = client()?;
+    let path = format!("/repos/{}/{}/issues/comments/{}", repo.owner, repo.name, id);
+    send_write(request(&client, Method::DELETE, &path, token))?.error_for_status()?;
+    Ok(())
+}
+
+/// Deletes the comment on `number` that starts with `marker`, if there is one.
+#[cfg(feature = "server")]
+pub fn remove_comment(
+    token: &str,
+    repo: &Repo,
+    number: i64,
+    marker: &str,
+) -> Result<(), reqwest::Error> {
+    let existing = issue_comments(token, repo, number)?
+        .into_iter()
+        .find(|comment| comment.body.starts_with(marker));
+
+    match existing {
+        Some(comment) => delete_comment(token, repo, comment.id),
+        None => Ok(()),
+    }
+}
+
+/// What happened to issue or pull request `number`, oldest first.
+pub fn issue_events(
+    token: Option<&str>,
+    repo: &Repo,
+    number: i64,
+) -> Result<Vec<IssueEvent>, reqwest::Error> {
+    let client = client()?;
+    let mut events = vec![];
+
+    for page in 1.. {
+        let path = format!(
+            "/repos/{}/{}/issues/{}/events?per_page=100&page={}",
+            repo.owner, repo.name, number, page
+        );
+        let builder = match token {
+            Some(token) => request(&client, Method::GET, &path, token),
+            None => anonymous_request(&client, Method::GET, &path),
+        };
+
+        let batch: Vec<IssueEvent> = builder.send_recorded()?.error_for_status()?.json()?;
+        let done = batch.len() < 100;
+        events.extend(batch);
+        if done {
+            break;
+        }
+    }
+
+    Ok(events)
+}
+
+pub fn issue_reactions(
+    token: &str,
+    repo: &Repo,
+    number: i64,
+) -> Result<Vec<Reaction>, reqwest::Error> {
+    let client = client()?;
+    let mut reactions = vec![];
+
+    for page in 1.. {
+        let path = format!(
+            "/repos/{}/{}/issues/{}/reactions?per_page=100&page={}",
+            repo.owner, repo.name, number, page
+        );
+        let batch: Vec<Reaction> = request(&client, Method::GET, &path, token)
+            .send_recorded()?
+            .error_for_status()?
+            .json()?;
+        let done = batch.len() < 100;
+        reactions.extend(batch);
+        if done {
+            break;
+        }
+    }
+
+    Ok(reactions)
+}
+
+pub fn is_team_member(
+    token: &str,
+    org: &str,
+    team: &str,
+    login: &str,
+) -> Result<bool, reqwest::Error> {
+    let client = client()?;
+    let path = format!("/orgs/{}/teams/{}/memberships/{}", org, team, login);
+    let res = request(&client, Method::GET, &path, token).send_recorded()?;
+
+    if res.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(false);
+    }
+
+    let membership: Membership = res.error_for_status()?.json()?;
+    Ok(membership.state == "active")
+}
+
+/// The numbers of the issues and pull requests matching a search
+/// expression, in the order the search returns them.
+pub fn search_issues(token: Option<&str>, query: &str) -> Result<Vec<i64>, reqwest::Error> {
+    let client = client()?;
+    let mut numbers = vec![];
+
+    for page in 1.. {
+        let path = format!("/search/issues?per_page=100&page={}", page);
+        let builder = match token {
+            Some(token) => request(&client, Method::GET, &path, token),
+            None => anonymous_request(&client, Method::GET, &path),
+        };
+
+        let batch: SearchResults<IssueNumber> = builder
+            .query(&[("q", query)])
+            .send_recorded()?
+            .error_for_status()?
+            .json()?;
+        let done = batch.items.len() < 100;
+        numbers.extend(batch.items.into_iter().map(|issue| issue.number));
+        if done {
+            break;
+        }
+    }
+
+    Ok(numbers)
+}
+
+/// Repositories of `org` tagged with `topic`, archived ones excluded.
+pub fn repositories_by_topic(
+    token: Option<&str>,
+    org: &str,
+    topic: &str,
+) -> Result<Vec<Repository>, reqwest::Error> {
+    let client = client()?;
+    let mut repositories = vec![];
+
+    for page in 1.. {
+        let path = format!(
+            "/search/repositories?q=org:{}+topic:{}+archived:false&per_page=100&page={}",
+            org, topic, page
+        );
+        let builder = match token {
+            Some(token) => request(&client, Method::GET, &path, token),
+            None => anonymous_request(&client, Method::GET, &path),
+        };
+
+        let batch: SearchResults<Repository> =
+            builder.send_recorded()?.error_for_status()?.json()?;
+        let done = batch.items.len() < 100;
+        repositories.extend(batch.items.into_iter().filter(|r| !r.archived));
+        if done {
+            break;
+        }
+    }
+
+    Ok(repositories)
+}
+
+pub fn repository(token: &str, repo: &Repo) -> Result<Repository, reqwest::Error> {
+    let client = client()?;
+    let path = format!("/repos/{}/{}", repo.owner, repo.name);
+    request(&client, Method::GET, &path, token)
+        .send_recorded()?
+        .error_for_status()?
+        .json()
+}
+
+/// The commit `branch` points at.
+pub fn branch_sha(token: &str, repo: &Repo, branch: &str) -> Result<String, reqwest::Error> {
+    let client = client()?;
+    let path = format!(
+        "/repos/{}/{}/git/ref/heads/{}",
+        repo.owner, repo.name, branch
+    );
+    let git_ref: GitRef = request(&client, Method::GET, &path, token)
+        .send_recorded()?
+        .error_for_status()?
+        .json()?;
+    Ok(git_ref.object.sha)
+}
+
+/// Commits `parent` with the submodule at `submodule_path` pointing at
+/// `submodule_sha` and returns the new commit, which no branch points at yet.
+pub fn commit_submodule(
+    token: &str,
+    repo: &Repo,
+    parent: &str,
+    submodule_path: &str,
+    submodule_sha: &str,
+    message: &str,
+) -> Result<String, reqwest::Error> {
+    if skip_write(&format!(
+        "committed {} at {} on top of {}",
+        submodule_path, submodule_sha, parent
+    )) {
+        return Ok(parent.to_string());
+    }
+    let client = client()?;
+    let path = format!("/repos/{}/{}/git/commits/{}", repo.owner, repo.name, parent);
+    let commit: GitCommit = request(&client, Method::GET, &path, token)
+        .send_recorded()?
+        .error_for_status()?
+        .json()?;
+
+    let path = format!("/repos/{}/{}/git/trees", repo.owner, repo.name);
+    let tree: GitObject =
+        send_write(request(&client, Method::POST, &path, token).json(&NewTree {
+            base_tree: &commit.tree.sha,
+            tree: &[TreeEntry {
+                path: submodule_path,
+                mode: "160000",
+                kind: "commit",
+                sha: submodule_sha,
+            }],
+        }))?
+        .error_for_status()?
+        .json()?;
+
+    let path = format!("/repos/{}/{}/git/commits", repo.owner, repo.name);
+    let commit: GitObject = send_write(request(&client, Method::POST, &path, token).json(
+        &NewCommit {
+            message,
+            tree: &tree.sha,
+            parents: &[parent],
+        },
+    ))?
+    .error_for_status()?
+    .json()?;
+    Ok(commit.sha)
+}
+
+/// Points `branch` at `sha`, creating it if needed and discarding whatever
+/// it pointed at before.
+pub fn force_branch(
+    token: &str,
+    repo: &Repo,
+    branch: &str,
+    sha: &str,
+) -> Result<(), reqwest::Error> {
+    if skip_write(&format!("pointed {} at {}", branch, sha)) {
+        return Ok(());
+    }
+    let client = client()?;
+    let path = format!(
+        "/repos/{}/{}/git/refs/heads/{}",
+        repo.owner, repo.name, branch
+    );
+    let res = send_write(
+        request(&client, Method::PATCH, &path, token).json(&RefUpdate { sha, force: true }),
+    )?;
+    if res.status() != reqwest::StatusCode::UNPROCESSABLE_ENTITY
+        && res.status() != reqwest::StatusCode::NOT_FOUND
+    {
+        res.error_for_status()?;
+        return Ok(());
+    }
+
+    let path = format!("/repos/{}/{}/git/refs", repo.owner, repo.name);
+    send_write(request(&client, Method::POST, &path, token).json(&NewRef {
+        name: &format!("refs/heads/{}", branch),
+        sha,
+    }))?
+    .error_for_status()?;
+    Ok(())
+}
+
+/// The open pull request from `branch` of the same repository, if any.
+pub fn open_pull_from(
+    token: &str,
+    repo: &Repo,
+    branch: &str,
+) -> Result<Option<Issue>, reqwest::Error> {
+    let client = client()?;
+    let path = format!(
+        "/repos/{}/{}/pulls?state=open&head={}:{}",
+        repo.owner, repo.name, repo.owner, branch
+    );
+    let pulls: Vec<Issue> = request(&client, Method::GET, &path, token)
+        .send_recorded()?
+        .error_for_status()?
+        .json()?;
+    Ok(pulls.into_iter().next())
+}
+
+pub fn create_pull(
+    token: &str,
+    repo: &Repo,
+    title: &str,
+    body: &str,
+    head: &str,
+    base: &str,
+) -> Result<Issue, reqwest::Error> {
+    if skip_write(&format!(
+        "opened the pull request \"{}\" from {}",
+        title, head
+    )) {
+        return Ok(not_created(title));
+    }
+    let client = client()?;
+    let path = format!("/repos/{}/{}/pulls", repo.owner, repo.name);
+    send_write(request(&client, Method::POST, &path, token).json(&NewPull {
+        title,
+        body,
+        head,
+        base,
+    }))?
+    .error_for_status()?
+    .json()
+}
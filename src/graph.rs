@@ -0,0 +1,201 @@
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use super::github::PullRequest;
+use super::speculate::Matrix;
+
+/// How many conflicted paths an edge lists before summing up the rest.
+const SHOWN_PATHS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Dot,
+    Mermaid,
+}
+
+impl Format {
+    pub fn parse(value: &str) -> Option<Format> {
+        match value {
+            "dot" => Some(Format::Dot),
+            "mermaid" => Some(Format::Mermaid),
+            _ => None,
+        }
+    }
+}
+
+enum Edge {
+    /// Merged right after.
+    Then,
+    /// The pull request is stacked on the other one.
+    BaseOf,
+    Conflict(String),
+}
+
+/// The plan as a graph: the merge order from the base on, pull requests
+/// stacked on others, and the conflicts the speculative merges found.
+pub fn render(matrix: &Matrix, format: Format) -> String {
+    let plan = &matrix.plan;
+    let node = |idx: usize| format!("pr{}", plan[idx].number);
+    let mut edges: Vec<(String, String, Edge)> = vec![];
+
+    let mut previous = "base".to_string();
+    for idx in 0..plan.len() {
+        edges.push((previous, node(idx), Edge::Then));
+        previous = node(idx);
+    }
+    for (idx, pr) in plan.iter().enumerate() {
+        if let Some(parent) = plan.iter().position(|other| other.branch == pr.base) {
+            edges.push((node(parent), node(idx), Edge::BaseOf));
+        }
+    }
+    for (idx, paths) in matrix.with_base.iter().enumerate() {
+        if !paths.is_empty() {
+            edges.push((
+                "base".to_string(),
+                node(idx),
+                Edge::Conflict(paths_label(paths)),
+            ));
+        }
+    }
+    for (i, j, paths) in &matrix.pairs {
+        edges.push((node(*i), node(*j), Edge::Conflict(paths_label(paths))));
+    }
+
+    let label = |idx: usize, pr: &PullRequest| {
+        format!("{}. #{} {}\n{}", idx + 1, pr.number, pr.title, pr.branch)
+    };
+    let mut out = String::new();
+    match format {
+        Format::Dot => {
+            let _ = writeln!(out, "digraph plan {{\n  rankdir=LR;\n  node [shape=box];");
+            let _ = writeln!(
+                out,
+                "  base [label=\"{}\", shape=ellipse];",
+                dot_escape(&matrix.base)
+            );
+            for (idx, pr) in plan.iter().enumerate() {
+                let _ = writeln!(
+                    out,
+                    "  {} [label=\"{}\"];",
+                    node(idx),
+                    dot_escape(&label(idx, pr))
+                );
+            }
+            for (from, to, edge) in &edges {
+                let attributes = match edge {
+                    Edge::Then => "style=bold".to_string(),
+                    Edge::BaseOf => "style=dashed, label=\"base of\"".to_string(),
+                    Edge::Conflict(paths) => format!(
+                        "dir=none, color=red, fontcolor=red, label=\"{}\"",
+                        dot_escape(paths)
+                    ),
+                };
+                let _ = writeln!(out, "  {} -> {} [{}];", from, to, attributes);
+            }
+            out.push_str("}\n");
+        }
+        Format::Mermaid => {
+            out.push_str("flowchart LR\n");
+            let _ = writeln!(out, "  base([\"{}\"])", mermaid_escape(&matrix.base));
+            for (idx, pr) in plan.iter().enumerate() {
+                let _ = writeln!(
+                    out,
+                    "  {}[\"{}\"]",
+                    node(idx),
+                    mermaid_escape(&label(idx, pr))
+                );
+            }
+            let mut conflicts = vec![];
+            for (idx, (from, to, edge)) in edges.iter().enumerate() {
+                let link = match edge {
+                    Edge::Then => "==>".to_string(),
+                    Edge::BaseOf => "-.->|\"base of\"|".to_string(),
+                    Edge::Conflict(paths) => {
+                        conflicts.push(idx.to_string());
+                        format!("---|\"{}\"|", mermaid_escape(paths))
+                    }
+                };
+                let _ = writeln!(out, "  {} {} {}", from, link, to);
+            }
+            if !conflicts.is_empty() {
+                let _ = writeln!(
+                    out,
+                    "  linkStyle {} stroke:red,color:red",
+                    conflicts.join(",")
+                );
+            }
+        }
+    }
+    out
+}
+
+fn paths_label(paths: &[PathBuf]) -> String {
+    let mut lines: Vec<String> = paths
+        .iter()
+        .take(SHOWN_PATHS)
+        .map(|path| path.display().to_string())
+        .collect();
+    if paths.len() > SHOWN_PATHS {
+        lines.push(format!("and {} more", paths.len() - SHOWN_PATHS));
+    }
+    format!("conflicts on {}", lines.join("\n"))
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn mermaid_escape(text: &str) -> String {
+    text.replace('"', "#quot;").replace('\n', "<br/>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::pull_request;
+
+    fn pr(number: i64, branch: &str, base: &str) -> PullRequest {
+        PullRequest {
+            title: format!("Say \"{}\"", number),
+            base: base.to_string(),
+            ..pull_request(number, branch)
+        }
+    }
+
+    fn matrix() -> Matrix {
+        Matrix {
+            base: "origin/master".to_string(),
+            plan: vec![
+                pr(1, "one", "master"),
+                pr(2, "two", "one"),
+                pr(3, "three", "master"),
+            ],
+            with_base: vec![vec![], vec![], vec![PathBuf::from("README.md")]],
+            pairs: vec![(0, 2, vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")])],
+        }
+    }
+
+    #[test]
+    fn dot_shows_order_stacks_and_conflicts() {
+        let dot = render(&matrix(), Format::Dot);
+        assert!(dot.contains("  pr1 [label=\"1. #1 Say \\\"1\\\"\\none\"];\n"));
+        assert!(dot.contains("  base -> pr1 [style=bold];\n  pr1 -> pr2 [style=bold];\n"));
+        assert!(dot.contains("  pr1 -> pr2 [style=dashed, label=\"base of\"];\n"));
+        assert!(dot.contains(
+            "  base -> pr3 [dir=none, color=red, fontcolor=red, label=\"conflicts on README.md\"];\n"
+        ));
+        assert!(dot.contains("label=\"conflicts on a.txt\\nb.txt\"];\n"));
+    }
+
+    #[test]
+    fn mermaid_colors_the_conflict_links() {
+        let mermaid = render(&matrix(), Format::Mermaid);
+        assert!(mermaid.starts_with("flowchart LR\n  base([\"origin/master\"])\n"));
+        assert!(mermaid.contains("  pr2[\"2. #2 Say #quot;2#quot;<br/>two\"]\n"));
+        assert!(mermaid.contains("  pr1 -.->|\"base of\"| pr2\n"));
+        assert!(mermaid.contains("  pr1 ---|\"conflicts on a.txt<br/>b.txt\"| pr3\n"));
+        assert!(mermaid.ends_with("  linkStyle 4,5 stroke:red,color:red\n"));
+    }
+}
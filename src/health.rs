@@ -0,0 +1,91 @@
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How quickly a stop request is noticed while waiting.
+const TICK: Duration = Duration::from_secs(1);
+
+/// How long a health check may take to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the probes of a long-running process report.
+#[derive(Default)]
+pub struct Status {
+    /// Set once the first pass finished, so the process does useful work.
+    ready: AtomicBool,
+    /// Set by SIGTERM or SIGINT; the process stops after the current pass.
+    stopping: Arc<AtomicBool>,
+}
+
+impl Status {
+    /// A status whose `stopping` flag is raised by SIGTERM and SIGINT instead
+    /// of those signals killing the process outright.
+    pub fn with_signals() -> io::Result<Arc<Status>> {
+        let status = Arc::new(Status::default());
+        for signal in &[SIGTERM, SIGINT] {
+            signal_hook::flag::register(*signal, Arc::clone(&status.stopping))?;
+        }
+        Ok(status)
+    }
+
+    pub fn set_ready(&self) {
+        self.ready.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    /// Waits up to `duration`, returning early when asked to stop.
+    pub fn sleep(&self, duration: Duration) {
+        let started = Instant::now();
+        while started.elapsed() < duration && !self.is_stopping() {
+            thread::sleep(TICK.min(duration.saturating_sub(started.elapsed())));
+        }
+    }
+}
+
+/// Answers `GET /healthz` for as long as the process runs and `GET /readyz`
+/// once it is ready and until it starts shutting down, on a background thread.
+pub fn listen(addr: &str, status: Arc<Status>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!("Health checks on http://{}/healthz", listener.local_addr()?);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = respond(stream, &status) {
+                warn!("Could not answer a health check: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, status: &Status) -> io::Result<()> {
+    // Connections are answered one at a time, so one that never sends its
+    // request line mustn't hold up the probes behind it.
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+
+    let (code, body) = match path {
+        "/healthz" => ("200 OK", "ok"),
+        "/readyz" if status.is_stopping() => ("503 Service Unavailable", "stopping"),
+        "/readyz" if !status.ready.load(Ordering::SeqCst) => {
+            ("503 Service Unavailable", "starting")
+        }
+        "/readyz" => ("200 OK", "ready"),
+        _ => ("404 Not Found", "not found"),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        body.len(),
+        body
+    )
+}
@@ -0,0 +1,163 @@
+use git2::Repository;
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use super::git_extras::common_dir;
+use super::github::{self, cost::QueryCost};
+use super::report::{Outcome, Report};
+
+/// What happened to one pull request in a past run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PullRecord {
+    pub number: i64,
+    pub branch: String,
+    pub author: Option<String>,
+    pub outcome: Outcome,
+    pub conflicts: Vec<PathBuf>,
+    pub duration_ms: u64,
+    /// The head commit of the branch, missing from runs recorded before
+    /// heads were.
+    #[serde(default)]
+    pub head: Option<String>,
+    /// The merge commit and the tree it left, as merged, missing from older
+    /// runs.
+    #[serde(default)]
+    pub merge_commit: Option<String>,
+    #[serde(default)]
+    pub tree: Option<String>,
+    /// How many times verification failed before it passed.
+    #[serde(default)]
+    pub verify_retries: usize,
+}
+
+/// One integration run, as stored in the history file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Run {
+    pub finished_at: String,
+    pub dest_branch: String,
+    /// The branch as configured, `{date}` and all, missing from older runs.
+    #[serde(default)]
+    pub branch_template: Option<String>,
+    pub selection: String,
+    pub success: bool,
+    pub pulls: Vec<PullRecord>,
+    /// The release candidate a `--train` run tagged.
+    #[serde(default)]
+    pub release_candidate: Option<u32>,
+    /// Whether the branch was pushed everywhere it goes.
+    #[serde(default)]
+    pub pushed: bool,
+    /// What its GraphQL queries cost, missing when it made none.
+    #[serde(default)]
+    pub query_cost: Option<QueryCost>,
+}
+
+impl Run {
+    pub fn from_report(report: &Report, success: bool) -> Run {
+        Run {
+            finished_at: chrono::Utc::now().to_rfc3339(),
+            dest_branch: report.dest_branch.clone(),
+            branch_template: Some(report.branch_template.clone()),
+            selection: report.selection.clone(),
+            success,
+            pulls: report
+                .entries
+                .iter()
+                .map(|entry| PullRecord {
+                    number: entry.pr.number,
+                    branch: entry.pr.branch.clone(),
+                    author: entry.pr.author.clone(),
+                    outcome: entry.outcome,
+                    conflicts: entry.conflicts.clone(),
+                    duration_ms: entry.duration.as_millis() as u64,
+                    head: entry.head.clone(),
+                    merge_commit: entry.merge_commit.clone(),
+                    tree: entry.tree.clone(),
+                    verify_retries: entry.verify_retries,
+                })
+                .collect(),
+            release_candidate: report.release_candidate,
+            pushed: !report.pushes.is_empty() && report.pushes.iter().all(|(_, pushed)| *pushed),
+            query_cost: github::cost::total(),
+        }
+    }
+
+    /// Whether this run built the branch configured as `template`, whatever
+    /// date it expanded to.
+    pub fn builds(&self, template: &str) -> bool {
+        self.branch_template.as_deref().unwrap_or(&self.dest_branch) == template
+    }
+
+    /// The head pull request `number` was merged at in this run, if it was.
+    pub fn head_of(&self, number: i64) -> Option<&str> {
+        self.pulls
+            .iter()
+            .find(|pull| pull.number == number && pull.outcome.is_success())
+            .and_then(|pull| pull.head.as_deref())
+    }
+}
+
+/// Append-only log of past runs, one JSON object per line, kept in
+/// `.git/integrate/history.jsonl` so every worktree shares it.
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub fn open(repository: &Repository) -> History {
+        History {
+            path: common_dir(repository)
+                .join("integrate")
+                .join("history.jsonl"),
+        }
+    }
+
+    pub fn append(&self, run: &Run) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(run)?)
+    }
+
+    /// The last run that built and pushed the branch configured as
+    /// `template`.
+    pub fn last_push(&self, template: &str) -> io::Result<Option<Run>> {
+        Ok(self
+            .runs()?
+            .into_iter()
+            .rev()
+            .find(|run| run.success && run.pushed && run.builds(template)))
+    }
+
+    /// Every recorded run, oldest first. Lines that can't be parsed, for
+    /// example from an interrupted write, are skipped.
+    pub fn runs(&self) -> io::Result<Vec<Run>> {
+        let file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        Ok(BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str(&line).ok())
+            .collect())
+    }
+
+    /// The last run that built the branch configured as `template`
+    /// successfully.
+    pub fn last_build(&self, template: &str) -> io::Result<Option<Run>> {
+        Ok(self
+            .runs()?
+            .into_iter()
+            .rev()
+            .find(|run| run.success && run.builds(template)))
+    }
+}
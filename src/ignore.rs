@@ -0,0 +1,83 @@
+use git2::Repository;
+
+use super::github::PullRequest;
+
+pub const IGNORE_FILE: &str = ".gitintegrateignore";
+
+/// One line of `.gitintegrateignore`: a pull request number or a branch
+/// pattern (`*` and `?` wildcards), optionally followed by `# reason`.
+pub struct Rule {
+    pattern: String,
+    pub reason: Option<String>,
+}
+
+impl Rule {
+    fn matches(&self, pr: &PullRequest) -> bool {
+        match self.pattern.parse::<i64>() {
+            Ok(number) => number == pr.number,
+            Err(_) => wildcard(self.pattern.as_bytes(), pr.branch.as_bytes()),
+        }
+    }
+}
+
+/// Pull requests and branches that must never be integrated, read from the
+/// `.gitintegrateignore` committed on the base branch.
+#[derive(Default)]
+pub struct IgnoreList {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreList {
+    pub fn load(repository: &Repository, base: &str) -> IgnoreList {
+        let contents = || -> Result<Vec<u8>, git2::Error> {
+            let tree = repository.revparse_single(base)?.peel_to_tree()?;
+            let entry = tree.get_path(IGNORE_FILE.as_ref())?;
+            Ok(entry
+                .to_object(repository)?
+                .peel_to_blob()?
+                .content()
+                .to_vec())
+        };
+
+        match contents() {
+            Ok(contents) => IgnoreList::parse(&String::from_utf8_lossy(&contents)),
+            Err(_) => IgnoreList::default(),
+        }
+    }
+
+    pub fn parse(contents: &str) -> IgnoreList {
+        let rules = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (pattern, reason) = match line.split_once('#') {
+                    Some((pattern, reason)) => (pattern, Some(reason.trim().to_string())),
+                    None => (line, None),
+                };
+                Rule {
+                    pattern: pattern.trim().to_string(),
+                    reason: reason.filter(|r| !r.is_empty()),
+                }
+            })
+            .collect();
+
+        IgnoreList { rules }
+    }
+
+    pub fn find(&self, pr: &PullRequest) -> Option<&Rule> {
+        self.rules.iter().find(|rule| rule.matches(pr))
+    }
+}
+
+fn wildcard(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            wildcard(&pattern[1..], text) || (!text.is_empty() && wildcard(pattern, &text[1..]))
+        }
+        (Some(b'?'), Some(_)) => wildcard(&pattern[1..], &text[1..]),
+        (Some(p), Some(t)) if p == t => wildcard(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
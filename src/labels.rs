@@ -0,0 +1,105 @@
+use std::collections::BTreeSet;
+
+use super::dates;
+use super::git_extras::Repo;
+use super::github::rest;
+use super::history::Run;
+use super::report::Report;
+
+/// A label kept on exactly the pull requests the last build of a branch
+/// includes, such as `in-staging`, so it tells what is deployed.
+pub struct StatusLabel<'a> {
+    pub token: &'a str,
+    pub repo: &'a Repo,
+    pub label: String,
+}
+
+impl StatusLabel<'_> {
+    /// Labels the pull requests `report` merged and unlabels the ones that
+    /// are not in it anymore: those `previous`, the last pushed build, merged
+    /// and those selected that have the label. Returns the numbers labelled
+    /// and unlabelled.
+    pub fn sync(
+        &self,
+        report: &Report,
+        previous: Option<&Run>,
+    ) -> Result<(Vec<i64>, Vec<i64>), reqwest::Error> {
+        let included: Vec<i64> = report
+            .entries
+            .iter()
+            .filter(|e| e.outcome.is_success())
+            .map(|e| e.pr.number)
+            .collect();
+        let labelled: BTreeSet<i64> = previous
+            .into_iter()
+            .flat_map(|run| &run.pulls)
+            .filter(|pull| pull.outcome.is_success())
+            .map(|pull| pull.number)
+            .chain(
+                report
+                    .planned
+                    .iter()
+                    .chain(report.excluded.iter().map(|(pr, _)| pr))
+                    .filter(|pr| pr.has_label(&self.label))
+                    .map(|pr| pr.number),
+            )
+            .collect();
+
+        let mut added = vec![];
+        for number in included.iter().filter(|n| !labelled.contains(n)) {
+            rest::add_labels(
+                self.token,
+                self.repo,
+                *number,
+                std::slice::from_ref(&self.label),
+            )?;
+            added.push(*number);
+        }
+        let mut removed = vec![];
+        for number in labelled.iter().filter(|n| !included.contains(n)) {
+            if rest::remove_label(self.token, self.repo, *number, &self.label)? {
+                removed.push(*number);
+            }
+        }
+        Ok((added, removed))
+    }
+}
+
+/// A label naming the build, such as `in-staging-{date}`, put on every pull
+/// request it merged and left there, so a pull request page tells which
+/// builds have it.
+pub struct BuildLabel<'a> {
+    pub token: &'a str,
+    pub repo: &'a Repo,
+    pub template: String,
+}
+
+impl BuildLabel<'_> {
+    /// Creates the label for this build unless it exists and puts it on the
+    /// pull requests `report` merged. Returns the label and the numbers
+    /// labelled.
+    pub fn apply(&self, report: &Report) -> Result<(String, Vec<i64>), reqwest::Error> {
+        let label = dates::expand(&self.template);
+        rest::create_label(
+            self.token,
+            self.repo,
+            &label,
+            "ededed",
+            &format!("Included in the {} build", report.dest_branch),
+        )?;
+        let mut labelled = vec![];
+        for entry in report.entries.iter().filter(|e| e.outcome.is_success()) {
+            if entry.pr.has_label(&label) {
+                continue;
+            }
+            rest::add_labels(
+                self.token,
+                self.repo,
+                entry.pr.number,
+                std::slice::from_ref(&label),
+            )?;
+            labelled.push(entry.pr.number);
+        }
+        Ok((label, labelled))
+    }
+}
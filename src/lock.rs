@@ -0,0 +1,97 @@
+use git2::Repository;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+
+use super::git_extras::common_dir;
+
+/// Keeps other runs out of the repository for as long as it is held. The
+/// operating system lets go of it when the process exits, however it exits,
+/// so a crashed run never leaves it behind.
+pub struct RunLock {
+    _file: File,
+}
+
+/// Next to the rest of what runs keep, so worktrees of one repository share
+/// it and different repositories never do.
+fn path(repository: &Repository) -> PathBuf {
+    common_dir(repository).join("integrate").join("lock")
+}
+
+/// Locks the repository against other runs, none when another run already
+/// has it.
+pub fn acquire(repository: &Repository) -> io::Result<Option<RunLock>> {
+    let path = path(repository);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)?;
+    match file.try_lock() {
+        Ok(()) => (),
+        Err(TryLockError::WouldBlock) => return Ok(None),
+        Err(TryLockError::Error(e)) => return Err(e),
+    }
+    // Only to tell whoever finds it locked, the lock itself is what counts.
+    file.set_len(0)?;
+    writeln!(file, "{}", process::id())?;
+    Ok(Some(RunLock { _file: file }))
+}
+
+/// The process id of the run holding the lock, as it wrote it.
+pub fn holder(repository: &Repository) -> Option<u32> {
+    fs::read_to_string(path(repository))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Locks the repository for the rest of the process, or stops the run when
+/// another one is using it.
+pub fn acquire_or_exit(repository: &Repository) -> RunLock {
+    match acquire(repository) {
+        Ok(Some(lock)) => lock,
+        Ok(None) => {
+            match holder(repository) {
+                Some(pid) => error!(
+                    "Another git-integrate run (process {}) is using this repository",
+                    pid
+                ),
+                None => error!("Another git-integrate run is using this repository"),
+            }
+            error!("Wait for it to finish, or run in a separate clone.");
+            process::exit(1);
+        }
+        Err(e) => panic!("Could not lock the repository: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn one_run_per_repository() {
+        let repository = |name: &str| {
+            let path = env::temp_dir().join(format!("git-integrate-{}-{}", process::id(), name));
+            let _ = fs::remove_dir_all(&path);
+            Repository::init(&path).unwrap()
+        };
+        let (first, second) = (repository("lock-first"), repository("lock-second"));
+
+        let lock = acquire(&first).unwrap();
+        assert!(lock.is_some());
+        assert!(acquire(&first).unwrap().is_none());
+        assert_eq!(holder(&first), Some(process::id()));
+        assert!(acquire(&second).unwrap().is_some());
+        drop(lock);
+        assert!(acquire(&first).unwrap().is_some());
+    }
+}
@@ -0,0 +1,60 @@
+use git2::{Repository, Sort};
+use std::io;
+use std::process::Stdio;
+
+use super::git_extras::git_command;
+use super::timeouts::Timed;
+
+/// The authors of the commits `revision` adds on top of `base`, in the order
+/// they first appear, each once under the identity `.mailmap` maps them to.
+pub fn authors(repository: &Repository, base: &str, revision: &str) -> io::Result<Vec<String>> {
+    let contacts = || -> Result<Vec<String>, git2::Error> {
+        let mut walk = repository.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        walk.push(repository.revparse_single(revision)?.id())?;
+        walk.hide(repository.revparse_single(base)?.id())?;
+
+        let mut contacts = vec![];
+        for id in walk {
+            let commit = repository.find_commit(id?)?;
+            let author = commit.author();
+            let contact = format!(
+                "{} <{}>",
+                author.name().unwrap_or_default(),
+                author.email().unwrap_or_default()
+            );
+            if !contacts.contains(&contact) {
+                contacts.push(contact);
+            }
+        }
+        Ok(contacts)
+    };
+
+    let contacts = contacts().map_err(|e| io::Error::other(e.to_string()))?;
+    canonical(repository, &contacts)
+}
+
+/// Maps `Name <email>` contacts through the repository's mailmap with `git
+/// check-mailmap`, which also honors `mailmap.file` and `mailmap.blob`, and
+/// drops the duplicates that leaves.
+pub fn canonical(repository: &Repository, contacts: &[String]) -> io::Result<Vec<String>> {
+    if contacts.is_empty() {
+        return Ok(vec![]);
+    }
+    let output = git_command(repository)
+        .arg("check-mailmap")
+        .args(contacts)
+        .stdout(Stdio::piped())
+        .output_timed()?;
+    if !output.status.success() {
+        return Err(io::Error::other("git check-mailmap failed"));
+    }
+
+    let mut canonical: Vec<String> = vec![];
+    for contact in String::from_utf8_lossy(&output.stdout).lines() {
+        if !canonical.iter().any(|c| c == contact) {
+            canonical.push(contact.to_string());
+        }
+    }
+    Ok(canonical)
+}
@@ -5,50 +5,281 @@ extern crate reqwest;
 extern crate serde;
 extern crate serde_derive;
 
+#[macro_use]
+mod messages;
+
+mod approval;
+mod audit;
+mod backend;
+mod binary;
+mod bugreport;
+mod bump;
+mod categories;
+mod cli;
+mod collisions;
+mod compare;
+mod conflicts;
+#[cfg(feature = "server")]
+mod daemon;
+mod dates;
+mod drift;
+mod escalate;
+mod executor;
+mod explain;
+mod freeze;
+mod gc;
 mod git_extras;
+mod git_ops;
 mod github;
+mod graph;
+#[cfg(feature = "server")]
+mod health;
+mod history;
+mod ignore;
+mod labels;
+mod lock;
+mod mailmap;
+mod mappings;
+mod merge;
+mod notify;
+mod org;
+mod ownership;
+mod planner;
+mod providers;
+mod quarantine;
+mod report;
+mod reporter;
+mod run_env;
+mod secrets;
+mod selftest;
+mod semantic;
+#[cfg(feature = "server")]
+mod serve;
+mod settings;
+mod shallow;
+#[cfg(feature = "conflict-shell")]
+mod shell;
+mod sizes;
+mod speculate;
+mod stacks;
+mod state;
+mod stats;
+mod temp_refs;
+mod timeouts;
+mod trains;
+mod verify;
+mod version;
+mod writeback;
 
-use clap::{App, Arg};
-use git2::{Config, Repository, Status};
-use git_extras::Repo;
-use std::process::{Command, ExitStatus};
-use std::{env, io, process};
+use git2::Repository;
+use git_extras::{Identity, Repo};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, process};
 
-use github::branches_by_milestone;
+use backend::GitBackend;
+use executor::{head_of, Executor};
+use history::History;
+use planner::{MergePlan, Planner};
+use report::Report;
+use reporter::Reporter;
+use settings::Settings;
 
 fn main() {
-    let opts = App::new("git-integrate")
-        .arg(
-            Arg::with_name("milestone")
-                .value_name("MILESTONE")
-                .help("GitHub milestone number")
-                .index(1),
-        )
-        .arg(
-            Arg::with_name("branch")
-                .value_name("BRANCH")
-                .help("Branch to build")
-                .index(2),
-        )
-        .get_matches();
-
-    let milestone = opts
-        .value_of("milestone")
-        .and_then(|x| x.trim().parse().ok())
-        .expect("No github label provided");
-    let dest_branch = opts.value_of("branch").expect("No branch provided");
+    bugreport::install();
+    let matches = cli::app().get_matches();
+    let run = cli::run(&matches);
+    let opts = run.opts;
+
+    messages::set_quiet(opts.is_present("quiet"));
+    if let Some(output) = opts.value_of("output") {
+        messages::set_output(messages::Output::parse(output).expect("Invalid --output"));
+    }
+    if let Some(remote) = opts.value_of("remote") {
+        git_extras::set_remote(remote).expect("Could not set the remote");
+    }
+    if opts.is_present("no-api-writes") {
+        github::block_writes();
+    }
+    if let Some(host) = opts.value_of("allowed-host") {
+        github::set_allowed_host(host).unwrap_or_else(|e| panic!("{}", e));
+    }
+    if let Some(timeout) = opts.value_of("http-timeout") {
+        let timeout = timeouts::parse(timeout).expect("Invalid --http-timeout");
+        timeouts::set_http(timeout).unwrap_or_else(|e| panic!("{}", e));
+    }
+    if let Some(timeout) = opts.value_of("git-timeout") {
+        let timeout = timeouts::parse(timeout).expect("Invalid --git-timeout");
+        timeouts::set_git(timeout).unwrap_or_else(|e| panic!("{}", e));
+    }
+    if let Some(budget) = opts.value_of("merge-timeout") {
+        let budget = timeouts::parse(budget).expect("Invalid --merge-timeout");
+        timeouts::set_merge(budget).unwrap_or_else(|e| panic!("{}", e));
+    }
+    if let Some(deadline) = opts.value_of("deadline") {
+        let deadline = timeouts::parse(deadline).expect("Invalid --deadline");
+        timeouts::set_deadline(deadline).unwrap_or_else(|e| panic!("{}", e));
+    }
+    let interactive = opts.is_present("interactive");
+    if let Some(opts) = opts.subcommand_matches("org") {
+        let settings = Settings::open_default().expect("Could not load the git configuration");
+        set_api(&settings);
+        set_dates(&settings);
+        let extra_args = extra_args(opts, interactive);
+        if !interactive {
+            git_extras::sandbox_env(&identity(&settings));
+        }
+        let token = settings.get_string("integrate.github-token");
+
+        let failed = match org::run(
+            token.as_deref(),
+            opts.value_of("org").unwrap(),
+            opts.value_of("topic").unwrap(),
+            &mappings::Mapping {
+                branch: opts.value_of("branch").unwrap().to_string(),
+                selector: opts.value_of("label").unwrap().to_string(),
+                base: None,
+            },
+            Path::new(opts.value_of_os("workspace").unwrap()),
+            &extra_args,
+            batch_size(&settings),
+        ) {
+            Ok(failed) => failed,
+            Err(e) => panic!("{}", e),
+        };
+
+        if !failed.is_empty() {
+            error!("\nFailed in: {}", failed.join(", "));
+            process::exit(1);
+        }
+        summary!("\nFinished integrating every repository successfully!");
+        return;
+    }
+
+    if opts.subcommand_matches("selftest").is_some() {
+        match selftest::run() {
+            Ok(true) => summary!("\nSelf-test passed"),
+            Ok(false) => {
+                error!("\nSelf-test failed");
+                process::exit(1);
+            }
+            Err(e) => panic!("Could not run the self-test: {}", e),
+        }
+        return;
+    }
 
     let current_dir = match env::current_dir() {
         Ok(current_dir) => current_dir,
         Err(e) => panic!("{}", e),
     };
 
-    let repository = match Repository::discover(current_dir.as_path()) {
+    let repository = match Repository::open_from_env() {
         Ok(repository) => repository,
         Err(e) => panic!("{}", e),
     };
 
-    let remote = match repository.find_remote("origin") {
+    if let Some(opts) = opts.subcommand_matches("compare") {
+        compare::run(
+            &repository,
+            opts.value_of("integration").unwrap(),
+            opts.value_of("production").unwrap(),
+        );
+        return;
+    }
+
+    if let Some(opts) = opts.subcommand_matches("audit") {
+        let runs = match opts.value_of_os("manifest") {
+            Some(path) => vec![std::fs::read(path)
+                .map_err(|e| e.to_string())
+                .and_then(|json| serde_json::from_slice(&json).map_err(|e| e.to_string()))
+                .unwrap_or_else(|e| panic!("Could not read the manifest: {}", e))],
+            None => History::open(&repository)
+                .runs()
+                .expect("Could not read the history"),
+        };
+        if !audit::run(
+            &repository,
+            opts.value_of("branch").unwrap(),
+            &opts
+                .value_of("base")
+                .map(String::from)
+                .unwrap_or_else(|| conflicts::default_base(&repository)),
+            &runs,
+            opts.is_present("allow-resolved"),
+        ) {
+            process::exit(1);
+        }
+        return;
+    }
+
+    if opts.subcommand_matches("continue").is_some() {
+        process::exit(continue_run(&repository));
+    }
+
+    if opts.is_present("abort") || opts.subcommand_matches("abort").is_some() {
+        let _lock = lock::acquire_or_exit(&repository);
+        let state = state::State::load(&repository)
+            .expect("Could not read the stopped run")
+            .expect("There is no stopped run to abort");
+        if !state.abort(&repository).expect("Could not abort the run") {
+            error!("Could not put {} back as it was", state.dest_branch);
+            process::exit(1);
+        }
+        state::State::clear(&repository).expect("Could not discard the stopped run");
+        temp_refs::clean_up(&repository);
+        // Nothing should keep what the run merged once it's given up on.
+        if state.started_at > 0 {
+            if let Err(e) = gc::forget_reflog(&repository, &state.dest_branch, state.started_at) {
+                warn!("Could not drop the run from the reflog: {}", e);
+            }
+        }
+        let settings = Settings::open(&repository).expect("Could not load the git configuration");
+        let gc = opts.is_present("gc")
+            || settings
+                .for_branch_bool(
+                    state.branch_template.as_ref().unwrap_or(&state.dest_branch),
+                    "gc",
+                )
+                .unwrap_or(false);
+        gc::clean_up(&repository, state.started_at, gc);
+        summary!("Aborted the run of {}", state.dest_branch);
+        return;
+    }
+
+    if opts.subcommand_matches("cleanup").is_some() {
+        let _lock = lock::acquire_or_exit(&repository);
+        if let Some(state) =
+            state::State::load(&repository).expect("Could not read the stopped run")
+        {
+            error!(
+                "The stopped run of {} still needs its refs, continue or abort it first",
+                state.dest_branch
+            );
+            process::exit(1);
+        }
+        let removed = temp_refs::remove_all(&repository)
+            .unwrap_or_else(|e| panic!("Could not delete the temporary refs: {}", e));
+        summary!("Deleted {} temporary refs", removed);
+        return;
+    }
+
+    if let Some(opts) = opts.subcommand_matches("stats") {
+        let limit = opts
+            .value_of("limit")
+            .and_then(|x| x.trim().parse().ok())
+            .expect("Invalid limit");
+        match History::open(&repository).runs() {
+            Ok(runs) => stats::print(&runs, limit),
+            Err(e) => panic!("{}", e),
+        }
+        return;
+    }
+
+    if repository.is_bare() {
+        panic!("{} has no work tree to integrate in", current_dir.display());
+    }
+
+    let remote = match repository.find_remote(git_extras::remote()) {
         Ok(remote) => remote,
         Err(e) => panic!("{}", e),
     };
@@ -58,90 +289,865 @@ fn main() {
         None => panic!("Could not build remote info"),
     };
 
-    let config = Config::open_default().expect("Could not find a git configuration file!");
-    let github_token = config
-        .get_string("integrate.github-token")
-        .expect("Could not find integrate.github-token in any git configuration file!");
+    bugreport::trace_git(&repository);
+    let settings = Settings::open(&repository).expect("Could not load the git configuration");
+    set_api(&settings);
+    set_dates(&settings);
+    if !interactive {
+        git_extras::sandbox_env(&identity(&settings));
+    }
+    if opts.is_present("version-check") {
+        let base = opts
+            .value_of("base")
+            .map(String::from)
+            .unwrap_or_else(|| conflicts::default_base(&repository));
+        let required = settings.get_string("integrate.required-version");
+        if !version::satisfied(&repository, &base, required) {
+            process::exit(1);
+        }
+        summary!(
+            "git-integrate {} is recent enough for this repository",
+            version::CURRENT
+        );
+        return;
+    }
+    let provider_name = settings
+        .get_string("integrate.provider")
+        .unwrap_or_else(|| "github".to_string());
+    let api_url = settings.get_string("integrate.api-url");
+    let provider_token = settings.get_string(&format!("integrate.{}-token", provider_name));
+    let provider = providers::open(
+        &provider_name,
+        api_url.as_deref(),
+        provider_token.as_deref(),
+        &repo,
+    )
+    .unwrap_or_else(|| panic!("Unknown integrate.provider {}", provider_name));
+    let github_token = settings.get_string("integrate.github-token");
+    if github_token.is_none() && provider_name == "github" {
+        warn!(
+            "No integrate.github-token found in any git configuration file, using the \
+             \nunauthenticated API (public repositories only, 60 requests an hour)"
+        );
+    }
+    if let (Some(token), "github") = (github_token.as_deref(), provider_name.as_str()) {
+        match github::rest::check_token(token, &repo) {
+            Ok(None) => (),
+            Ok(Some(problem)) => {
+                error!("{}", problem);
+                process::exit(1);
+            }
+            Err(e) => warn!("Could not check integrate.github-token: {}", e),
+        }
+        if opts.is_present("push") && !run.dry_run && !opts.is_present("explain") {
+            match github::push_denied(token, &repo) {
+                Ok(None) => (),
+                Ok(Some(problem)) => {
+                    error!("{}", problem);
+                    process::exit(1);
+                }
+                Err(e) => warn!("Could not check the permission to push: {}", e),
+            }
+        }
+    }
+    let require_token = || {
+        github_token
+            .as_deref()
+            .expect("Could not find integrate.github-token in any git configuration file!")
+    };
 
-    if !git_fetch().expect("Error fetching from remote").success() {
-        process::exit(1)
+    let derive_rules: Vec<mappings::Rule> = opts
+        .values_of("derive-branch")
+        .map(|rules| rules.map(String::from).collect::<Vec<_>>())
+        .unwrap_or_default()
+        .iter()
+        .map(|rule| mappings::Rule::parse(rule).unwrap_or_else(|e| panic!("Invalid rule: {}", e)))
+        .collect();
+    if !derive_rules.is_empty() {
+        let mappings = mappings::derive(github_token.as_deref(), &repo, &derive_rules)
+            .unwrap_or_else(|e| panic!("{}", e));
+        if mappings.is_empty() {
+            summary!("No open pull request has a label matching --derive-branch");
+            return;
+        }
+        for mapping in &mappings {
+            info!("{} => {}", mapping.selector, mapping.branch);
+        }
+        build_all(
+            github_token.as_deref(),
+            &repo,
+            &settings,
+            &mappings,
+            forwarded_args(interactive),
+        );
     }
 
-    if !git_checkout(dest_branch)
-        .expect(&format!("Could not checkout branch {}", dest_branch))
-        .success()
-    {
-        process::exit(1)
+    if let Some(opts) = opts.subcommand_matches("all") {
+        let mut mappings = mappings::load(&settings);
+        let rules: Vec<mappings::Rule> = settings
+            .get_all("integrate.derive-branch")
+            .iter()
+            .map(|rule| {
+                mappings::Rule::parse(rule)
+                    .unwrap_or_else(|e| panic!("Invalid integrate.derive-branch: {}", e))
+            })
+            .collect();
+        if !rules.is_empty() {
+            for derived in mappings::derive(github_token.as_deref(), &repo, &rules)
+                .unwrap_or_else(|e| panic!("{}", e))
+            {
+                if !mappings.iter().any(|m| m.branch == derived.branch) {
+                    mappings.push(derived);
+                }
+            }
+        }
+        if mappings.is_empty() {
+            error!("No branches configured with integrate.<branch>.selection");
+            process::exit(1);
+        }
+        let extra_args = extra_args(opts, interactive);
+
+        build_all(
+            github_token.as_deref(),
+            &repo,
+            &settings,
+            &mappings,
+            extra_args,
+        );
     }
 
-    let branches = match branches_by_milestone(github_token, repo, milestone) {
-        Ok(branches) => branches,
-        Err(e) => panic!("{}", e),
-    };
+    #[cfg(feature = "server")]
+    if let Some(opts) = opts.subcommand_matches("daemon") {
+        let default_schedule = opts.value_of("schedule");
+        let schedule = daemon::load(&repository, mappings::load(&settings), |mapping| {
+            settings
+                .get_string(&format!("integrate.{}.schedule", mapping.branch))
+                .or_else(|| default_schedule.map(String::from))
+        });
+        if schedule.is_empty() {
+            error!("No branches configured with integrate.<branch>.selection and a schedule");
+            process::exit(1);
+        }
+        let jitter: u64 = opts
+            .value_of("jitter")
+            .and_then(|x| x.trim().parse().ok())
+            .expect("Invalid jitter");
 
-    for branch in branches {
-        println!("\nMerging {}", branch);
-        merge_branch(branch, &repository);
+        let extra_args = extra_args(opts, interactive);
+        daemon::run(
+            github_token.as_deref(),
+            &repo,
+            schedule,
+            Duration::from_secs(jitter),
+            batch_size(&settings),
+            &extra_args,
+            opts.value_of("listen"),
+        );
     }
 
-    println!("\nFinished merging successfully!");
-}
+    if let Some(opts) = opts.subcommand_matches("bump-parents") {
+        let branch = opts.value_of("branch").unwrap();
+        bump_parents(require_token(), &repo, &repository, &settings, branch);
+        process::exit(0);
+    }
 
-fn merge_branch(branch: String, repository: &Repository) {
-    if !git_merge(&branch)
-        .expect(&format!("Failure merging branch {}", branch))
-        .success()
-    {
-        let dirty = repository
-            .statuses(None)
-            .expect("Error checking dirty repository")
-            .iter()
-            .any(|s| s.status() == Status::CONFLICTED);
+    #[cfg(feature = "server")]
+    if let Some(opts) = opts.subcommand_matches("serve") {
+        let interval: u64 = opts
+            .value_of("interval")
+            .and_then(|x| x.trim().parse().ok())
+            .expect("Invalid interval");
+        let selector = opts.value_of("selection").unwrap();
+        let criteria = planner::SelectionCriteria {
+            selector: selector.to_string(),
+            selection: github::Selection::parse(selector),
+            dest_branch: opts.value_of("branch").unwrap(),
+        };
+        let base = conflicts::default_base(&repository);
+        let quarantine_label = settings
+            .for_branch(criteria.dest_branch, "quarantine-label")
+            .unwrap_or_else(|| quarantine::DEFAULT_LABEL.to_string());
+        // Only its exclusions are used, to leave out what a run would.
+        let planner = Planner {
+            opts,
+            settings: &settings,
+            criteria: &criteria,
+            dest_branch: &dates::expand(criteria.dest_branch),
+            repository: &repository,
+            repo: &repo,
+            git: &backend::Subprocess {
+                repository: &repository,
+                base: &base,
+            },
+            base: &base,
+            remote_base: base.strip_prefix(&format!("{}/", git_extras::remote())),
+            provider: &*provider,
+            provider_name: &provider_name,
+            github_token: github_token.as_deref(),
+            quarantine_label: &quarantine_label,
+            dry_run: true,
+            graph: false,
+        };
+        serve::run(
+            require_token(),
+            &planner,
+            Duration::from_secs(interval * 60),
+            opts.value_of("listen"),
+        );
+    }
 
-        if dirty {
-            println!(
-                "\nMerge conflict detected, either fix the conflict and \
-                 \nuse `git commit --no-edit` to record and commit or use \
-                 \n`git merge --abort` to quit this merge"
+    // Held until the process exits.
+    let _lock = lock::acquire_or_exit(&repository);
+
+    // A release train is built into a branch of its own.
+    let train_branch;
+    let criteria = match opts.value_of("train") {
+        Some(version) => {
+            if opts.is_present("milestone") {
+                panic!("Give either a milestone or label, or --train");
+            }
+            if !trains::is_valid(version) {
+                panic!("{} can't name a branch", version);
+            }
+            train_branch = trains::branch(version);
+            // Only GitHub milestones are looked up by title, and pull
+            // requests read from a file aren't selected at all.
+            let selection = if provider_name == "github" && !opts.is_present("pull-requests-from") {
+                trains::selection(github_token.as_deref(), &repo, version).unwrap_or_else(|e| {
+                    panic!("Could not look up the milestone {}: {}", version, e)
+                })
+            } else {
+                github::Selection::Label(version.to_string())
+            };
+            planner::SelectionCriteria {
+                selector: version.to_string(),
+                selection,
+                dest_branch: &train_branch,
+            }
+        }
+        None => planner::SelectionCriteria::from_args(opts),
+    };
+    let dest_branch = criteria.dest_branch;
+    let selector = criteria.selector.as_str();
+    let setting = |key: &str| settings.for_branch(dest_branch, key);
+    let writes_back = opts.is_present("write-back") || setting("write-back").is_some();
+    // Parents follow what is pushed.
+    let bump_parents = if opts.is_present("push") {
+        bump::parents(&settings, dest_branch)
+    } else {
+        vec![]
+    };
+    // Posting back goes to the GitHub API whatever the provider, where the
+    // project doesn't exist or, worse, another one of the same name does.
+    if provider_name != "github" {
+        let github_only: Vec<&str> = [
+            ("--notify-conflicts", opts.is_present("notify-conflicts")),
+            ("write-back", writes_back),
+            ("bump-parent", !bump_parents.is_empty()),
+            (
+                "require-approval-from",
+                opts.is_present("require-approval-from")
+                    || setting("require-approval-from").is_some(),
+            ),
+            ("status-label", setting("status-label").is_some()),
+            ("build-label", setting("build-label").is_some()),
+            ("quarantine-after", setting("quarantine-after").is_some()),
+            ("open-issue-after", setting("open-issue-after").is_some()),
+        ]
+        .iter()
+        .filter(|(_, given)| *given)
+        .map(|(name, _)| *name)
+        .collect();
+        if !github_only.is_empty() {
+            error!(
+                "{} only work with GitHub, not integrate.provider {}",
+                github_only.join(", "),
+                provider_name
             );
             process::exit(1);
         }
+    }
+    // Found out now rather than after merging, or pushing, everything.
+    if (opts.is_present("notify-conflicts") || writes_back || !bump_parents.is_empty())
+        && github_token.is_none()
+    {
+        error!(
+            "--notify-conflicts, integrate.write-back and bumping integrate.{}.bump-parent \
+             need integrate.github-token, which isn't in any git configuration file",
+            dest_branch
+        );
+        process::exit(1);
+    }
+    // A run stopped by a conflict is carried on by `continue`, which runs it
+    // again with --resume. Any other run starts over.
+    let resumed = match state::State::load(&repository).expect("Could not read the stopped run") {
+        // It stays until the run finishes, so a crash on the way still
+        // leaves it to continue or abort. Stopping again saves it anew.
+        Some(state) if opts.is_present("resume") => Some(state),
+        // A dry run leaves the stopped run alone.
+        Some(_) if run.dry_run || run.graph || opts.is_present("explain") => None,
+        Some(stopped) => {
+            warn!(
+                "Starting over instead of continuing the stopped run of {}",
+                stopped.dest_branch
+            );
+            state::State::clear(&repository).expect("Could not discard the stopped run");
+            None
+        }
+        None if opts.is_present("resume") => panic!("There is no stopped run to continue"),
+        None => None,
+    };
+    // Whatever a crashed run left behind.
+    if resumed.is_none() {
+        temp_refs::clean_up(&repository);
+    }
+
+    // Settings and history stay keyed by the branch as given, dates and all.
+    // A continued run keeps the name it started with.
+    let branch_template = dest_branch;
+    let dest_branch = &resumed.as_ref().map_or_else(
+        || dates::expand(dest_branch),
+        |state| state.dest_branch.clone(),
+    )[..];
+    if !git2::Reference::is_valid_name(&format!("refs/heads/{}", dest_branch)) {
+        error!("{} is not a valid branch name", dest_branch);
+        process::exit(1);
+    }
+
+    // A configured base names another integration branch, as pushed; mappings
+    // built in the same run pass the local one they just built.
+    let base = opts
+        .value_of("base")
+        .map(String::from)
+        .or_else(|| {
+            setting("base").map(|branch| git_extras::remote_branch(&dates::expand(&branch)))
+        })
+        .unwrap_or_else(|| conflicts::default_base(&repository));
+    if !version::satisfied(&repository, &base, setting("required-version")) {
+        process::exit(1);
+    }
+    let remote_base = base.strip_prefix(&format!("{}/", git_extras::remote()));
+    // git itself stays the default, as only it does rerere.
+    let git: Box<dyn GitBackend> = match opts
+        .value_of("git-backend")
+        .map(String::from)
+        .or_else(|| setting("git-backend"))
+        .as_deref()
+    {
+        None | Some("subprocess") => Box::new(backend::Subprocess {
+            repository: &repository,
+            base: &base,
+        }),
+        Some("in-process") => Box::new(git_ops::InProcess {
+            repository: &repository,
+            base: &base,
+        }),
+        Some(other) => panic!("Unknown integrate.git-backend {}", other),
+    };
+    let base_moved = opts
+        .value_of("on-base-moved")
+        .map(String::from)
+        .or_else(|| setting("on-base-moved"))
+        .map(|value| {
+            drift::Policy::parse(&value)
+                .unwrap_or_else(|| panic!("Unknown integrate.on-base-moved {}", value))
+        })
+        .unwrap_or(drift::Policy::Fail);
+    let quarantine_label =
+        setting("quarantine-label").unwrap_or_else(|| quarantine::DEFAULT_LABEL.to_string());
+
+    let plan = match &resumed {
+        Some(state) => MergePlan::resume(state, &base, remote_base),
+        None => Planner {
+            opts,
+            settings: &settings,
+            criteria: &criteria,
+            dest_branch,
+            repository: &repository,
+            repo: &repo,
+            git: &*git,
+            base: &base,
+            remote_base,
+            provider: &*provider,
+            provider_name: &provider_name,
+            github_token: github_token.as_deref(),
+            quarantine_label: &quarantine_label,
+            dry_run: run.dry_run,
+            graph: run.graph,
+        }
+        .plan(),
+    };
+
+    let checkpoint_every: Option<usize> = opts
+        .value_of("checkpoint-every")
+        .map(|x| x.trim().parse().expect("Invalid checkpoint interval"))
+        .filter(|&n| n > 0);
+    let checkpoint_branch = opts
+        .value_of("checkpoint-branch")
+        .map(String::from)
+        .unwrap_or_else(|| format!("integrate-checkpoint/{}", dest_branch));
+
+    let freeze = freeze::Freeze {
+        windows: settings
+            .get_all("integrate.freeze")
+            .into_iter()
+            .chain(settings.get_all(&format!("integrate.{}.freeze", branch_template)))
+            .map(|spec| {
+                let window = freeze::Window::parse(&spec)
+                    .unwrap_or_else(|| panic!("Invalid freeze window {}", spec));
+                (spec, window)
+            })
+            .collect(),
+        overridden: opts.is_present("override-freeze"),
+    };
 
-        if !git_commit()
-            .expect(&format!("Failure merging branch {}", branch))
-            .success()
+    let secret_scan = if opts.is_present("scan-secrets")
+        || settings.get_bool("integrate.scan-secrets").unwrap_or(false)
+    {
+        let rules = secrets::rules(&settings.get_all("integrate.secret-pattern"))
+            .unwrap_or_else(|e| panic!("Invalid integrate.secret-pattern: {}", e));
+        let allow: Vec<regex::Regex> = settings
+            .get_all("integrate.secret-allow")
+            .iter()
+            .map(|pattern| {
+                regex::Regex::new(pattern)
+                    .unwrap_or_else(|e| panic!("Invalid integrate.secret-allow: {}", e))
+            })
+            .collect();
+        Some((rules, allow))
+    } else {
+        None
+    };
+
+    let summary = opts
+        .value_of_os("summary")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("GITHUB_STEP_SUMMARY").map(PathBuf::from));
+    // A continued run reports from the merge it stopped on.
+    let start = resumed.as_ref().map_or(0, |state| state.stopped_at);
+    let mut report = Report::new(dest_branch, selector, &plan.pull_requests[start..]);
+    report.branch_template = branch_template.to_string();
+    let history = History::open(&repository);
+    report.excluded = plan.excluded.clone();
+
+    let last_build = history.last_build(branch_template).unwrap_or_else(|e| {
+        warn!("Could not read the run history: {}", e);
+        None
+    });
+    if let Some(last_build) = &last_build {
+        report.updated = plan
+            .pull_requests
+            .iter()
+            .filter(
+                |pr| match (last_build.head_of(pr.number), head_of(&repository, pr)) {
+                    (Some(built), Some(head)) => built != head,
+                    _ => false,
+                },
+            )
+            .map(|pr| pr.number)
+            .collect();
+    }
+    if !report.updated.is_empty() {
+        info!("\nUpdated since the last build of {}:", dest_branch);
+        for pr in plan
+            .pull_requests
+            .iter()
+            .filter(|pr| report.updated.contains(&pr.number))
         {
-            println!("Failure mergeing branch {}", branch);
+            info!("  #{} {} ({})", pr.number, pr.title, pr.branch);
+        }
+    }
+
+    let notify = opts
+        .value_of("notify-conflicts")
+        .and_then(notify::Notify::parse);
+    let write_back = opts
+        .value_of("write-back")
+        .map(String::from)
+        .or_else(|| setting("write-back"))
+        .map(|value| {
+            writeback::WriteBack::parse(&value)
+                .unwrap_or_else(|| panic!("Unknown integrate.write-back {}", value))
+        });
+    if write_back.is_some() && !opts.is_present("push") {
+        warn!("Without --push only conflicts are written back");
+    }
+    let push_remotes = planner::push_remotes(opts, &settings, branch_template);
+    for push_remote in &push_remotes {
+        if repository.find_remote(push_remote).is_err() {
+            error!("There is no remote {} to push to", push_remote);
             process::exit(1);
         }
     }
+    if !push_remotes.is_empty() && !opts.is_present("push") {
+        warn!(
+            "Without --push nothing is pushed to {}",
+            push_remotes.join(", ")
+        );
+    }
+    let verify = opts
+        .value_of("verify")
+        .map(String::from)
+        .or_else(|| setting("verify"))
+        .map(|command| verify::Verify {
+            command,
+            skip_failures: opts.is_present("verify-skip-failures")
+                || settings
+                    .for_branch_bool(branch_template, "verify-skip-failures")
+                    .unwrap_or(false),
+            retries: opts
+                .value_of("verify-retries")
+                .map(String::from)
+                .or_else(|| setting("verify-retries"))
+                .map_or(0, |retries| {
+                    retries
+                        .parse()
+                        .unwrap_or_else(|_| panic!("Invalid --verify-retries {}", retries))
+                }),
+        });
+    report.verified = verify.is_some();
+    let train = opts.value_of("train").map(|version| {
+        let runs = history.runs().unwrap_or_else(|e| {
+            warn!("Could not read the run history: {}", e);
+            vec![]
+        });
+        trains::Train::next(version, &runs)
+    });
+    let reporter = Reporter::configure(
+        &settings,
+        criteria.dest_branch,
+        &repo,
+        github_token.as_deref(),
+        history,
+        summary,
+        &quarantine_label,
+    )
+    .with_manifest(
+        opts.value_of_os("manifest")
+            .map(PathBuf::from)
+            .or_else(|| setting("manifest").map(PathBuf::from)),
+    );
+
+    let binary_policy = opts
+        .value_of("binary-conflicts")
+        .map(String::from)
+        .or_else(|| setting("binary-conflicts"))
+        .map(|value| {
+            binary::Policy::parse(&value)
+                .unwrap_or_else(|| panic!("Unknown binary conflict policy {}", value))
+        })
+        .unwrap_or(binary::Policy::Fail);
+    let merge_options = merge::Options {
+        rerun: "git integrate continue",
+        autocommit: !opts.is_present("no-rerere-autocommit"),
+        binary_policy,
+    };
+    #[cfg(feature = "conflict-shell")]
+    let resolver = if opts.is_present("mergetool") {
+        Some(shell::Resolver::Mergetool)
+    } else if interactive {
+        Some(shell::Resolver::Shell(setting("conflict-shell")))
+    } else {
+        None
+    };
+    let args: Vec<String> = match &resumed {
+        Some(state) => state.args.clone(),
+        None => env::args().skip(1).collect(),
+    };
+    let run_id = resumed
+        .as_ref()
+        .map(|state| state.run_id.clone())
+        .filter(|run_id| !run_id.is_empty())
+        .unwrap_or_else(run_env::new_run_id);
+    run_env::set_run(&repository, &run_id, &repo, dest_branch, &base)
+        .expect("Could not set the run environment");
+
+    Executor {
+        git: &*git,
+        repository: &repository,
+        repo: &repo,
+        dest_branch,
+        base: &base,
+        github_token: github_token.as_deref(),
+        merge_options,
+        #[cfg(feature = "conflict-shell")]
+        resolver,
+        notify,
+        checkpoint_every,
+        checkpoint_branch,
+        freeze,
+        secret_scan,
+        base_moved,
+        verify,
+        push: opts.is_present("push"),
+        push_remotes,
+        write_back,
+        bump_parents,
+        ownership: opts
+            .value_of_os("ownership")
+            .map(PathBuf::from)
+            .or_else(|| setting("ownership").map(PathBuf::from)),
+        train,
+        args,
+        run_id,
+        started_at: resumed
+            .as_ref()
+            .map_or_else(|| chrono::Utc::now().timestamp(), |state| state.started_at),
+        gc: opts.is_present("gc")
+            || settings
+                .for_branch_bool(branch_template, "gc")
+                .unwrap_or(false),
+        reporter,
+    }
+    .run(plan, report, resumed.as_ref());
 }
 
-fn git_fetch() -> io::Result<ExitStatus> {
-    Command::new("git").arg("fetch").arg("--all").status()
+/// Opens or updates a pull request in every `integrate.<branch>.bump-parent`
+/// repository pointing its submodule at the pushed `branch`.
+fn bump_parents(
+    token: &str,
+    repo: &Repo,
+    repository: &Repository,
+    settings: &Settings,
+    branch: &str,
+) {
+    let parents = bump::parents(settings, branch);
+    if parents.is_empty() {
+        warn!("No integrate.{}.bump-parent configured", branch);
+        return;
+    }
+
+    let base = settings
+        .for_branch(branch, "base")
+        .map(|base| git_extras::remote_branch(&dates::expand(&base)))
+        .unwrap_or_else(|| conflicts::default_base(repository));
+    let git = backend::Subprocess {
+        repository,
+        base: &base,
+    };
+    let pushed = git
+        .ls_remote(&format!("refs/heads/{}", branch))
+        .expect("Error listing remote refs")
+        .unwrap_or_else(|| panic!("{} has not been pushed to {}", branch, git_extras::remote()));
+    let local = repository
+        .revparse_single(branch)
+        .map(|commit| commit.id().to_string());
+    if local.as_ref().is_ok_and(|local| *local != pushed) {
+        error!(
+            "{} on {} is not the local {}, push it before bumping the parents",
+            branch,
+            git_extras::remote(),
+            branch
+        );
+        process::exit(1);
+    }
+
+    if !bump::bump_all(token, repo, branch, &pushed, &parents) {
+        process::exit(1);
+    }
 }
 
-fn git_checkout(branch: &str) -> io::Result<ExitStatus> {
-    Command::new("git")
-        .arg("checkout")
-        .arg("--no-track")
-        .arg("-B")
-        .arg(branch)
-        .arg("origin/master")
-        .status()
+/// Builds each mapping in its own run and exits.
+fn build_all(
+    token: Option<&str>,
+    repo: &Repo,
+    settings: &Settings,
+    mappings: &[mappings::Mapping],
+    extra_args: Vec<String>,
+) -> ! {
+    let failed = match mappings::run(token, repo, mappings, batch_size(settings), &extra_args) {
+        Ok(failed) => failed,
+        Err(e) => panic!("{}", e),
+    };
+
+    if !failed.is_empty() {
+        error!("\nFailed to build: {}", failed.join(", "));
+        process::exit(1);
+    }
+    summary!("\nFinished building every branch successfully!");
+    process::exit(0)
 }
 
-fn git_merge(branch: &String) -> io::Result<ExitStatus> {
-    Command::new("git")
-        .arg("merge")
-        .arg("--no-ff")
-        .arg("--no-edit")
-        .arg("--rerere-autoupdate")
-        .arg("--log")
-        .arg(&format!("origin/{}", branch))
-        .status()
+/// This run's options minus `--derive-branch`, for the runs it starts.
+fn forwarded_args(interactive: bool) -> Vec<String> {
+    let mut forwarded = vec![];
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--derive-branch" {
+            args.next();
+        } else if !arg.starts_with("--derive-branch=") {
+            forwarded.push(arg);
+        }
+    }
+    if interactive && !forwarded.iter().any(|a| a == "--interactive") {
+        forwarded.push("--interactive".to_string());
+    }
+    forwarded
+}
+
+/// The options passed after `--`, plus those of this run that the runs it
+/// starts keep.
+fn extra_args(opts: &clap::ArgMatches, interactive: bool) -> Vec<String> {
+    let mut extra_args: Vec<String> = opts
+        .values_of("args")
+        .map(|args| args.map(String::from).collect())
+        .unwrap_or_default();
+    if messages::is_quiet() {
+        extra_args.push("--quiet".to_string());
+    }
+    if messages::output() == messages::Output::Legacy {
+        extra_args.extend(["--output".to_string(), "legacy".to_string()]);
+    }
+    if github::writes_blocked() {
+        extra_args.push("--no-api-writes".to_string());
+    }
+    if git_extras::remote() != "origin" {
+        extra_args.extend(["--remote".to_string(), git_extras::remote().to_string()]);
+    }
+    if interactive {
+        extra_args.push("--interactive".to_string());
+    }
+    extra_args
+}
+
+/// How many selections are asked for in one GraphQL request when several
+/// branches or repositories are built together.
+fn batch_size(settings: &Settings) -> usize {
+    settings
+        .get_string("integrate.graphql-batch-size")
+        .map(|size| {
+            size.trim()
+                .parse()
+                .expect("Invalid integrate.graphql-batch-size")
+        })
+        .unwrap_or(github::batch::DEFAULT_BATCH_SIZE)
+}
+
+/// Adds the `integrate.http-header` headers to every API request, paces the
+/// writes and sets the timeouts. The settings go into bug reports too.
+fn set_api(settings: &Settings) {
+    bugreport::set_config(settings);
+    if let Some(days) = settings.get_string("integrate.token-expiry-warning") {
+        let days = days
+            .trim()
+            .parse()
+            .expect("Invalid integrate.token-expiry-warning");
+        github::set_expiry_warning(days).unwrap_or_else(|e| panic!("{}", e));
+    }
+    // --allowed-host, when given, wins.
+    if let Some(host) = settings.get_string("integrate.allowed-host") {
+        let _ = github::set_allowed_host(&host);
+    }
+    // So do --http-timeout, --git-timeout, --merge-timeout and --deadline.
+    if let Some(timeout) = settings.get_string("integrate.http-timeout") {
+        let timeout = timeouts::parse(&timeout).expect("Invalid integrate.http-timeout");
+        let _ = timeouts::set_http(timeout);
+    }
+    if let Some(timeout) = settings.get_string("integrate.git-timeout") {
+        let timeout = timeouts::parse(&timeout).expect("Invalid integrate.git-timeout");
+        let _ = timeouts::set_git(timeout);
+    }
+    if let Some(budget) = settings.get_string("integrate.merge-timeout") {
+        let budget = timeouts::parse(&budget).expect("Invalid integrate.merge-timeout");
+        let _ = timeouts::set_merge(budget);
+    }
+    if let Some(deadline) = settings.get_string("integrate.deadline") {
+        let deadline = timeouts::parse(&deadline).expect("Invalid integrate.deadline");
+        let _ = timeouts::set_deadline(deadline);
+    }
+    github::set_headers(&settings.get_all("integrate.http-header"))
+        .unwrap_or_else(|e| panic!("Invalid integrate.http-header: {}", e));
+    // integrate.api-url points at GitHub Enterprise unless another provider
+    // is configured.
+    let provider = settings.get_string("integrate.provider");
+    if let (None | Some("github"), Some(url)) = (
+        provider.as_deref(),
+        settings.get_string("integrate.api-url"),
+    ) {
+        github::set_api_url(&url).unwrap_or_else(|e| panic!("{}", e));
+    }
+    if let (None | Some("github"), Some(url)) = (
+        provider.as_deref(),
+        settings.get_string("integrate.fallback-api-url"),
+    ) {
+        github::set_fallback_api_url(&url).unwrap_or_else(|e| panic!("{}", e));
+    }
+    if let Some(delay) = settings.get_string("integrate.write-delay") {
+        let delay = delay
+            .trim()
+            .parse()
+            .ok()
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            .expect("Invalid integrate.write-delay");
+        github::set_write_delay(delay).unwrap_or_else(|e| panic!("{}", e));
+    }
+}
+
+fn set_dates(settings: &Settings) {
+    if let Some(timezone) = settings.get_string("integrate.timezone") {
+        dates::set_timezone(&timezone)
+            .unwrap_or_else(|e| panic!("Invalid integrate.timezone: {}", e));
+    }
+    if let Some(format) = settings.get_string("integrate.date-format") {
+        dates::set_format(&format)
+            .unwrap_or_else(|e| panic!("Invalid integrate.date-format: {}", e));
+    }
+}
+
+/// The identity merge commits are made with unless `--interactive` is given.
+fn identity(settings: &Settings) -> Identity {
+    Identity {
+        name: settings
+            .get_string("integrate.author-name")
+            .unwrap_or_else(|| "git-integrate".to_string()),
+        email: settings
+            .get_string("integrate.author-email")
+            .unwrap_or_else(|| "git-integrate@localhost".to_string()),
+    }
 }
 
-fn git_commit() -> io::Result<ExitStatus> {
-    Command::new("git").arg("commit").arg("--no-edit").status()
+/// Commits the resolved merge a run stopped on, unless that was done by
+/// hand, and runs the rest of it. Answers the exit code.
+fn continue_run(repository: &Repository) -> i32 {
+    let state = state::State::load(repository)
+        .expect("Could not read the stopped run")
+        .expect("There is no stopped run to continue");
+    let git = backend::Subprocess {
+        repository,
+        base: &state.base,
+    };
+    if git.merging() {
+        if git.has_conflicts() {
+            error!(
+                "Resolve and `git add` the conflicts first, or give up with `git integrate --abort`"
+            );
+            return 1;
+        }
+        if !git.commit().expect("Could not commit the merge") {
+            return 1;
+        }
+    }
+
+    let pr = &state.pull_requests[state.stopped_at];
+    let head = repository.head().ok();
+    let on_branch = head.as_ref().and_then(|head| head.shorthand()) == Some(&state.dest_branch[..]);
+    let merged = head
+        .and_then(|head| head.peel_to_commit().ok())
+        .and_then(|commit| compare::pr_number(&commit));
+    if !on_branch || merged != Some(pr.number) {
+        error!(
+            "{} should end with the merge of #{} {} to continue",
+            state.dest_branch, pr.number, pr.branch
+        );
+        return 1;
+    }
+
+    let exe = env::current_exe().expect("Could not find git-integrate");
+    process::Command::new(exe)
+        .args(&state.args)
+        .arg("--resume")
+        .status()
+        .expect("Could not continue the run")
+        .code()
+        .unwrap_or(1)
 }
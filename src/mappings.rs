@@ -0,0 +1,167 @@
+use std::process::Command;
+use std::{env, fs, io};
+
+use regex::Regex;
+
+use super::dates;
+use super::git_extras::Repo;
+use super::github::{self, batch, rest, Selection};
+use super::settings::Settings;
+
+/// A branch built from a selection, configured as
+/// `integrate.<branch>.selection`.
+#[derive(Debug, Clone)]
+pub struct Mapping {
+    pub branch: String,
+    /// Milestone number or label, as it would be given on the command line.
+    pub selector: String,
+    /// Another integration branch to build on instead of master, configured as
+    /// `integrate.<branch>.base`.
+    pub base: Option<String>,
+}
+
+/// Every configured mapping, in configuration order.
+pub fn load(settings: &Settings) -> Vec<Mapping> {
+    settings
+        .subsections("integrate", "selection")
+        .into_iter()
+        .map(|(branch, selector)| Mapping {
+            base: settings.get_string(&format!("integrate.{}.base", branch)),
+            branch,
+            selector,
+        })
+        .collect()
+}
+
+/// Turns labels into mappings, as in `deploy:(?P<env>\w+) => staging-${env}`.
+/// The pattern has to match the whole label; the branch can refer to its
+/// groups by name or number.
+pub struct Rule {
+    pattern: Regex,
+    branch: String,
+}
+
+impl Rule {
+    pub fn parse(rule: &str) -> Result<Rule, String> {
+        let (pattern, branch) = rule
+            .split_once("=>")
+            .ok_or_else(|| format!("`{}` is not PATTERN => BRANCH", rule))?;
+        let pattern =
+            Regex::new(&format!("^(?:{})$", pattern.trim())).map_err(|e| e.to_string())?;
+        Ok(Rule {
+            pattern,
+            branch: branch.trim().to_string(),
+        })
+    }
+
+    fn derive(&self, label: &str) -> Option<String> {
+        let captures = self.pattern.captures(label)?;
+        let mut branch = String::new();
+        captures.expand(&self.branch, &mut branch);
+        Some(branch).filter(|branch| !branch.is_empty())
+    }
+}
+
+/// A mapping for every label on an open pull request that one of `rules`
+/// matches, the first matching rule winning.
+pub fn derive(
+    token: Option<&str>,
+    repo: &Repo,
+    rules: &[Rule],
+) -> Result<Vec<Mapping>, reqwest::Error> {
+    let mut mappings: Vec<Mapping> = vec![];
+    for pull in rest::open_pull_requests(token, repo)? {
+        for label in pull.labels {
+            if mappings
+                .iter()
+                .any(|m| github::same_label(&m.selector, &label.name))
+            {
+                continue;
+            }
+            if let Some(branch) = rules.iter().find_map(|rule| rule.derive(&label.name)) {
+                mappings.push(Mapping {
+                    branch,
+                    selector: label.name,
+                    base: None,
+                });
+            }
+        }
+    }
+    Ok(mappings)
+}
+
+/// `mappings` reordered so every mapping comes after the one it is based on,
+/// keeping the configuration order otherwise.
+fn ordered(mappings: &[Mapping]) -> io::Result<Vec<&Mapping>> {
+    let mut ordered: Vec<&Mapping> = vec![];
+    while ordered.len() < mappings.len() {
+        let next = mappings.iter().find(|mapping| {
+            !ordered.iter().any(|done| done.branch == mapping.branch)
+                && mapping.base.as_ref().is_none_or(|base| {
+                    !mappings.iter().any(|m| m.branch == *base)
+                        || ordered.iter().any(|done| done.branch == *base)
+                })
+        });
+        match next {
+            Some(mapping) => ordered.push(mapping),
+            None => {
+                return Err(io::Error::other(
+                    "the bases of the integration branches form a cycle",
+                ))
+            }
+        }
+    }
+    Ok(ordered)
+}
+
+/// Builds every mapping one after the other, fetching all their pull requests
+/// up front in batched queries. A mapping based on another one is built after
+/// it, on top of what it just built, and not at all if that one failed.
+/// Returns the branches that failed.
+pub fn run(
+    token: Option<&str>,
+    repo: &Repo,
+    mappings: &[Mapping],
+    batch_size: usize,
+    extra_args: &[String],
+) -> io::Result<Vec<String>> {
+    let mappings = ordered(mappings)?;
+    let selections: Vec<Selection> = mappings
+        .iter()
+        .map(|mapping| Selection::parse(&mapping.selector))
+        .collect();
+    let requests: Vec<(&Repo, &Selection)> = selections.iter().map(|s| (repo, s)).collect();
+    let fetched = batch::pull_requests_batched(token, &requests, batch_size)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+
+    let program = env::current_exe()?;
+    let mut failed: Vec<String> = vec![];
+    for (mapping, pull_requests) in mappings.iter().zip(fetched) {
+        info!("\n=== {} ===", mapping.branch);
+        let mut command = Command::new(&program);
+        if let Some(base) = &mapping.base {
+            if failed.contains(base) {
+                error!("Not building {}, its base {} failed", mapping.branch, base);
+                failed.push(mapping.branch.clone());
+                continue;
+            }
+            // The local branch just built, by the name its `{date}` expanded to.
+            if mappings.iter().any(|m| m.branch == *base) {
+                command.arg("--base").arg(dates::expand(base));
+            }
+        }
+        let prefetched = batch::save(&pull_requests, &mapping.branch)?;
+        let integrated = command
+            .arg(&mapping.selector)
+            .arg(&mapping.branch)
+            .arg("--pull-requests-from")
+            .arg(&prefetched)
+            .args(extra_args)
+            .status();
+        let _ = fs::remove_file(&prefetched);
+        if !integrated?.success() {
+            failed.push(mapping.branch.clone());
+        }
+    }
+    Ok(failed)
+}
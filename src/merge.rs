@@ -0,0 +1,365 @@
+use std::io;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use super::backend::GitBackend;
+use super::binary;
+use super::git_extras::PR_TRAILER;
+use super::github::PullRequest;
+use super::report::Outcome;
+use super::timeouts;
+
+/// How conflicts are handled for every branch of a run.
+pub struct Options<'a> {
+    /// The command to run again once a conflict has been resolved.
+    pub rerun: &'a str,
+    /// Commit merges rerere fully resolved instead of stopping.
+    pub autocommit: bool,
+    pub binary_policy: binary::Policy,
+}
+
+/// Undoes a merge of `branch` that took longer than `--merge-timeout`.
+fn give_up(git: &dyn GitBackend, branch: &str) -> (Outcome, Vec<PathBuf>, Vec<binary::Choice>) {
+    match git.reset_hard("HEAD") {
+        Ok(true) => (Outcome::TimedOut, vec![], vec![]),
+        _ => {
+            error!("Failure undoing the merge of {}", branch);
+            (Outcome::Failed, vec![], vec![])
+        }
+    }
+}
+
+/// Merges `pr` on top of the `merged` ones, resolving what rerere and the
+/// binary policy can, and says how it went along with the paths left
+/// conflicted and the binary conflicts resolved.
+pub fn merge_branch(
+    git: &dyn GitBackend,
+    pr: &PullRequest,
+    merged: &[PullRequest],
+    options: &Options,
+) -> (Outcome, Vec<PathBuf>, Vec<binary::Choice>) {
+    let branch = &pr.branch;
+    let started = Instant::now();
+    let merge = match git.merge(&pr.revision, pr.has_directive("allow-unrelated")) {
+        // In-process merges can't be interrupted, so one that ran over its
+        // budget is only given up on once it's done.
+        Ok(_) if timeouts::merge().is_some_and(|budget| started.elapsed() > budget) => {
+            return give_up(git, branch);
+        }
+        Ok(merge) => merge,
+        Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
+            warn!("{}", e);
+            return give_up(git, branch);
+        }
+        Err(e) => {
+            error!("Failure merging branch {}: {}", branch, e);
+            return (Outcome::Failed, vec![], vec![]);
+        }
+    };
+
+    // Merges stop before committing so the pull request can be recorded as a
+    // trailer, which also ends up in commits made after resolving conflicts.
+    if git.merging()
+        && !git
+            .add_trailer(&format!("{}: {}", PR_TRAILER, pr.number))
+            .expect("Error recording the pull request in the merge message")
+    {
+        error!("Failure recording the pull request for {}", branch);
+        return (Outcome::Failed, vec![], vec![]);
+    }
+
+    if !merge {
+        // rerere replays recorded resolutions into the work tree but leaves the
+        // paths conflicted in the index; only stage the ones it fully resolved.
+        let remaining = git.rerere_remaining().expect("Error checking rerere state");
+        let resolved: Vec<String> = git
+            .conflicted_paths()
+            .into_iter()
+            .filter(|path| !remaining.contains(path))
+            .filter(|path| !git.has_conflict_markers(path))
+            .filter_map(|path| path.to_str().map(String::from))
+            .collect();
+
+        if !resolved.is_empty()
+            && !git
+                .add(&resolved)
+                .expect("Error staging rerere resolutions")
+        {
+            error!("Failure staging rerere resolutions for {}", branch);
+            return (Outcome::Failed, vec![], vec![]);
+        }
+
+        let binary = match git.resolve_binary(options.binary_policy, pr, merged) {
+            Ok(binary) => binary,
+            Err(e) => {
+                error!("Failure resolving binary conflicts for {}: {}", branch, e);
+                return (Outcome::Failed, vec![], vec![]);
+            }
+        };
+        if !binary.is_empty() {
+            info!("\nResolved binary conflicts:");
+            for choice in &binary {
+                info!("  {}", choice.describe());
+            }
+        }
+
+        if git.has_conflicts() {
+            git.explain_conflicts(pr, merged, options.rerun);
+            return (Outcome::Conflicted, git.conflicted_paths(), binary);
+        }
+
+        if !resolved.is_empty() && !options.autocommit {
+            error!("\nrerere resolved conflicts in:");
+            for path in &resolved {
+                error!("  {}", path);
+            }
+            error!(
+                "\nInspect the resolution with `git diff --cached`, then use \
+                 \n`git commit --no-edit` to commit it and re-run:\
+                 \n  {}",
+                options.rerun
+            );
+            return (Outcome::ResolutionPending, vec![], binary);
+        }
+
+        if !committed(git, branch) {
+            return (Outcome::Failed, vec![], binary);
+        }
+
+        return (Outcome::Resolved, vec![], binary);
+    }
+
+    if git.merging() && !committed(git, branch) {
+        return (Outcome::Failed, vec![], vec![]);
+    }
+
+    (Outcome::Merged, vec![], vec![])
+}
+
+/// Commits the merge of `branch`, saying why if that fails.
+fn committed(git: &dyn GitBackend, branch: &str) -> bool {
+    match git.commit() {
+        Ok(true) => true,
+        Ok(false) => {
+            error!("Failure mergeing branch {}", branch);
+            false
+        }
+        Err(e) => {
+            error!("Failure merging branch {}: {}", branch, e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::super::backend::fake::{Fake, Merge};
+    use super::*;
+    use crate::github::pull_request;
+
+    fn options(autocommit: bool, binary_policy: binary::Policy) -> Options<'static> {
+        Options {
+            rerun: "git integrate 1 staging",
+            autocommit,
+            binary_policy,
+        }
+    }
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn clean_merge_is_committed_with_trailer() {
+        let git = Fake::new();
+        let (outcome, conflicts, _) = merge_branch(
+            &git,
+            &pull_request(7, "feature"),
+            &[],
+            &options(true, binary::Policy::Fail),
+        );
+
+        assert_eq!(outcome, Outcome::Merged);
+        assert!(conflicts.is_empty());
+        assert_eq!(
+            git.calls(),
+            vec![
+                "merge origin/feature",
+                "add_trailer Integrated-PR: 7",
+                "commit"
+            ]
+        );
+    }
+
+    #[test]
+    fn up_to_date_branch_makes_no_commit() {
+        let git = Fake::new().merge_of(
+            "origin/feature",
+            Merge {
+                up_to_date: true,
+                ..Merge::default()
+            },
+        );
+        let (outcome, _, _) = merge_branch(
+            &git,
+            &pull_request(7, "feature"),
+            &[],
+            &options(true, binary::Policy::Fail),
+        );
+
+        assert_eq!(outcome, Outcome::Merged);
+        assert_eq!(git.calls(), vec!["merge origin/feature"]);
+    }
+
+    #[test]
+    fn conflicts_are_explained_and_not_committed() {
+        let git = Fake::new().merge_of(
+            "origin/feature",
+            Merge {
+                conflicts: paths(&["src/lib.rs"]),
+                ..Merge::default()
+            },
+        );
+        let (outcome, conflicts, _) = merge_branch(
+            &git,
+            &pull_request(7, "feature"),
+            &[],
+            &options(true, binary::Policy::Fail),
+        );
+
+        assert_eq!(outcome, Outcome::Conflicted);
+        assert_eq!(conflicts, paths(&["src/lib.rs"]));
+        assert!(git
+            .calls()
+            .contains(&"explain_conflicts feature".to_string()));
+        assert!(!git.calls().contains(&"commit".to_string()));
+    }
+
+    #[test]
+    fn rerere_resolutions_are_staged_and_committed() {
+        let git = Fake::new().merge_of(
+            "origin/feature",
+            Merge {
+                conflicts: paths(&["a.txt", "b.txt"]),
+                rerere: paths(&["a.txt", "b.txt"]),
+                ..Merge::default()
+            },
+        );
+        let (outcome, _, _) = merge_branch(
+            &git,
+            &pull_request(7, "feature"),
+            &[],
+            &options(true, binary::Policy::Fail),
+        );
+
+        assert_eq!(outcome, Outcome::Resolved);
+        assert_eq!(
+            git.calls(),
+            vec![
+                "merge origin/feature",
+                "add_trailer Integrated-PR: 7",
+                "add a.txt b.txt",
+                "commit"
+            ]
+        );
+    }
+
+    #[test]
+    fn rerere_resolutions_wait_without_autocommit() {
+        let git = Fake::new().merge_of(
+            "origin/feature",
+            Merge {
+                conflicts: paths(&["a.txt"]),
+                rerere: paths(&["a.txt"]),
+                ..Merge::default()
+            },
+        );
+        let (outcome, _, _) = merge_branch(
+            &git,
+            &pull_request(7, "feature"),
+            &[],
+            &options(false, binary::Policy::Fail),
+        );
+
+        assert_eq!(outcome, Outcome::ResolutionPending);
+        assert!(!git.calls().contains(&"commit".to_string()));
+    }
+
+    #[test]
+    fn partial_rerere_resolution_still_conflicts() {
+        let git = Fake::new().merge_of(
+            "origin/feature",
+            Merge {
+                conflicts: paths(&["a.txt", "b.txt"]),
+                rerere: paths(&["a.txt"]),
+                ..Merge::default()
+            },
+        );
+        let (outcome, conflicts, _) = merge_branch(
+            &git,
+            &pull_request(7, "feature"),
+            &[],
+            &options(true, binary::Policy::Fail),
+        );
+
+        assert_eq!(outcome, Outcome::Conflicted);
+        assert_eq!(conflicts, paths(&["b.txt"]));
+    }
+
+    #[test]
+    fn binary_policy_resolves_binary_conflicts() {
+        let merge = Merge {
+            conflicts: paths(&["logo.png"]),
+            binary: paths(&["logo.png"]),
+            ..Merge::default()
+        };
+
+        let git = Fake::new().merge_of("origin/feature", merge.clone());
+        let (outcome, _, binary) = merge_branch(
+            &git,
+            &pull_request(7, "feature"),
+            &[],
+            &options(true, binary::Policy::Theirs),
+        );
+        assert_eq!(outcome, Outcome::Resolved);
+        assert_eq!(binary.len(), 1);
+        assert_eq!(binary[0].side, binary::Side::Theirs);
+
+        let git = Fake::new().merge_of("origin/feature", merge);
+        let (outcome, _, binary) = merge_branch(
+            &git,
+            &pull_request(7, "feature"),
+            &[],
+            &options(true, binary::Policy::Fail),
+        );
+        assert_eq!(outcome, Outcome::Conflicted);
+        assert!(binary.is_empty());
+    }
+
+    #[test]
+    fn failing_commit_fails_the_merge() {
+        let git = Fake::new().failing("commit");
+        let (outcome, _, _) = merge_branch(
+            &git,
+            &pull_request(7, "feature"),
+            &[],
+            &options(true, binary::Policy::Fail),
+        );
+        assert_eq!(outcome, Outcome::Failed);
+    }
+
+    #[test]
+    fn failing_trailer_stops_before_committing() {
+        let git = Fake::new().failing("add_trailer");
+        let (outcome, _, _) = merge_branch(
+            &git,
+            &pull_request(7, "feature"),
+            &[],
+            &options(true, binary::Policy::Fail),
+        );
+        assert_eq!(outcome, Outcome::Failed);
+        assert!(!git.calls().contains(&"commit".to_string()));
+    }
+}
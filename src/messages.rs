@@ -0,0 +1,106 @@
+//! Everything git-integrate tells the user goes through `emit` so `--quiet`
+//! can cut a cron run down to its errors and final summary.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    /// Why the run stopped or what went wrong; always shown.
+    Error,
+    /// Something worth knowing that didn't stop the run.
+    Warn,
+    /// Progress.
+    Info,
+    /// The result of the command; always shown.
+    Summary,
+}
+
+/// How output is written, from `--output`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    /// The current format, which may change between versions.
+    Text,
+    /// The plain text lines as they were before output was restructured,
+    /// such as `Merging <branch>`, for scripts that grep for them. Changes to
+    /// `Text` leave it alone.
+    Legacy,
+}
+
+impl Output {
+    pub fn parse(value: &str) -> Option<Output> {
+        match value {
+            "text" => Some(Output::Text),
+            "legacy" => Some(Output::Legacy),
+            _ => None,
+        }
+    }
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static LEGACY: AtomicBool = AtomicBool::new(false);
+/// Everything emitted so far, including what `--quiet` hid.
+static LOG: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Hides warnings and progress from now on.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+pub fn set_output(output: Output) {
+    LEGACY.store(output == Output::Legacy, Ordering::Relaxed);
+}
+
+pub fn output() -> Output {
+    match LEGACY.load(Ordering::Relaxed) {
+        true => Output::Legacy,
+        false => Output::Text,
+    }
+}
+
+/// The last `lines` lines emitted during this run, for attaching to reports.
+pub fn log_tail(lines: usize) -> Vec<String> {
+    let log = LOG.lock().unwrap_or_else(|e| e.into_inner());
+    log[log.len().saturating_sub(lines)..].to_vec()
+}
+
+pub fn emit(level: Level, message: fmt::Arguments) {
+    let message = message.to_string();
+    LOG.lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .extend(message.lines().map(String::from));
+
+    match level {
+        Level::Warn | Level::Info if is_quiet() => (),
+        _ => println!("{}", message),
+    }
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::messages::emit($crate::messages::Level::Error, format_args!($($arg)*))
+    };
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        $crate::messages::emit($crate::messages::Level::Warn, format_args!($($arg)*))
+    };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::messages::emit($crate::messages::Level::Info, format_args!($($arg)*))
+    };
+}
+
+macro_rules! summary {
+    ($($arg:tt)*) => {
+        $crate::messages::emit($crate::messages::Level::Summary, format_args!($($arg)*))
+    };
+}
@@ -0,0 +1,59 @@
+use std::path::PathBuf;
+
+use super::git_extras::Repo;
+use super::github::{rest, PullRequest};
+
+/// How the author of a pull request that failed to integrate is told about it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Notify {
+    /// Post a comment on the pull request.
+    Comment,
+    /// Submit a "request changes" review, which lands in the author's review
+    /// queue and notifications.
+    RequestChanges,
+}
+
+impl Notify {
+    pub fn parse(value: &str) -> Option<Notify> {
+        match value {
+            "comment" => Some(Notify::Comment),
+            "request-changes" => Some(Notify::RequestChanges),
+            _ => None,
+        }
+    }
+}
+
+pub fn conflict(
+    token: &str,
+    repo: &Repo,
+    notify: Notify,
+    pr: &PullRequest,
+    dest_branch: &str,
+    conflicts: &[PathBuf],
+) -> Result<(), reqwest::Error> {
+    let mut body = format!(
+        "{}This branch no longer merges cleanly into `{}`. Conflicting files:\n\n",
+        pr.author
+            .as_ref()
+            .map(|login| format!("@{} ", login))
+            .unwrap_or_default(),
+        dest_branch
+    );
+    for path in conflicts {
+        body.push_str(&format!("- `{}`\n", path.display()));
+    }
+    body.push_str(&format!(
+        "\nPlease merge `{}` into the branch and resolve the conflicts.",
+        pr.base
+    ));
+
+    match notify {
+        Notify::Comment => rest::create_comment(token, repo, pr.number, &body),
+        // GitHub refuses reviews on your own pull requests, fall back to a
+        // comment so the author still hears about it.
+        Notify::RequestChanges => {
+            rest::create_review(token, repo, pr.number, "REQUEST_CHANGES", &body)
+                .or_else(|_| rest::create_comment(token, repo, pr.number, &body))
+        }
+    }
+}
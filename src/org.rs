@@ -0,0 +1,88 @@
+use std::path::Path;
+use std::process::Command;
+use std::{env, fs, io};
+
+use super::git_extras::Repo;
+use super::github::{batch, rest, Selection};
+use super::mappings::Mapping;
+use super::messages;
+
+/// Builds the same mapping in every repository of `org` tagged
+/// with `topic`, cloning missing ones into `workspace`.
+///
+/// Each repository gets its own `git-integrate` process so a conflict in one
+/// doesn't stop the others; returns the names of the repositories that failed.
+pub fn run(
+    token: Option<&str>,
+    org: &str,
+    topic: &str,
+    mapping: &Mapping,
+    workspace: &Path,
+    extra_args: &[String],
+    batch_size: usize,
+) -> io::Result<Vec<String>> {
+    let repositories = rest::repositories_by_topic(token, org, topic)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    let program = env::current_exe()?;
+    let mut failed = vec![];
+
+    fs::create_dir_all(workspace)?;
+    info!(
+        "Found {} repositories in {} tagged {}",
+        repositories.len(),
+        org,
+        topic
+    );
+
+    // One round of batched queries for every repository instead of one
+    // process after the other asking for its own pull requests.
+    let selection = Selection::parse(&mapping.selector);
+    let repos: Vec<Repo> = repositories
+        .iter()
+        .map(|repository| Repo {
+            owner: org.to_string(),
+            name: repository.name.clone(),
+        })
+        .collect();
+    let requests: Vec<(&Repo, &Selection)> = repos.iter().map(|repo| (repo, &selection)).collect();
+    let fetched = batch::pull_requests_batched(token, &requests, batch_size)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+
+    for (repository, pull_requests) in repositories.into_iter().zip(fetched) {
+        let dir = workspace.join(&repository.name);
+        info!("\n=== {} ===", repository.name);
+
+        if !dir.exists() {
+            let cloned = Command::new("git")
+                .arg("clone")
+                .args(if messages::is_quiet() {
+                    &["--quiet"][..]
+                } else {
+                    &[]
+                })
+                .arg(&repository.clone_url)
+                .arg(&dir)
+                .status()?;
+            if !cloned.success() {
+                failed.push(repository.name);
+                continue;
+            }
+        }
+
+        let prefetched = batch::save(&pull_requests, &repository.name)?;
+        let integrated = Command::new(&program)
+            .arg(&mapping.selector)
+            .arg(&mapping.branch)
+            .arg("--pull-requests-from")
+            .arg(&prefetched)
+            .args(extra_args)
+            .current_dir(&dir)
+            .status();
+        let _ = fs::remove_file(&prefetched);
+        if !integrated?.success() {
+            failed.push(repository.name);
+        }
+    }
+
+    Ok(failed)
+}
@@ -0,0 +1,178 @@
+use git2::{Commit, Repository, Sort, Tree};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use super::compare::pr_number;
+
+/// Where GitHub looks for CODEOWNERS, in the order it looks.
+const CODEOWNERS: [&str; 3] = [".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+
+/// The rules of a CODEOWNERS file, the last matching one owning a path.
+pub struct Owners {
+    rules: Vec<(Regex, Vec<String>)>,
+}
+
+impl Owners {
+    /// The first CODEOWNERS file of `tree`, none if it has no such file.
+    pub fn load(repository: &Repository, tree: &Tree) -> Result<Owners, git2::Error> {
+        for path in &CODEOWNERS {
+            if let Ok(entry) = tree.get_path(Path::new(path)) {
+                let blob = repository.find_blob(entry.id())?;
+                return Ok(Owners::parse(&String::from_utf8_lossy(blob.content())));
+            }
+        }
+        Ok(Owners { rules: vec![] })
+    }
+
+    pub fn parse(contents: &str) -> Owners {
+        let rules = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut words = line.split_whitespace();
+                let pattern = pattern(words.next()?)?;
+                let owners = words
+                    .take_while(|word| !word.starts_with('#'))
+                    .map(String::from)
+                    .collect();
+                Some((pattern, owners))
+            })
+            .collect();
+        Owners { rules }
+    }
+
+    /// The owners of `path`, none when the last matching rule names none.
+    pub fn of(&self, path: &Path) -> &[String] {
+        let path = path.to_string_lossy();
+        self.rules
+            .iter()
+            .rev()
+            .find(|(pattern, _)| pattern.is_match(&path))
+            .map_or(&[], |(_, owners)| owners.as_slice())
+    }
+}
+
+/// A CODEOWNERS pattern, gitignore style: anchored when it has a slash other
+/// than a trailing one, and matching everything under a directory it names.
+fn pattern(glob: &str) -> Option<Regex> {
+    let directory = glob.ends_with('/');
+    let glob = glob.trim_end_matches('/');
+    let anchored = glob.contains('/');
+    let glob = glob.trim_start_matches('/');
+
+    let mut regex = String::from(if anchored { "^" } else { "^(?:.*/)?" });
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push_str(if directory { "/.*$" } else { "(?:/.*)?$" });
+    Regex::new(&regex).ok()
+}
+
+/// The paths the merge `commit` brought into the integration branch.
+fn changed_paths(repository: &Repository, commit: &Commit) -> Result<Vec<PathBuf>, git2::Error> {
+    let before = commit.parent(0)?.tree()?;
+    let diff = repository.diff_tree_to_tree(Some(&before), Some(&commit.tree()?), None)?;
+    Ok(diff
+        .deltas()
+        .filter_map(|delta| delta.new_file().path().or_else(|| delta.old_file().path()))
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Every path the pull requests merged into HEAD since `base` changed, with
+/// those pull requests and the path's owners on HEAD, as Markdown. Merges of
+/// a continued run's earlier invocation are included.
+pub fn to_markdown(
+    repository: &Repository,
+    base: &str,
+    dest_branch: &str,
+) -> Result<String, git2::Error> {
+    let mut revwalk = repository.revwalk()?;
+    revwalk.push_head()?;
+    revwalk.hide(repository.revparse_single(base)?.peel_to_commit()?.id())?;
+    revwalk.simplify_first_parent()?;
+    revwalk.set_sorting(Sort::REVERSE)?;
+
+    let mut changed_by: BTreeMap<PathBuf, Vec<i64>> = BTreeMap::new();
+    for id in revwalk {
+        let commit = repository.find_commit(id?)?;
+        if let Some(number) = pr_number(&commit) {
+            for path in changed_paths(repository, &commit)? {
+                changed_by.entry(path).or_default().push(number);
+            }
+        }
+    }
+    let tree = repository.head()?.peel_to_tree()?;
+    let owners = Owners::load(repository, &tree)?;
+
+    let mut out = format!(
+        "## Ownership of `{}`\n\n| File | Pull requests | Owners |\n| --- | --- | --- |\n",
+        dest_branch
+    );
+    for (path, numbers) in &changed_by {
+        let numbers: Vec<String> = numbers.iter().map(|n| format!("#{}", n)).collect();
+        let _ = writeln!(
+            out,
+            "| `{}` | {} | {} |",
+            path.display(),
+            numbers.join(", "),
+            owners.of(path).join(" ")
+        );
+    }
+    Ok(out)
+}
+
+pub fn write(
+    repository: &Repository,
+    base: &str,
+    dest_branch: &str,
+    path: &Path,
+) -> io::Result<()> {
+    let markdown = to_markdown(repository, base, dest_branch).map_err(io::Error::other)?;
+    fs::write(path, markdown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_last_matching_rule_owns_a_path() {
+        let owners = Owners::parse(
+            "# Everything\n\
+             *       @org/everyone\n\
+             *.rs    @rustaceans # inline comment\n\
+             /docs/  @writers\n\
+             apps/   @apps\n\
+             /build/**/logs @ops\n\
+             /src/generated.rs\n",
+        );
+        let of = |path: &str| owners.of(Path::new(path)).join(" ");
+        assert_eq!(of("README.md"), "@org/everyone");
+        assert_eq!(of("src/main.rs"), "@rustaceans");
+        assert_eq!(of("docs/guide/intro.md"), "@writers");
+        assert_eq!(of("src/docs/intro.md"), "@org/everyone");
+        assert_eq!(of("web/apps/index.js"), "@apps");
+        assert_eq!(of("build/a/b/logs/out.txt"), "@ops");
+        assert_eq!(of("build/logs/out.txt"), "@ops");
+        assert_eq!(of("src/generated.rs"), "");
+    }
+}
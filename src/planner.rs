@@ -0,0 +1,1152 @@
+use chrono::Utc;
+use clap::ArgMatches;
+use git2::Repository;
+use std::io::{self, BufRead, IsTerminal};
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+use super::backend::GitBackend;
+use super::git_extras::{remote, remote_branch, Repo};
+use super::github::{self, PullRequest, Selection};
+use super::ignore::IgnoreList;
+use super::providers::Provider;
+use super::settings::Settings;
+use super::state::State;
+use super::{
+    approval, categories, collisions, conflicts, dates, drift, explain, graph, semantic, shallow,
+    sizes, speculate, stacks, temp_refs,
+};
+
+/// Which pull requests to integrate into which branch, as given on the
+/// command line.
+pub struct SelectionCriteria<'a> {
+    /// The milestone, label, pattern or search as given, for the report.
+    pub selector: String,
+    pub selection: Selection,
+    /// The branch as given, before its dates are filled in. Settings are keyed
+    /// by it.
+    pub dest_branch: &'a str,
+}
+
+impl<'a> SelectionCriteria<'a> {
+    pub fn from_args(opts: &'a ArgMatches) -> SelectionCriteria<'a> {
+        if opts.is_present("stdin") {
+            if opts.is_present("branch") {
+                panic!("Give either a milestone or label, or --stdin");
+            }
+            // A continued run doesn't select again, and its stdin is no longer
+            // the pipe.
+            let items: Vec<String> = match opts.is_present("resume") {
+                true => vec![],
+                false => io::stdin()
+                    .lock()
+                    .lines()
+                    .map(|line| line.expect("Could not read the pull requests from stdin"))
+                    .map(|line| line.trim().to_string())
+                    .filter(|line| !line.is_empty())
+                    .collect(),
+            };
+            return SelectionCriteria {
+                selector: items.join(", "),
+                selection: Selection::Listed(items),
+                dest_branch: opts.value_of("milestone").expect("No branch provided"),
+            };
+        }
+        if let Some(labels) = opts.values_of("label") {
+            if opts.is_present("branch") {
+                panic!("Give either a milestone or label, or --label");
+            }
+            let labels: Vec<&str> = labels.collect();
+            let selection = match labels.as_slice() {
+                [label] => Selection::Label(label.trim().to_string()),
+                _ => {
+                    // Label names are case-insensitive.
+                    let pattern = labels
+                        .iter()
+                        .map(|label| regex::escape(label.trim()))
+                        .collect::<Vec<_>>()
+                        .join("|");
+                    let pattern = format!("(?i){}", pattern);
+                    Selection::label_pattern(&pattern).expect("Invalid label")
+                }
+            };
+            return SelectionCriteria {
+                selector: labels.join(", "),
+                selection,
+                dest_branch: opts.value_of("milestone").expect("No branch provided"),
+            };
+        }
+        let (selector, selection, dest_branch) =
+            match (opts.value_of("label-pattern"), opts.value_of("search")) {
+                // The branch is the only positional argument then.
+                (Some(pattern), _) => {
+                    if opts.is_present("branch") {
+                        panic!("Give either a milestone or label, or --label-pattern");
+                    }
+                    let selection = Selection::label_pattern(pattern)
+                        .unwrap_or_else(|e| panic!("Invalid label pattern: {}", e));
+                    let dest_branch = opts.value_of("milestone").expect("No branch provided");
+                    (pattern, selection, dest_branch)
+                }
+                (_, Some(query)) => {
+                    if opts.is_present("branch") {
+                        panic!("Give either a milestone or label, or --search");
+                    }
+                    let dest_branch = opts.value_of("milestone").expect("No branch provided");
+                    (query, Selection::Search(query.to_string()), dest_branch)
+                }
+                (None, None) => {
+                    let selector = opts
+                        .value_of("milestone")
+                        .expect("No github milestone or label provided");
+                    let dest_branch = opts.value_of("branch").expect("No branch provided");
+                    (selector, Selection::parse(selector), dest_branch)
+                }
+            };
+        SelectionCriteria {
+            selector: selector.to_string(),
+            selection,
+            dest_branch,
+        }
+    }
+}
+
+/// The remotes other than origin the built branch goes to, from
+/// `--push-remote` or else the settings of `dest_branch` as configured, dates
+/// and all.
+pub fn push_remotes(opts: &ArgMatches, settings: &Settings, dest_branch: &str) -> Vec<String> {
+    match opts.values_of("push-remote") {
+        Some(remotes) => remotes.map(String::from).collect(),
+        None => settings
+            .get_all("integrate.push-remote")
+            .into_iter()
+            .chain(settings.get_all(&format!("integrate.{}.push-remote", dest_branch)))
+            .collect(),
+    }
+}
+
+/// What a run merges, from planning it or from the stopped run it continues.
+pub struct MergePlan<'a> {
+    pub pull_requests: Vec<PullRequest>,
+    pub sections: Vec<Option<String>>,
+    pub excluded: Vec<(PullRequest, Option<String>)>,
+    pub tracked_base: Option<drift::Base<'a>>,
+    pub original_head: Option<String>,
+    pub original_branch: Option<String>,
+}
+
+impl<'a> MergePlan<'a> {
+    /// The plan of the run `state` saved when it stopped.
+    pub fn resume(state: &State, base: &'a str, remote_base: Option<&'a str>) -> MergePlan<'a> {
+        MergePlan {
+            pull_requests: state.pull_requests.clone(),
+            sections: state.sections.clone(),
+            excluded: state.excluded.clone(),
+            tracked_base: remote_base
+                .zip(state.built_on.clone())
+                .map(|(branch, built_on)| drift::Base {
+                    name: base,
+                    branch,
+                    built_on,
+                }),
+            original_head: state.original_head.clone(),
+            original_branch: state.original_branch.clone(),
+        }
+    }
+}
+
+/// Fetches, selects, orders and checks the pull requests of a run, then
+/// checks out the branch to merge them into. Anything that rules the run out
+/// is explained and exits.
+pub struct Planner<'a> {
+    pub opts: &'a ArgMatches<'a>,
+    pub settings: &'a Settings,
+    pub criteria: &'a SelectionCriteria<'a>,
+    /// The branch to build, dates filled in.
+    pub dest_branch: &'a str,
+    pub repository: &'a Repository,
+    pub repo: &'a Repo,
+    pub git: &'a dyn GitBackend,
+    pub base: &'a str,
+    /// The branch on origin the base tracks, if it is a remote-tracking ref.
+    pub remote_base: Option<&'a str>,
+    pub provider: &'a dyn Provider,
+    pub provider_name: &'a str,
+    pub github_token: Option<&'a str>,
+    pub quarantine_label: &'a str,
+    /// Only show what would be merged, with `--dry-run`, `plan`, `--graph`
+    /// or `graph`.
+    pub dry_run: bool,
+    pub graph: bool,
+}
+
+impl<'a> Planner<'a> {
+    fn setting(&self, key: &str) -> Option<String> {
+        self.settings.for_branch(self.criteria.dest_branch, key)
+    }
+
+    fn setting_bool(&self, key: &str) -> bool {
+        self.settings
+            .for_branch_bool(self.criteria.dest_branch, key)
+            .unwrap_or(false)
+    }
+
+    fn require_token(&self) -> &'a str {
+        self.github_token
+            .expect("Could not find integrate.github-token in any git configuration file!")
+    }
+
+    /// The branches fetched before the pull requests with `--fetch-jobs`.
+    fn bases(&self) -> Vec<String> {
+        let default_branch = conflicts::default_branch(self.repository);
+        let mut bases = vec![default_branch.clone()];
+        bases.extend(
+            self.remote_base
+                .filter(|b| *b != default_branch)
+                .map(String::from),
+        );
+        bases
+    }
+
+    /// Leaves out the pull requests that are ignored, excluded, quarantined or
+    /// not ready, each with the reason why.
+    pub fn exclude(
+        &self,
+        pull_requests: Vec<PullRequest>,
+    ) -> (Vec<PullRequest>, Vec<(PullRequest, Option<String>)>) {
+        let ignored = IgnoreList::load(self.repository, self.base);
+        let require_resolved_threads = self.opts.is_present("require-resolved-threads")
+            || self.setting_bool("require-resolved-threads");
+        // Asked for separately, as paging through every thread of every pull
+        // request costs query points most runs don't need to spend.
+        let unresolved_threads = |pr: &PullRequest| match &self.github_token {
+            _ if self.provider_name != "github" => {
+                error!("--require-resolved-threads needs GitHub pull requests");
+                process::exit(1)
+            }
+            Some(token) => {
+                github::unresolved_threads(token, self.repo, pr.number).unwrap_or_else(|e| {
+                    error!(
+                        "Could not check the review threads of #{}: {}",
+                        pr.number, e
+                    );
+                    process::exit(1)
+                })
+            }
+            None => {
+                error!("--require-resolved-threads needs integrate.github-token");
+                process::exit(1)
+            }
+        };
+        let enabled = |name: &str| self.opts.is_present(name) || self.setting_bool(name);
+        let skip_draft = enabled("skip-draft");
+        let require_approved = enabled("require-approved");
+        let require_checks_passing = enabled("require-checks-passing");
+        let skip_conflicting = enabled("skip-conflicting");
+        // Nor about their reviews and checks.
+        let status = |pr: &PullRequest| match (&pr.status, &self.github_token) {
+            (Some(status), _) => Some(status.clone()),
+            (None, _) if self.provider_name != "github" => {
+                error!("Filtering by reviews and checks needs GitHub pull requests");
+                process::exit(1)
+            }
+            (None, Some(token)) => {
+                github::status(token, self.repo, pr.number).unwrap_or_else(|e| {
+                    error!(
+                        "Could not check the reviews and checks of #{}: {}",
+                        pr.number, e
+                    );
+                    process::exit(1)
+                })
+            }
+            (None, None) => {
+                error!("Filtering by reviews and checks needs integrate.github-token");
+                process::exit(1)
+            }
+        };
+        // Soak time: changes wait for CI and for reviewers to object.
+        let min_age = self
+            .opts
+            .value_of("min-age")
+            .map(String::from)
+            .or_else(|| self.setting("min-age"))
+            .map(|age| {
+                dates::parse_age(&age).unwrap_or_else(|| {
+                    error!("Invalid minimum age {}", age);
+                    process::exit(1)
+                })
+            });
+        if min_age.is_some() {
+            if let Selection::Search(_) | Selection::Listed(_) = self.criteria.selection {
+                error!("--min-age needs pull requests selected by label or milestone");
+                process::exit(1);
+            }
+        }
+        let breaking = self.breaking();
+        let now = Utc::now();
+        let not_ready = |pr: &PullRequest| -> Option<String> {
+            if skip_draft && pr.draft {
+                return Some("draft".to_string());
+            }
+            if breaking == categories::BreakingPolicy::Exclude && categories::is_breaking(pr) {
+                return Some(format!(
+                    "breaking change, {} takes none",
+                    self.criteria.dest_branch
+                ));
+            }
+            if let Some(min_age) = min_age {
+                let selected_at = self
+                    .provider
+                    .selected_at(pr.number, &self.criteria.selection)
+                    .unwrap_or_else(|e| {
+                        error!("Could not check when #{} was selected: {}", pr.number, e);
+                        process::exit(1)
+                    });
+                if let Some(age) = selected_at.map(|at| now - at).filter(|age| *age < min_age) {
+                    return Some(format!(
+                        "selected {} ago, under the minimum age of {}",
+                        dates::describe_age(age),
+                        dates::describe_age(min_age)
+                    ));
+                }
+            }
+            if !(require_approved || require_checks_passing || skip_conflicting) {
+                return None;
+            }
+            let status = status(pr)?;
+            if require_approved && !status.is_approved() {
+                return Some(match status.review_decision.as_deref() {
+                    Some("CHANGES_REQUESTED") => "changes requested".to_string(),
+                    _ => "not approved".to_string(),
+                });
+            }
+            if require_checks_passing && status.checks.as_deref() != Some("SUCCESS") {
+                return Some(match &status.checks {
+                    Some(checks) => format!("checks {}", checks.to_lowercase()),
+                    None => "no checks".to_string(),
+                });
+            }
+            if skip_conflicting && status.mergeable == "CONFLICTING" {
+                return Some(format!("conflicts with {}", pr.base));
+            }
+            None
+        };
+        let left_out: Vec<i64> =
+            self.opts
+                .values_of("exclude")
+                .map(|numbers| {
+                    numbers
+                        .map(|number| {
+                            number.trim_start_matches('#').parse().unwrap_or_else(|_| {
+                                panic!("Invalid pull request number {}", number)
+                            })
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+        let mut excluded = vec![];
+        let pull_requests = pull_requests
+            .into_iter()
+            .filter(|pr| match ignored.find(pr) {
+                Some(rule) => {
+                    excluded.push((pr.clone(), rule.reason.clone()));
+                    false
+                }
+                None if left_out.contains(&pr.number) => {
+                    excluded.push((pr.clone(), Some("--exclude".to_string())));
+                    false
+                }
+                None if pr.has_label(self.quarantine_label) => {
+                    excluded.push((
+                        pr.clone(),
+                        Some(format!("labelled {}", self.quarantine_label)),
+                    ));
+                    false
+                }
+                None => match not_ready(pr) {
+                    Some(reason) => {
+                        excluded.push((pr.clone(), Some(reason)));
+                        false
+                    }
+                    None if require_resolved_threads => match unresolved_threads(pr) {
+                        0 => true,
+                        threads => {
+                            excluded.push((
+                                pr.clone(),
+                                Some(format!(
+                                    "{} unresolved review {}",
+                                    threads,
+                                    if threads == 1 { "thread" } else { "threads" }
+                                )),
+                            ));
+                            false
+                        }
+                    },
+                    None => true,
+                },
+            })
+            .collect();
+        (pull_requests, excluded)
+    }
+
+    fn breaking(&self) -> categories::BreakingPolicy {
+        self.opts
+            .value_of("breaking")
+            .map(String::from)
+            .or_else(|| self.setting("breaking"))
+            .map_or(categories::BreakingPolicy::Allow, |value| {
+                categories::BreakingPolicy::parse(&value).unwrap_or_else(|| {
+                    error!("Unknown breaking change policy {}", value);
+                    process::exit(1)
+                })
+            })
+    }
+
+    pub fn plan(&self) -> MergePlan<'a> {
+        // With fetch jobs only the bases are fetched now, the branches once it is
+        // known which ones are merged.
+        let fetch_jobs: Option<usize> = self
+            .opts
+            .value_of("fetch-jobs")
+            .map(String::from)
+            .or_else(|| self.setting("fetch-jobs"))
+            .map(|jobs| jobs.trim().parse().expect("Invalid number of fetch jobs"))
+            .filter(|&jobs| jobs > 0);
+        let no_fetch = self.opts.is_present("no-fetch");
+        let fetched = match fetch_jobs {
+            _ if no_fetch => Ok(true),
+            Some(_) => self.git.fetch_branches(&self.bases(), 1),
+            None => self.git.fetch(),
+        };
+        if !fetched.expect("Error fetching from remote") {
+            process::exit(1)
+        }
+
+        let local_base = self
+            .repository
+            .revparse_single(self.base)
+            .map(|base| base.id().to_string())
+            .unwrap_or_else(|_| panic!("Could not find {}", self.base));
+        // Only a remote-tracking base can be behind the remote, and only a
+        // fetched one is expected to be current.
+        let advertised_base = self.remote_base.filter(|_| !no_fetch).map(|branch| {
+            self.git
+                .ls_remote(&format!("refs/heads/{}", branch))
+                .unwrap_or_else(|e| {
+                    error!("Could not look up {} on origin: {}", branch, e);
+                    process::exit(1)
+                })
+                .unwrap_or_else(|| {
+                    error!("Could not find {} on origin", branch);
+                    process::exit(1)
+                })
+        });
+
+        if let Some(remote_sha) = advertised_base.as_ref().filter(|sha| **sha != local_base) {
+            error!(
+                "\n{} is at {} but {} on origin is at {}; the fetch did \
+                 \nnot update it{}",
+                self.base,
+                &local_base[..10],
+                self.remote_base.unwrap(),
+                &remote_sha[..10],
+                if self.repository.is_shallow() {
+                    " (shallow clones may refuse to fetch the new history)"
+                } else {
+                    ""
+                }
+            );
+            if !self.opts.is_present("allow-stale-base") {
+                process::exit(1)
+            }
+            warn!("Continuing on the stale base because of --allow-stale-base");
+        }
+
+        // A base that was already stale on purpose isn't watched for moving.
+        let tracked_base = self
+            .remote_base
+            .filter(|_| advertised_base.as_ref() == Some(&local_base))
+            .map(|branch| drift::Base {
+                name: self.base,
+                branch,
+                built_on: local_base.clone(),
+            });
+
+        let pull_requests = match self.opts.value_of_os("pull-requests-from") {
+            Some(path) => github::batch::load(Path::new(path)).unwrap_or_else(|e| {
+                error!("Could not read the fetched pull requests: {}", e);
+                process::exit(1)
+            }),
+            None => match self.provider.pull_requests(&self.criteria.selection) {
+                Ok(pull_requests) => pull_requests,
+                Err(e) => {
+                    error!("Could not fetch the pull requests: {}", e);
+                    process::exit(1)
+                }
+            },
+        };
+
+        // Pull requests are numbered in the order they were opened.
+        let mut pull_requests = pull_requests;
+        match self
+            .opts
+            .value_of("order")
+            .map(String::from)
+            .or_else(|| self.setting("order"))
+            .as_deref()
+        {
+            None | Some("oldest") => pull_requests.sort_by_key(|pr| pr.number),
+            Some("newest") => pull_requests.sort_by_key(|pr| -pr.number),
+            Some(order) => {
+                error!("Invalid order {}, expected oldest or newest", order);
+                process::exit(1)
+            }
+        }
+        // A pull request can show up on two pages when the list changes
+        // while it is paginated.
+        pull_requests.dedup_by_key(|pr| pr.number);
+
+        let (pull_requests, mut excluded) = self.exclude(pull_requests);
+        let breaking = self.breaking();
+
+        if !excluded.is_empty() {
+            info!("\nExcluded from the build:");
+            for (pr, reason) in &excluded {
+                match reason {
+                    Some(reason) => {
+                        info!("  #{} {} ({}): {}", pr.number, pr.title, pr.branch, reason)
+                    }
+                    None => info!("  #{} {} ({})", pr.number, pr.title, pr.branch),
+                }
+            }
+        }
+
+        let breaking_changes: Vec<&PullRequest> = pull_requests
+            .iter()
+            .filter(|pr| categories::is_breaking(pr))
+            .collect();
+        match breaking {
+            _ if breaking_changes.is_empty() => (),
+            categories::BreakingPolicy::Warn => {
+                warn!(
+                    "\nThese breaking changes are going into {}:",
+                    self.criteria.dest_branch
+                );
+                for pr in &breaking_changes {
+                    warn!("  #{} {} ({})", pr.number, pr.title, pr.branch);
+                }
+            }
+            categories::BreakingPolicy::Fail => {
+                error!(
+                    "\n{} takes no breaking changes, but these are selected:",
+                    self.criteria.dest_branch
+                );
+                for pr in &breaking_changes {
+                    error!("  #{} {} ({})", pr.number, pr.title, pr.branch);
+                }
+                error!(
+                    "\nRemove them from the selection, or set integrate.{}.breaking to warn \
+                     \nor exclude.",
+                    self.criteria.dest_branch
+                );
+                process::exit(1);
+            }
+            _ => (),
+        }
+
+        let stacks = match stacks::resolve(
+            self.github_token,
+            self.repo,
+            &conflicts::default_branch(self.repository),
+            pull_requests,
+            self.opts.is_present("include-stack-parents"),
+        ) {
+            Ok(stacks) => stacks,
+            Err(e) => panic!("{}", e),
+        };
+
+        if !stacks.missing.is_empty() {
+            error!("\nThese pull requests are stacked on pull requests that were not selected:");
+            for missing in &stacks.missing {
+                error!(
+                    "  #{} ({}) is based on #{} ({})",
+                    missing.child.number,
+                    missing.child.branch,
+                    missing.parent.number,
+                    missing.parent.branch
+                );
+            }
+            error!(
+                "\nMerging them alone would pull in their parents' commits unreviewed. Select \
+                 \nthe parents too, or use --include-stack-parents to merge them automatically."
+            );
+            process::exit(1);
+        }
+        let mut pull_requests = stacks.ordered;
+
+        let advertised = self
+            .git
+            .remote_branches()
+            .expect("Error listing the branches on origin");
+        let missing: Vec<&PullRequest> = pull_requests
+            .iter()
+            .filter(|pr| pr.fork_ref.is_none() && !advertised.contains_key(&pr.branch))
+            .collect();
+        if !missing.is_empty() {
+            error!("\nThese branches are not on origin anymore:");
+            for pr in missing {
+                error!("  #{} {} ({})", pr.number, pr.title, pr.branch);
+            }
+            error!(
+                "\nThey were deleted or renamed after the pull requests were listed. Restore \
+                 \nthem, or re-run once GitHub shows the new branch name."
+            );
+            process::exit(1);
+        }
+
+        let forks = temp_refs::refspecs(&pull_requests);
+        if !no_fetch && !forks.is_empty() {
+            info!("Fetching {} pull requests from forks", forks.len());
+            if !self
+                .git
+                .fetch_refs(&forks)
+                .expect("Error fetching from remote")
+            {
+                process::exit(1)
+            }
+        }
+
+        if let Some(jobs) = fetch_jobs.filter(|_| !no_fetch) {
+            let branches: Vec<String> = pull_requests
+                .iter()
+                .filter(|pr| pr.fork_ref.is_none())
+                .map(|pr| pr.branch.clone())
+                .collect();
+            info!(
+                "Fetching {} branches with {} concurrent fetches",
+                branches.len(),
+                jobs.min(branches.len())
+            );
+            if !self
+                .git
+                .fetch_branches(&branches, jobs)
+                .expect("Error fetching from remote")
+            {
+                process::exit(1)
+            }
+        }
+
+        // CI often clones only the last few commits, too few to merge.
+        if !no_fetch && self.repository.is_shallow() {
+            let policy = if self.opts.is_present("unshallow") {
+                shallow::Policy::Unshallow
+            } else if let Some(depth) = self.opts.value_of("deepen") {
+                depth
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|&depth| depth > 0)
+                    .map(shallow::Policy::Deepen)
+                    .unwrap_or_else(|| panic!("Invalid --deepen {}", depth))
+            } else {
+                self.setting("shallow")
+                    .map(|value| {
+                        shallow::Policy::parse(&value)
+                            .unwrap_or_else(|| panic!("Invalid integrate.shallow {}", value))
+                    })
+                    .unwrap_or(shallow::Policy::Deepen(shallow::DEFAULT_DEPTH))
+            };
+            let unrelated =
+                shallow::complete(self.repository, self.git, self.base, &pull_requests, policy)
+                    .expect("Error deepening the shallow clone");
+            if !unrelated.is_empty() {
+                warn!(
+                    "These pull requests share no history with {}, merging them will fail:",
+                    self.base
+                );
+                for pr in unrelated {
+                    warn!("  #{} {} ({})", pr.number, pr.title, pr.branch);
+                }
+            }
+        }
+
+        match self.opts.value_of("stacks").and_then(stacks::Mode::parse) {
+            Some(stacks::Mode::Tops) => {
+                let (tops, carried) = stacks::tops(pull_requests);
+                for (pr, top) in carried {
+                    error!(
+                        "\n#{} ({}) is merged as part of #{}",
+                        pr.number, pr.branch, top
+                    );
+                }
+                pull_requests = tops;
+            }
+            Some(stacks::Mode::Flatten) => {
+                if let Err(idx) = stacks::flatten(self.repository, &mut pull_requests) {
+                    let pr = &pull_requests[idx];
+                    error!(
+                        "\nCould not rebase #{} ({}) onto its parent, merge its parent into \
+                         \nit or use --stacks independent",
+                        pr.number, pr.branch
+                    );
+                    process::exit(1);
+                }
+            }
+            _ => (),
+        }
+
+        let section_markers =
+            self.opts.is_present("section-markers") || self.setting_bool("section-markers");
+        let categories = if section_markers
+            || self.opts.is_present("group-by-category")
+            || self.setting_bool("group-by-category")
+        {
+            let categories = self
+                .setting("categories")
+                .map(|names| categories::Categories::parse(&names))
+                .unwrap_or_default();
+            pull_requests = categories.sort(pull_requests);
+            Some(categories)
+        } else {
+            None
+        };
+        for pr in pull_requests.iter().filter(|pr| pr.fork_ref.is_none()) {
+            let fetched = self
+                .repository
+                .revparse_single(&remote_branch(&pr.branch))
+                .map(|commit| commit.id().to_string());
+            match (fetched, advertised.get(&pr.branch)) {
+                (Ok(fetched), Some(sha)) if fetched != *sha => warn!(
+                    "{} is at {} but {} on {} is at {}, it was pushed to since the fetch",
+                    remote_branch(&pr.branch),
+                    &fetched[..10],
+                    pr.branch,
+                    remote(),
+                    &sha[..10]
+                ),
+                _ => (),
+            }
+        }
+
+        let unrelated: Vec<&PullRequest> = pull_requests
+            .iter()
+            .filter(|pr| !pr.has_directive("allow-unrelated"))
+            .filter(|pr| conflicts::is_unrelated(self.repository, self.base, pr))
+            .collect();
+
+        if !unrelated.is_empty() {
+            error!("\nThese branches share no history with {}:", self.base);
+            for pr in unrelated {
+                error!("  #{} {} ({})", pr.number, pr.title, pr.branch);
+            }
+            error!(
+                "\nThey are usually squashed mirrors or re-created branches. To merge \
+                 \none anyway add the `integrate:allow-unrelated` label or a \
+                 \n`/integrate allow-unrelated` line to its pull request description."
+            );
+            process::exit(1);
+        }
+
+        let collisions = match collisions::check(self.repository, self.base, &pull_requests) {
+            Ok(collisions) => collisions,
+            Err(e) => {
+                error!("\nCould not check the paths the pull requests add: {}", e);
+                process::exit(1);
+            }
+        };
+
+        if !collisions.is_empty() {
+            error!("\nThese branches add paths that can't be checked out together:");
+            let mut last = None;
+            for collision in &collisions {
+                if last != Some(collision.pr.number) {
+                    error!(
+                        "  #{} {} ({})",
+                        collision.pr.number, collision.pr.title, collision.pr.branch
+                    );
+                    last = Some(collision.pr.number);
+                }
+                error!("    {}", collision.message);
+            }
+            error!(
+                "\nPaths that only differ in case overwrite each other on macOS and \
+                 \nWindows checkouts. Rename them in the pull request before integrating."
+            );
+            process::exit(1);
+        }
+
+        let rules: Vec<semantic::Rule> = self
+            .settings
+            .get_all("integrate.semantic-rule")
+            .into_iter()
+            .chain(self.settings.get_all(&format!(
+                "integrate.{}.semantic-rule",
+                self.criteria.dest_branch
+            )))
+            .map(|rule| {
+                semantic::Rule::parse(&rule)
+                    .unwrap_or_else(|e| panic!("Invalid integrate.semantic-rule: {}", e))
+            })
+            .collect();
+        if !rules.is_empty() {
+            let fail = match self.setting("semantic-conflicts").as_deref() {
+                None | Some("warn") => false,
+                Some("fail") => true,
+                Some(value) => panic!("Unknown semantic conflict policy {}", value),
+            };
+            let mut plan: Vec<(i64, semantic::Changes)> = vec![];
+            for pr in &pull_requests {
+                match semantic::changes(self.repository, self.base, pr) {
+                    Ok(changes) => plan.push((pr.number, changes)),
+                    Err(e) => {
+                        error!(
+                            "\nCould not read the changes of #{} ({}): {}",
+                            pr.number, pr.branch, e
+                        );
+                        process::exit(1);
+                    }
+                }
+            }
+            let findings = semantic::check(&rules, &plan);
+
+            if !findings.is_empty() {
+                let report = |line: String| match fail {
+                    true => error!("{}", line),
+                    false => warn!("{}", line),
+                };
+                report("\nThese pull requests merge cleanly but may not work together:".into());
+                for finding in &findings {
+                    let numbers: Vec<String> =
+                        finding.numbers.iter().map(|n| format!("#{}", n)).collect();
+                    match &finding.detail {
+                        Some(detail) => report(format!(
+                            "  {} ({}): `{}`",
+                            numbers.join(", "),
+                            finding.rule,
+                            detail
+                        )),
+                        None => report(format!("  {} ({})", numbers.join(", "), finding.rule)),
+                    }
+                }
+                if fail {
+                    error!(
+                        "\nLeave all but one of each out, or set \
+                         \nintegrate.semantic-conflicts = warn to integrate them anyway."
+                    );
+                    process::exit(1);
+                }
+            }
+        }
+
+        let max_file_size = self
+            .opts
+            .value_of("max-file-size")
+            .map(String::from)
+            .or_else(|| self.setting("max-file-size"))
+            .map(|size| {
+                sizes::parse_size(&size).unwrap_or_else(|| panic!("Invalid file size {}", size))
+            });
+        if let Some(limit) = max_file_size {
+            let policy = self
+                .setting("large-files")
+                .map(|value| {
+                    sizes::Policy::parse(&value)
+                        .unwrap_or_else(|| panic!("Unknown large file policy {}", value))
+                })
+                .unwrap_or(sizes::Policy::Fail);
+
+            let mut oversized = vec![];
+            for pr in &pull_requests {
+                let files = match sizes::oversized(self.repository, self.base, pr, limit) {
+                    Ok(files) => files,
+                    Err(e) => {
+                        error!(
+                            "\nCould not measure the files #{} ({}) adds: {}",
+                            pr.number, pr.branch, e
+                        );
+                        process::exit(1);
+                    }
+                };
+                if !files.is_empty() {
+                    oversized.push((pr.clone(), files));
+                }
+            }
+
+            if !oversized.is_empty() {
+                let report = |line: String| match policy {
+                    sizes::Policy::Fail => error!("{}", line),
+                    sizes::Policy::Exclude => warn!("{}", line),
+                };
+                report(format!(
+                    "\nThese pull requests add files over {} bytes that are not in git-lfs:",
+                    limit
+                ));
+                for (pr, files) in &oversized {
+                    report(format!("  #{} {} ({})", pr.number, pr.title, pr.branch));
+                    for (path, size) in files {
+                        report(format!("    {} ({} bytes)", path.display(), size));
+                    }
+                }
+
+                match policy {
+                    sizes::Policy::Fail => {
+                        error!(
+                            "\nTrack the files with `git lfs track` or shrink them before integrating."
+                        );
+                        process::exit(1);
+                    }
+                    sizes::Policy::Exclude => {
+                        warn!("\nLeaving them out of {}", self.dest_branch);
+                        pull_requests
+                            .retain(|pr| !oversized.iter().any(|(o, _)| o.number == pr.number));
+                        for (pr, files) in oversized {
+                            excluded.push((
+                                pr,
+                                Some(format!("{} files over the size limit", files.len())),
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        // Only now that nothing more is left out, they go by index.
+        let sections = match &categories {
+            Some(categories) if section_markers => categories.sections(&pull_requests),
+            _ => vec![None; pull_requests.len()],
+        };
+
+        if self.graph {
+            let format = self
+                .opts
+                .value_of("graph-format")
+                .and_then(graph::Format::parse)
+                .expect("Invalid graph format");
+            let matrix = speculate::matrix(self.repository, self.base, &pull_requests)
+                .unwrap_or_else(|e| panic!("{}", e));
+            summary!("{}", graph::render(&matrix, format).trim_end());
+            temp_refs::clean_up(self.repository);
+            process::exit(0);
+        }
+
+        if self.dry_run {
+            let matrix = speculate::matrix(self.repository, self.base, &pull_requests)
+                .unwrap_or_else(|e| panic!("{}", e));
+            summary!(
+                "\nDry run of merging {} pull requests into {}",
+                pull_requests.len(),
+                self.dest_branch
+            );
+            summary!("{}", matrix.to_text());
+            temp_refs::clean_up(self.repository);
+            process::exit(if matrix.is_clean() { 0 } else { 1 });
+        }
+
+        if self.opts.is_present("explain") {
+            let mut fetches = vec![];
+            if !no_fetch {
+                match fetch_jobs {
+                    Some(_) => fetches.extend(explain::fetch_branches(&self.bases(), 1)),
+                    None => fetches.push(vec!["fetch".to_string(), "--all".to_string()]),
+                }
+                if !forks.is_empty() {
+                    let mut fetch = vec!["fetch".to_string(), remote().to_string()];
+                    fetch.extend(forks.iter().cloned());
+                    fetches.push(fetch);
+                }
+                if let Some(jobs) = fetch_jobs {
+                    let branches: Vec<String> = pull_requests
+                        .iter()
+                        .filter(|pr| pr.fork_ref.is_none())
+                        .map(|pr| pr.branch.clone())
+                        .collect();
+                    fetches.extend(explain::fetch_branches(&branches, jobs));
+                }
+            }
+            let mut pushes = vec![];
+            if self.opts.is_present("push") {
+                pushes.push(remote().to_string());
+                pushes.extend(push_remotes(
+                    self.opts,
+                    self.settings,
+                    self.criteria.dest_branch,
+                ));
+            }
+            summary!(
+                "{}",
+                explain::Script {
+                    base: self.base,
+                    dest_branch: self.dest_branch,
+                    fetches,
+                    pull_requests: &pull_requests,
+                    sections: &sections,
+                    pushes,
+                }
+                .to_text()
+                .trim_end()
+            );
+            temp_refs::clean_up(self.repository);
+            process::exit(0);
+        }
+
+        let forecast = match speculate::forecast(self.repository, self.base, &pull_requests) {
+            Ok(forecast) => forecast,
+            Err(e) => panic!("{}", e),
+        };
+
+        if !forecast.is_empty() {
+            warn!("\nThese merges are expected to conflict (rerere may resolve some):");
+            for conflict in &forecast {
+                warn!(
+                    "  #{} {} ({})",
+                    conflict.pr.number, conflict.pr.title, conflict.pr.branch
+                );
+                for path in &conflict.paths {
+                    warn!("    {}", path.display());
+                }
+            }
+
+            if !self.opts.is_present("yes") && !confirm("\nContinue anyway?") {
+                process::exit(1);
+            }
+        }
+
+        let approval_team = self
+            .opts
+            .value_of("require-approval-from")
+            .map(String::from)
+            .or_else(|| self.setting("require-approval-from"));
+
+        if let Some(team) = approval_team {
+            let team = approval::Team::parse(&team, self.repo);
+            let timeout = self
+                .opts
+                .value_of("approval-timeout")
+                .and_then(|x| x.trim().parse().ok())
+                .map(|minutes: u64| Duration::from_secs(minutes * 60))
+                .expect("Invalid approval timeout");
+
+            if github::writes_blocked() {
+                error!(
+                    "\nWould have asked {}/{} for approval, which --no-api-writes can't do",
+                    team.org, team.slug
+                );
+                process::exit(1)
+            }
+            match approval::wait_for_approval(
+                self.require_token(),
+                self.repo,
+                &team,
+                self.dest_branch,
+                self.base,
+                &pull_requests,
+                timeout,
+            ) {
+                Ok(Some(approver)) => info!("\nApproved by {}", approver),
+                Ok(None) => {
+                    error!("\nTimed out waiting for approval");
+                    process::exit(1)
+                }
+                Err(e) => panic!("{}", e),
+            }
+        }
+
+        let original_head = self.repository.head().ok().and_then(|head| {
+            if head.is_branch() {
+                head.shorthand().map(String::from)
+            } else {
+                head.target().map(|id| id.to_string())
+            }
+        });
+        let original_branch = self
+            .repository
+            .revparse_single(&format!("refs/heads/{}", self.dest_branch))
+            .map(|commit| commit.id().to_string())
+            .ok();
+        if !self
+            .git
+            .checkout(self.dest_branch)
+            .unwrap_or_else(|_| panic!("Could not checkout branch {}", self.dest_branch))
+        {
+            process::exit(1)
+        }
+
+        MergePlan {
+            pull_requests,
+            sections,
+            excluded,
+            tracked_base,
+            original_head,
+            original_branch,
+        }
+    }
+}
+
+/// Asks a yes/no question on the terminal, defaulting to no when there is no
+/// one to answer it.
+fn confirm(question: &str) -> bool {
+    if !io::stdin().is_terminal() {
+        error!(
+            "{} Not a terminal, pass --yes to continue without asking",
+            question
+        );
+        return false;
+    }
+
+    error!("{} [y/N]", question);
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer).is_ok() && matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::cli;
+    use super::*;
+
+    fn criteria<T: Fn(&SelectionCriteria)>(args: &[&str], check: T) {
+        let opts = cli::app().get_matches_from(args);
+        check(&SelectionCriteria::from_args(&opts));
+    }
+
+    #[test]
+    fn unicode_labels_from_the_command_line() {
+        criteria(
+            &["git-integrate", "🚀 deploy to staging", "staging"],
+            |c| {
+                assert!(matches!(&c.selection, Selection::Label(l) if l == "🚀 deploy to staging"));
+                assert_eq!(c.dest_branch, "staging");
+            },
+        );
+        criteria(
+            &["git-integrate", "--label=🚀 deploy to staging", "staging"],
+            |c| {
+                assert!(matches!(&c.selection, Selection::Label(l) if l == "🚀 deploy to staging"));
+                assert_eq!(c.selector, "🚀 deploy to staging");
+            },
+        );
+    }
+
+    #[test]
+    fn several_labels_match_exactly_whatever_they_contain() {
+        criteria(
+            &[
+                "git-integrate",
+                "--label",
+                "🚀 deploy (eu)",
+                "--label",
+                "qa+1 [beta]",
+                "staging",
+            ],
+            |c| match &c.selection {
+                Selection::LabelPattern(pattern) => {
+                    assert!(pattern.is_match("🚀 Deploy (EU)"));
+                    assert!(pattern.is_match("qa+1 [beta]"));
+                    assert!(!pattern.is_match("🚀 deploy eu"));
+                    assert!(!pattern.is_match("qa1 [beta]"));
+                }
+                other => panic!("{:?}", other),
+            },
+        );
+    }
+}
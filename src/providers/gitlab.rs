@@ -0,0 +1,217 @@
+use chrono::{DateTime, Utc};
+use reqwest::header::USER_AGENT;
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+
+use super::super::git_extras::Repo;
+use super::super::github::rest::path_segment;
+use super::super::github::{self, client, head_ref, PullRequest, Selection, SendRecorded};
+use super::Provider;
+
+/// The API of gitlab.com. Self-hosted instances serve it at `/api/v4` too.
+pub const DEFAULT_API_URL: &str = "https://gitlab.com/api/v4";
+
+#[derive(Debug, Clone, Deserialize)]
+struct User {
+    username: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Milestone {
+    title: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Label {
+    name: String,
+}
+
+/// A label or milestone added to or removed from a merge request.
+#[derive(Debug, Clone, Deserialize)]
+struct ResourceEvent {
+    action: String,
+    created_at: String,
+    #[serde(default)]
+    label: Option<Label>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct MergeRequest {
+    iid: i64,
+    title: String,
+    description: Option<String>,
+    source_branch: String,
+    target_branch: String,
+    author: Option<User>,
+    labels: Vec<String>,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    source_project_id: i64,
+    #[serde(default)]
+    target_project_id: i64,
+}
+
+impl From<MergeRequest> for PullRequest {
+    fn from(mr: MergeRequest) -> PullRequest {
+        let fork = mr.source_project_id != mr.target_project_id;
+        let fork_ref = fork.then(|| format!("refs/merge-requests/{}/head", mr.iid));
+        PullRequest {
+            number: mr.iid,
+            title: mr.title,
+            body: mr.description.unwrap_or_default(),
+            revision: head_ref(mr.iid, &mr.source_branch, fork),
+            branch: mr.source_branch,
+            base: mr.target_branch,
+            author: mr.author.map(|a| a.username),
+            labels: mr.labels,
+            draft: mr.draft,
+            status: None,
+            fork_ref,
+        }
+    }
+}
+
+/// Merge requests of a GitLab project, the owner of the repository being its
+/// group path.
+pub struct GitLab<'a> {
+    pub url: &'a str,
+    pub token: Option<&'a str>,
+    pub repo: &'a Repo,
+}
+
+impl GitLab<'_> {
+    fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<T, reqwest::Error> {
+        let project = path_segment(&format!("{}/{}", self.repo.owner, self.repo.name));
+        let url = format!(
+            "{}/projects/{}{}",
+            self.url.trim_end_matches('/'),
+            project,
+            path
+        );
+        let mut request = client()?
+            .get(&url)
+            .header(USER_AGENT, "git-integrate")
+            .query(query);
+        if let Some(token) = self.token {
+            request = request.header("PRIVATE-TOKEN", token);
+        }
+        request.send_recorded()?.error_for_status()?.json()
+    }
+
+    /// Open merge requests matching `filter`, oldest first.
+    fn merge_requests(
+        &self,
+        filter: &[(&str, String)],
+    ) -> Result<Vec<PullRequest>, reqwest::Error> {
+        let mut pull_requests = vec![];
+        for page in 1.. {
+            let mut query = vec![
+                ("state", "opened".to_string()),
+                ("order_by", "created_at".to_string()),
+                ("sort", "asc".to_string()),
+                ("per_page", "100".to_string()),
+                ("page", page.to_string()),
+            ];
+            query.extend_from_slice(filter);
+            let batch: Vec<MergeRequest> = self.get("/merge_requests", &query)?;
+            let done = batch.len() < 100;
+            pull_requests.extend(batch.into_iter().map(PullRequest::from));
+            if done {
+                break;
+            }
+        }
+        Ok(pull_requests)
+    }
+
+    /// The open merge requests `selection` picks.
+    fn selected(&self, selection: &Selection) -> Result<Vec<PullRequest>, reqwest::Error> {
+        match selection {
+            // Milestones are filtered by title, numbers are their IIDs.
+            Selection::Milestone(number) => {
+                let milestones: Vec<Milestone> =
+                    self.get("/milestones", &[("iids[]", number.to_string())])?;
+                match milestones.into_iter().next() {
+                    Some(milestone) => self.merge_requests(&[("milestone", milestone.title)]),
+                    None => Ok(vec![]),
+                }
+            }
+            Selection::Label(label) => self.merge_requests(&[("labels", label.clone())]),
+            Selection::LabelPattern(pattern) => Ok(self
+                .merge_requests(&[])?
+                .into_iter()
+                .filter(|mr| mr.labels.iter().any(|label| pattern.is_match(label)))
+                .collect()),
+            // GitLab only searches titles and descriptions.
+            Selection::Search(query) => self.merge_requests(&[("search", query.clone())]),
+            Selection::Listed(items) => {
+                let mut merge_requests: Vec<PullRequest> = vec![];
+                for item in items {
+                    let filter = match item.trim_start_matches('!').parse::<i64>() {
+                        Ok(iid) => ("iids[]", iid.to_string()),
+                        Err(_) => ("source_branch", item.clone()),
+                    };
+                    match self.merge_requests(&[filter])?.into_iter().next() {
+                        Some(mr)
+                            if !merge_requests
+                                .iter()
+                                .any(|listed| listed.number == mr.number) =>
+                        {
+                            merge_requests.push(mr)
+                        }
+                        Some(_) => (),
+                        None => warn!("No open merge request for {}", item),
+                    }
+                }
+                Ok(merge_requests)
+            }
+        }
+    }
+}
+
+impl Provider for GitLab<'_> {
+    fn pull_requests(&self, selection: &Selection) -> Result<Vec<PullRequest>, github::Error> {
+        Ok(self.selected(selection)?)
+    }
+
+    fn selected_at(
+        &self,
+        number: i64,
+        selection: &Selection,
+    ) -> Result<Option<DateTime<Utc>>, reqwest::Error> {
+        let events = match selection {
+            Selection::Milestone(_) => "resource_milestone_events",
+            Selection::Label(_) | Selection::LabelPattern(_) => "resource_label_events",
+            Selection::Search(_) | Selection::Listed(_) => return Ok(None),
+        };
+        let mut added = None;
+        for page in 1.. {
+            let batch: Vec<ResourceEvent> = self.get(
+                &format!("/merge_requests/{}/{}", number, events),
+                &[("per_page", "100".to_string()), ("page", page.to_string())],
+            )?;
+            let done = batch.len() < 100;
+            added = batch
+                .into_iter()
+                .rev()
+                .find(|event| {
+                    event.action == "add"
+                        && match &event.label {
+                            Some(label) => selection.picks_label(&label.name),
+                            None => matches!(selection, Selection::Milestone(_)),
+                        }
+                })
+                .or(added);
+            if done {
+                break;
+            }
+        }
+        Ok(added
+            .and_then(|event| DateTime::parse_from_rfc3339(&event.created_at).ok())
+            .map(|time| time.with_timezone(&Utc)))
+    }
+}
@@ -0,0 +1,60 @@
+use chrono::{DateTime, Utc};
+
+use super::git_extras::Repo;
+use super::github::{self, PullRequest, Selection};
+
+pub mod gitlab;
+
+/// Where the pull requests to integrate come from.
+pub trait Provider {
+    /// The open pull requests `selection` picks, oldest first.
+    fn pull_requests(&self, selection: &Selection) -> Result<Vec<PullRequest>, github::Error>;
+
+    /// When pull request `number` was last given the label or milestone
+    /// `selection` picks it by, none when it selects by something else.
+    fn selected_at(
+        &self,
+        number: i64,
+        selection: &Selection,
+    ) -> Result<Option<DateTime<Utc>>, reqwest::Error>;
+}
+
+pub struct GitHub<'a> {
+    pub token: Option<&'a str>,
+    pub repo: &'a Repo,
+}
+
+impl Provider for GitHub<'_> {
+    fn pull_requests(&self, selection: &Selection) -> Result<Vec<PullRequest>, github::Error> {
+        github::pull_requests(self.token, self.repo, selection)
+    }
+
+    fn selected_at(
+        &self,
+        number: i64,
+        selection: &Selection,
+    ) -> Result<Option<DateTime<Utc>>, reqwest::Error> {
+        github::selected_at(self.token, self.repo, number, selection)
+    }
+}
+
+/// The provider `integrate.provider` names, `github` or `gitlab`, talking to
+/// `api_url` if given instead of the public instance.
+pub fn open<'a>(
+    name: &str,
+    api_url: Option<&'a str>,
+    token: Option<&'a str>,
+    repo: &'a Repo,
+) -> Option<Box<dyn Provider + 'a>> {
+    match name {
+        // The GitHub API URL is set once for every request, see
+        // `github::set_api_url`.
+        "github" => Some(Box::new(GitHub { token, repo })),
+        "gitlab" => Some(Box::new(gitlab::GitLab {
+            url: api_url.unwrap_or(gitlab::DEFAULT_API_URL),
+            token,
+            repo,
+        })),
+        _ => None,
+    }
+}
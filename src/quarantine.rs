@@ -0,0 +1,61 @@
+use super::git_extras::Repo;
+use super::github::{rest, PullRequest};
+use super::history::Run;
+use super::report::Report;
+
+/// Label that keeps a pull request out of every build until it is removed.
+pub const DEFAULT_LABEL: &str = "quarantined";
+
+/// Labels pull requests that broke `after` runs of a branch in a row, so one
+/// rotten branch stops failing the queue every night.
+pub struct Quarantine<'a> {
+    pub token: &'a str,
+    pub repo: &'a Repo,
+    pub label: String,
+    pub after: usize,
+}
+
+impl Quarantine<'_> {
+    /// Quarantines the pull requests that failed in `report` if they also
+    /// failed in every earlier run of the branch they took part in,
+    /// `after` times in total. Returns the ones that were labelled.
+    pub fn check(&self, runs: &[Run], report: &Report) -> Result<Vec<PullRequest>, reqwest::Error> {
+        let mut quarantined = vec![];
+        for entry in report.entries.iter().filter(|e| !e.outcome.is_success()) {
+            if entry.pr.has_label(&self.label) {
+                continue;
+            }
+
+            let failures = runs
+                .iter()
+                .rev()
+                .filter(|run| run.builds(&report.branch_template))
+                .filter_map(|run| run.pulls.iter().find(|p| p.number == entry.pr.number))
+                .take_while(|pull| !pull.outcome.is_success())
+                .count();
+            if failures < self.after {
+                continue;
+            }
+
+            rest::add_labels(
+                self.token,
+                self.repo,
+                entry.pr.number,
+                std::slice::from_ref(&self.label),
+            )?;
+            rest::create_comment(
+                self.token,
+                self.repo,
+                entry.pr.number,
+                &format!(
+                    "This branch failed to integrate into `{}` {} runs in a row, so it is \
+                     quarantined and left out of future builds. Remove the `{}` label once \
+                     it merges cleanly again.",
+                    report.dest_branch, failures, self.label
+                ),
+            )?;
+            quarantined.push(entry.pr.clone());
+        }
+        Ok(quarantined)
+    }
+}
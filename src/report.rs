@@ -0,0 +1,336 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, FixedOffset};
+use serde_derive::{Deserialize, Serialize};
+
+use super::binary::Choice;
+use super::dates;
+use super::github::PullRequest;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    /// Merged without conflicts.
+    Merged,
+    /// Conflicts were resolved by rerere or the binary conflict policy and
+    /// committed.
+    Resolved,
+    /// Conflicts were resolved by hand in the conflict shell.
+    ResolvedByHand,
+    /// Conflicts were resolved by rerere but left for inspection.
+    ResolutionPending,
+    /// Conflicts need to be resolved by hand.
+    Conflicted,
+    /// git failed for another reason.
+    Failed,
+    /// Merged, but the `--verify` command failed on the result.
+    VerifyFailed,
+    /// The merge took longer than `--merge-timeout` and was given up on.
+    TimedOut,
+}
+
+impl Outcome {
+    pub fn is_success(self) -> bool {
+        self == Outcome::Merged || self == Outcome::Resolved || self == Outcome::ResolvedByHand
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Outcome::Merged => "✅ merged",
+            Outcome::Resolved => "✅ merged (conflicts resolved automatically)",
+            Outcome::ResolvedByHand => "✅ merged (conflicts resolved by hand)",
+            Outcome::ResolutionPending => "⏸️ resolved by rerere, awaiting review",
+            Outcome::Conflicted => "❌ conflict",
+            Outcome::Failed => "❌ failed",
+            Outcome::VerifyFailed => "❌ verification failed",
+            Outcome::TimedOut => "⏭️ skipped, the merge took too long",
+        }
+    }
+}
+
+pub struct Entry {
+    pub pr: PullRequest,
+    pub outcome: Outcome,
+    pub conflicts: Vec<PathBuf>,
+    /// Binary conflicts resolved by the binary conflict policy.
+    pub binary: Vec<Choice>,
+    /// Commit authors of the branch, as `.mailmap` names them.
+    pub authors: Vec<String>,
+    /// The head commit of the branch when it was merged.
+    pub head: Option<String>,
+    /// The merge commit, missing when the branch was already merged.
+    pub merge_commit: Option<String>,
+    /// The tree of the integration branch after the merge.
+    pub tree: Option<String>,
+    pub duration: Duration,
+    /// How many times `--verify` failed before it passed.
+    pub verify_retries: usize,
+}
+
+/// Collects what happened to each pull request during a run.
+pub struct Report {
+    pub dest_branch: String,
+    /// The branch as configured, before `{date}` was expanded.
+    pub branch_template: String,
+    /// The milestone or label the pull requests were selected by.
+    pub selection: String,
+    pub started: Instant,
+    pub started_at: DateTime<FixedOffset>,
+    pub planned: Vec<PullRequest>,
+    pub entries: Vec<Entry>,
+    /// Pull requests left out on purpose, with the reason when known.
+    pub excluded: Vec<(PullRequest, Option<String>)>,
+    /// Pull requests in the last build whose head changed since, so what
+    /// they do needs testing again.
+    pub updated: Vec<i64>,
+    /// Whether every merge was checked with `--verify`.
+    pub verified: bool,
+    /// Every remote the branch was pushed to, and whether that worked.
+    pub pushes: Vec<(String, bool)>,
+    /// The release candidate a `--train` run tagged.
+    pub release_candidate: Option<u32>,
+}
+
+impl Report {
+    pub fn new(dest_branch: &str, selection: &str, planned: &[PullRequest]) -> Report {
+        Report {
+            dest_branch: dest_branch.to_string(),
+            branch_template: dest_branch.to_string(),
+            selection: selection.to_string(),
+            started: Instant::now(),
+            started_at: dates::now(),
+            planned: planned.to_vec(),
+            entries: vec![],
+            excluded: vec![],
+            updated: vec![],
+            verified: false,
+            pushes: vec![],
+            release_candidate: None,
+        }
+    }
+
+    pub fn record(&mut self, entry: Entry) {
+        self.entries.push(entry);
+    }
+
+    /// A short plain text version of the report for the end of the output.
+    pub fn to_text(&self) -> String {
+        let merged = self
+            .entries
+            .iter()
+            .filter(|e| e.outcome.is_success())
+            .count();
+        let mut out = format!(
+            "Integrated {} of {} pull requests into {}",
+            merged,
+            self.planned.len(),
+            self.dest_branch
+        );
+
+        // Verified runs tell which branches passed too.
+        for entry in self
+            .entries
+            .iter()
+            .filter(|e| self.verified || !e.outcome.is_success())
+        {
+            out.push_str(&format!(
+                "\n  #{} {} ({}): {}",
+                entry.pr.number,
+                entry.pr.title,
+                entry.pr.branch,
+                self.describe(entry.outcome)
+            ));
+        }
+        let skipped = self.planned.len().saturating_sub(self.entries.len());
+        if skipped > 0 {
+            out.push_str(&format!("\n  {} not attempted", skipped));
+        }
+        if !self.excluded.is_empty() {
+            out.push_str(&format!("\n  {} excluded", self.excluded.len()));
+        }
+        let flaky = self.flaky().count();
+        if flaky > 0 {
+            out.push_str(&format!("\n  {} passed verification only on retry", flaky));
+        }
+        if !self.updated.is_empty() {
+            out.push_str(&format!(
+                "\n  {} updated since the last build",
+                self.updated.len()
+            ));
+        }
+        if !self.pushes.is_empty() {
+            out.push_str(&format!("\n  Pushed: {}", self.describe_pushes()));
+        }
+        if let Some(tag) = self.release_candidate_tag() {
+            out.push_str(&format!("\n  Tagged {}", tag));
+        }
+        out
+    }
+
+    /// Merges whose verification failed before it passed, so the suite
+    /// rather than the pull request is suspect.
+    fn flaky(&self) -> impl Iterator<Item = &Entry> {
+        self.entries
+            .iter()
+            .filter(|e| e.outcome.is_success() && e.verify_retries > 0)
+    }
+
+    fn release_candidate_tag(&self) -> Option<String> {
+        self.release_candidate
+            .map(|rc| format!("{}/rc.{}", self.dest_branch, rc))
+    }
+
+    fn describe_pushes(&self) -> String {
+        self.pushes
+            .iter()
+            .map(|(remote, pushed)| format!("{} {}", remote, if *pushed { "✅" } else { "❌" }))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn describe(&self, outcome: Outcome) -> &'static str {
+        match outcome {
+            Outcome::Merged | Outcome::Resolved | Outcome::ResolvedByHand if self.verified => {
+                "✅ passed verification"
+            }
+            _ => outcome.describe(),
+        }
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!(
+            "## Integration of `{}`\n\nStarted {}\n\n",
+            self.dest_branch,
+            dates::date_time(&self.started_at)
+        );
+        out.push_str("| Pull request | Branch | Result | Time |\n");
+        out.push_str("| --- | --- | --- | --- |\n");
+
+        for entry in &self.entries {
+            out.push_str(&format!(
+                "| #{} {} | `{}` | {}{} | {:.1}s |\n",
+                entry.pr.number,
+                escape(&entry.pr.title),
+                entry.pr.branch,
+                self.describe(entry.outcome),
+                if self.updated.contains(&entry.pr.number) {
+                    ", 🔄 updated since last build"
+                } else {
+                    ""
+                },
+                entry.duration.as_secs_f64()
+            ));
+        }
+
+        for pr in self.planned.iter().skip(self.entries.len()) {
+            out.push_str(&format!(
+                "| #{} {} | `{}` | ⏭️ not attempted | |\n",
+                pr.number,
+                escape(&pr.title),
+                pr.branch
+            ));
+        }
+
+        for (pr, reason) in &self.excluded {
+            out.push_str(&format!(
+                "| #{} {} | `{}` | 🚫 excluded{} | |\n",
+                pr.number,
+                escape(&pr.title),
+                pr.branch,
+                reason
+                    .as_ref()
+                    .map(|r| format!(": {}", escape(r)))
+                    .unwrap_or_default()
+            ));
+        }
+
+        for entry in self.entries.iter().filter(|e| !e.conflicts.is_empty()) {
+            out.push_str(&format!("\n### Conflicts merging #{}\n\n", entry.pr.number));
+            for path in &entry.conflicts {
+                out.push_str(&format!("- `{}`\n", path.display()));
+            }
+        }
+
+        for entry in self.entries.iter().filter(|e| !e.binary.is_empty()) {
+            out.push_str(&format!(
+                "\n### Binary conflicts resolved merging #{}\n\n",
+                entry.pr.number
+            ));
+            for choice in &entry.binary {
+                out.push_str(&format!("- `{}`\n", escape(&choice.describe())));
+            }
+        }
+
+        let updated: Vec<&Entry> = self
+            .entries
+            .iter()
+            .filter(|e| e.outcome.is_success() && self.updated.contains(&e.pr.number))
+            .collect();
+        if !updated.is_empty() {
+            out.push_str("\n### Updated since last build\n\n");
+            for entry in updated {
+                out.push_str(&format!(
+                    "- #{} {}\n",
+                    entry.pr.number,
+                    escape(&entry.pr.title)
+                ));
+            }
+        }
+
+        if self.flaky().next().is_some() {
+            out.push_str("\n### Flaky verification\n\n");
+            for entry in self.flaky() {
+                out.push_str(&format!(
+                    "- #{} {}, failed {} times before passing\n",
+                    entry.pr.number,
+                    escape(&entry.pr.title),
+                    entry.verify_retries
+                ));
+            }
+        }
+
+        let mut authors: Vec<(&str, Vec<i64>)> = vec![];
+        for entry in self.entries.iter().filter(|e| e.outcome.is_success()) {
+            for author in &entry.authors {
+                match authors.iter_mut().find(|(a, _)| a == author) {
+                    Some((_, numbers)) => numbers.push(entry.pr.number),
+                    None => authors.push((author, vec![entry.pr.number])),
+                }
+            }
+        }
+        if !authors.is_empty() {
+            out.push_str("\n### Authors\n\n");
+            for (author, numbers) in authors {
+                let numbers: Vec<String> = numbers.iter().map(|n| format!("#{}", n)).collect();
+                out.push_str(&format!("- {} ({})\n", escape(author), numbers.join(", ")));
+            }
+        }
+
+        if !self.pushes.is_empty() {
+            out.push_str(&format!("\nPushed to {}\n", self.describe_pushes()));
+        }
+        if let Some(tag) = self.release_candidate_tag() {
+            out.push_str(&format!("\nTagged `{}`\n", tag));
+        }
+
+        out.push_str(&format!(
+            "\nTotal time: {:.1}s\n",
+            self.started.elapsed().as_secs_f64()
+        ));
+        out
+    }
+
+    /// Appends the Markdown report to `path`, which is how GitHub Actions
+    /// expects step summaries to be written.
+    pub fn write_summary(&self, path: &Path) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(self.to_markdown().as_bytes())
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('|', "\\|")
+}
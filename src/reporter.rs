@@ -0,0 +1,234 @@
+use std::path::PathBuf;
+use std::{fs, io, process};
+
+use super::git_extras::Repo;
+use super::history::{self, History};
+use super::report::Report;
+use super::settings::Settings;
+use super::{escalate, github, labels, quarantine};
+
+/// What happens at the end of a run, whichever way it ends: the report is
+/// printed and recorded, repeated failures are escalated and a complete build
+/// labels what it merged.
+pub struct Reporter<'a> {
+    history: History,
+    summary: Option<PathBuf>,
+    manifest: Option<PathBuf>,
+    escalation: Option<escalate::Escalation<'a>>,
+    quarantine: Option<quarantine::Quarantine<'a>>,
+    status_label: Option<labels::StatusLabel<'a>>,
+    build_label: Option<labels::BuildLabel<'a>>,
+}
+
+impl<'a> Reporter<'a> {
+    /// Escalates, quarantines and labels as the settings of `branch` ask.
+    pub fn configure(
+        settings: &Settings,
+        branch: &str,
+        repo: &'a Repo,
+        token: Option<&'a str>,
+        history: History,
+        summary: Option<PathBuf>,
+        quarantine_label: &str,
+    ) -> Reporter<'a> {
+        let setting = |key: &str| settings.for_branch(branch, key);
+        let escalation = match (setting("open-issue-after"), token) {
+            (Some(after), Some(token)) => Some(escalate::Escalation {
+                token,
+                repo,
+                after: after
+                    .trim()
+                    .parse()
+                    .expect("Invalid integrate.open-issue-after"),
+                owners: setting("owners")
+                    .map(|owners| {
+                        owners
+                            .split(|c: char| c == ',' || c.is_whitespace())
+                            .filter(|owner| !owner.is_empty())
+                            .map(|owner| owner.trim_start_matches('@').to_string())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            }),
+            (Some(_), None) => {
+                warn!(
+                    "integrate.open-issue-after needs integrate.github-token, not opening issues"
+                );
+                None
+            }
+            _ => None,
+        };
+
+        let quarantine = match (setting("quarantine-after"), token) {
+            (Some(after), Some(token)) => Some(quarantine::Quarantine {
+                token,
+                repo,
+                label: quarantine_label.to_string(),
+                after: after
+                    .trim()
+                    .parse()
+                    .expect("Invalid integrate.quarantine-after"),
+            }),
+            (Some(_), None) => {
+                warn!("integrate.quarantine-after needs integrate.github-token, not quarantining");
+                None
+            }
+            _ => None,
+        };
+
+        let status_label = match (setting("status-label"), token) {
+            (Some(label), Some(token)) => Some(labels::StatusLabel { token, repo, label }),
+            (Some(_), None) => {
+                warn!("integrate.status-label needs integrate.github-token, not syncing the label");
+                None
+            }
+            _ => None,
+        };
+
+        let build_label = match (setting("build-label"), token) {
+            (Some(template), Some(token)) => Some(labels::BuildLabel {
+                token,
+                repo,
+                template,
+            }),
+            (Some(_), None) => {
+                warn!(
+                    "integrate.build-label needs integrate.github-token, not labelling the build"
+                );
+                None
+            }
+            _ => None,
+        };
+
+        Reporter {
+            history,
+            summary,
+            manifest: None,
+            escalation,
+            quarantine,
+            status_label,
+            build_label,
+        }
+    }
+
+    /// Also writes the run as JSON to `path`, for scripts.
+    pub fn with_manifest(self, path: Option<PathBuf>) -> Reporter<'a> {
+        Reporter {
+            manifest: path,
+            ..self
+        }
+    }
+
+    pub fn finish(&self, report: &Report, code: i32) -> ! {
+        let run = history::Run::from_report(report, code == 0);
+        // Labels say what is deployed, so only a pushed build moves them.
+        let pushed = code == 0 && run.pushed;
+        // Read before this run is recorded.
+        let last_push = match &self.status_label {
+            Some(_) if pushed => self
+                .history
+                .last_push(&report.branch_template)
+                .unwrap_or_else(|e| {
+                    error!("Could not read the run history: {}", e);
+                    None
+                }),
+            _ => None,
+        };
+        if let Some(cost) = &run.query_cost {
+            info!("\n{}", cost.describe());
+        }
+        if let Some(url) = github::failed_over() {
+            warn!("The API requests of this run went to the fallback {}", url);
+        }
+        summary!("\n{}", report.to_text());
+
+        if let Err(e) = self.history.append(&run) {
+            error!("Could not record the run in the history: {}", e);
+        }
+        if let Some(path) = &self.manifest {
+            let written = serde_json::to_vec_pretty(&run)
+                .map_err(io::Error::from)
+                .and_then(|json| fs::write(path, json));
+            if let Err(e) = written {
+                error!("Could not write the manifest to {}: {}", path.display(), e);
+            }
+        }
+
+        // A pull request can fail in a run that still succeeds, such as one
+        // that timed out or skipped its failed verification.
+        let failed = report.entries.iter().any(|e| !e.outcome.is_success());
+        let quarantine = self.quarantine.as_ref().filter(|_| failed);
+        let escalation = self.escalation.as_ref().filter(|_| code != 0);
+        if escalation.is_some() || quarantine.is_some() {
+            match self.history.runs() {
+                Ok(runs) => {
+                    if let Some(quarantine) = quarantine {
+                        match quarantine.check(&runs, report) {
+                            Ok(quarantined) => {
+                                for pr in quarantined {
+                                    error!(
+                                        "Quarantined #{} {} after repeated failures",
+                                        pr.number, pr.branch
+                                    );
+                                }
+                            }
+                            Err(e) => {
+                                error!("Could not quarantine the failing pull request: {}", e)
+                            }
+                        }
+                    }
+                    if let Some(escalation) = escalation {
+                        match escalation.check(&runs, report) {
+                            Ok(Some(issue)) => {
+                                error!("Reported the repeated failures in {}", issue.html_url)
+                            }
+                            Ok(None) => (),
+                            Err(e) => error!("Could not open an issue about the failures: {}", e),
+                        }
+                    }
+                }
+                Err(e) => error!("Could not read the run history: {}", e),
+            }
+        }
+
+        // Only a complete build says what ends up deployed.
+        if let (true, Some(status_label)) = (pushed, &self.status_label) {
+            match status_label.sync(report, last_push.as_ref()) {
+                Ok((added, removed)) => {
+                    let numbers = |numbers: Vec<i64>| {
+                        numbers
+                            .iter()
+                            .map(|n| format!("#{}", n))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    };
+                    if !added.is_empty() {
+                        info!("Labelled {} {}", numbers(added), status_label.label);
+                    }
+                    if !removed.is_empty() {
+                        info!("Unlabelled {} {}", numbers(removed), status_label.label);
+                    }
+                }
+                Err(e) => error!("Could not sync the {} label: {}", status_label.label, e),
+            }
+        }
+
+        if let (true, Some(build_label)) = (pushed, &self.build_label) {
+            match build_label.apply(report) {
+                Ok((label, labelled)) if !labelled.is_empty() => {
+                    let numbers: Vec<String> = labelled.iter().map(|n| format!("#{}", n)).collect();
+                    info!("Labelled {} {}", numbers.join(", "), label);
+                }
+                Ok(_) => (),
+                Err(e) => error!("Could not label the build: {}", e),
+            }
+        }
+
+        if let Some(path) = &self.summary {
+            if let Err(e) = report.write_summary(path) {
+                error!("Could not write the summary to {}: {}", path.display(), e);
+            }
+        }
+        process::exit(code)
+    }
+}
@@ -0,0 +1,62 @@
+use chrono::Utc;
+use git2::Repository;
+use std::process::{self, Command};
+use std::sync::OnceLock;
+
+use super::executor::head_of;
+use super::git_extras::Repo;
+use super::github::PullRequest;
+
+/// The `GI_*` variables of the run, set once it knows what it builds.
+static RUN: OnceLock<Vec<(&'static str, String)>> = OnceLock::new();
+
+/// Identifies a run in the environment of its commands, the time it started
+/// and the process that started it.
+pub fn new_run_id() -> String {
+    format!("{}-{}", Utc::now().format("%Y%m%dT%H%M%SZ"), process::id())
+}
+
+/// Tells every command started from now on which run started it.
+pub fn set_run(
+    repository: &Repository,
+    run_id: &str,
+    repo: &Repo,
+    dest_branch: &str,
+    base: &str,
+) -> Result<(), String> {
+    let base_sha = repository
+        .revparse_single(base)
+        .map(|commit| commit.id().to_string())
+        .unwrap_or_default();
+    RUN.set(vec![
+        ("GI_RUN_ID", run_id.to_string()),
+        ("GI_REPOSITORY", format!("{}/{}", repo.owner, repo.name)),
+        ("GI_DEST_BRANCH", dest_branch.to_string()),
+        ("GI_BASE", base.to_string()),
+        ("GI_BASE_SHA", base_sha),
+    ])
+    .map_err(|_| "The run environment was already set".to_string())
+}
+
+/// Passes the run to `command`, such as git and the hooks it runs.
+pub fn apply_run(command: &mut Command) {
+    for (name, value) in RUN.get().map(Vec::as_slice).unwrap_or_default() {
+        command.env(name, value);
+    }
+}
+
+/// Passes the run, and `pr` as the pull request being merged, to `command`.
+pub fn apply(command: &mut Command, repository: &Repository, pr: &PullRequest) {
+    apply_run(command);
+    command
+        .env("GI_PR_NUMBER", pr.number.to_string())
+        .env("GI_PR_TITLE", &pr.title)
+        .env("GI_PR_AUTHOR", pr.author.as_deref().unwrap_or_default())
+        .env("GI_PR_LABELS", pr.labels.join("\n"))
+        .env("GI_PR_BASE", &pr.base)
+        .env(
+            "GI_PR_HEAD_SHA",
+            head_of(repository, pr).unwrap_or_default(),
+        )
+        .env("GI_BRANCH", &pr.branch);
+}
@@ -0,0 +1,111 @@
+use git2::{DiffOptions, Repository};
+use regex::Regex;
+use std::path::PathBuf;
+
+use super::conflicts;
+use super::github::PullRequest;
+
+/// Patterns for credentials that are easy to recognise. Deliberately short:
+/// anything project-specific goes in `integrate.secret-pattern`.
+const BUILTIN_RULES: &[(&str, &str)] = &[
+    ("AWS access key", r"\b(AKIA|ASIA)[0-9A-Z]{16}\b"),
+    (
+        "GitHub token",
+        r"\b(gh[pousr]_[A-Za-z0-9]{36,}|github_pat_[A-Za-z0-9_]{22,})",
+    ),
+    ("GitLab token", r"\bglpat-[A-Za-z0-9_\-]{20,}"),
+    ("Slack token", r"\bxox[abprs]-[A-Za-z0-9\-]{10,}"),
+    ("Stripe key", r"\b[sr]k_live_[0-9A-Za-z]{24,}"),
+    ("Google API key", r"\bAIza[0-9A-Za-z_\-]{35}"),
+    ("private key", r"-----BEGIN [A-Z ]*PRIVATE KEY-----"),
+    (
+        "hard-coded credential",
+        r#"(?i)\b(password|passwd|secret|api[_-]?key|access[_-]?token|auth[_-]?token)\b["']?\s*[:=]\s*["'][^"'\s]{8,}["']"#,
+    ),
+];
+
+pub struct Rule {
+    name: String,
+    pattern: Regex,
+}
+
+/// The built-in rules plus `extra` patterns from the configuration.
+pub fn rules(extra: &[String]) -> Result<Vec<Rule>, regex::Error> {
+    let mut rules = vec![];
+    for (name, pattern) in BUILTIN_RULES {
+        rules.push(Rule {
+            name: name.to_string(),
+            pattern: Regex::new(pattern)?,
+        });
+    }
+    for pattern in extra {
+        rules.push(Rule {
+            name: format!("`{}`", pattern),
+            pattern: Regex::new(pattern)?,
+        });
+    }
+    Ok(rules)
+}
+
+/// An added line that looks like it contains a credential.
+pub struct Finding {
+    pub path: PathBuf,
+    pub line: u32,
+    pub rule: String,
+    /// The merged pull request that last changed the file.
+    pub pr: Option<PullRequest>,
+}
+
+/// Looks for credentials in the lines `head` adds on top of `base`. Files
+/// whose path matches one of `allow` are skipped, for test fixtures and the
+/// like. Binary files are never scanned.
+pub fn scan(
+    repository: &Repository,
+    base: &str,
+    head: &str,
+    rules: &[Rule],
+    allow: &[Regex],
+    merged: &[PullRequest],
+) -> Result<Vec<Finding>, git2::Error> {
+    let base_tree = repository.revparse_single(base)?.peel_to_tree()?;
+    let head = repository.revparse_single(head)?.peel_to_tree()?;
+    let mut options = DiffOptions::new();
+    options.context_lines(0);
+    let diff = repository.diff_tree_to_tree(Some(&base_tree), Some(&head), Some(&mut options))?;
+
+    let mut findings = vec![];
+    diff.foreach(
+        &mut |_, _| true,
+        None,
+        None,
+        Some(&mut |delta, _, line| {
+            if line.origin() != '+' {
+                return true;
+            }
+            let path = match delta.new_file().path() {
+                Some(path) => path,
+                None => return true,
+            };
+            let display = path.to_string_lossy();
+            if allow.iter().any(|allow| allow.is_match(&display)) {
+                return true;
+            }
+
+            let content = String::from_utf8_lossy(line.content());
+            if let Some(rule) = rules.iter().find(|rule| rule.pattern.is_match(&content)) {
+                findings.push(Finding {
+                    path: path.to_path_buf(),
+                    line: line.new_lineno().unwrap_or(0),
+                    rule: rule.name.clone(),
+                    pr: None,
+                });
+            }
+            true
+        }),
+    )?;
+
+    for finding in &mut findings {
+        finding.pr = conflicts::last_changed_by(repository, base, merged, &finding.path).cloned();
+    }
+    Ok(findings)
+}
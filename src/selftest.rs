@@ -0,0 +1,226 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::{env, fs, io, process};
+
+use super::git_extras::PR_TRAILER;
+use super::github::{pull_request, PullRequest};
+use super::history::Run;
+use super::timeouts::Timed;
+
+/// A throwaway origin and clone, with nothing from the machine's git
+/// configuration leaking into them, so what the runs do only depends on this
+/// binary.
+struct Scratch {
+    root: PathBuf,
+    work: PathBuf,
+}
+
+impl Scratch {
+    fn create() -> io::Result<Scratch> {
+        let root = env::temp_dir().join(format!("git-integrate-selftest-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root)?;
+        fs::write(root.join("gitconfig"), "")?;
+        let scratch = Scratch {
+            work: root.join("work"),
+            root,
+        };
+        scratch.git(&scratch.root, &["init", "--quiet", "--bare", "origin.git"])?;
+        scratch.git(&scratch.root, &["init", "--quiet", "work"])?;
+        scratch.git(&scratch.work, &["checkout", "--quiet", "-b", "master"])?;
+        scratch.git(&scratch.work, &["remote", "add", "origin", "../origin.git"])?;
+        Ok(scratch)
+    }
+
+    fn command(&self, program: &OsStr, dir: &Path) -> Command {
+        let mut command = Command::new(program);
+        command
+            .current_dir(dir)
+            .stdin(Stdio::null())
+            .env("HOME", &self.root)
+            .env("XDG_CONFIG_HOME", &self.root)
+            .env("GIT_CONFIG_GLOBAL", self.root.join("gitconfig"))
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_AUTHOR_NAME", "Self-test")
+            .env("GIT_AUTHOR_EMAIL", "selftest@localhost")
+            .env("GIT_COMMITTER_NAME", "Self-test")
+            .env("GIT_COMMITTER_EMAIL", "selftest@localhost")
+            .env_remove("GIT_DIR")
+            .env_remove("GIT_WORK_TREE")
+            .env_remove("GIT_CONFIG_COUNT");
+        command
+    }
+
+    fn git(&self, dir: &Path, args: &[&str]) -> io::Result<String> {
+        let output = self
+            .command(OsStr::new("git"), dir)
+            .args(args)
+            .output_timed()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Commits `contents` to `path` on `branch`, forked from master.
+    fn branch(&self, branch: &str, path: &str, contents: &str) -> io::Result<()> {
+        self.git(&self.work, &["checkout", "--quiet", "-b", branch, "master"])?;
+        self.commit(path, contents, &format!("Change {} on {}", path, branch))?;
+        self.git(&self.work, &["checkout", "--quiet", "master"])?;
+        Ok(())
+    }
+
+    fn commit(&self, path: &str, contents: &str, message: &str) -> io::Result<()> {
+        fs::write(self.work.join(path), contents)?;
+        self.git(&self.work, &["add", path])?;
+        self.git(&self.work, &["commit", "--quiet", "--message", message])?;
+        Ok(())
+    }
+
+    /// Runs this very binary in the clone, with `pull_requests` as the
+    /// selection.
+    fn integrate(&self, branch: &str, pull_requests: &[PullRequest]) -> io::Result<Output> {
+        let listed = self.root.join(format!("{}.json", branch));
+        fs::write(&listed, serde_json::to_vec(pull_requests)?)?;
+        self.command(env::current_exe()?.as_os_str(), &self.work)
+            .arg("--pull-requests-from")
+            .arg(&listed)
+            .arg("--yes")
+            .arg("--no-api-writes")
+            .arg("deploy:scratch")
+            .arg(branch)
+            .output_timed()
+    }
+
+    fn abort(&self) -> io::Result<Output> {
+        self.command(env::current_exe()?.as_os_str(), &self.work)
+            .arg("abort")
+            .output_timed()
+    }
+
+    fn runs(&self) -> Vec<Run> {
+        fs::read_to_string(self.work.join(".git/integrate/history.jsonl"))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()
+    }
+}
+
+/// Tells how a check went, answering whether it passed.
+fn check(passed: bool, what: &str, output: Option<&Output>) -> bool {
+    if passed {
+        info!("  ok: {}", what);
+    } else {
+        error!("  FAILED: {}", what);
+        if let Some(output) = output {
+            for line in String::from_utf8_lossy(&output.stdout).lines() {
+                error!("    | {}", line);
+            }
+            for line in String::from_utf8_lossy(&output.stderr).lines() {
+                error!("    | {}", line);
+            }
+        }
+    }
+    passed
+}
+
+/// Builds an integration branch in a scratch repository, then makes a run
+/// stop on a conflict and aborts it, checking what each step left behind.
+/// Nothing goes over the network. Answers whether every check passed.
+pub fn run() -> io::Result<bool> {
+    let scratch = Scratch::create()?;
+    info!("Self-test in {}", scratch.root.display());
+
+    scratch.commit("base.txt", "one\ntwo\nthree\n", "Base")?;
+    scratch.branch("feature/first", "first.txt", "first\n")?;
+    scratch.branch("feature/second", "base.txt", "one\n2\nthree\n")?;
+    scratch.branch("feature/clash", "base.txt", "one\nzwei\nthree\n")?;
+    scratch.git(&scratch.work, &["push", "--quiet", "origin", "--all"])?;
+
+    let mut passed = true;
+
+    info!("\nA clean build:");
+    let clean = [
+        pull_request(1, "feature/first"),
+        pull_request(2, "feature/second"),
+    ];
+    let output = scratch.integrate("scratch-built", &clean)?;
+    passed &= check(output.status.success(), "the run succeeds", Some(&output));
+    let log = scratch
+        .git(
+            &scratch.work,
+            &["log", "--first-parent", "--format=%B", "scratch-built"],
+        )
+        .unwrap_or_default();
+    passed &= check(
+        clean
+            .iter()
+            .all(|pr| log.contains(&format!("{}: {}", PR_TRAILER, pr.number))),
+        "every pull request is merged with its trailer",
+        None,
+    );
+    let tree = scratch
+        .git(&scratch.work, &["show", "scratch-built:base.txt"])
+        .unwrap_or_default();
+    passed &= check(
+        tree == "one\n2\nthree\n"
+            && scratch
+                .git(
+                    &scratch.work,
+                    &["cat-file", "-e", "scratch-built:first.txt"],
+                )
+                .is_ok(),
+        "the branch has the changes of both",
+        None,
+    );
+    let runs = scratch.runs();
+    passed &= check(
+        runs.last().is_some_and(|run| {
+            run.success && run.dest_branch == "scratch-built" && run.pulls.len() == 2
+        }),
+        "the run is recorded in the history",
+        None,
+    );
+
+    info!("\nA build stopped by a conflict:");
+    let clashing = [
+        pull_request(2, "feature/second"),
+        pull_request(3, "feature/clash"),
+    ];
+    let output = scratch.integrate("scratch-stopped", &clashing)?;
+    let state = scratch.work.join(".git/integrate/state.json");
+    passed &= check(
+        !output.status.success() && state.exists(),
+        "the run stops and saves where it was",
+        Some(&output),
+    );
+    let output = scratch.abort()?;
+    passed &= check(
+        output.status.success() && !state.exists(),
+        "`abort` gives up on it",
+        Some(&output),
+    );
+    passed &= check(
+        scratch
+            .git(&scratch.work, &["status", "--porcelain"])
+            .is_ok_and(|status| status.trim().is_empty()),
+        "the work tree is left clean",
+        None,
+    );
+
+    if passed {
+        let _ = fs::remove_dir_all(&scratch.root);
+    } else {
+        error!(
+            "\nThe scratch repository is left in {}",
+            scratch.root.display()
+        );
+    }
+    Ok(passed)
+}
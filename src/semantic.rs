@@ -0,0 +1,216 @@
+use git2::{DiffOptions, Repository};
+use regex::Regex;
+use std::collections::BTreeMap;
+
+use super::conflicts::fork_point_tree;
+use super::github::PullRequest;
+
+/// A logical conflict to look for across the plan, from
+/// `integrate.semantic-rule`: two pull requests changing paths that match
+/// `path`, such as two migrations, or with `line` two pull requests adding
+/// the same line that matches it to those paths, such as the same enum
+/// variant. With a capture group only what it captures has to be the same.
+pub struct Rule {
+    path: Regex,
+    line: Option<Regex>,
+}
+
+impl Rule {
+    /// Parses `touches <path>` or `duplicate <path> => <line>`, both regular
+    /// expressions.
+    pub fn parse(value: &str) -> Result<Rule, String> {
+        let (kind, rest) = value
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| format!("`{}` needs a kind and a pattern", value))?;
+        let regex = |pattern: &str| Regex::new(pattern.trim()).map_err(|e| e.to_string());
+        match kind {
+            "touches" => Ok(Rule {
+                path: regex(rest)?,
+                line: None,
+            }),
+            "duplicate" => {
+                let (path, line) = rest
+                    .split_once("=>")
+                    .ok_or_else(|| format!("`{}` needs `<path> => <line>`", value))?;
+                Ok(Rule {
+                    path: regex(path)?,
+                    line: Some(regex(line)?),
+                })
+            }
+            _ => Err(format!("Unknown kind of semantic rule `{}`", kind)),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match &self.line {
+            None => format!("touches `{}`", self.path),
+            Some(line) => format!("duplicate `{}` => `{}`", self.path, line),
+        }
+    }
+}
+
+/// What a pull request changes, as far as the rules care.
+#[derive(Default)]
+pub struct Changes {
+    pub paths: Vec<String>,
+    /// The lines it adds, with the path of each.
+    pub added: Vec<(String, String)>,
+}
+
+/// Pull requests that git may merge cleanly but that a rule says don't go
+/// together.
+pub struct Finding {
+    pub rule: String,
+    pub numbers: Vec<i64>,
+    /// What they have in common, such as the duplicated line.
+    pub detail: Option<String>,
+}
+
+/// The changes of `pr` since it forked from `base`.
+pub fn changes(
+    repository: &Repository,
+    base: &str,
+    pr: &PullRequest,
+) -> Result<Changes, git2::Error> {
+    let base = repository.revparse_single(base)?.peel_to_commit()?;
+    let head = repository.revparse_single(&pr.revision)?.peel_to_commit()?;
+    let fork_point = fork_point_tree(repository, &base, &head)?;
+    let mut options = DiffOptions::new();
+    options.context_lines(0);
+    let diff = repository.diff_tree_to_tree(
+        fork_point.as_ref(),
+        Some(&head.tree()?),
+        Some(&mut options),
+    )?;
+
+    let mut changes = Changes::default();
+    for delta in diff.deltas() {
+        for file in [delta.old_file(), delta.new_file()] {
+            if let Some(path) = file.path().and_then(|p| p.to_str()) {
+                if !changes.paths.iter().any(|known| known == path) {
+                    changes.paths.push(path.to_string());
+                }
+            }
+        }
+    }
+    diff.foreach(
+        &mut |_, _| true,
+        None,
+        None,
+        Some(&mut |delta, _, line| {
+            if line.origin() == '+' {
+                if let Some(path) = delta.new_file().path().and_then(|p| p.to_str()) {
+                    let content = String::from_utf8_lossy(line.content());
+                    changes
+                        .added
+                        .push((path.to_string(), content.trim().to_string()));
+                }
+            }
+            true
+        }),
+    )?;
+    Ok(changes)
+}
+
+/// Evaluates `rules` over the changes of every pull request in the plan.
+pub fn check(rules: &[Rule], plan: &[(i64, Changes)]) -> Vec<Finding> {
+    let mut findings = vec![];
+    for rule in rules {
+        let path = &rule.path;
+        match &rule.line {
+            None => {
+                let numbers: Vec<i64> = plan
+                    .iter()
+                    .filter(|(_, changes)| changes.paths.iter().any(|p| path.is_match(p)))
+                    .map(|(number, _)| *number)
+                    .collect();
+                if numbers.len() > 1 {
+                    findings.push(Finding {
+                        rule: rule.describe(),
+                        numbers,
+                        detail: None,
+                    });
+                }
+            }
+            Some(line) => {
+                let mut added: BTreeMap<String, Vec<i64>> = BTreeMap::new();
+                for (number, changes) in plan {
+                    for (file, content) in &changes.added {
+                        if !path.is_match(file) {
+                            continue;
+                        }
+                        let key = match line.captures(content) {
+                            Some(captures) => captures
+                                .get(1)
+                                .or_else(|| captures.get(0))
+                                .map_or("", |m| m.as_str()),
+                            None => continue,
+                        };
+                        let numbers = added.entry(key.to_string()).or_default();
+                        if !numbers.contains(number) {
+                            numbers.push(*number);
+                        }
+                    }
+                }
+                for (key, numbers) in added {
+                    if numbers.len() > 1 {
+                        findings.push(Finding {
+                            rule: rule.describe(),
+                            numbers,
+                            detail: Some(key),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes(paths: &[&str], added: &[(&str, &str)]) -> Changes {
+        Changes {
+            paths: paths.iter().map(|p| p.to_string()).collect(),
+            added: added
+                .iter()
+                .map(|(path, line)| (path.to_string(), line.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn rules_flag_what_git_merges_cleanly() {
+        let rules = [
+            Rule::parse("touches ^migrations/").unwrap(),
+            Rule::parse(r"duplicate \.rs$ => ^(\w+)(\(.*\))?,$").unwrap(),
+        ];
+        let plan = [
+            (
+                1,
+                changes(
+                    &["migrations/0042_users.sql", "src/kind.rs"],
+                    &[("src/kind.rs", "Admin,")],
+                ),
+            ),
+            (
+                2,
+                changes(&["src/kind.rs"], &[("src/kind.rs", "Admin(Level),")]),
+            ),
+            (
+                3,
+                changes(&["migrations/0042_orders.sql"], &[("README", "Admin,")]),
+            ),
+        ];
+        let findings = check(&rules, &plan);
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].numbers, [1, 3]);
+        assert_eq!(findings[1].numbers, [1, 2]);
+        assert_eq!(findings[1].detail.as_deref(), Some("Admin"));
+        assert!(Rule::parse("duplicate \\.rs$").is_err());
+        assert!(Rule::parse("renames x").is_err());
+    }
+}
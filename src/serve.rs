@@ -0,0 +1,148 @@
+use chrono::{DateTime, Duration as Interval, Utc};
+use std::collections::HashSet;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::git_extras::{git_command, remote};
+use super::github::{self, rest};
+use super::health;
+use super::history::{History, Run};
+use super::planner::Planner;
+use super::stacks;
+use super::timeouts::Timed;
+use super::{conflicts, dates};
+
+/// How many past runs the next build time is estimated from.
+const ESTIMATE_RUNS: usize = 10;
+
+/// Keeps one comment on every pull request `planner` would build up to date
+/// with its position in the next build and when that build is expected,
+/// refreshing every `interval`. Runs until SIGTERM or SIGINT, which let the
+/// refresh in progress finish first, and answers health checks on `listen` if
+/// given.
+pub fn run(token: &str, planner: &Planner, interval: Duration, listen: Option<&str>) -> ! {
+    let dest_branch = planner.criteria.dest_branch;
+    let history = History::open(planner.repository);
+    let status = health::Status::with_signals().expect("Could not handle signals");
+    if let Some(addr) = listen {
+        health::listen(addr, Arc::clone(&status))
+            .unwrap_or_else(|e| panic!("Could not listen on {}: {}", addr, e));
+    }
+
+    // Those whose comment was kept up to date, to take it back once they
+    // leave the queue.
+    let mut commented = HashSet::new();
+    while !status.is_stopping() {
+        match refresh(token, planner, &history, &mut commented) {
+            Ok(()) => status.set_ready(),
+            Err(e) => error!("Could not refresh the queue for {}: {}", dest_branch, e),
+        }
+
+        status.sleep(interval);
+    }
+
+    info!("Stopped refreshing the queue for {}", dest_branch);
+    process::exit(0)
+}
+
+fn refresh(
+    token: &str,
+    planner: &Planner,
+    history: &History,
+    commented: &mut HashSet<i64>,
+) -> Result<(), github::Error> {
+    let repo = planner.repo;
+    let repository = planner.repository;
+    let dest_branch = planner.criteria.dest_branch;
+    // The ignore file is read from the default branch, keep it current.
+    let _ = git_command(repository)
+        .arg("fetch")
+        .arg("--quiet")
+        .arg(remote())
+        .status_timed();
+
+    let selected = github::pull_requests(Some(token), repo, &planner.criteria.selection)?;
+    // Left out of the build the same way a run leaves them out.
+    let (selected, excluded) = planner.exclude(selected);
+    let queue = stacks::resolve(
+        Some(token),
+        repo,
+        &conflicts::default_branch(repository),
+        selected,
+        false,
+    )?
+    .ordered;
+
+    let runs = history.runs().unwrap_or_default();
+    let next_build = next_build(&runs, dest_branch, &planner.criteria.selector);
+
+    info!("Queue for {}: {} pull requests", dest_branch, queue.len());
+    for (idx, pr) in queue.iter().enumerate() {
+        let body = comment(dest_branch, idx + 1, queue.len(), next_build);
+        // The comment left earlier is edited rather than added to.
+        rest::upsert_comment(token, repo, pr.number, &marker(dest_branch), &body)?;
+    }
+
+    // Excluded ones may have been commented on before this started.
+    let queued: HashSet<i64> = queue.iter().map(|pr| pr.number).collect();
+    let left: HashSet<i64> = commented
+        .iter()
+        .copied()
+        .chain(excluded.iter().map(|(pr, _)| pr.number))
+        .filter(|number| !queued.contains(number))
+        .collect();
+    for number in left {
+        rest::remove_comment(token, repo, number, &marker(dest_branch))?;
+        commented.remove(&number);
+    }
+    commented.extend(queued);
+    Ok(())
+}
+
+/// Estimates the next build from the median time between recent runs.
+fn next_build(runs: &[Run], dest_branch: &str, selector: &str) -> Option<DateTime<Utc>> {
+    let finished: Vec<DateTime<Utc>> = runs
+        .iter()
+        .filter(|run| run.builds(dest_branch) && run.selection == selector)
+        .filter_map(|run| DateTime::parse_from_rfc3339(&run.finished_at).ok())
+        .map(|time| time.with_timezone(&Utc))
+        .collect();
+    let recent = &finished[finished.len().saturating_sub(ESTIMATE_RUNS)..];
+
+    let mut gaps: Vec<Interval> = recent.windows(2).map(|w| w[1] - w[0]).collect();
+    if gaps.is_empty() {
+        return None;
+    }
+    gaps.sort();
+    let next = *recent.last()? + gaps[gaps.len() / 2];
+    Some(next).filter(|next| *next > Utc::now())
+}
+
+fn marker(dest_branch: &str) -> String {
+    format!("<!-- git-integrate queue {} -->", dest_branch)
+}
+
+fn comment(
+    dest_branch: &str,
+    position: usize,
+    total: usize,
+    next_build: Option<DateTime<Utc>>,
+) -> String {
+    let when = match next_build {
+        Some(time) => format!(
+            "The next build is expected around {}.",
+            dates::date_time(&time)
+        ),
+        None => "It will be included in the next build.".to_string(),
+    };
+    format!(
+        "{}\n🚦 Position **{} of {}** in the queue for `{}`. {}\n\n\
+         _Updated automatically by git-integrate._",
+        marker(dest_branch),
+        position,
+        total,
+        dest_branch,
+        when
+    )
+}
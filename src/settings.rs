@@ -0,0 +1,305 @@
+use git2::{Config, Repository};
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Git configuration for the repository, overlaid with the values of an
+/// optional encrypted config file so shared CI setups can carry tokens
+/// without committing them in plain text.
+///
+/// The encrypted file uses git-config syntax and is located through
+/// `integrate.encrypted-config` (relative to the work tree) or the
+/// `GIT_INTEGRATE_ENCRYPTED_CONFIG` environment variable. Files ending in
+/// `.age` are decrypted with `age` using the identity from
+/// `integrate.age-identity` or `GIT_INTEGRATE_AGE_KEY_FILE`; anything else is
+/// handed to `sops`, which receives the same identity as `SOPS_AGE_KEY_FILE`.
+pub struct Settings {
+    config: Config,
+    secrets: HashMap<String, String>,
+}
+
+impl Settings {
+    pub fn open(repository: &Repository) -> io::Result<Settings> {
+        let config = repository.config().map_err(to_io)?;
+        let root = repository
+            .workdir()
+            .unwrap_or_else(|| repository.path())
+            .to_path_buf();
+        Settings::load(config, root)
+    }
+
+    /// Settings from the global and system configuration only, for commands
+    /// that run outside of a repository.
+    pub fn open_default() -> io::Result<Settings> {
+        let config = Config::open_default().map_err(to_io)?;
+        Settings::load(config, env::current_dir()?)
+    }
+
+    fn load(config: Config, root: PathBuf) -> io::Result<Settings> {
+        let encrypted = env::var("GIT_INTEGRATE_ENCRYPTED_CONFIG")
+            .ok()
+            .or_else(|| config.get_string("integrate.encrypted-config").ok())
+            .map(|path| root.join(path));
+
+        let secrets = match encrypted {
+            Some(path) => {
+                let identity = env::var_os("GIT_INTEGRATE_AGE_KEY_FILE")
+                    .map(PathBuf::from)
+                    .or_else(|| config.get_path("integrate.age-identity").ok());
+                parse(&decrypt(&path, identity.as_deref())?)
+            }
+            None => HashMap::new(),
+        };
+
+        Ok(Settings { config, secrets })
+    }
+
+    pub fn get_string(&self, key: &str) -> Option<String> {
+        self.secrets
+            .get(&normalize(key))
+            .cloned()
+            .or_else(|| self.config.get_string(key).ok())
+    }
+
+    /// `integrate.<branch>.<key>`, or `integrate.<key>` for every branch.
+    pub fn for_branch(&self, branch: &str, key: &str) -> Option<String> {
+        self.get_string(&format!("integrate.{}.{}", branch, key))
+            .or_else(|| self.get_string(&format!("integrate.{}", key)))
+    }
+
+    /// A boolean in any of the spellings git accepts.
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get_string(key)?.to_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" | "" => Some(true),
+            "false" | "no" | "off" | "0" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// `integrate.<branch>.<key>` as a boolean, falling back to
+    /// `integrate.<key>`.
+    pub fn for_branch_bool(&self, branch: &str, key: &str) -> Option<bool> {
+        self.get_bool(&format!("integrate.{}.{}", branch, key))
+            .or_else(|| self.get_bool(&format!("integrate.{}", key)))
+    }
+
+    /// `(subsection, value)` for every `<section>.<subsection>.<name>` key,
+    /// such as every branch with an `integrate.<branch>.selection`.
+    pub fn subsections(&self, section: &str, name: &str) -> Vec<(String, String)> {
+        let prefix = format!("{}.", section.to_lowercase());
+        let suffix = format!(".{}", name.to_lowercase());
+        let subsection = |key: &str| {
+            let key = normalize(key);
+            key.strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(&suffix))
+                .filter(|sub| !sub.is_empty())
+                .map(String::from)
+        };
+
+        // Later files (local over global) override earlier ones, and the
+        // encrypted config overrides them all, as with `get_string`.
+        let mut found: Vec<(String, String)> = vec![];
+        let mut set = |sub: String, value: String| match found.iter_mut().find(|(s, _)| *s == sub) {
+            Some(existing) => existing.1 = value,
+            None => found.push((sub, value)),
+        };
+        if let Ok(entries) = self.config.entries(None) {
+            for entry in &entries {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(_) => continue,
+                };
+                if let (Some(sub), Some(value)) = (entry.name().and_then(subsection), entry.value())
+                {
+                    set(sub, value.to_string());
+                }
+            }
+        }
+        for (key, value) in &self.secrets {
+            if let Some(sub) = subsection(key) {
+                set(sub, value.clone());
+            }
+        }
+        found
+    }
+
+    /// Every value of a multi-valued key. A value from the encrypted config
+    /// comes first.
+    pub fn get_all(&self, key: &str) -> Vec<String> {
+        let mut values: Vec<String> = self
+            .secrets
+            .get(&normalize(key))
+            .cloned()
+            .into_iter()
+            .collect();
+        let name = normalize(key);
+        let pattern: String = name
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '-' {
+                    c.to_string()
+                } else {
+                    format!("\\{}", c)
+                }
+            })
+            .collect();
+        if let Ok(entries) = self.config.entries(Some(&format!("^{}$", pattern))) {
+            for entry in &entries {
+                if let Some(value) = entry.ok().and_then(|e| e.value().map(String::from)) {
+                    values.push(value);
+                }
+            }
+        }
+        values
+    }
+
+    /// Every `integrate.*` key with its value, and whether the value came
+    /// from the encrypted config.
+    pub fn integrate_entries(&self) -> Vec<(String, String, bool)> {
+        let mut found: Vec<(String, String, bool)> = vec![];
+        if let Ok(entries) = self.config.entries(Some("^integrate\\.")) {
+            for entry in &entries {
+                if let Some((name, value)) = entry
+                    .ok()
+                    .and_then(|e| Some((normalize(e.name()?), e.value()?.to_string())))
+                {
+                    found.retain(|(key, _, _)| *key != name);
+                    found.push((name, value, false));
+                }
+            }
+        }
+        for (key, value) in &self.secrets {
+            if key.starts_with("integrate.") {
+                found.retain(|(name, _, _)| name != key);
+                found.push((key.clone(), value.clone(), true));
+            }
+        }
+        found
+    }
+}
+
+fn decrypt(path: &Path, identity: Option<&Path>) -> io::Result<String> {
+    let is_age = path.extension().is_some_and(|ext| ext == "age");
+
+    let mut command = if is_age {
+        let mut command = Command::new("age");
+        command.arg("--decrypt");
+        if let Some(identity) = identity {
+            command.arg("--identity").arg(identity);
+        }
+        command
+    } else {
+        let mut command = Command::new("sops");
+        command
+            .arg("--decrypt")
+            .arg("--input-type")
+            .arg("binary")
+            .arg("--output-type")
+            .arg("binary");
+        if let Some(identity) = identity {
+            command.env("SOPS_AGE_KEY_FILE", identity);
+        }
+        command
+    };
+
+    let output = command.arg(path).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Could not decrypt {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    String::from_utf8(output.stdout).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Parses the subset of git-config syntax needed for secrets: section
+/// headers (with optional quoted subsections) followed by `key = value`.
+fn parse(contents: &str) -> HashMap<String, String> {
+    let mut values = HashMap::new();
+    let mut section = String::new();
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            let header = &line[1..line.len() - 1];
+            section = match header.find('"') {
+                Some(idx) => format!(
+                    "{}.{}",
+                    header[..idx].trim().to_lowercase(),
+                    header[idx..].trim_matches('"')
+                ),
+                None => header.trim().to_lowercase(),
+            };
+            continue;
+        }
+
+        if let Some(idx) = line.find('=') {
+            let key = line[..idx].trim().to_lowercase();
+            let value = line[idx + 1..].trim().trim_matches('"');
+            values.insert(format!("{}.{}", section, key), value.to_string());
+        }
+    }
+
+    values
+}
+
+/// Section and key names are case-insensitive in git, subsections are not.
+fn normalize(key: &str) -> String {
+    let (head, name) = match key.rsplit_once('.') {
+        Some(parts) => parts,
+        None => return key.to_lowercase(),
+    };
+
+    match head.split_once('.') {
+        Some((section, sub)) => {
+            format!("{}.{}.{}", section.to_lowercase(), sub, name.to_lowercase())
+        }
+        None => format!("{}.{}", head.to_lowercase(), name.to_lowercase()),
+    }
+}
+
+fn to_io(e: git2::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_config_keeps_unicode_labels() {
+        let values = parse(
+            "[integrate \"staging\"]\n  selection = \"🚀 deploy to staging\"\n\
+             [integrate]\n  quarantine-label = ⛔ quarantine\n",
+        );
+        assert_eq!(
+            values
+                .get("integrate.staging.selection")
+                .map(String::as_str),
+            Some("🚀 deploy to staging")
+        );
+        assert_eq!(
+            values.get("integrate.quarantine-label").map(String::as_str),
+            Some("⛔ quarantine")
+        );
+    }
+
+    #[test]
+    fn branch_booleans_override_the_default_in_any_spelling() {
+        let settings = Settings {
+            config: Config::new().unwrap(),
+            secrets: parse("[integrate \"nightly\"]\n  gc = no\n[integrate]\n  gc = on\n"),
+        };
+        assert_eq!(settings.for_branch_bool("nightly", "gc"), Some(false));
+        assert_eq!(settings.for_branch_bool("staging", "gc"), Some(true));
+        assert_eq!(settings.for_branch_bool("staging", "skip-draft"), None);
+    }
+}
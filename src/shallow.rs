@@ -0,0 +1,121 @@
+use git2::Repository;
+use std::io;
+use std::process::Stdio;
+
+use super::backend::GitBackend;
+use super::git_extras::git_command;
+use super::github::PullRequest;
+use super::timeouts::Timed;
+
+/// How many commits each round of deepening fetches unless told otherwise.
+pub const DEFAULT_DEPTH: usize = 100;
+
+/// Rounds of deepening before the rest of the history is fetched at once.
+const MAX_ROUNDS: usize = 5;
+
+/// What to do when a shallow clone lacks the history a merge needs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /// Fetch that many more commits at a time until every merge base is there.
+    Deepen(usize),
+    /// Fetch the whole history.
+    Unshallow,
+    /// Leave the clone as it is and let the merges fail.
+    Keep,
+}
+
+impl Policy {
+    /// `deepen`, `deepen=N`, `unshallow` or `keep`.
+    pub fn parse(value: &str) -> Option<Policy> {
+        match value.trim() {
+            "deepen" => Some(Policy::Deepen(DEFAULT_DEPTH)),
+            "unshallow" => Some(Policy::Unshallow),
+            "keep" => Some(Policy::Keep),
+            value => value
+                .strip_prefix("deepen=")?
+                .parse()
+                .ok()
+                .filter(|&depth| depth > 0)
+                .map(Policy::Deepen),
+        }
+    }
+}
+
+/// Whether `base` and `pr` have a common ancestor in the local history. A
+/// head that wasn't fetched is left for the run to explain.
+fn has_merge_base(repository: &Repository, base: &str, pr: &PullRequest) -> io::Result<bool> {
+    if repository.revparse_single(&pr.head_ref()).is_err() {
+        return Ok(true);
+    }
+    Ok(git_command(repository)
+        .arg("merge-base")
+        .arg(base)
+        .arg(pr.head_ref())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status_timed()?
+        .success())
+}
+
+/// Fetches history into a shallow clone until every pull request shares an
+/// ancestor with `base`, as `policy` allows. Answers the pull requests that
+/// still don't, which will fail to merge.
+pub fn complete<'p>(
+    repository: &Repository,
+    git: &dyn GitBackend,
+    base: &str,
+    pull_requests: &'p [PullRequest],
+    policy: Policy,
+) -> io::Result<Vec<&'p PullRequest>> {
+    let mut rounds = 0;
+    loop {
+        let mut missing = vec![];
+        for pr in pull_requests {
+            if !has_merge_base(repository, base, pr)? {
+                missing.push(pr);
+            }
+        }
+        if missing.is_empty() || !repository.is_shallow() {
+            return Ok(missing);
+        }
+        let depth = match policy {
+            Policy::Keep => return Ok(missing),
+            Policy::Deepen(depth) if rounds < MAX_ROUNDS => Some(depth),
+            _ => None,
+        };
+        match depth {
+            Some(depth) => info!(
+                "{} pull requests share no history with {} in this shallow clone, \
+                 fetching {} more commits",
+                missing.len(),
+                base,
+                depth
+            ),
+            None => info!(
+                "{} pull requests share no history with {} in this shallow clone, \
+                 fetching all of it",
+                missing.len(),
+                base
+            ),
+        }
+        if !git.deepen(depth)? {
+            return Ok(missing);
+        }
+        rounds += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies_name_how_deep_to_fetch() {
+        assert_eq!(Policy::parse("deepen"), Some(Policy::Deepen(DEFAULT_DEPTH)));
+        assert_eq!(Policy::parse("deepen=50"), Some(Policy::Deepen(50)));
+        assert_eq!(Policy::parse(" unshallow "), Some(Policy::Unshallow));
+        assert_eq!(Policy::parse("keep"), Some(Policy::Keep));
+        assert_eq!(Policy::parse("deepen=0"), None);
+        assert_eq!(Policy::parse("deepen 50"), None);
+    }
+}
@@ -0,0 +1,66 @@
+use git2::Repository;
+use signal_hook::consts::SIGINT;
+use std::env;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use super::github::PullRequest;
+use super::run_env;
+
+/// Whether SIGINT still stops git-integrate. It is lowered while a shell
+/// runs, so Ctrl-C there only interrupts what the shell is running.
+static INTERRUPTIBLE: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+fn interruptible() -> io::Result<&'static AtomicBool> {
+    if let Some(flag) = INTERRUPTIBLE.get() {
+        return Ok(flag);
+    }
+    let flag = Arc::new(AtomicBool::new(true));
+    signal_hook::flag::register_conditional_default(SIGINT, Arc::clone(&flag))?;
+    Ok(INTERRUPTIBLE.get_or_init(|| flag))
+}
+
+/// How the user resolves the conflicts of the merge in progress.
+pub enum Resolver {
+    /// A shell, or the configured command, in the work tree.
+    Shell(Option<String>),
+    /// `git mergetool` with the user's configured tool.
+    Mergetool,
+}
+
+/// Hands the conflicts of the merge in progress to the user and answers
+/// whether that ended cleanly, `pr` being the pull request being merged.
+pub fn resolve(repository: &Repository, resolver: &Resolver, pr: &PullRequest) -> io::Result<bool> {
+    let workdir = repository.workdir().unwrap_or_else(|| Path::new("."));
+    let mut command = match resolver {
+        Resolver::Shell(Some(command)) => {
+            let mut shell = Command::new("sh");
+            shell.arg("-c").arg(command);
+            shell
+        }
+        Resolver::Shell(None) => Command::new(env::var_os("SHELL").unwrap_or_else(|| "sh".into())),
+        Resolver::Mergetool => {
+            let mut mergetool = Command::new("git");
+            mergetool.arg("mergetool");
+            mergetool
+        }
+    };
+
+    match resolver {
+        Resolver::Shell(_) => info!(
+            "\nStarting a shell to resolve the conflicts. Fix and `git add` them, then \
+             \nexit to carry on with the run, or `exit 1` to stop it."
+        ),
+        Resolver::Mergetool => info!("\nStarting git mergetool to resolve the conflicts"),
+    }
+    let flag = interruptible()?;
+    flag.store(false, Ordering::SeqCst);
+    command.current_dir(workdir).env("GIT_INTEGRATE_SHELL", "1");
+    run_env::apply(&mut command, repository, pr);
+    let status = command.status();
+    flag.store(true, Ordering::SeqCst);
+    Ok(status?.success())
+}
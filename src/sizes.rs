@@ -0,0 +1,156 @@
+use git2::{Delta, Repository};
+use std::path::PathBuf;
+
+use super::conflicts::fork_point_tree;
+use super::github::PullRequest;
+
+/// What git-lfs stores in place of the file contents.
+const LFS_POINTER: &[u8] = b"version https://git-lfs.github.com/spec/v1";
+
+/// What to do with a pull request that adds a file over the size limit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /// Stop the run before anything is merged.
+    Fail,
+    /// Leave the pull request out and build the rest.
+    Exclude,
+}
+
+impl Policy {
+    pub fn parse(value: &str) -> Option<Policy> {
+        match value {
+            "fail" => Some(Policy::Fail),
+            "exclude" => Some(Policy::Exclude),
+            _ => None,
+        }
+    }
+}
+
+/// Parses sizes like `500k`, `10M` or `1G` (powers of 1024), or a plain
+/// number of bytes.
+pub fn parse_size(value: &str) -> Option<usize> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => value.split_at(idx),
+        None => (value, ""),
+    };
+    let multiplier = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        _ => return None,
+    };
+    number.parse::<usize>().ok().map(|n| n * multiplier)
+}
+
+/// Files over `limit` bytes the pull request adds or grows past the limit,
+/// with their size. Files stored as git-lfs pointers are never reported.
+pub fn oversized(
+    repository: &Repository,
+    base: &str,
+    pr: &PullRequest,
+    limit: usize,
+) -> Result<Vec<(PathBuf, usize)>, git2::Error> {
+    let base = repository.revparse_single(base)?.peel_to_commit()?;
+    let head = repository.revparse_single(&pr.revision)?.peel_to_commit()?;
+    let fork_point = fork_point_tree(repository, &base, &head)?;
+    let diff = repository.diff_tree_to_tree(fork_point.as_ref(), Some(&head.tree()?), None)?;
+
+    let mut oversized = vec![];
+    for delta in diff.deltas() {
+        if delta.status() == Delta::Deleted {
+            continue;
+        }
+        let file = delta.new_file();
+        let blob = match repository.find_blob(file.id()) {
+            Ok(blob) => blob,
+            // Submodules point at commits, not blobs.
+            Err(_) => continue,
+        };
+        // Only what the pull request made too big counts, not what already was.
+        let was_oversized = repository
+            .find_blob(delta.old_file().id())
+            .is_ok_and(|old| old.size() > limit);
+        if blob.size() > limit && !was_oversized && !blob.content().starts_with(LFS_POINTER) {
+            if let Some(path) = file.path() {
+                oversized.push((path.to_path_buf(), blob.size()));
+            }
+        }
+    }
+    Ok(oversized)
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::{Oid, Signature};
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::github::pull_request;
+
+    /// A commit holding `files` on `branch`, on top of `parent` if given.
+    fn commit(
+        repository: &Repository,
+        branch: &str,
+        parent: Option<Oid>,
+        files: &[(&str, &str)],
+    ) -> Oid {
+        let mut builder = repository.treebuilder(None).unwrap();
+        for (name, contents) in files {
+            let blob = repository.blob(contents.as_bytes()).unwrap();
+            builder.insert(name, blob, 0o100644).unwrap();
+        }
+        let tree = repository.find_tree(builder.write().unwrap()).unwrap();
+        let signature = Signature::now("Test", "test@localhost").unwrap();
+        let parents: Vec<_> = parent
+            .map(|oid| repository.find_commit(oid).unwrap())
+            .into_iter()
+            .collect();
+        let parents: Vec<_> = parents.iter().collect();
+        let reference = format!("refs/heads/{}", branch);
+        repository
+            .commit(
+                Some(&reference),
+                &signature,
+                &signature,
+                branch,
+                &tree,
+                &parents,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn files_already_over_the_limit_are_not_reported() {
+        let path = env::temp_dir().join(format!("git-integrate-{}-sizes", process::id()));
+        let _ = fs::remove_dir_all(&path);
+        let repository = Repository::init(&path).unwrap();
+        let base = commit(
+            &repository,
+            "base",
+            None,
+            &[("big", "already too big\n"), ("small", "ok\n")],
+        );
+        commit(
+            &repository,
+            "feature",
+            Some(base),
+            &[
+                ("big", "already too big, and bigger\n"),
+                ("small", "grown past the limit\n"),
+                ("new", "added over the limit\n"),
+            ],
+        );
+
+        let mut pr = pull_request(1, "feature");
+        pr.revision = "feature".to_string();
+        let mut paths: Vec<PathBuf> = oversized(&repository, "base", &pr, 10)
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        paths.sort();
+        assert_eq!(paths, vec![PathBuf::from("new"), PathBuf::from("small")]);
+    }
+}
@@ -0,0 +1,208 @@
+use git2::{Commit, Index, Oid, Repository, Signature};
+use std::path::PathBuf;
+
+use super::git_extras::path_from_bytes;
+use super::github::PullRequest;
+
+/// A pull request expected to conflict, with the paths it conflicts on.
+pub struct Forecast {
+    pub pr: PullRequest,
+    pub paths: Vec<PathBuf>,
+}
+
+/// Merges the whole plan onto `base` in memory, without touching the work
+/// tree or the index, and returns the pull requests that would conflict.
+///
+/// A conflicting pull request is left out of the simulated branch so the
+/// rest of the plan is still checked against everything before it. The merge
+/// commits only exist as unreferenced objects. Conflicts rerere knows how to
+/// resolve are still reported.
+pub fn forecast(
+    repository: &Repository,
+    base: &str,
+    plan: &[PullRequest],
+) -> Result<Vec<Forecast>, git2::Error> {
+    let signature = signature(repository)?;
+    let mut current = repository.revparse_single(base)?.peel_to_commit()?;
+    let mut forecast = vec![];
+
+    for pr in plan {
+        let head = repository.revparse_single(&pr.revision)?.peel_to_commit()?;
+        let mut index = repository.merge_commits(&current, &head, None)?;
+
+        if index.has_conflicts() {
+            forecast.push(Forecast {
+                pr: pr.clone(),
+                paths: conflicted_paths(&index)?,
+            });
+            continue;
+        }
+
+        let id = commit(repository, &signature, &mut index, &current, &head)?;
+        current = repository.find_commit(id)?;
+    }
+
+    Ok(forecast)
+}
+
+/// How every pull request of a plan merges with the base and with each of the
+/// others, one merge at a time.
+pub struct Matrix {
+    pub base: String,
+    pub plan: Vec<PullRequest>,
+    /// The paths each pull request conflicts on with the base.
+    pub with_base: Vec<Vec<PathBuf>>,
+    /// The paths pull requests `i` and `j > i` conflict on with each other.
+    pub pairs: Vec<(usize, usize, Vec<PathBuf>)>,
+}
+
+/// Merges each pull request of the plan into `base`, and each pair of them
+/// into each other, all in memory like `forecast`.
+pub fn matrix(
+    repository: &Repository,
+    base: &str,
+    plan: &[PullRequest],
+) -> Result<Matrix, git2::Error> {
+    let base_commit = repository.revparse_single(base)?.peel_to_commit()?;
+    let heads = plan
+        .iter()
+        .map(|pr| repository.revparse_single(&pr.revision)?.peel_to_commit())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut with_base = vec![];
+    for head in &heads {
+        let index = repository.merge_commits(&base_commit, head, None)?;
+        with_base.push(conflicted_paths(&index)?);
+    }
+
+    let mut pairs = vec![];
+    for (i, ours) in heads.iter().enumerate() {
+        for (j, theirs) in heads.iter().enumerate().skip(i + 1) {
+            let index = repository.merge_commits(ours, theirs, None)?;
+            if index.has_conflicts() {
+                pairs.push((i, j, conflicted_paths(&index)?));
+            }
+        }
+    }
+
+    Ok(Matrix {
+        base: base.to_string(),
+        plan: plan.to_vec(),
+        with_base,
+        pairs,
+    })
+}
+
+impl Matrix {
+    pub fn is_clean(&self) -> bool {
+        self.pairs.is_empty() && self.with_base.iter().all(Vec::is_empty)
+    }
+
+    pub fn to_text(&self) -> String {
+        let describe = |pr: &PullRequest| format!("#{} {} ({})", pr.number, pr.title, pr.branch);
+        let mut out = String::new();
+
+        let against_base: Vec<_> = self
+            .plan
+            .iter()
+            .zip(&self.with_base)
+            .filter(|(_, paths)| !paths.is_empty())
+            .collect();
+        if !against_base.is_empty() {
+            out.push_str(&format!("Conflicts with {}:\n", self.base));
+            for (pr, paths) in against_base {
+                out.push_str(&format!("  {}\n", describe(pr)));
+                for path in paths {
+                    out.push_str(&format!("    {}\n", path.display()));
+                }
+            }
+        }
+
+        if !self.pairs.is_empty() {
+            out.push_str("Conflicts between pull requests:\n");
+            for (i, j, paths) in &self.pairs {
+                out.push_str(&format!(
+                    "  {} and {}\n",
+                    describe(&self.plan[*i]),
+                    describe(&self.plan[*j])
+                ));
+                for path in paths {
+                    out.push_str(&format!("    {}\n", path.display()));
+                }
+            }
+        }
+
+        // One row and column per pull request, `B` marking a conflict with
+        // the base on the diagonal.
+        let labels: Vec<String> = self
+            .plan
+            .iter()
+            .map(|pr| format!("#{}", pr.number))
+            .collect();
+        let width = labels.iter().map(String::len).max().unwrap_or(0) + 1;
+        out.push_str(&format!("\n{:width$}", "", width = width));
+        for label in &labels {
+            out.push_str(&format!("{:>width$}", label, width = width));
+        }
+        out.push('\n');
+        for (i, label) in labels.iter().enumerate() {
+            out.push_str(&format!("{:width$}", label, width = width));
+            for j in 0..labels.len() {
+                let cell = if i == j {
+                    if self.with_base[i].is_empty() {
+                        "·"
+                    } else {
+                        "B"
+                    }
+                } else if self
+                    .pairs
+                    .iter()
+                    .any(|(a, b, _)| (*a, *b) == (i.min(j), i.max(j)))
+                {
+                    "✗"
+                } else {
+                    "✓"
+                };
+                out.push_str(&format!("{:>width$}", cell, width = width));
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+fn commit(
+    repository: &Repository,
+    signature: &Signature,
+    index: &mut Index,
+    ours: &Commit,
+    theirs: &Commit,
+) -> Result<Oid, git2::Error> {
+    let tree = repository.find_tree(index.write_tree_to(repository)?)?;
+    repository.commit(
+        None,
+        signature,
+        signature,
+        "speculative merge",
+        &tree,
+        &[ours, theirs],
+    )
+}
+
+fn conflicted_paths(index: &Index) -> Result<Vec<PathBuf>, git2::Error> {
+    let mut paths = vec![];
+    for conflict in index.conflicts()? {
+        let conflict = conflict?;
+        let entry = conflict.our.or(conflict.their).or(conflict.ancestor);
+        if let Some(entry) = entry {
+            paths.push(path_from_bytes(&entry.path));
+        }
+    }
+    Ok(paths)
+}
+
+fn signature(repository: &Repository) -> Result<Signature<'static>, git2::Error> {
+    repository
+        .signature()
+        .or_else(|_| Signature::now("git-integrate", "git-integrate@localhost"))
+}
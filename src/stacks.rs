@@ -0,0 +1,171 @@
+use git2::Repository;
+
+use super::git_extras::{git_command, Repo};
+use super::github::{pull_request_by_head, PullRequest};
+use super::timeouts::Timed;
+
+/// How stacked pull requests end up on the integration branch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Merge every member of a stack on its own.
+    Independent,
+    /// Merge only the top of each stack, which contains the whole stack.
+    Tops,
+    /// Rebase each member onto its parent first so commits the parent
+    /// rewrote (rebased, squashed) are not merged twice.
+    Flatten,
+}
+
+impl Mode {
+    pub fn parse(value: &str) -> Option<Mode> {
+        match value {
+            "independent" => Some(Mode::Independent),
+            "tops" => Some(Mode::Tops),
+            "flatten" => Some(Mode::Flatten),
+            _ => None,
+        }
+    }
+}
+
+/// A selected pull request based on another open pull request that was not
+/// selected itself.
+pub struct MissingParent {
+    pub child: PullRequest,
+    pub parent: PullRequest,
+}
+
+pub struct Stacks {
+    pub ordered: Vec<PullRequest>,
+    pub missing: Vec<MissingParent>,
+}
+
+/// Makes sure every stacked pull request (one whose base is the head of
+/// another open pull request) is merged after its parents.
+///
+/// Pull requests into `default_branch` are never stacked. Parents that are
+/// already selected are moved ahead of their children. With
+/// `include_parents` the missing ones are fetched and added too, otherwise
+/// they are reported so the caller can explain why the stack can't be built.
+pub fn resolve(
+    token: Option<&str>,
+    repo: &Repo,
+    default_branch: &str,
+    selected: Vec<PullRequest>,
+    include_parents: bool,
+) -> Result<Stacks, reqwest::Error> {
+    let mut ordered: Vec<PullRequest> = vec![];
+    let mut missing = vec![];
+
+    for pr in &selected {
+        let mut chain = vec![pr.clone()];
+
+        loop {
+            let child = chain.last().unwrap().clone();
+            if child.base == default_branch
+                || in_list(&ordered, &child.base)
+                || in_list(&chain, &child.base)
+            {
+                break;
+            }
+
+            let parent = match selected.iter().find(|p| p.branch == child.base) {
+                Some(parent) => parent.clone(),
+                None => match pull_request_by_head(token, repo, &child.base)? {
+                    Some(parent) if include_parents => parent,
+                    Some(parent) => {
+                        missing.push(MissingParent { child, parent });
+                        break;
+                    }
+                    None => break,
+                },
+            };
+            chain.push(parent);
+        }
+
+        for pr in chain.into_iter().rev() {
+            if !in_list(&ordered, &pr.branch) {
+                ordered.push(pr);
+            }
+        }
+    }
+
+    Ok(Stacks { ordered, missing })
+}
+
+fn in_list(list: &[PullRequest], branch: &str) -> bool {
+    list.iter().any(|pr| pr.branch == branch)
+}
+
+/// Drops the pull requests another selected pull request is stacked on,
+/// returning the kept ones and the dropped ones with the top that carries them.
+pub fn tops(ordered: Vec<PullRequest>) -> (Vec<PullRequest>, Vec<(PullRequest, i64)>) {
+    let mut carried = vec![];
+    for pr in &ordered {
+        let mut top = pr;
+        while let Some(child) = ordered.iter().find(|c| c.base == top.branch) {
+            top = child;
+        }
+        if top.number != pr.number {
+            carried.push((pr.clone(), top.number));
+        }
+    }
+
+    let kept = ordered
+        .into_iter()
+        .filter(|pr| !carried.iter().any(|(c, _)| c.number == pr.number))
+        .collect();
+    (kept, carried)
+}
+
+/// Rebases every stacked pull request onto its (already flattened) parent in a
+/// local `integrate-flatten/<branch>` branch and merges that instead. Commits
+/// that are already in the parent by content are dropped by the rebase.
+///
+/// Fails with the index of the pull request that could not be rebased.
+/// Leaves HEAD on the last rebased branch; the integration branch is checked
+/// out afterwards anyway.
+pub fn flatten(repository: &Repository, ordered: &mut [PullRequest]) -> Result<(), usize> {
+    for idx in 0..ordered.len() {
+        let parent = match ordered[..idx]
+            .iter()
+            .find(|p| p.branch == ordered[idx].base)
+        {
+            Some(parent) => parent.revision.clone(),
+            None => continue,
+        };
+
+        let pr = &mut ordered[idx];
+        let flattened = format!("integrate-flatten/{}", pr.branch);
+        let rebased = git_command(repository)
+            .arg("checkout")
+            .arg("--quiet")
+            .arg("--no-track")
+            .arg("-B")
+            .arg(&flattened)
+            .arg(&pr.revision)
+            .status_timed()
+            .and_then(|checkout| {
+                if !checkout.success() {
+                    return Ok(checkout);
+                }
+                git_command(repository)
+                    .arg("rebase")
+                    .arg("--quiet")
+                    .arg(&parent)
+                    .status_timed()
+            })
+            .map(|status| status.success())
+            .unwrap_or(false);
+
+        if !rebased {
+            let _ = git_command(repository)
+                .arg("rebase")
+                .arg("--abort")
+                .status_timed();
+            return Err(idx);
+        }
+        pr.revision = flattened;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use git2::{Repository, RepositoryState};
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use super::git_extras::{common_dir, git_command};
+use super::github::PullRequest;
+use super::timeouts::Timed;
+
+/// A run that stopped on a conflict, kept in `.git/integrate/state.json` so
+/// `continue` can carry on with the remaining merges once it is resolved.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct State {
+    /// The arguments the run was started with.
+    pub args: Vec<String>,
+    pub dest_branch: String,
+    /// The branch as configured, `{date}` and all, which its settings are
+    /// under; missing from runs saved before it was recorded.
+    #[serde(default)]
+    pub branch_template: Option<String>,
+    pub base: String,
+    /// Where the base was when the run started, if it tracks a remote.
+    pub built_on: Option<String>,
+    /// Every pull request of the run, in merge order.
+    pub pull_requests: Vec<PullRequest>,
+    pub sections: Vec<Option<String>>,
+    pub excluded: Vec<(PullRequest, Option<String>)>,
+    /// The pull request whose merge stopped the run.
+    pub stopped_at: usize,
+    pub conflicts: Vec<PathBuf>,
+    /// What was checked out before the run, to go back to on abort.
+    pub original_head: Option<String>,
+    /// Where the destination branch was before the run, if it existed.
+    pub original_branch: Option<String>,
+    /// Identifies the run to the commands it starts, the same once continued.
+    #[serde(default)]
+    pub run_id: String,
+    /// When the run started, in seconds since the epoch, 0 for runs saved
+    /// before that was.
+    #[serde(default)]
+    pub started_at: i64,
+}
+
+fn path(repository: &Repository) -> PathBuf {
+    common_dir(repository).join("integrate").join("state.json")
+}
+
+impl State {
+    /// Replaces whatever state was saved at once, so there always is one to
+    /// continue or abort, however the process ends.
+    pub fn save(&self, repository: &Repository) -> io::Result<()> {
+        let path = path(repository);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let written = path.with_extension("json.new");
+        fs::write(&written, serde_json::to_string_pretty(self)?)?;
+        fs::rename(written, path)
+    }
+
+    /// The stopped run, if there is one.
+    pub fn load(repository: &Repository) -> io::Result<Option<State>> {
+        match fs::read(path(repository)) {
+            Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn clear(repository: &Repository) -> io::Result<()> {
+        match fs::remove_file(path(repository)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Gives up on the stopped run: drops the merge in progress, puts the
+    /// destination branch back where it was and checks out what was checked
+    /// out before the run.
+    pub fn abort(&self, repository: &Repository) -> io::Result<bool> {
+        let git = |args: &[&str]| -> io::Result<bool> {
+            Ok(git_command(repository).args(args).status_timed()?.success())
+        };
+        if repository.state() == RepositoryState::Merge && !git(&["merge", "--abort"])? {
+            return Ok(false);
+        }
+        if !git(&["checkout", "--quiet", "--detach"])? {
+            return Ok(false);
+        }
+        let restored = match &self.original_branch {
+            Some(commit) => git(&["branch", "--force", &self.dest_branch, commit])?,
+            None => git(&["branch", "--delete", "--force", &self.dest_branch])?,
+        };
+        if !restored {
+            return Ok(false);
+        }
+        match &self.original_head {
+            Some(head) => git(&["checkout", "--quiet", head]),
+            None => Ok(true),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use super::history::Run;
+use super::report::Outcome;
+
+#[derive(Default)]
+struct Tally {
+    conflicts: usize,
+    failures: usize,
+}
+
+/// Prints the files and authors that most often broke past runs, from the
+/// recorded run history.
+pub fn print(runs: &[Run], limit: usize) {
+    if runs.is_empty() {
+        summary!("No integration runs recorded yet");
+        return;
+    }
+
+    let mut files: HashMap<String, usize> = HashMap::new();
+    let mut authors: HashMap<String, Tally> = HashMap::new();
+    let mut flaky: HashMap<String, (usize, usize)> = HashMap::new();
+    let mut merges = 0;
+
+    for pull in runs.iter().flat_map(|run| &run.pulls) {
+        merges += 1;
+        let author = pull.author.clone().unwrap_or_else(|| "(unknown)".into());
+        match pull.outcome {
+            Outcome::Conflicted => authors.entry(author).or_default().conflicts += 1,
+            Outcome::Failed | Outcome::VerifyFailed => {
+                authors.entry(author).or_default().failures += 1
+            }
+            _ => (),
+        }
+        if pull.verify_retries > 0 {
+            let tally = flaky.entry(pull.branch.clone()).or_default();
+            tally.0 += 1;
+            tally.1 += pull.verify_retries;
+        }
+        for path in &pull.conflicts {
+            *files.entry(path.display().to_string()).or_default() += 1;
+        }
+    }
+
+    summary!("{} runs, {} merges attempted", runs.len(), merges);
+
+    let mut files: Vec<(String, usize)> = files.into_iter().collect();
+    files.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    summary!("\nFiles conflicting most often:");
+    if files.is_empty() {
+        summary!("  none");
+    }
+    for (path, count) in files.iter().take(limit) {
+        summary!("  {:>4}  {}", count, path);
+    }
+
+    let mut authors: Vec<(String, Tally)> = authors.into_iter().collect();
+    authors.sort_by(|a, b| {
+        (b.1.conflicts + b.1.failures)
+            .cmp(&(a.1.conflicts + a.1.failures))
+            .then_with(|| a.0.cmp(&b.0))
+    });
+    summary!("\nAuthors whose branches broke runs most often:");
+    if authors.is_empty() {
+        summary!("  none");
+    }
+    for (author, tally) in authors.iter().take(limit) {
+        summary!(
+            "  {:>4} conflicts  {:>4} failures  {}",
+            tally.conflicts,
+            tally.failures,
+            author
+        );
+    }
+
+    let mut flaky: Vec<(String, (usize, usize))> = flaky.into_iter().collect();
+    flaky.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    summary!("\nBranches passing verification only on retry most often:");
+    if flaky.is_empty() {
+        summary!("  none");
+    }
+    for (branch, (merges, retries)) in flaky.iter().take(limit) {
+        summary!("  {:>4} merges  {:>4} retries  {}", merges, retries, branch);
+    }
+}
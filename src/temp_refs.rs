@@ -0,0 +1,47 @@
+use git2::Repository;
+
+use super::github::PullRequest;
+use super::state::State;
+
+/// Where the heads of pull requests from forks are fetched to. Nothing else
+/// is kept there, so whatever is found under it was left by a run.
+const NAMESPACE: &str = "refs/integrate/";
+
+/// The temporary ref the head of pull request `number` is fetched to.
+pub fn pull_ref(number: i64) -> String {
+    format!("{}pull/{}", NAMESPACE, number)
+}
+
+/// Fetches the heads of the pull requests from forks out of origin, where
+/// they are published under a ref of the base repository, instead of adding
+/// a remote for each fork.
+pub fn refspecs(pull_requests: &[PullRequest]) -> Vec<String> {
+    pull_requests
+        .iter()
+        .filter_map(|pr| {
+            let fork_ref = pr.fork_ref.as_ref()?;
+            Some(format!("+{}:{}", fork_ref, pull_ref(pr.number)))
+        })
+        .collect()
+}
+
+/// Deletes every temporary ref, answering how many there were.
+pub fn remove_all(repository: &Repository) -> Result<usize, git2::Error> {
+    let mut removed = 0;
+    for reference in repository.references_glob(&format!("{}*", NAMESPACE))? {
+        reference?.delete()?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+/// Deletes the temporary refs at the end of a run, or those left by one that
+/// crashed, unless a stopped run still has to merge them.
+pub fn clean_up(repository: &Repository) {
+    if matches!(State::load(repository), Ok(Some(_))) {
+        return;
+    }
+    if let Err(e) = remove_all(repository) {
+        warn!("Could not delete the temporary refs: {}", e);
+    }
+}
@@ -0,0 +1,291 @@
+use std::fmt;
+use std::io::{self, Read};
+use std::process::{self, Child, Command, ExitStatus, Output, Stdio};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long an API request may take unless `integrate.http-timeout` says
+/// otherwise, the default of the HTTP client.
+pub const DEFAULT_HTTP: Duration = Duration::from_secs(30);
+
+/// How often a running git command is checked on.
+const POLL: Duration = Duration::from_millis(20);
+
+static HTTP: OnceLock<Duration> = OnceLock::new();
+static GIT: OnceLock<Duration> = OnceLock::new();
+static MERGE: OnceLock<Duration> = OnceLock::new();
+/// When the run has to be over, and how long it was given.
+static DEADLINE: OnceLock<(Instant, Duration)> = OnceLock::new();
+
+/// A limit the run ran into, naming the knob that sets it.
+pub enum Expired {
+    Http(Duration),
+    Git { command: String, after: Duration },
+    Deadline(Duration),
+}
+
+impl fmt::Display for Expired {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expired::Http(after) => write!(
+                f,
+                "An API request got no answer within {}s, see --http-timeout",
+                after.as_secs()
+            ),
+            Expired::Git { command, after } => write!(
+                f,
+                "`{}` did not finish within {}s and was stopped, see --git-timeout",
+                command,
+                after.as_secs()
+            ),
+            Expired::Deadline(after) => write!(
+                f,
+                "The run did not finish within its {}s deadline, see --deadline",
+                after.as_secs()
+            ),
+        }
+    }
+}
+
+/// Explains what ran out of time and stops.
+pub fn expire(expired: Expired) -> ! {
+    error!("{}", expired);
+    process::exit(1)
+}
+
+/// A length of time such as `90`, `90s`, `5m` or `1h`, seconds without a
+/// unit.
+pub fn parse(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => value.split_at(idx),
+        None => (value, "s"),
+    };
+    let number: u64 = number.parse().ok().filter(|&number| number > 0)?;
+    match unit {
+        "s" => Some(Duration::from_secs(number)),
+        "m" => Some(Duration::from_secs(number * 60)),
+        "h" => Some(Duration::from_secs(number * 60 * 60)),
+        _ => None,
+    }
+}
+
+pub fn set_http(timeout: Duration) -> Result<(), String> {
+    HTTP.set(timeout)
+        .map_err(|_| "The HTTP timeout was already set".to_string())
+}
+
+pub fn set_git(timeout: Duration) -> Result<(), String> {
+    GIT.set(timeout)
+        .map_err(|_| "The git timeout was already set".to_string())
+}
+
+pub fn set_merge(budget: Duration) -> Result<(), String> {
+    MERGE
+        .set(budget)
+        .map_err(|_| "The merge timeout was already set".to_string())
+}
+
+/// How long a single merge may take before its pull request is skipped, from
+/// `--merge-timeout`.
+pub fn merge() -> Option<Duration> {
+    MERGE.get().copied()
+}
+
+/// Gives the run `length` from now to finish.
+pub fn set_deadline(length: Duration) -> Result<(), String> {
+    DEADLINE
+        .set((Instant::now() + length, length))
+        .map_err(|_| "The deadline was already set".to_string())
+}
+
+/// What is left before the deadline, none without one.
+fn remaining() -> Option<Duration> {
+    DEADLINE
+        .get()
+        .map(|(end, _)| end.saturating_duration_since(Instant::now()))
+}
+
+/// Stops the run once its deadline has passed.
+pub fn check_deadline() -> Result<(), Expired> {
+    match DEADLINE.get() {
+        Some((_, length)) if remaining() == Some(Duration::ZERO) => Err(Expired::Deadline(*length)),
+        _ => Ok(()),
+    }
+}
+
+/// How long the next API request may take, never past the deadline.
+pub fn http() -> Duration {
+    let timeout = HTTP.get().copied().unwrap_or(DEFAULT_HTTP);
+    remaining().map_or(timeout, |left| timeout.min(left.max(POLL)))
+}
+
+/// What ran out when the HTTP client timed out.
+pub fn http_expired() -> Expired {
+    match check_deadline() {
+        Err(deadline) => deadline,
+        Ok(()) => Expired::Http(HTTP.get().copied().unwrap_or(DEFAULT_HTTP)),
+    }
+}
+
+/// Waits for `child`, started as `command`. One that takes longer than
+/// `--git-timeout` or runs past the deadline is killed, and the run ends.
+pub fn wait(child: &mut Child, command: &str) -> io::Result<ExitStatus> {
+    wait_within(child, command, None)
+}
+
+/// Like `wait`, also killing `child` once it takes longer than `budget`,
+/// which answers a `TimedOut` error and lets the run carry on.
+pub fn wait_within(
+    child: &mut Child,
+    command: &str,
+    budget: Option<Duration>,
+) -> io::Result<ExitStatus> {
+    let git = GIT.get().copied();
+    if git.is_none() && budget.is_none() && DEADLINE.get().is_none() {
+        return child.wait();
+    }
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if let Some(expired) = expired(command, started, git) {
+            let _ = child.kill();
+            let _ = child.wait();
+            expire(expired);
+        }
+        if let Some(budget) = budget.filter(|&budget| started.elapsed() >= budget) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("`{}` took longer than {}s", command, budget.as_secs()),
+            ));
+        }
+        thread::sleep(POLL);
+    }
+}
+
+/// Like `wait`, for `children` all started as `command` at once. When one
+/// can't be waited for or they run out of time, the others are killed too
+/// rather than left running.
+pub fn wait_all(children: &mut [Child], command: &str) -> io::Result<Vec<ExitStatus>> {
+    let git = GIT.get().copied();
+    let started = Instant::now();
+    let mut statuses: Vec<Option<ExitStatus>> = vec![None; children.len()];
+    loop {
+        for idx in 0..children.len() {
+            if statuses[idx].is_none() {
+                match children[idx].try_wait() {
+                    Ok(status) => statuses[idx] = status,
+                    Err(e) => {
+                        kill_all(children);
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        if statuses.iter().all(Option::is_some) {
+            return Ok(statuses.into_iter().flatten().collect());
+        }
+        if let Some(expired) = expired(command, started, git) {
+            kill_all(children);
+            expire(expired);
+        }
+        thread::sleep(POLL);
+    }
+}
+
+/// Kills whichever of `children` are still running, and reaps them all.
+pub fn kill_all(children: &mut [Child]) {
+    for child in children {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// The limit `command`, started at `started`, ran into, if any.
+fn expired(command: &str, started: Instant, git: Option<Duration>) -> Option<Expired> {
+    match (git, check_deadline()) {
+        (_, Err(deadline)) => Some(deadline),
+        (Some(after), _) if started.elapsed() >= after => Some(Expired::Git {
+            command: command.to_string(),
+            after,
+        }),
+        _ => None,
+    }
+}
+
+/// Running a command within the git timeout and the deadline, see `wait`.
+pub trait Timed {
+    fn status_timed(&mut self) -> io::Result<ExitStatus>;
+
+    /// See `wait_within`.
+    fn status_within(&mut self, budget: Option<Duration>) -> io::Result<ExitStatus>;
+
+    fn output_timed(&mut self) -> io::Result<Output>;
+}
+
+fn describe(command: &Command) -> String {
+    let args: Vec<String> = command
+        .get_args()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    format!(
+        "{} {}",
+        command.get_program().to_string_lossy(),
+        args.join(" ")
+    )
+}
+
+impl Timed for Command {
+    fn status_timed(&mut self) -> io::Result<ExitStatus> {
+        let mut child = self.spawn()?;
+        wait(&mut child, &describe(self))
+    }
+
+    fn status_within(&mut self, budget: Option<Duration>) -> io::Result<ExitStatus> {
+        let mut child = self.spawn()?;
+        wait_within(&mut child, &describe(self), budget)
+    }
+
+    fn output_timed(&mut self) -> io::Result<Output> {
+        let mut child = self.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+        // Read while waiting, a full pipe would keep the command from ending.
+        let stdout = read(child.stdout.take());
+        let stderr = read(child.stderr.take());
+        let status = wait(&mut child, &describe(self))?;
+        Ok(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
+    }
+}
+
+fn read(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = vec![];
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lengths_default_to_seconds() {
+        assert_eq!(parse("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse(" 5m"), Some(Duration::from_secs(300)));
+        assert_eq!(parse("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse("0"), None);
+        assert_eq!(parse("5 minutes"), None);
+    }
+}
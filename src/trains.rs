@@ -0,0 +1,119 @@
+use git2::Repository;
+use std::io;
+
+use super::git_extras::{git_command, remote, Repo};
+use super::github::{rest, Selection};
+use super::history::Run;
+use super::timeouts::Timed;
+
+/// A version being released, built into `train/<version>` and tagged as
+/// each release candidate of it.
+pub struct Train {
+    pub version: String,
+    /// The number of the release candidate this run builds.
+    pub rc: u32,
+}
+
+impl Train {
+    /// The release candidate after the last one `runs` built of `version`.
+    pub fn next(version: &str, runs: &[Run]) -> Train {
+        let branch = branch(version);
+        let rc = runs
+            .iter()
+            .filter(|run| run.dest_branch == branch)
+            .filter_map(|run| run.release_candidate)
+            .max()
+            .map_or(1, |last| last + 1);
+        Train {
+            version: version.to_string(),
+            rc,
+        }
+    }
+
+    pub fn tag(&self) -> String {
+        format!("{}/rc.{}", branch(&self.version), self.rc)
+    }
+}
+
+/// The branch the train of `version` is built into.
+pub fn branch(version: &str) -> String {
+    format!("train/{}", version)
+}
+
+/// Whether `version` can name a branch and its tags.
+pub fn is_valid(version: &str) -> bool {
+    git2::Reference::is_valid_name(&format!("refs/tags/{}/rc.1", branch(version)))
+}
+
+/// The pull requests of the open milestone titled `version`, or of the
+/// label named it when there is no such milestone.
+pub fn selection(
+    token: Option<&str>,
+    repo: &Repo,
+    version: &str,
+) -> Result<Selection, reqwest::Error> {
+    Ok(rest::open_milestones(token, repo)?
+        .into_iter()
+        .find(|milestone| milestone.title == version)
+        .map_or_else(
+            || Selection::Label(version.to_string()),
+            |milestone| Selection::Milestone(milestone.number),
+        ))
+}
+
+/// Tags HEAD as the release candidate `train` builds.
+pub fn tag(repository: &Repository, train: &Train) -> io::Result<bool> {
+    Ok(git_command(repository)
+        .arg("tag")
+        .arg("--annotate")
+        .arg("--message")
+        .arg(format!("{} release candidate {}", train.version, train.rc))
+        .arg(train.tag())
+        .arg("HEAD")
+        .status_timed()?
+        .success())
+}
+
+pub fn push_tag(repository: &Repository, train: &Train) -> io::Result<bool> {
+    Ok(git_command(repository)
+        .arg("push")
+        .arg(remote())
+        .arg(format!("refs/tags/{}", train.tag()))
+        .status_timed()?
+        .success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(dest_branch: &str, release_candidate: Option<u32>) -> Run {
+        Run {
+            finished_at: String::new(),
+            dest_branch: dest_branch.to_string(),
+            branch_template: None,
+            selection: String::new(),
+            success: release_candidate.is_some(),
+            pulls: vec![],
+            release_candidate,
+            pushed: false,
+            query_cost: None,
+        }
+    }
+
+    #[test]
+    fn release_candidates_count_up_per_version() {
+        let runs = [
+            run("train/2.4", Some(1)),
+            run("train/2.4", None),
+            run("train/2.5", Some(7)),
+            run("train/2.4", Some(2)),
+            run("staging", None),
+        ];
+        assert_eq!(Train::next("2.4", &runs).tag(), "train/2.4/rc.3");
+        assert_eq!(Train::next("2.5", &runs).rc, 8);
+        assert_eq!(Train::next("3.0", &runs).tag(), "train/3.0/rc.1");
+        assert!(is_valid("2.4.1"));
+        assert!(!is_valid("2.4 beta"));
+    }
+}
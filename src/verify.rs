@@ -0,0 +1,57 @@
+use git2::Repository;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use super::github::PullRequest;
+use super::messages;
+use super::run_env;
+
+/// A command checking the integration branch after every merge, such as
+/// `cargo test`.
+pub struct Verify {
+    pub command: String,
+    /// Undo merges that fail the check and carry on without them instead of
+    /// stopping.
+    pub skip_failures: bool,
+    /// How many more times a failing command runs before the merge is
+    /// blamed, for suites with flaky tests.
+    pub retries: usize,
+}
+
+impl Verify {
+    /// Runs the command in the work tree with `pr` just merged, answering
+    /// whether it passed.
+    pub fn passes(&self, repository: &Repository, pr: &PullRequest) -> io::Result<bool> {
+        let workdir = repository.workdir().unwrap_or_else(|| Path::new("."));
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(&self.command)
+            .current_dir(workdir)
+            .env("GIT_INTEGRATE_PR", pr.number.to_string())
+            .env("GIT_INTEGRATE_BRANCH", &pr.branch);
+        run_env::apply(&mut command, repository, pr);
+        if messages::is_quiet() {
+            command.stdout(Stdio::null());
+        }
+        Ok(command.status()?.success())
+    }
+
+    /// Runs the command until it passes or is out of retries, answering
+    /// whether it passed and how many times it failed first.
+    pub fn check(&self, repository: &Repository, pr: &PullRequest) -> io::Result<(bool, usize)> {
+        let mut failures = 0;
+        while !self.passes(repository, pr)? {
+            failures += 1;
+            if failures > self.retries {
+                return Ok((false, failures));
+            }
+            warn!(
+                "`{}` failed, retrying ({} of {})",
+                self.command, failures, self.retries
+            );
+        }
+        Ok((true, failures))
+    }
+}
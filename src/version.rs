@@ -0,0 +1,62 @@
+use git2::Repository;
+
+pub const VERSION_FILE: &str = ".git-integrate-version";
+
+/// The version of this binary.
+pub const CURRENT: &str = env!("CARGO_PKG_VERSION");
+
+/// The minimum version the repository asks for, from `.git-integrate-version`
+/// committed on the base branch or else `integrate.required-version`.
+pub fn required(repository: &Repository, base: &str, setting: Option<String>) -> Option<String> {
+    let contents = || -> Result<Vec<u8>, git2::Error> {
+        let tree = repository.revparse_single(base)?.peel_to_tree()?;
+        let entry = tree.get_path(VERSION_FILE.as_ref())?;
+        Ok(entry
+            .to_object(repository)?
+            .peel_to_blob()?
+            .content()
+            .to_vec())
+    };
+    contents()
+        .ok()
+        .map(|contents| String::from_utf8_lossy(&contents).trim().to_string())
+        .filter(|version| !version.is_empty())
+        .or(setting)
+}
+
+/// The numbers of a version such as `1.2.0`, ignoring any `v` prefix and
+/// `-beta` suffix.
+fn parse(version: &str) -> Option<Vec<u64>> {
+    let version = version.trim().trim_start_matches('v');
+    let version = version.split(['-', '+']).next().unwrap_or_default();
+    version.split('.').map(|part| part.parse().ok()).collect()
+}
+
+/// Whether `version` is older than `required`, missing numbers counting as
+/// 0. `None` if `required` is not a version.
+pub fn is_older(version: &str, required: &str) -> Option<bool> {
+    let (mut version, mut required) = (parse(version)?, parse(required)?);
+    let len = version.len().max(required.len());
+    version.resize(len, 0);
+    required.resize(len, 0);
+    Some(version < required)
+}
+
+/// Whether this binary is recent enough for the repository, explaining why
+/// not if it isn't.
+pub fn satisfied(repository: &Repository, base: &str, setting: Option<String>) -> bool {
+    let required = match required(repository, base, setting) {
+        Some(required) => required,
+        None => return true,
+    };
+    let older = is_older(CURRENT, &required)
+        .unwrap_or_else(|| panic!("Invalid required version {}", required));
+    if older {
+        error!(
+            "This repository requires git-integrate {} or newer, this is {}. Update \
+             \ngit-integrate so everyone builds integration branches the same way.",
+            required, CURRENT
+        );
+    }
+    !older
+}
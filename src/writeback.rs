@@ -0,0 +1,131 @@
+use std::path::PathBuf;
+
+use super::git_extras::Repo;
+use super::github::{rest, PullRequest};
+
+/// GitHub limits commit status descriptions to 140 characters.
+const MAX_DESCRIPTION: usize = 140;
+
+/// How pull requests are told whether they made it into the integration
+/// branch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteBack {
+    /// Set a `git-integrate/<branch>` commit status on the pull request head.
+    Status,
+    /// Post a comment on the pull request, or edit the one an earlier run
+    /// posted.
+    Comment,
+}
+
+impl WriteBack {
+    pub fn parse(value: &str) -> Option<WriteBack> {
+        match value {
+            "status" => Some(WriteBack::Status),
+            "comment" => Some(WriteBack::Comment),
+            _ => None,
+        }
+    }
+}
+
+fn context(dest_branch: &str) -> String {
+    format!("git-integrate/{}", dest_branch)
+}
+
+/// Starts the comment on a pull request about `dest_branch`, so the next run
+/// edits it instead of adding another.
+fn marker(dest_branch: &str) -> String {
+    format!("<!-- git-integrate write-back {} -->", dest_branch)
+}
+
+fn comment(
+    token: &str,
+    repo: &Repo,
+    pr: &PullRequest,
+    dest_branch: &str,
+    text: &str,
+) -> Result<(), reqwest::Error> {
+    let marker = marker(dest_branch);
+    let body = format!("{}\n{}", marker, text);
+    rest::upsert_comment(token, repo, pr.number, &marker, &body)
+}
+
+fn truncate(description: String) -> String {
+    match description.char_indices().nth(MAX_DESCRIPTION - 1) {
+        Some((idx, _)) => format!("{}…", &description[..idx]),
+        None => description,
+    }
+}
+
+/// Tells `pr`, whose head is `head`, that it is in `dest_branch` as pushed
+/// at `sha`.
+pub fn included(
+    token: &str,
+    repo: &Repo,
+    write_back: WriteBack,
+    pr: &PullRequest,
+    head: &str,
+    dest_branch: &str,
+    sha: &str,
+) -> Result<(), reqwest::Error> {
+    match write_back {
+        WriteBack::Status => rest::create_status(
+            token,
+            repo,
+            head,
+            "success",
+            &context(dest_branch),
+            &format!("Included in {} at {}", dest_branch, &sha[..10]),
+        ),
+        WriteBack::Comment => comment(
+            token,
+            repo,
+            pr,
+            dest_branch,
+            &format!(
+                "Included in integration branch `{}` at {}.",
+                dest_branch, sha
+            ),
+        ),
+    }
+}
+
+/// Tells `pr`, whose head is `head`, that it conflicted and was left out of
+/// `dest_branch`.
+pub fn conflicted(
+    token: &str,
+    repo: &Repo,
+    write_back: WriteBack,
+    pr: &PullRequest,
+    head: &str,
+    dest_branch: &str,
+    conflicts: &[PathBuf],
+) -> Result<(), reqwest::Error> {
+    let paths = conflicts
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>();
+    match write_back {
+        WriteBack::Status => rest::create_status(
+            token,
+            repo,
+            head,
+            "failure",
+            &context(dest_branch),
+            &truncate(format!("Conflicts in {}", paths.join(", "))),
+        ),
+        WriteBack::Comment => comment(
+            token,
+            repo,
+            pr,
+            dest_branch,
+            &format!(
+                "Could not be included in integration branch `{}`, merging it conflicts in:\n\n{}",
+                dest_branch,
+                paths
+                    .iter()
+                    .map(|path| format!("- `{}`\n", path))
+                    .collect::<String>()
+            ),
+        ),
+    }
+}